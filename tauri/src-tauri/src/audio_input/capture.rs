@@ -0,0 +1,271 @@
+use super::processing::{InputProcessing, InputProcessor, DENOISE_SAMPLE_RATE};
+use crate::audio_output::meter::LevelMeter;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{Device, FromSample, SampleFormat, SizedSample, Stream, StreamConfig, SupportedStreamConfig};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::Arc;
+
+/// Capture buffers queued for a channel consumer before new ones are dropped.
+const SINK_BUFFER_CHUNKS: usize = 512;
+
+/// Something fed every buffer an input stream captures.
+pub(super) trait InputSink: Send {
+    /// Take the next interleaved buffer. Returns false once the sink is done and
+    /// can be dropped.
+    fn push(&mut self, samples: &[f32]) -> bool;
+}
+
+/// Hands captured buffers to another thread without blocking the callback.
+pub(super) struct ChannelSink {
+    tx: mpsc::SyncSender<Vec<f32>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl ChannelSink {
+    /// A sink and the receiver its buffers arrive on, with a count of buffers
+    /// dropped because the receiver fell behind.
+    pub(super) fn new() -> (Self, mpsc::Receiver<Vec<f32>>, Arc<AtomicUsize>) {
+        let (tx, rx) = mpsc::sync_channel(SINK_BUFFER_CHUNKS);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        (
+            Self {
+                tx,
+                dropped: dropped.clone(),
+            },
+            rx,
+            dropped,
+        )
+    }
+}
+
+impl InputSink for ChannelSink {
+    fn push(&mut self, samples: &[f32]) -> bool {
+        match self.tx.try_send(samples.to_vec()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// One open input stream. Consumers are added as sinks; the stream closes when
+/// this is dropped.
+pub(super) struct InputCapture {
+    pub(super) sample_rate: u32,
+    pub(super) channels: u16,
+    /// Features using this capture; it is closed when the last one lets go
+    pub(super) consumers: usize,
+    /// Levels of the raw input, before any processing
+    pub(super) levels: Arc<LevelMeter>,
+    sink_tx: mpsc::Sender<Box<dyn InputSink>>,
+    // Dropping the sender closes the stream
+    _stop_tx: mpsc::Sender<()>,
+}
+
+impl InputCapture {
+    /// Open `device` with `config` and start capturing on a dedicated thread,
+    /// running each buffer through the input processing first.
+    pub(super) fn open(
+        device: Device,
+        config: SupportedStreamConfig,
+        processing: Arc<InputProcessing>,
+    ) -> Result<Self, String> {
+        let device_name = device.name().unwrap_or_else(|_| "unknown".to_string());
+        let sample_rate = config.sample_rate().0;
+        let channels = config.channels();
+        let sample_format = config.sample_format();
+        eprintln!(
+            "InputCapture::open: {} - {}Hz, {} channels, format: {:?}",
+            device_name, sample_rate, channels, sample_format
+        );
+
+        let (sink_tx, sink_rx) = mpsc::channel();
+        let levels = Arc::new(LevelMeter::new(channels.max(1) as usize));
+        let mut bus = CaptureBus {
+            sinks: Vec::new(),
+            incoming: sink_rx,
+            channels: channels.max(1) as usize,
+            levels: levels.clone(),
+            processor: InputProcessor::new(processing, sample_rate, channels),
+        };
+        let stream_config = StreamConfig {
+            channels,
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        };
+        let stop_tx = spawn_input_thread(device, device_name, stream_config, sample_format, move |data| {
+            bus.deliver(data)
+        })?;
+
+        Ok(Self {
+            sample_rate,
+            channels,
+            consumers: 0,
+            levels,
+            sink_tx,
+            _stop_tx: stop_tx,
+        })
+    }
+
+    pub(super) fn add_sink(&self, sink: Box<dyn InputSink>) -> Result<(), String> {
+        self.sink_tx
+            .send(sink)
+            .map_err(|_| "Input stream is no longer running".to_string())
+    }
+}
+
+/// Capture state owned by an input stream's callback.
+struct CaptureBus {
+    sinks: Vec<Box<dyn InputSink>>,
+    incoming: mpsc::Receiver<Box<dyn InputSink>>,
+    channels: usize,
+    levels: Arc<LevelMeter>,
+    processor: InputProcessor,
+}
+
+impl CaptureBus {
+    fn deliver(&mut self, data: &[f32]) {
+        self.sinks.extend(self.incoming.try_iter());
+        self.levels.record(data, self.channels);
+        let processed = self.processor.process(data);
+        if processed.is_empty() {
+            return;
+        }
+        self.sinks.retain_mut(|sink| sink.push(processed));
+    }
+}
+
+/// The device's default input config, switched to 48 kHz when the device can
+/// do that in the same format, so noise suppression runs without resampling.
+pub(super) fn preferred_input_config(device: &Device) -> Result<SupportedStreamConfig, String> {
+    let default = device
+        .default_input_config()
+        .map_err(|e| format!("Failed to get default input config: {}", e))?;
+    if default.sample_rate().0 == DENOISE_SAMPLE_RATE {
+        return Ok(default);
+    }
+    let mut configs = match device.supported_input_configs() {
+        Ok(configs) => configs,
+        Err(_) => return Ok(default),
+    };
+    let rate = cpal::SampleRate(DENOISE_SAMPLE_RATE);
+    Ok(configs
+        .find(|range| {
+            range.channels() == default.channels()
+                && range.sample_format() == default.sample_format()
+                && range.min_sample_rate() <= rate
+                && rate <= range.max_sample_rate()
+        })
+        .map(|range| range.with_sample_rate(rate))
+        .unwrap_or(default))
+}
+
+/// Open and start an input stream on a dedicated thread that keeps it alive
+/// until the returned sender is dropped, as for output streams.
+fn spawn_input_thread<F>(
+    device: Device,
+    device_name: String,
+    config: StreamConfig,
+    sample_format: SampleFormat,
+    deliver: F,
+) -> Result<mpsc::Sender<()>, String>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<(), String>>();
+
+    std::thread::spawn(move || {
+        let stream = build_input_stream(&device, &config, sample_format, deliver).and_then(|stream| {
+            stream
+                .play()
+                .map_err(|e| format!("Failed to start capture: {}", e))
+                .map(|_| stream)
+        });
+
+        let stream = match stream {
+            Ok(stream) => {
+                eprintln!("Started input stream on device: {}", device_name);
+                stream
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(format!("Failed to open input device {}: {}", device_name, e)));
+                return;
+            }
+        };
+
+        let _ = ready_tx.send(Ok(()));
+        // Blocks until the owning handle is dropped
+        let _ = stop_rx.recv();
+        drop(stream);
+    });
+
+    ready_rx
+        .recv()
+        .map_err(|_| "Input stream thread exited unexpectedly".to_string())??;
+    Ok(stop_tx)
+}
+
+/// Build an input stream that converts the device's samples to f32 for `deliver`.
+fn build_input_stream<F>(
+    device: &Device,
+    stream_config: &StreamConfig,
+    sample_format: SampleFormat,
+    mut deliver: F,
+) -> Result<Stream, String>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    let stream = match sample_format {
+        SampleFormat::F32 => device.build_input_stream(
+            stream_config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| deliver(data),
+            stream_error,
+            None,
+        ),
+        SampleFormat::F64 => build_converting_stream::<f64, F>(device, stream_config, deliver),
+        SampleFormat::I8 => build_converting_stream::<i8, F>(device, stream_config, deliver),
+        SampleFormat::I16 => build_converting_stream::<i16, F>(device, stream_config, deliver),
+        SampleFormat::I32 => build_converting_stream::<i32, F>(device, stream_config, deliver),
+        SampleFormat::I64 => build_converting_stream::<i64, F>(device, stream_config, deliver),
+        SampleFormat::U8 => build_converting_stream::<u8, F>(device, stream_config, deliver),
+        SampleFormat::U16 => build_converting_stream::<u16, F>(device, stream_config, deliver),
+        SampleFormat::U32 => build_converting_stream::<u32, F>(device, stream_config, deliver),
+        SampleFormat::U64 => build_converting_stream::<u64, F>(device, stream_config, deliver),
+        other => return Err(format!("Unsupported sample format: {:?}", other)),
+    };
+
+    stream.map_err(|e| format!("Failed to build input stream: {}", e))
+}
+
+/// Convert each captured buffer into an f32 scratch buffer before delivering it.
+fn build_converting_stream<T, F>(
+    device: &Device,
+    stream_config: &StreamConfig,
+    mut deliver: F,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    let mut scratch = Vec::new();
+    device.build_input_stream(
+        stream_config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            scratch.clear();
+            scratch.extend(data.iter().map(|sample| sample.to_sample::<f32>()));
+            deliver(&scratch);
+        },
+        stream_error,
+        None,
+    )
+}
+
+fn stream_error(err: cpal::StreamError) {
+    eprintln!("Capture error: {}", err);
+}
@@ -0,0 +1,267 @@
+use crate::audio_output::convert::FormatConverter;
+use crate::audio_output::OutputTap;
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use std::collections::VecDeque;
+use std::sync::mpsc::TryRecvError;
+use std::sync::Arc;
+
+/// Frames per adaptive filter block; the canceller adds this much latency.
+const AEC_BLOCK_FRAMES: usize = 256;
+/// Longest echo path covered, including the output and input latency.
+const AEC_FILTER_MS: usize = 250;
+/// Adaptation step size of the normalised filter update.
+const AEC_STEP: f32 = 0.6;
+/// Smallest share of the full step used while the residual is much louder than
+/// the estimated echo, which usually means the user is talking over playback.
+const AEC_MIN_STEP_SCALE: f32 = 0.01;
+/// Playback adapted on at the full step after a reset, before the filter has
+/// found enough of the echo for the residual to say anything about double-talk.
+const AEC_WARMUP_MS: usize = 2000;
+/// Reference blocks quieter than this (mean square) aren't adapted on.
+const AEC_MIN_REFERENCE_POWER: f32 = 1e-8;
+/// Reference queued ahead of the mic, beyond which the oldest is skipped so
+/// the delay between the two streams can't keep growing.
+const AEC_MAX_REFERENCE_MS: usize = 100;
+
+/// Forward and inverse real FFTs of one size, with their scratch space.
+struct Fft {
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl Fft {
+    fn new(len: usize) -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(len);
+        let inverse = planner.plan_fft_inverse(len);
+        let scratch_len = forward.get_scratch_len().max(inverse.get_scratch_len());
+        Self {
+            forward,
+            inverse,
+            scratch: vec![Complex::default(); scratch_len],
+        }
+    }
+
+    /// Buffer sizes are fixed at construction, so these can't fail; the
+    /// inverse only complains about DC and Nyquist imaginary parts, which it
+    /// zeroes anyway.
+    fn forward(&mut self, time: &mut [f32], spectrum: &mut [Complex<f32>]) {
+        let _ = self.forward.process_with_scratch(time, spectrum, &mut self.scratch);
+    }
+
+    fn inverse(&mut self, spectrum: &mut [Complex<f32>], time: &mut [f32]) {
+        let _ = self.inverse.process_with_scratch(spectrum, time, &mut self.scratch);
+    }
+}
+
+/// Removes what the speakers play from the mic signal, using the output mix
+/// as the reference. A partitioned-block frequency-domain adaptive filter
+/// (overlap-save) learns the echo path from speaker to mic; each mic channel
+/// gets its own filter.
+pub(super) struct EchoCanceller {
+    reference: OutputTap,
+    /// Converts the output mix to mono at the mic's rate
+    converter: FormatConverter,
+    reference_queue: VecDeque<f32>,
+    max_reference: usize,
+    fft: Fft,
+    channels: usize,
+    /// Spectra of the last reference blocks, newest first
+    history: VecDeque<Vec<Complex<f32>>>,
+    previous_reference: Vec<f32>,
+    /// Filter weights per mic channel, per partition
+    weights: Vec<Vec<Vec<Complex<f32>>>>,
+    /// Partition whose weights are trimmed back to a linear filter next
+    constrain_next: usize,
+    /// Interleaved mic input not yet making up a whole block
+    pending: Vec<f32>,
+    output: Vec<f32>,
+    time: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    warmup_blocks: usize,
+    /// Blocks left to adapt on at the full step, per mic channel
+    warmup_remaining: Vec<usize>,
+    reference_lost: bool,
+}
+
+impl EchoCanceller {
+    pub(super) fn new(reference: OutputTap, sample_rate: u32, channels: u16) -> Result<Self, String> {
+        let converter = FormatConverter::new(reference.sample_rate, reference.channels, sample_rate, 1)?;
+        let channels = channels.max(1) as usize;
+        let partitions = (AEC_FILTER_MS * sample_rate as usize / 1000).div_ceil(AEC_BLOCK_FRAMES);
+        let bins = AEC_BLOCK_FRAMES + 1;
+        let warmup_blocks = AEC_WARMUP_MS * sample_rate as usize / 1000 / AEC_BLOCK_FRAMES;
+        Ok(Self {
+            reference,
+            converter,
+            reference_queue: VecDeque::new(),
+            max_reference: AEC_MAX_REFERENCE_MS * sample_rate as usize / 1000,
+            fft: Fft::new(AEC_BLOCK_FRAMES * 2),
+            channels,
+            history: (0..partitions).map(|_| vec![Complex::default(); bins]).collect(),
+            previous_reference: vec![0.0; AEC_BLOCK_FRAMES],
+            weights: vec![vec![vec![Complex::default(); bins]; partitions]; channels],
+            constrain_next: 0,
+            pending: Vec::new(),
+            output: Vec::new(),
+            time: vec![0.0; AEC_BLOCK_FRAMES * 2],
+            spectrum: vec![Complex::default(); bins],
+            warmup_blocks,
+            warmup_remaining: vec![warmup_blocks; channels],
+            reference_lost: false,
+        })
+    }
+
+    /// Cancel echo from a captured buffer. Audio is held back until a whole
+    /// block has arrived, so the result can be shorter or longer than `data`.
+    pub(super) fn process(&mut self, data: &[f32]) -> &[f32] {
+        self.receive_reference();
+        self.pending.extend_from_slice(data);
+        self.output.clear();
+
+        let pending = std::mem::take(&mut self.pending);
+        let mut blocks = pending.chunks_exact(AEC_BLOCK_FRAMES * self.channels);
+        for mic in &mut blocks {
+            self.process_block(mic);
+        }
+        self.pending = blocks.remainder().to_vec();
+        &self.output
+    }
+
+    fn receive_reference(&mut self) {
+        loop {
+            match self.reference.rx.try_recv() {
+                Ok(chunk) => {
+                    let mono = self.converter.process(&chunk);
+                    self.reference_queue.extend(mono);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    if !self.reference_lost {
+                        eprintln!("Echo cancellation lost its reference output; passing the mic through");
+                        self.reference_lost = true;
+                    }
+                    break;
+                }
+            }
+        }
+        if self.reference_queue.len() > self.max_reference {
+            let excess = self.reference_queue.len() - self.max_reference;
+            self.reference_queue.drain(..excess);
+        }
+    }
+
+    fn process_block(&mut self, mic: &[f32]) {
+        // Playback that hasn't arrived yet is treated as silence
+        let mut reference = vec![0.0; AEC_BLOCK_FRAMES];
+        let available = self.reference_queue.len().min(AEC_BLOCK_FRAMES);
+        for (sample, queued) in reference.iter_mut().zip(self.reference_queue.drain(..available)) {
+            *sample = queued;
+        }
+        let reference_power = reference.iter().map(|s| s * s).sum::<f32>() / AEC_BLOCK_FRAMES as f32;
+
+        // Overlap-save: the spectrum of the previous and current block
+        self.time[..AEC_BLOCK_FRAMES].copy_from_slice(&self.previous_reference);
+        self.time[AEC_BLOCK_FRAMES..].copy_from_slice(&reference);
+        self.previous_reference = reference;
+        let mut newest = self.history.pop_back().unwrap_or_default();
+        self.fft.forward(&mut self.time, &mut newest);
+        self.history.push_front(newest);
+
+        let bins = AEC_BLOCK_FRAMES + 1;
+        let regularisation = AEC_MIN_REFERENCE_POWER * (AEC_BLOCK_FRAMES * 2) as f32;
+        let norm: Vec<f32> = (0..bins)
+            .map(|bin| self.history.iter().map(|x| x[bin].norm_sqr()).sum::<f32>() + regularisation)
+            .collect();
+        let scale = 1.0 / (AEC_BLOCK_FRAMES * 2) as f32;
+
+        let output_start = self.output.len();
+        self.output.resize(output_start + mic.len(), 0.0);
+        for channel in 0..self.channels {
+            // Echo estimate: the last half of the filtered reference
+            for bin in 0..bins {
+                self.spectrum[bin] = self
+                    .history
+                    .iter()
+                    .zip(&self.weights[channel])
+                    .map(|(x, w)| x[bin] * w[bin])
+                    .sum();
+            }
+            self.fft.inverse(&mut self.spectrum, &mut self.time);
+
+            let mut mic_energy = 0.0;
+            let mut echo_energy = 0.0;
+            let mut error_energy = 0.0;
+            let mut error = vec![0.0; AEC_BLOCK_FRAMES * 2];
+            for i in 0..AEC_BLOCK_FRAMES {
+                let idx = i * self.channels + channel;
+                let echo = self.time[AEC_BLOCK_FRAMES + i] * scale;
+                let residual = mic[idx] - echo;
+                mic_energy += mic[idx] * mic[idx];
+                echo_energy += echo * echo;
+                error_energy += residual * residual;
+                error[AEC_BLOCK_FRAMES + i] = residual;
+                self.output[output_start + idx] = residual;
+            }
+
+            if error_energy > mic_energy * 4.0 && mic_energy > 0.0 {
+                // The filter has diverged and is adding echo; start over
+                self.reset_channel(channel);
+                for i in 0..AEC_BLOCK_FRAMES {
+                    let idx = i * self.channels + channel;
+                    self.output[output_start + idx] = mic[idx];
+                }
+                continue;
+            }
+            if reference_power < AEC_MIN_REFERENCE_POWER || self.reference_lost {
+                continue;
+            }
+
+            // Once warmed up, slow down sharply while the residual is louder
+            // than the echo we know about, so talking over playback doesn't
+            // wreck the filter
+            let step_scale = if self.warmup_remaining[channel] > 0 {
+                self.warmup_remaining[channel] -= 1;
+                1.0
+            } else {
+                (echo_energy / error_energy.max(f32::MIN_POSITIVE)).powi(2).clamp(AEC_MIN_STEP_SCALE, 1.0)
+            };
+            let step = AEC_STEP * step_scale;
+            self.fft.forward(&mut error, &mut self.spectrum);
+            for (x, w) in self.history.iter().zip(self.weights[channel].iter_mut()) {
+                for bin in 0..bins {
+                    w[bin] += x[bin].conj() * self.spectrum[bin] * (step / norm[bin]);
+                }
+            }
+        }
+
+        self.constrain_partition();
+    }
+
+    /// Trim one partition's weights back to a filter of `AEC_BLOCK_FRAMES`
+    /// taps. The unconstrained update slowly picks up circular-convolution
+    /// terms; doing one partition per block keeps that in check cheaply.
+    fn constrain_partition(&mut self) {
+        let partition = self.constrain_next;
+        self.constrain_next = (partition + 1) % self.history.len().max(1);
+        let scale = 1.0 / (AEC_BLOCK_FRAMES * 2) as f32;
+        for channel in 0..self.channels {
+            let weights = &mut self.weights[channel][partition];
+            self.spectrum.copy_from_slice(weights);
+            self.fft.inverse(&mut self.spectrum, &mut self.time);
+            for (i, tap) in self.time.iter_mut().enumerate() {
+                *tap = if i < AEC_BLOCK_FRAMES { *tap * scale } else { 0.0 };
+            }
+            self.fft.forward(&mut self.time, weights);
+        }
+    }
+
+    fn reset_channel(&mut self, channel: usize) {
+        self.warmup_remaining[channel] = self.warmup_blocks;
+        for weights in &mut self.weights[channel] {
+            weights.fill(Complex::default());
+        }
+    }
+}
@@ -0,0 +1,821 @@
+mod capture;
+mod echo;
+mod processing;
+mod recording;
+mod vad;
+pub(crate) mod viseme;
+
+use base64::{engine::general_purpose, Engine as _};
+use crate::audio_output::device_id::identified_input_devices;
+use crate::audio_output::{to_dbfs, AudioOutputState, LiveInputControl, LiveInputFeed, LEVEL_METER_INTERVAL_MS};
+use capture::{preferred_input_config, ChannelSink, InputCapture, InputSink};
+use echo::EchoCanceller;
+use processing::{AutoGain, AutoGainControl, InputProcessing, PushToTalk, PushToTalkGate};
+use recording::MicRecording;
+use vad::VoiceActivityDetector;
+use viseme::{Viseme, VisemeAnalyzer};
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host};
+use hound::{WavSpec, WavWriter};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Key captures of the default input device are stored under.
+const DEFAULT_INPUT_KEY: &str = "default";
+
+/// Highest gain the microphone monitor can be set to.
+const MAX_MONITOR_GAIN: f32 = 4.0;
+
+/// Peak level counted as clipping; integer inputs top out just under 1.0.
+const INPUT_CLIP_LEVEL: f32 = 0.999;
+
+/// Longest push-to-talk release tail.
+const MAX_RELEASE_TAIL_MS: u32 = 5000;
+
+/// Range automatic gain control can aim for, in dBFS RMS.
+const AGC_TARGET_RANGE_DBFS: (f32, f32) = (-40.0, -6.0);
+
+/// Most gain automatic gain control can be allowed to add.
+const MAX_AGC_GAIN_DB: f32 = 30.0;
+
+/// How often a take's collector, or the voice detector, checks whether it has
+/// been stopped.
+const TAKE_POLL_MS: u64 = 50;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AudioInputDevice {
+    pub id: String,
+    /// WASAPI endpoint ID or ALSA PCM name, when the host exposes one
+    pub platform_id: Option<String>,
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// Payload of `recording://elapsed`, emitted a few times a second while the
+/// microphone is being recorded to a file.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RecordingElapsed {
+    pub path: String,
+    pub elapsed_secs: f64,
+}
+
+/// Payload of `mic://voice_activity`, emitted when speech starts or stops on
+/// the input device being watched.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VoiceActivity {
+    pub device_id: String,
+    pub speaking: bool,
+}
+
+/// Payload of `mic://viseme`, emitted when the mouth shape of the voice on
+/// the input device being lip-synced changes.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MicViseme {
+    pub device_id: String,
+    pub viseme: Viseme,
+    /// How far open the mouth is, 0-1
+    pub weight: f32,
+}
+
+/// Levels of one input device since the previous `mic://levels` event, per
+/// channel in dBFS, measured before any processing.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct InputLevel {
+    pub device_id: String,
+    pub peak_dbfs: Vec<f32>,
+    pub rms_dbfs: Vec<f32>,
+    /// Whether the input hit full scale
+    pub clipping: bool,
+}
+
+/// Keeps a capture open for the input meter until told to let go.
+struct MeterSink(Arc<AtomicBool>);
+
+impl InputSink for MeterSink {
+    fn push(&mut self, _samples: &[f32]) -> bool {
+        !self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Voice activity detection running on its own thread.
+struct VoiceDetection {
+    device_key: String,
+    stop: Arc<AtomicBool>,
+    worker: JoinHandle<()>,
+}
+
+/// Mouth shapes being estimated from an input device on its own thread.
+struct LipSync {
+    device_key: String,
+    stop: Arc<AtomicBool>,
+    worker: JoinHandle<()>,
+}
+
+/// A microphone take being collected in memory by `start_mic_capture`.
+struct MicTake {
+    device_key: String,
+    stop: Arc<AtomicBool>,
+    /// Yields the captured samples once `stop` is set or the take is full
+    collector: JoinHandle<Vec<f32>>,
+    dropped: Arc<AtomicUsize>,
+    sample_rate: u32,
+    channels: u16,
+}
+
+/// The microphone being passed through to output devices.
+struct MicMonitor {
+    device_key: String,
+    control: Arc<LiveInputControl>,
+    sample_rate: u32,
+    channels: u16,
+    /// Swaps the sink's echo canceller while it runs
+    echo_tx: mpsc::Sender<Option<EchoCanceller>>,
+}
+
+/// Pushes captured audio into every output mixer the monitor is routed to,
+/// through the echo canceller, automatic gain control and the push-to-talk
+/// gate.
+struct MonitorSink {
+    echo: Option<EchoCanceller>,
+    echo_rx: mpsc::Receiver<Option<EchoCanceller>>,
+    auto_gain: AutoGainControl,
+    gate: PushToTalkGate,
+    feeds: Vec<LiveInputFeed>,
+}
+
+impl InputSink for MonitorSink {
+    fn push(&mut self, samples: &[f32]) -> bool {
+        if let Some(echo) = self.echo_rx.try_iter().last() {
+            self.echo = echo;
+        }
+        let cancelled = match &mut self.echo {
+            Some(echo) => echo.process(samples),
+            None => samples,
+        };
+        let levelled = self.auto_gain.process(cancelled);
+        let gated = self.gate.process(levelled);
+        self.feeds.retain_mut(|feed| feed.push(gated));
+        !self.feeds.is_empty()
+    }
+}
+
+/// Microphone and line-in capture. Each input device is opened once and
+/// shared by every feature that consumes it, and closed when the last one is
+/// done.
+pub struct AudioInputState {
+    host: Mutex<Host>,
+    /// Open input streams, keyed by device
+    captures: Mutex<HashMap<String, InputCapture>>,
+    take: Mutex<Option<MicTake>>,
+    /// File recording in progress and the device it is from
+    recording: Mutex<Option<(String, MicRecording)>>,
+    monitor: Mutex<Option<MicMonitor>>,
+    voice_detection: Mutex<Option<VoiceDetection>>,
+    lip_sync: Mutex<Option<LipSync>>,
+    /// Device held open by the input meter, and its sink's stop switch
+    meter: Mutex<Option<(String, Arc<AtomicBool>)>>,
+    processing: Arc<InputProcessing>,
+    push_to_talk: Arc<PushToTalk>,
+    auto_gain: Arc<AutoGain>,
+    /// Output device whose mix is cancelled from the mic route, if any
+    echo_reference: Mutex<Option<String>>,
+    app: Mutex<Option<AppHandle>>,
+}
+
+impl AudioInputState {
+    pub fn new() -> Self {
+        Self {
+            host: Mutex::new(cpal::default_host()),
+            captures: Mutex::new(HashMap::new()),
+            take: Mutex::new(None),
+            recording: Mutex::new(None),
+            monitor: Mutex::new(None),
+            voice_detection: Mutex::new(None),
+            lip_sync: Mutex::new(None),
+            meter: Mutex::new(None),
+            processing: Arc::new(InputProcessing::new()),
+            push_to_talk: Arc::new(PushToTalk::new()),
+            auto_gain: Arc::new(AutoGain::new()),
+            echo_reference: Mutex::new(None),
+            app: Mutex::new(None),
+        }
+    }
+
+    /// Give the state a handle for emitting events.
+    pub fn attach_app_handle(&self, app: AppHandle) {
+        *self.app.lock().unwrap() = Some(app.clone());
+        spawn_input_level_meter(app);
+    }
+
+    /// Keep an input device open just so its levels are metered, for setting
+    /// up the mic before anything else uses it.
+    pub fn start_input_meter(&self, device_id: Option<String>) -> Result<(), String> {
+        let mut meter = self.meter.lock().unwrap();
+        if meter.is_some() {
+            return Err("The input meter is already running".to_string());
+        }
+        let device_key = device_id.unwrap_or_else(|| DEFAULT_INPUT_KEY.to_string());
+        let done = Arc::new(AtomicBool::new(false));
+        let sink = MeterSink(done.clone());
+        self.add_sink(&device_key, |_, _| Ok(Box::new(sink)))?;
+        *meter = Some((device_key, done));
+        Ok(())
+    }
+
+    pub fn stop_input_meter(&self) -> Result<(), String> {
+        let (device_key, done) = self
+            .meter
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| "The input meter is not running".to_string())?;
+        done.store(true, Ordering::Relaxed);
+        self.release(&device_key);
+        Ok(())
+    }
+
+    /// Levels of every open capture since the last call.
+    fn input_levels(&self) -> Vec<InputLevel> {
+        self.captures
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(device_key, capture)| {
+                capture.levels.take().map(|(peak, rms)| InputLevel {
+                    device_id: device_key.clone(),
+                    clipping: peak.iter().any(|peak| *peak >= INPUT_CLIP_LEVEL),
+                    peak_dbfs: peak.into_iter().map(to_dbfs).collect(),
+                    rms_dbfs: rms.into_iter().map(to_dbfs).collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// List input devices, with IDs that stay the same across restarts.
+    pub fn list_input_devices(&self) -> Result<Vec<AudioInputDevice>, String> {
+        let host = self.host.lock().unwrap();
+        let devices = identified_input_devices(&host)?;
+        let default_name = host.default_input_device().and_then(|device| device.name().ok());
+        let default_index = default_name.and_then(|name| devices.iter().position(|device| device.name == name));
+
+        Ok(devices
+            .into_iter()
+            .enumerate()
+            .map(|(index, device)| AudioInputDevice {
+                id: device.id,
+                platform_id: device.platform_id,
+                name: device.name,
+                is_default: Some(index) == default_index,
+            })
+            .collect())
+    }
+
+    /// Capture from an input device (the default one if `device_id` is
+    /// `None`) into memory, for at most `max_duration_secs`.
+    pub fn start_mic_capture(&self, device_id: Option<String>, max_duration_secs: u32) -> Result<(), String> {
+        let mut take = self.take.lock().unwrap();
+        if take.is_some() {
+            return Err("A microphone capture is already running".to_string());
+        }
+
+        let device_key = device_id.unwrap_or_else(|| DEFAULT_INPUT_KEY.to_string());
+        let (sink, rx, dropped) = ChannelSink::new();
+        let (sample_rate, channels) = self.add_sink(&device_key, |_, _| Ok(Box::new(sink)))?;
+        let max_samples = max_duration_secs as usize * sample_rate as usize * channels as usize;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let collector_stop = stop.clone();
+        let collector = std::thread::spawn(move || {
+            let mut samples = Vec::new();
+            while !collector_stop.load(Ordering::Relaxed) && samples.len() < max_samples {
+                match rx.recv_timeout(Duration::from_millis(TAKE_POLL_MS)) {
+                    Ok(chunk) => samples.extend_from_slice(&chunk),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            samples.truncate(max_samples);
+            // Dropping the receiver detaches the sink from the stream
+            samples
+        });
+
+        eprintln!(
+            "start_mic_capture: Capturing from {} ({}Hz, {} channels, max {}s)",
+            device_key, sample_rate, channels, max_duration_secs
+        );
+        *take = Some(MicTake {
+            device_key,
+            stop,
+            collector,
+            dropped,
+            sample_rate,
+            channels,
+        });
+        Ok(())
+    }
+
+    /// Stop the running capture and return it as a base64-encoded 16-bit WAV.
+    pub fn stop_mic_capture(&self) -> Result<String, String> {
+        let take = self
+            .take
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| "No microphone capture is running".to_string())?;
+        take.stop.store(true, Ordering::Relaxed);
+        let samples = take
+            .collector
+            .join()
+            .map_err(|_| "Microphone capture thread panicked".to_string())?;
+        self.release(&take.device_key);
+
+        let dropped = take.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            eprintln!("stop_mic_capture: {} buffers were dropped", dropped);
+        }
+        if samples.is_empty() {
+            return Err("No audio was captured from the microphone".to_string());
+        }
+        let wav = samples_to_wav(&samples, take.sample_rate, take.channels)?;
+        Ok(general_purpose::STANDARD.encode(wav))
+    }
+
+    /// Record an input device (the default one if `device_id` is `None`) to a
+    /// WAV, FLAC or Opus file, at `sample_rate` or the device's own rate.
+    pub fn start_mic_recording(
+        &self,
+        device_id: Option<String>,
+        path: &Path,
+        sample_rate: Option<u32>,
+    ) -> Result<(), String> {
+        let mut recording = self.recording.lock().unwrap();
+        if recording.is_some() {
+            return Err("The microphone is already being recorded".to_string());
+        }
+
+        let device_key = device_id.unwrap_or_else(|| DEFAULT_INPUT_KEY.to_string());
+        let (sink, rx, dropped) = ChannelSink::new();
+        let (source_rate, source_channels) = self.add_sink(&device_key, |_, _| Ok(Box::new(sink)))?;
+
+        let app = self.app.lock().unwrap().clone();
+        let event_path = path.display().to_string();
+        let on_elapsed = move |elapsed_secs| {
+            let elapsed = RecordingElapsed {
+                path: event_path.clone(),
+                elapsed_secs,
+            };
+            emit_event(&app, "recording://elapsed", &elapsed);
+        };
+        match MicRecording::start(path, rx, dropped, source_rate, source_channels, sample_rate, on_elapsed) {
+            Ok(started) => {
+                *recording = Some((device_key, started));
+                Ok(())
+            }
+            Err(e) => {
+                self.release(&device_key);
+                Err(e)
+            }
+        }
+    }
+
+    /// Stop recording the microphone and finish writing the file.
+    pub fn stop_mic_recording(&self) -> Result<(), String> {
+        let (device_key, recording) = self
+            .recording
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| "The microphone is not being recorded".to_string())?;
+        let result = recording.finish();
+        self.release(&device_key);
+        result
+    }
+
+    /// Watch an input device (the default one if `device_id` is `None`) for
+    /// speech, emitting `mic://voice_activity` whenever it starts or stops.
+    pub fn start_voice_detection(&self, device_id: Option<String>) -> Result<(), String> {
+        let mut detection = self.voice_detection.lock().unwrap();
+        if detection.is_some() {
+            return Err("Voice activity detection is already running".to_string());
+        }
+
+        let device_key = device_id.unwrap_or_else(|| DEFAULT_INPUT_KEY.to_string());
+        let (sink, rx, _) = ChannelSink::new();
+        let (sample_rate, channels) = self.add_sink(&device_key, |_, _| Ok(Box::new(sink)))?;
+
+        let app = self.app.lock().unwrap().clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = stop.clone();
+        let worker_device = device_key.clone();
+        let worker = std::thread::spawn(move || {
+            let mut detector = VoiceActivityDetector::new(sample_rate, channels);
+            let mut speaking = false;
+            while !worker_stop.load(Ordering::Relaxed) {
+                match rx.recv_timeout(Duration::from_millis(TAKE_POLL_MS)) {
+                    Ok(chunk) => {
+                        if let Some(now_speaking) = detector.process(&chunk) {
+                            speaking = now_speaking;
+                            let activity = VoiceActivity {
+                                device_id: worker_device.clone(),
+                                speaking,
+                            };
+                            emit_event(&app, "mic://voice_activity", &activity);
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            // Don't leave listeners thinking the user is still talking
+            if speaking {
+                let activity = VoiceActivity {
+                    device_id: worker_device,
+                    speaking: false,
+                };
+                emit_event(&app, "mic://voice_activity", &activity);
+            }
+        });
+
+        eprintln!("start_voice_detection: Watching {} for speech", device_key);
+        *detection = Some(VoiceDetection {
+            device_key,
+            stop,
+            worker,
+        });
+        Ok(())
+    }
+
+    pub fn stop_voice_detection(&self) -> Result<(), String> {
+        let detection = self
+            .voice_detection
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| "Voice activity detection is not running".to_string())?;
+        detection.stop.store(true, Ordering::Relaxed);
+        if detection.worker.join().is_err() {
+            eprintln!("Voice activity detection thread panicked");
+        }
+        self.release(&detection.device_key);
+        Ok(())
+    }
+
+    /// Estimate mouth shapes from an input device (the default one if
+    /// `device_id` is `None`) for avatar lip-sync, emitting `mic://viseme`
+    /// whenever the shape changes.
+    pub fn start_lip_sync(&self, device_id: Option<String>) -> Result<(), String> {
+        let mut lip_sync = self.lip_sync.lock().unwrap();
+        if lip_sync.is_some() {
+            return Err("Lip-sync is already running".to_string());
+        }
+
+        let device_key = device_id.unwrap_or_else(|| DEFAULT_INPUT_KEY.to_string());
+        let (sink, rx, _) = ChannelSink::new();
+        let (sample_rate, channels) = self.add_sink(&device_key, |_, _| Ok(Box::new(sink)))?;
+
+        let app = self.app.lock().unwrap().clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = stop.clone();
+        let worker_device = device_key.clone();
+        let worker = std::thread::spawn(move || {
+            let mut analyzer = VisemeAnalyzer::new(sample_rate, channels);
+            let mut open = false;
+            while !worker_stop.load(Ordering::Relaxed) {
+                match rx.recv_timeout(Duration::from_millis(TAKE_POLL_MS)) {
+                    Ok(chunk) => {
+                        for change in analyzer.process(&chunk) {
+                            open = change.viseme != Viseme::Sil;
+                            let viseme = MicViseme {
+                                device_id: worker_device.clone(),
+                                viseme: change.viseme,
+                                weight: change.weight,
+                            };
+                            emit_event(&app, "mic://viseme", &viseme);
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            // Don't leave the avatar with its mouth open
+            if open {
+                let viseme = MicViseme {
+                    device_id: worker_device,
+                    viseme: Viseme::Sil,
+                    weight: 0.0,
+                };
+                emit_event(&app, "mic://viseme", &viseme);
+            }
+        });
+
+        eprintln!("start_lip_sync: Following {}", device_key);
+        *lip_sync = Some(LipSync {
+            device_key,
+            stop,
+            worker,
+        });
+        Ok(())
+    }
+
+    pub fn stop_lip_sync(&self) -> Result<(), String> {
+        let lip_sync = self
+            .lip_sync
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| "Lip-sync is not running".to_string())?;
+        lip_sync.stop.store(true, Ordering::Relaxed);
+        if lip_sync.worker.join().is_err() {
+            eprintln!("Lip-sync thread panicked");
+        }
+        self.release(&lip_sync.device_key);
+        Ok(())
+    }
+
+    /// Turn RNNoise noise suppression on or off for every capture. `strength`
+    /// (0.0-1.0) blends the denoised signal with the original.
+    pub fn set_noise_suppression(&self, enabled: bool, strength: f32) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&strength) {
+            return Err(format!("Noise suppression strength must be 0-1, got {}", strength));
+        }
+        self.processing.set_noise_suppression(enabled, strength);
+        Ok(())
+    }
+
+    /// With push-to-talk enabled, the mic route only passes audio while
+    /// `set_push_to_talk_held` says the key is down, plus `release_tail_ms`
+    /// after it is let go.
+    pub fn set_push_to_talk(&self, enabled: bool, release_tail_ms: u32) -> Result<(), String> {
+        if release_tail_ms > MAX_RELEASE_TAIL_MS {
+            return Err(format!("Release tail must be at most {} ms", MAX_RELEASE_TAIL_MS));
+        }
+        self.push_to_talk.configure(enabled, release_tail_ms);
+        Ok(())
+    }
+
+    pub fn set_push_to_talk_held(&self, held: bool) {
+        self.push_to_talk.set_held(held);
+    }
+
+    /// Turn automatic gain control on the mic route on or off. Speech is
+    /// steered towards `target_dbfs` (RMS), adding at most `max_gain_db`.
+    pub fn set_auto_gain(&self, enabled: bool, target_dbfs: f32, max_gain_db: f32) -> Result<(), String> {
+        let (min_target, max_target) = AGC_TARGET_RANGE_DBFS;
+        if !(min_target..=max_target).contains(&target_dbfs) {
+            return Err(format!(
+                "AGC target must be between {} and {} dBFS",
+                min_target, max_target
+            ));
+        }
+        if !(0.0..=MAX_AGC_GAIN_DB).contains(&max_gain_db) {
+            return Err(format!("AGC max gain must be between 0 and {} dB", MAX_AGC_GAIN_DB));
+        }
+        self.auto_gain.configure(enabled, target_dbfs, max_gain_db);
+        Ok(())
+    }
+
+    /// Pass an input device (the default one if `device_id` is `None`) through
+    /// to output devices at `gain`, for example to send the user's voice to a
+    /// virtual cable along with the soundboard.
+    pub fn start_mic_monitor(
+        &self,
+        output: &AudioOutputState,
+        device_id: Option<String>,
+        output_device_ids: Vec<String>,
+        gain: f32,
+    ) -> Result<(), String> {
+        if output_device_ids.is_empty() {
+            return Err("No output devices specified".to_string());
+        }
+        validate_monitor_gain(gain)?;
+        let mut monitor = self.monitor.lock().unwrap();
+        if monitor.is_some() {
+            return Err("The microphone is already being monitored".to_string());
+        }
+
+        let device_key = device_id.unwrap_or_else(|| DEFAULT_INPUT_KEY.to_string());
+        let control = Arc::new(LiveInputControl::new(gain));
+        let echo_reference = self.echo_reference.lock().unwrap().clone();
+        let (echo_tx, echo_rx) = mpsc::channel();
+        let (sample_rate, channels) = self.add_sink(&device_key, |sample_rate, channels| {
+            let echo = match &echo_reference {
+                Some(reference_id) => Some(EchoCanceller::new(
+                    output.tap_output(reference_id)?,
+                    sample_rate,
+                    channels,
+                )?),
+                None => None,
+            };
+            let feeds = output_device_ids
+                .iter()
+                .map(|output_id| output.add_live_input(output_id, sample_rate, channels, control.clone()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let auto_gain = AutoGainControl::new(self.auto_gain.clone(), sample_rate, channels);
+            let gate = PushToTalkGate::new(self.push_to_talk.clone(), sample_rate, channels);
+            Ok(Box::new(MonitorSink {
+                echo,
+                echo_rx,
+                auto_gain,
+                gate,
+                feeds,
+            }))
+        })?;
+
+        eprintln!(
+            "start_mic_monitor: Monitoring {} on {}",
+            device_key,
+            output_device_ids.join(", ")
+        );
+        *monitor = Some(MicMonitor {
+            device_key,
+            control,
+            sample_rate,
+            channels,
+            echo_tx,
+        });
+        Ok(())
+    }
+
+    /// Cancel what `output_device_id` plays from the mic route, for when the
+    /// speakers are picked up by the mic. `None` turns echo cancellation off.
+    /// Takes effect on a running monitor straight away.
+    pub fn set_echo_cancellation(
+        &self,
+        output: &AudioOutputState,
+        output_device_id: Option<String>,
+    ) -> Result<(), String> {
+        // Same order as start_mic_monitor: monitor, then echo reference
+        let monitor = self.monitor.lock().unwrap();
+        let mut echo_reference = self.echo_reference.lock().unwrap();
+        if let Some(monitor) = monitor.as_ref() {
+            let echo = match &output_device_id {
+                Some(reference_id) => Some(EchoCanceller::new(
+                    output.tap_output(reference_id)?,
+                    monitor.sample_rate,
+                    monitor.channels,
+                )?),
+                None => None,
+            };
+            // The sink is gone if the monitor's outputs have all closed
+            let _ = monitor.echo_tx.send(echo);
+        }
+        *echo_reference = output_device_id;
+        Ok(())
+    }
+
+    /// Change the monitor's gain while it runs.
+    pub fn set_mic_monitor_gain(&self, gain: f32) -> Result<(), String> {
+        validate_monitor_gain(gain)?;
+        match self.monitor.lock().unwrap().as_ref() {
+            Some(monitor) => {
+                monitor.control.set_gain(gain);
+                Ok(())
+            }
+            None => Err("The microphone is not being monitored".to_string()),
+        }
+    }
+
+    /// Fade the monitor out of its outputs.
+    pub fn stop_mic_monitor(&self) -> Result<(), String> {
+        let monitor = self
+            .monitor
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| "The microphone is not being monitored".to_string())?;
+        monitor.control.stop();
+        self.release(&monitor.device_key);
+        Ok(())
+    }
+
+    /// Feed a sink everything captured from a device, opening it on first use.
+    /// `make_sink` is given the capture's sample rate and channel count, which
+    /// are also returned. Every call must be paired with a `release`.
+    fn add_sink<F>(&self, device_key: &str, make_sink: F) -> Result<(u32, u16), String>
+    where
+        F: FnOnce(u32, u16) -> Result<Box<dyn InputSink>, String>,
+    {
+        let mut captures = self.captures.lock().unwrap();
+        let capture = match captures.entry(device_key.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let device = self.find_input_device(device_key)?;
+                let config = preferred_input_config(&device)?;
+                entry.insert(InputCapture::open(device, config, self.processing.clone())?)
+            }
+        };
+
+        let sink = match make_sink(capture.sample_rate, capture.channels) {
+            Ok(sink) => sink,
+            Err(e) => {
+                // Only close a capture that was opened for this call
+                if capture.consumers == 0 {
+                    captures.remove(device_key);
+                }
+                return Err(e);
+            }
+        };
+        if let Err(e) = capture.add_sink(sink) {
+            captures.remove(device_key);
+            return Err(e);
+        }
+        capture.consumers += 1;
+        Ok((capture.sample_rate, capture.channels))
+    }
+
+    /// Let go of a device taken with `add_sink`, closing its stream if nothing
+    /// else is using it.
+    fn release(&self, device_key: &str) {
+        let mut captures = self.captures.lock().unwrap();
+        let idle = match captures.get_mut(device_key) {
+            Some(capture) => {
+                capture.consumers = capture.consumers.saturating_sub(1);
+                capture.consumers == 0
+            }
+            None => false,
+        };
+        if idle {
+            captures.remove(device_key);
+            eprintln!("Closed input device {}", device_key);
+        }
+    }
+
+    fn find_input_device(&self, device_key: &str) -> Result<Device, String> {
+        let host = self.host.lock().unwrap();
+        if device_key == DEFAULT_INPUT_KEY {
+            return host
+                .default_input_device()
+                .ok_or_else(|| "No default input device".to_string());
+        }
+        identified_input_devices(&host)?
+            .into_iter()
+            .find(|device| device.id == device_key)
+            .map(|device| device.device)
+            .ok_or_else(|| format!("Input device not found: {}", device_key))
+    }
+}
+
+/// Emit `mic://levels` with the level of every open capture for the app's mic
+/// meters.
+fn spawn_input_level_meter(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(LEVEL_METER_INTERVAL_MS));
+
+        let levels = app.state::<AudioInputState>().input_levels();
+        if levels.is_empty() {
+            continue;
+        }
+        if let Err(e) = app.emit("mic://levels", &levels) {
+            eprintln!("Failed to emit mic://levels event: {}", e);
+        }
+    });
+}
+
+fn emit_event<S: serde::Serialize + Clone>(app: &Option<AppHandle>, event: &str, payload: &S) {
+    if let Some(app) = app {
+        if let Err(e) = app.emit(event, payload) {
+            eprintln!("Failed to emit {} event: {}", event, e);
+        }
+    }
+}
+
+fn validate_monitor_gain(gain: f32) -> Result<(), String> {
+    if !(0.0..=MAX_MONITOR_GAIN).contains(&gain) {
+        return Err(format!("Monitor gain must be between 0 and {}", MAX_MONITOR_GAIN));
+    }
+    Ok(())
+}
+
+impl Default for AudioInputState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn samples_to_wav(samples: &[f32], sample_rate: u32, channels: u16) -> Result<Vec<u8>, String> {
+    let spec = WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut buffer = Vec::new();
+    let mut writer = WavWriter::new(Cursor::new(&mut buffer), spec)
+        .map_err(|e| format!("Failed to create WAV writer: {}", e))?;
+    for sample in samples {
+        writer
+            .write_sample((sample.clamp(-1.0, 1.0) * 32767.0) as i16)
+            .map_err(|e| format!("Failed to write sample: {}", e))?;
+    }
+    writer
+        .finalize()
+        .map_err(|e| format!("Failed to finalize WAV: {}", e))?;
+    Ok(buffer)
+}
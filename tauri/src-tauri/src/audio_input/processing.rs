@@ -0,0 +1,404 @@
+use crate::audio_output::convert::FormatConverter;
+use nnnoiseless::DenoiseState;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// The only sample rate RNNoise works at. Captures are opened at this rate
+/// when the device allows it, and resampled around the denoiser otherwise.
+pub(super) const DENOISE_SAMPLE_RATE: u32 = 48_000;
+
+/// RNNoise expects samples in the 16-bit range rather than -1.0..1.0.
+const DENOISE_SCALE: f32 = 32767.0;
+
+/// Fade used when the push-to-talk gate opens or closes, so it never clicks.
+const GATE_FADE_MS: f32 = 5.0;
+
+/// Length of the blocks automatic gain control measures and steers over.
+const AGC_FRAME_MS: u32 = 10;
+/// How quickly the measured speech level follows louder and quieter input.
+const AGC_ATTACK_MS: f32 = 50.0;
+const AGC_RELEASE_MS: f32 = 500.0;
+/// Frames quieter than this are treated as pauses and leave the gain alone, so
+/// background noise isn't pumped up between sentences.
+const AGC_MIN_LEVEL_DBFS: f32 = -50.0;
+/// How fast the gain may rise; it falls as fast as it needs to.
+const AGC_GAIN_RISE_DB_PER_SEC: f32 = 6.0;
+/// Highest peak the gain is allowed to push a frame to.
+const AGC_PEAK_CEILING: f32 = 0.95;
+
+/// Processing settings for the input pipeline, shared with every capture's
+/// callback.
+pub(super) struct InputProcessing {
+    noise_suppression: AtomicBool,
+    /// 0.0-1.0, as f32 bits
+    suppression_strength: AtomicU32,
+}
+
+impl InputProcessing {
+    pub(super) fn new() -> Self {
+        Self {
+            noise_suppression: AtomicBool::new(false),
+            suppression_strength: AtomicU32::new(1.0f32.to_bits()),
+        }
+    }
+
+    pub(super) fn set_noise_suppression(&self, enabled: bool, strength: f32) {
+        self.suppression_strength.store(strength.to_bits(), Ordering::Relaxed);
+        self.noise_suppression.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// Runs the enabled processing stages on one capture's buffers before they
+/// reach its sinks.
+pub(super) struct InputProcessor {
+    settings: Arc<InputProcessing>,
+    /// `None` if the capture's rate can't be resampled for RNNoise
+    denoiser: Option<NoiseSuppressor>,
+}
+
+impl InputProcessor {
+    pub(super) fn new(settings: Arc<InputProcessing>, sample_rate: u32, channels: u16) -> Self {
+        let denoiser = match NoiseSuppressor::new(sample_rate, channels) {
+            Ok(denoiser) => Some(denoiser),
+            Err(e) => {
+                eprintln!("Noise suppression unavailable at {}Hz, it will be skipped: {}", sample_rate, e);
+                None
+            }
+        };
+        Self { settings, denoiser }
+    }
+
+    /// Process a captured buffer. Stages that work on fixed-size blocks hold
+    /// audio back, so the result can be shorter or longer than `data`.
+    pub(super) fn process<'a>(&'a mut self, data: &'a [f32]) -> &'a [f32] {
+        let denoiser = match &mut self.denoiser {
+            Some(denoiser) => denoiser,
+            None => return data,
+        };
+        if !self.settings.noise_suppression.load(Ordering::Relaxed) {
+            denoiser.reset();
+            return data;
+        }
+        let strength = f32::from_bits(self.settings.suppression_strength.load(Ordering::Relaxed));
+        denoiser.process(data, strength)
+    }
+}
+
+/// RNNoise run separately on each channel. `strength` blends the denoised
+/// signal with the original, which is delayed to line up with RNNoise's
+/// one-frame lag. Captures at other rates are resampled to 48 kHz and back,
+/// which holds the audio back by a few tens of milliseconds more.
+struct NoiseSuppressor {
+    states: Vec<Box<DenoiseState<'static>>>,
+    sample_rate: u32,
+    channels: usize,
+    /// To and from RNNoise's rate, if the capture runs at another
+    resamplers: Option<(FormatConverter, FormatConverter)>,
+    /// Interleaved input not yet making up a whole frame
+    pending: Vec<f32>,
+    /// The previous frame's input, interleaved
+    dry: Vec<f32>,
+    frame_in: Vec<f32>,
+    frame_out: Vec<f32>,
+    output: Vec<f32>,
+    /// Whether anything has been processed since the last reset
+    active: bool,
+}
+
+impl NoiseSuppressor {
+    fn new(sample_rate: u32, channels: u16) -> Result<Self, String> {
+        let channels = channels.max(1) as usize;
+        Ok(Self {
+            states: (0..channels).map(|_| DenoiseState::new()).collect(),
+            sample_rate,
+            channels,
+            resamplers: denoise_resamplers(sample_rate, channels as u16)?,
+            pending: Vec::new(),
+            dry: vec![0.0; DenoiseState::FRAME_SIZE * channels],
+            frame_in: vec![0.0; DenoiseState::FRAME_SIZE],
+            frame_out: vec![0.0; DenoiseState::FRAME_SIZE],
+            output: Vec::new(),
+            active: false,
+        })
+    }
+
+    /// Drop any buffered audio so turning suppression back on starts clean.
+    fn reset(&mut self) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+        self.states = (0..self.channels).map(|_| DenoiseState::new()).collect();
+        self.pending.clear();
+        self.dry.fill(0.0);
+        self.output.clear();
+        if self.resamplers.is_some() {
+            match denoise_resamplers(self.sample_rate, self.channels as u16) {
+                Ok(resamplers) => self.resamplers = resamplers,
+                Err(e) => eprintln!("Failed to reset noise suppression resamplers: {}", e),
+            }
+        }
+    }
+
+    fn process(&mut self, data: &[f32], strength: f32) -> &[f32] {
+        self.active = true;
+        match &mut self.resamplers {
+            Some((to_denoise, _)) => self.pending.extend(to_denoise.process(data)),
+            None => self.pending.extend_from_slice(data),
+        }
+        self.output.clear();
+
+        let block = DenoiseState::FRAME_SIZE * self.channels;
+        let mut start = 0;
+        while self.pending.len() - start >= block {
+            let frame = &self.pending[start..start + block];
+            let out_start = self.output.len();
+            self.output.resize(out_start + block, 0.0);
+            for (channel, state) in self.states.iter_mut().enumerate() {
+                let inputs = frame.iter().skip(channel).step_by(self.channels);
+                for (sample, input) in self.frame_in.iter_mut().zip(inputs) {
+                    *sample = input * DENOISE_SCALE;
+                }
+                state.process_frame(&mut self.frame_out, &self.frame_in);
+                for (i, denoised) in self.frame_out.iter().enumerate() {
+                    let idx = i * self.channels + channel;
+                    self.output[out_start + idx] =
+                        denoised / DENOISE_SCALE * strength + self.dry[idx] * (1.0 - strength);
+                }
+            }
+            self.dry.copy_from_slice(frame);
+            start += block;
+        }
+        self.pending.drain(..start);
+        if let Some((_, from_denoise)) = &mut self.resamplers {
+            self.output = from_denoise.process(&self.output);
+        }
+        &self.output
+    }
+}
+
+/// Converters taking a capture to RNNoise's rate and back, or `None` if it is
+/// already at that rate.
+fn denoise_resamplers(sample_rate: u32, channels: u16) -> Result<Option<(FormatConverter, FormatConverter)>, String> {
+    if sample_rate == DENOISE_SAMPLE_RATE {
+        return Ok(None);
+    }
+    Ok(Some((
+        FormatConverter::new(sample_rate, channels, DENOISE_SAMPLE_RATE, channels)?,
+        FormatConverter::new(DENOISE_SAMPLE_RATE, channels, sample_rate, channels)?,
+    )))
+}
+
+/// Push-to-talk state for the mic route, set from commands (and hotkeys).
+pub(super) struct PushToTalk {
+    enabled: AtomicBool,
+    held: AtomicBool,
+    release_tail_ms: AtomicU32,
+}
+
+impl PushToTalk {
+    pub(super) fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            held: AtomicBool::new(false),
+            release_tail_ms: AtomicU32::new(0),
+        }
+    }
+
+    pub(super) fn configure(&self, enabled: bool, release_tail_ms: u32) {
+        self.release_tail_ms.store(release_tail_ms, Ordering::Relaxed);
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(super) fn set_held(&self, held: bool) {
+        self.held.store(held, Ordering::Relaxed);
+    }
+}
+
+/// Mutes audio while push-to-talk is enabled and the key isn't held. After
+/// release the gate stays open for the tail, so the end of a word isn't cut.
+pub(super) struct PushToTalkGate {
+    state: Arc<PushToTalk>,
+    sample_rate: u32,
+    channels: usize,
+    gain: f32,
+    step: f32,
+    /// Frames the gate stays open for after release
+    tail_remaining: usize,
+    output: Vec<f32>,
+}
+
+impl PushToTalkGate {
+    pub(super) fn new(state: Arc<PushToTalk>, sample_rate: u32, channels: u16) -> Self {
+        let open = !state.enabled.load(Ordering::Relaxed);
+        Self {
+            state,
+            sample_rate,
+            channels: channels.max(1) as usize,
+            gain: if open { 1.0 } else { 0.0 },
+            step: 1.0 / (GATE_FADE_MS * sample_rate as f32 / 1000.0).max(1.0),
+            tail_remaining: 0,
+            output: Vec::new(),
+        }
+    }
+
+    pub(super) fn process<'a>(&'a mut self, data: &'a [f32]) -> &'a [f32] {
+        let enabled = self.state.enabled.load(Ordering::Relaxed);
+        let held = self.state.held.load(Ordering::Relaxed);
+        if !enabled && self.gain >= 1.0 {
+            return data;
+        }
+        if held {
+            let tail_ms = self.state.release_tail_ms.load(Ordering::Relaxed);
+            self.tail_remaining = tail_ms as usize * self.sample_rate as usize / 1000;
+        }
+
+        self.output.clear();
+        self.output.extend_from_slice(data);
+        for frame in self.output.chunks_mut(self.channels) {
+            let open = !enabled || held || self.tail_remaining > 0;
+            self.tail_remaining = self.tail_remaining.saturating_sub(1);
+            self.gain = if open {
+                (self.gain + self.step).min(1.0)
+            } else {
+                (self.gain - self.step).max(0.0)
+            };
+            for sample in frame.iter_mut() {
+                *sample *= self.gain;
+            }
+        }
+        &self.output
+    }
+}
+
+/// Automatic gain control settings for the mic route.
+pub(super) struct AutoGain {
+    enabled: AtomicBool,
+    /// f32 bits
+    target_dbfs: AtomicU32,
+    max_gain_db: AtomicU32,
+}
+
+impl AutoGain {
+    pub(super) fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            target_dbfs: AtomicU32::new((-18.0f32).to_bits()),
+            max_gain_db: AtomicU32::new(12.0f32.to_bits()),
+        }
+    }
+
+    pub(super) fn configure(&self, enabled: bool, target_dbfs: f32, max_gain_db: f32) {
+        self.target_dbfs.store(target_dbfs.to_bits(), Ordering::Relaxed);
+        self.max_gain_db.store(max_gain_db.to_bits(), Ordering::Relaxed);
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// Evens out quiet and loud speech by steering the gain towards the target
+/// RMS level. Gain is only ever added up to the maximum or taken away, pauses
+/// don't move it, and each frame's peak is kept under the ceiling.
+pub(super) struct AutoGainControl {
+    settings: Arc<AutoGain>,
+    channels: usize,
+    /// Interleaved samples per frame
+    frame_samples: usize,
+    attack: f32,
+    release: f32,
+    gain_rise_db: f32,
+    /// Smoothed speech level, `None` until the first frame of speech
+    level_db: Option<f32>,
+    /// Gain at the end of the last frame
+    gain: f32,
+    /// Interleaved input not yet making up a whole frame
+    pending: Vec<f32>,
+    output: Vec<f32>,
+}
+
+impl AutoGainControl {
+    pub(super) fn new(settings: Arc<AutoGain>, sample_rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        let frames_per_sec = 1000.0 / AGC_FRAME_MS as f32;
+        let smoothing = |ms: f32| 1.0 - (-(AGC_FRAME_MS as f32) / ms).exp();
+        Self {
+            settings,
+            channels,
+            frame_samples: (sample_rate * AGC_FRAME_MS / 1000).max(1) as usize * channels,
+            attack: smoothing(AGC_ATTACK_MS),
+            release: smoothing(AGC_RELEASE_MS),
+            gain_rise_db: AGC_GAIN_RISE_DB_PER_SEC / frames_per_sec,
+            level_db: None,
+            gain: 1.0,
+            pending: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Works on whole frames, so the result can be shorter or longer than
+    /// `data`.
+    pub(super) fn process<'a>(&'a mut self, data: &'a [f32]) -> &'a [f32] {
+        if !self.settings.enabled.load(Ordering::Relaxed) {
+            self.level_db = None;
+            self.gain = 1.0;
+            if self.pending.is_empty() {
+                return data;
+            }
+            // Let the held-back samples through so nothing is lost
+            self.output.clear();
+            self.output.append(&mut self.pending);
+            self.output.extend_from_slice(data);
+            return &self.output;
+        }
+        let target_db = f32::from_bits(self.settings.target_dbfs.load(Ordering::Relaxed));
+        let max_gain_db = f32::from_bits(self.settings.max_gain_db.load(Ordering::Relaxed));
+
+        self.pending.extend_from_slice(data);
+        self.output.clear();
+        let pending = std::mem::take(&mut self.pending);
+        let mut frames = pending.chunks_exact(self.frame_samples);
+        for frame in &mut frames {
+            let next_gain = self.frame_gain(frame, target_db, max_gain_db);
+            // Ramp across the frame so gain changes don't click
+            let frame_count = frame.len() / self.channels;
+            let step = (next_gain - self.gain) / frame_count as f32;
+            for (i, input) in frame.chunks(self.channels).enumerate() {
+                let gain = self.gain + step * (i + 1) as f32;
+                self.output.extend(input.iter().map(|sample| sample * gain));
+            }
+            self.gain = next_gain;
+        }
+        self.pending = frames.remainder().to_vec();
+        &self.output
+    }
+
+    /// Gain to reach by the end of `frame`.
+    fn frame_gain(&mut self, frame: &[f32], target_db: f32, max_gain_db: f32) -> f32 {
+        let mean_square = frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+        let frame_db = 10.0 * mean_square.max(1e-12).log10();
+        let peak = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+        let mut gain_db = 20.0 * self.gain.log10();
+        if frame_db > AGC_MIN_LEVEL_DBFS {
+            let level_db = match self.level_db {
+                Some(level) => {
+                    let coefficient = if frame_db > level { self.attack } else { self.release };
+                    level + (frame_db - level) * coefficient
+                }
+                None => frame_db,
+            };
+            self.level_db = Some(level_db);
+            let wanted_db = (target_db - level_db).min(max_gain_db);
+            gain_db = if wanted_db > gain_db {
+                (gain_db + self.gain_rise_db).min(wanted_db)
+            } else {
+                wanted_db
+            };
+        }
+        let gain = 10f32.powf(gain_db.min(max_gain_db) / 20.0);
+        if peak * gain > AGC_PEAK_CEILING {
+            AGC_PEAK_CEILING / peak
+        } else {
+            gain
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use crate::audio_output::convert::FormatConverter;
+use crate::audio_output::record::{RecordingEncoder, RecordingFormat};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Rate Opus recordings are made at unless another is asked for.
+const OPUS_SAMPLE_RATE: u32 = 48_000;
+
+/// How often the writer checks whether the recording has been stopped.
+const WRITER_POLL_MS: u64 = 50;
+
+/// How often the elapsed time is reported while recording.
+const ELAPSED_INTERVAL_MS: u64 = 250;
+
+/// A microphone being recorded to a WAV, FLAC or Opus file (chosen by the
+/// path's extension), converted and encoded on its own thread.
+pub(super) struct MicRecording {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    dropped: Arc<AtomicUsize>,
+    writer: JoinHandle<Result<u64, String>>,
+}
+
+impl MicRecording {
+    /// Create the file and write everything arriving on `rx` to it, at
+    /// `sample_rate` if given and otherwise the capture's own rate (48 kHz for
+    /// Opus). `on_elapsed` is called with the recorded length in seconds every
+    /// `ELAPSED_INTERVAL_MS`.
+    pub(super) fn start<F>(
+        path: &Path,
+        rx: mpsc::Receiver<Vec<f32>>,
+        dropped: Arc<AtomicUsize>,
+        source_rate: u32,
+        source_channels: u16,
+        sample_rate: Option<u32>,
+        mut on_elapsed: F,
+    ) -> Result<Self, String>
+    where
+        F: FnMut(f64) + Send + 'static,
+    {
+        let (sample_rate, channels) = match RecordingFormat::from_path(path)? {
+            RecordingFormat::Opus => (sample_rate.unwrap_or(OPUS_SAMPLE_RATE), source_channels.min(2)),
+            _ => (sample_rate.unwrap_or(source_rate), source_channels),
+        };
+        if !(8_000..=192_000).contains(&sample_rate) {
+            return Err(format!("Recording sample rate must be 8000-192000 Hz, got {}", sample_rate));
+        }
+        let mut converter = FormatConverter::new(source_rate, source_channels, sample_rate, channels)?;
+        let mut encoder = RecordingEncoder::create(path, sample_rate, channels)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer_stop = stop.clone();
+        let writer = std::thread::spawn(move || {
+            let mut samples_written = 0u64;
+            let mut last_report = Instant::now();
+            while !writer_stop.load(Ordering::Relaxed) {
+                match rx.recv_timeout(Duration::from_millis(WRITER_POLL_MS)) {
+                    Ok(chunk) => {
+                        let converted = converter.process(&chunk);
+                        encoder.write(&converted)?;
+                        samples_written += converted.len() as u64;
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+                if last_report.elapsed() >= Duration::from_millis(ELAPSED_INTERVAL_MS) {
+                    let frames = samples_written / channels as u64;
+                    on_elapsed(frames as f64 / sample_rate as f64);
+                    last_report = Instant::now();
+                }
+            }
+            // Detach from the stream before the last of the resampler is written
+            drop(rx);
+
+            let tail = converter.flush();
+            encoder.write(&tail)?;
+            samples_written += tail.len() as u64;
+            encoder.finalize()?;
+            Ok(samples_written / channels as u64)
+        });
+
+        eprintln!(
+            "Recording microphone to {} ({}Hz, {} channels)",
+            path.display(),
+            sample_rate,
+            channels
+        );
+        Ok(Self {
+            path: path.to_path_buf(),
+            stop,
+            dropped,
+            writer,
+        })
+    }
+
+    /// Stop capturing and wait for the file to be finished.
+    pub(super) fn finish(self) -> Result<(), String> {
+        self.stop.store(true, Ordering::Relaxed);
+        let frames = self
+            .writer
+            .join()
+            .map_err(|_| "Recording writer thread panicked".to_string())??;
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            eprintln!(
+                "Recording to {} fell behind and skipped {} buffer(s)",
+                self.path.display(),
+                dropped
+            );
+        }
+        eprintln!("Finished recording {} frames to {}", frames, self.path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+/// Length of each analysis frame.
+const VAD_FRAME_MS: u32 = 20;
+/// How far over the noise floor a frame has to be to count as speech.
+const VAD_MARGIN_DB: f32 = 10.0;
+/// Frames quieter than this are never speech, however low the noise floor is.
+const VAD_MIN_LEVEL_DBFS: f32 = -55.0;
+/// Loud frames in a row needed before speech starts, so clicks and bumps are ignored.
+const VAD_ONSET_FRAMES: u32 = 3;
+/// How long speech continues after the last loud frame, to bridge pauses between words.
+const VAD_HANGOVER_MS: u32 = 300;
+/// How fast the noise floor creeps up to follow rising background noise. It
+/// drops straight down to quieter frames.
+const NOISE_FLOOR_RISE_DB_PER_SEC: f32 = 1.0;
+/// Noise floor assumed before anything has been measured.
+const INITIAL_NOISE_FLOOR_DBFS: f32 = -60.0;
+
+/// Energy-based voice activity detection with an adaptive noise floor. Cheap
+/// enough to run on every capture, at the cost of also reacting to loud
+/// non-speech sounds.
+pub(super) struct VoiceActivityDetector {
+    /// Interleaved samples per analysis frame
+    frame_samples: usize,
+    sum_squares: f64,
+    count: usize,
+    noise_floor_db: f32,
+    floor_rise_db: f32,
+    hangover_frames: u32,
+    loud_frames: u32,
+    quiet_frames: u32,
+    speaking: bool,
+}
+
+impl VoiceActivityDetector {
+    pub(super) fn new(sample_rate: u32, channels: u16) -> Self {
+        let frames_per_sec = 1000.0 / VAD_FRAME_MS as f32;
+        Self {
+            frame_samples: (sample_rate * VAD_FRAME_MS / 1000).max(1) as usize * channels.max(1) as usize,
+            sum_squares: 0.0,
+            count: 0,
+            noise_floor_db: INITIAL_NOISE_FLOOR_DBFS,
+            floor_rise_db: NOISE_FLOOR_RISE_DB_PER_SEC / frames_per_sec,
+            hangover_frames: VAD_HANGOVER_MS / VAD_FRAME_MS,
+            loud_frames: 0,
+            quiet_frames: 0,
+            speaking: false,
+        }
+    }
+
+    /// Analyse the next interleaved buffer. Returns the new state if speech
+    /// started or stopped during it.
+    pub(super) fn process(&mut self, samples: &[f32]) -> Option<bool> {
+        let was_speaking = self.speaking;
+        for sample in samples {
+            self.sum_squares += (*sample as f64) * (*sample as f64);
+            self.count += 1;
+            if self.count == self.frame_samples {
+                let mean_square = self.sum_squares / self.count as f64;
+                self.end_frame(10.0 * mean_square.max(1e-12).log10() as f32);
+                self.sum_squares = 0.0;
+                self.count = 0;
+            }
+        }
+        (self.speaking != was_speaking).then_some(self.speaking)
+    }
+
+    fn end_frame(&mut self, level_db: f32) {
+        if level_db < self.noise_floor_db {
+            self.noise_floor_db = level_db;
+        } else {
+            self.noise_floor_db += self.floor_rise_db;
+        }
+
+        let loud = level_db > VAD_MIN_LEVEL_DBFS && level_db > self.noise_floor_db + VAD_MARGIN_DB;
+        if loud {
+            self.loud_frames += 1;
+            self.quiet_frames = 0;
+            if self.loud_frames >= VAD_ONSET_FRAMES {
+                self.speaking = true;
+            }
+        } else {
+            self.loud_frames = 0;
+            self.quiet_frames += 1;
+            if self.quiet_frames > self.hangover_frames {
+                self.speaking = false;
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use std::sync::Arc;
+
+/// Length of each analysis frame.
+const VISEME_FRAME_MS: u32 = 20;
+/// Frames quieter than this close the mouth.
+const VISEME_SILENCE_DBFS: f32 = -45.0;
+/// Level at which the mouth is fully open.
+const VISEME_FULL_DBFS: f32 = -15.0;
+/// Smallest change in openness worth reporting.
+const VISEME_WEIGHT_STEP: f32 = 0.1;
+/// Frames a new mouth shape has to last before it is reported, so it doesn't
+/// flicker between shapes within a vowel.
+const VISEME_HOLD_FRAMES: u32 = 2;
+/// Width of the smoothing applied to the spectrum before looking for
+/// formants, so harmonics of the voice don't count as peaks.
+const FORMANT_SMOOTHING_HZ: f32 = 200.0;
+/// Where the first and second formants are looked for.
+const F1_RANGE_HZ: (f32, f32) = (250.0, 1000.0);
+const F2_RANGE_HZ: (f32, f32) = (800.0, 2800.0);
+/// Energy above this is mostly hiss from fricatives such as "s" and "f".
+const FRICATIVE_HZ: f32 = 4000.0;
+
+/// Mouth shape for an avatar, following the five-vowel set most VTuber
+/// rigs use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Viseme {
+    /// Closed
+    Sil,
+    Aa,
+    E,
+    Ih,
+    Oh,
+    Ou,
+}
+
+/// Typical first and second formants of each vowel shape, in Hz.
+const VOWEL_FORMANTS: [(Viseme, f32, f32); 5] = [
+    (Viseme::Aa, 750.0, 1300.0),
+    (Viseme::E, 550.0, 1900.0),
+    (Viseme::Ih, 350.0, 2200.0),
+    (Viseme::Oh, 550.0, 900.0),
+    (Viseme::Ou, 350.0, 800.0),
+];
+
+/// A change of mouth shape found by `VisemeAnalyzer`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct VisemeChange {
+    pub(crate) viseme: Viseme,
+    /// How far open the mouth is, 0-1
+    pub(crate) weight: f32,
+    /// Start of the frame it was found in, counted from the first sample
+    pub(crate) offset_ms: u64,
+}
+
+/// Estimates mouth shapes from speech for lip-sync. Each frame's level sets
+/// how open the mouth is, and its two lowest formants pick the nearest vowel
+/// shape. Crude next to a phoneme recognizer, but it works on any voice,
+/// live or synthesized, without knowing the words.
+pub(crate) struct VisemeAnalyzer {
+    sample_rate: u32,
+    channels: usize,
+    frame_len: usize,
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    /// Mono frame being collected
+    frame: Vec<f32>,
+    /// Previous mono sample, for pre-emphasis
+    previous: f32,
+    /// Partial interleaved frame of channels not yet mixed down
+    pending: Vec<f32>,
+    fft_input: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    frames: u64,
+    current: (Viseme, f32),
+    candidate: Option<(Viseme, u32)>,
+}
+
+impl VisemeAnalyzer {
+    pub(crate) fn new(sample_rate: u32, channels: u16) -> Self {
+        let frame_len = (sample_rate * VISEME_FRAME_MS / 1000).max(1) as usize;
+        let fft_len = frame_len.next_power_of_two();
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(fft_len);
+        let window = (0..frame_len)
+            .map(|i| {
+                let phase = std::f32::consts::PI * 2.0 * i as f32 / frame_len as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Self {
+            sample_rate,
+            channels: channels.max(1) as usize,
+            frame_len,
+            spectrum: fft.make_output_vec(),
+            fft_input: fft.make_input_vec(),
+            fft,
+            window,
+            frame: Vec::with_capacity(frame_len),
+            previous: 0.0,
+            pending: Vec::new(),
+            frames: 0,
+            current: (Viseme::Sil, 0.0),
+            candidate: None,
+        }
+    }
+
+    /// Analyse the next interleaved buffer, returning the mouth shapes that
+    /// changed during it.
+    pub(crate) fn process(&mut self, samples: &[f32]) -> Vec<VisemeChange> {
+        let mut changes = Vec::new();
+        self.pending.extend_from_slice(samples);
+        let whole = self.pending.len() / self.channels * self.channels;
+        for i in (0..whole).step_by(self.channels) {
+            let mono = self.pending[i..i + self.channels].iter().sum::<f32>() / self.channels as f32;
+            self.frame.push(mono);
+            if self.frame.len() == self.frame_len {
+                if let Some(change) = self.end_frame() {
+                    changes.push(change);
+                }
+                self.frame.clear();
+                self.frames += 1;
+            }
+        }
+        self.pending.drain(..whole);
+        changes
+    }
+
+    fn end_frame(&mut self) -> Option<VisemeChange> {
+        let mean_square = self.frame.iter().map(|s| s * s).sum::<f32>() / self.frame.len() as f32;
+        let level_db = 10.0 * mean_square.max(1e-12).log10();
+        let (viseme, weight) = if level_db < VISEME_SILENCE_DBFS {
+            (Viseme::Sil, 0.0)
+        } else {
+            let weight = (level_db - VISEME_SILENCE_DBFS) / (VISEME_FULL_DBFS - VISEME_SILENCE_DBFS);
+            (self.vowel(), weight.clamp(0.0, 1.0))
+        };
+
+        // Closing the mouth is never held back; other shapes have to last
+        let viseme = if viseme == self.current.0 || viseme == Viseme::Sil {
+            self.candidate = None;
+            viseme
+        } else {
+            let count = match self.candidate {
+                Some((candidate, count)) if candidate == viseme => count + 1,
+                _ => 1,
+            };
+            self.candidate = Some((viseme, count));
+            if count >= VISEME_HOLD_FRAMES {
+                self.candidate = None;
+                viseme
+            } else {
+                self.current.0
+            }
+        };
+        let weight = if viseme == Viseme::Sil { 0.0 } else { weight };
+
+        if viseme == self.current.0 && (weight - self.current.1).abs() < VISEME_WEIGHT_STEP {
+            return None;
+        }
+        self.current = (viseme, weight);
+        Some(VisemeChange {
+            viseme,
+            weight,
+            offset_ms: self.frames * VISEME_FRAME_MS as u64,
+        })
+    }
+
+    /// The vowel shape nearest the frame's formants.
+    fn vowel(&mut self) -> Viseme {
+        // Pre-emphasis lifts the second formant, which is much weaker than
+        // the first in voiced speech
+        self.fft_input.iter_mut().for_each(|s| *s = 0.0);
+        let mut previous = self.previous;
+        for ((input, sample), window) in self.fft_input.iter_mut().zip(&self.frame).zip(&self.window) {
+            *input = (sample - 0.97 * previous) * window;
+            previous = *sample;
+        }
+        self.previous = previous;
+        if self.fft.process(&mut self.fft_input, &mut self.spectrum).is_err() {
+            return self.current.0;
+        }
+
+        let bin_hz = self.sample_rate as f32 / self.fft_input.len() as f32;
+        let power: Vec<f32> = self.spectrum.iter().map(|bin| bin.norm_sqr()).collect();
+        let fricative_bin = ((FRICATIVE_HZ / bin_hz) as usize).min(power.len());
+        let voiced_bin = ((F1_RANGE_HZ.1 / bin_hz) as usize).min(fricative_bin);
+        let hiss: f32 = power[fricative_bin..].iter().sum();
+        let voiced: f32 = power[..voiced_bin].iter().sum();
+        if hiss > voiced {
+            // Teeth together for "s", "sh" and "f"
+            return Viseme::Ih;
+        }
+
+        let radius = ((FORMANT_SMOOTHING_HZ / bin_hz / 2.0) as usize).max(1);
+        let envelope: Vec<f32> = (0..power.len())
+            .map(|i| {
+                let from = i.saturating_sub(radius);
+                let to = (i + radius + 1).min(power.len());
+                power[from..to].iter().sum::<f32>() / (to - from) as f32
+            })
+            .collect();
+        let peak = |(low, high): (f32, f32)| -> f32 {
+            let from = ((low / bin_hz) as usize).min(envelope.len() - 1);
+            let to = ((high / bin_hz) as usize).clamp(from + 1, envelope.len());
+            let bin = (from..to)
+                .max_by(|&a, &b| envelope[a].total_cmp(&envelope[b]))
+                .unwrap_or(from);
+            bin as f32 * bin_hz
+        };
+        let f1 = peak(F1_RANGE_HZ).max(1.0);
+        let f2 = peak((F2_RANGE_HZ.0.max(f1 + FORMANT_SMOOTHING_HZ), F2_RANGE_HZ.1)).max(1.0);
+
+        // Nearest on a log scale, where formant differences are heard evenly
+        VOWEL_FORMANTS
+            .iter()
+            .map(|&(viseme, p1, p2)| {
+                let distance = (f1 / p1).ln().powi(2) + (f2 / p2).ln().powi(2);
+                (viseme, distance)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(viseme, _)| viseme)
+            .unwrap_or(Viseme::Aa)
+    }
+}
@@ -0,0 +1,157 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Memory the decoded-clip cache may use, in bytes of f32 PCM.
+const CLIP_CACHE_BYTES: usize = 256 * 1024 * 1024;
+
+/// A fully decoded clip at its source rate and channel count.
+#[derive(Clone)]
+pub(super) struct DecodedClip {
+    pub(super) samples: Arc<[f32]>,
+    pub(super) sample_rate: u32,
+    pub(super) channels: u16,
+}
+
+impl DecodedClip {
+    fn bytes(&self) -> usize {
+        self.samples.len() * std::mem::size_of::<f32>()
+    }
+}
+
+/// A clip's SHA-256, and the device format it was converted to, if any.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    hash: String,
+    device_format: Option<(u32, u16)>,
+}
+
+struct CacheEntry {
+    clip: DecodedClip,
+    last_used: u64,
+}
+
+/// Size-bounded LRU cache of decoded clips, keyed by the SHA-256 of their
+/// content, so repeat triggers of the same clip skip decoding. Clips can
+/// also be kept converted to a device's format, to skip resampling too.
+#[derive(Default)]
+pub(super) struct ClipCache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    bytes: usize,
+    /// Incremented on every access to order entries by recency
+    clock: u64,
+}
+
+impl ClipCache {
+    /// The clip at its source rate and channel count.
+    pub(super) fn get(&mut self, hash: &str) -> Option<DecodedClip> {
+        self.lookup(CacheKey {
+            hash: hash.to_string(),
+            device_format: None,
+        })
+    }
+
+    /// The clip converted to `sample_rate` and `channels`.
+    pub(super) fn get_converted(&mut self, hash: &str, sample_rate: u32, channels: u16) -> Option<Arc<[f32]>> {
+        let key = CacheKey {
+            hash: hash.to_string(),
+            device_format: Some((sample_rate, channels)),
+        };
+        self.lookup(key).map(|clip| clip.samples)
+    }
+
+    /// Largest clip worth starting to collect for the cache.
+    pub(super) fn max_samples() -> usize {
+        CLIP_CACHE_BYTES / std::mem::size_of::<f32>()
+    }
+
+    pub(super) fn insert(&mut self, hash: String, clip: DecodedClip) {
+        let key = CacheKey {
+            hash,
+            device_format: None,
+        };
+        self.store(key, clip);
+    }
+
+    pub(super) fn insert_converted(&mut self, hash: String, samples: Arc<[f32]>, sample_rate: u32, channels: u16) {
+        let key = CacheKey {
+            hash,
+            device_format: Some((sample_rate, channels)),
+        };
+        let clip = DecodedClip {
+            samples,
+            sample_rate,
+            channels,
+        };
+        self.store(key, clip);
+    }
+
+    fn lookup(&mut self, key: CacheKey) -> Option<DecodedClip> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(&key)?;
+        entry.last_used = self.clock;
+        Some(entry.clip.clone())
+    }
+
+    /// Add a clip, evicting the least recently used ones to make room.
+    fn store(&mut self, key: CacheKey, clip: DecodedClip) {
+        let size = clip.bytes();
+        if size > CLIP_CACHE_BYTES {
+            return;
+        }
+        if let Some(old) = self.entries.remove(&key) {
+            self.bytes -= old.clip.bytes();
+        }
+
+        while self.bytes + size > CLIP_CACHE_BYTES {
+            let oldest = match self.entries.iter().min_by_key(|(_, entry)| entry.last_used) {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.clip.bytes();
+            }
+        }
+
+        self.clock += 1;
+        self.bytes += size;
+        self.entries.insert(
+            key,
+            CacheEntry {
+                clip,
+                last_used: self.clock,
+            },
+        );
+    }
+}
+
+/// SHA-256 of a clip held in memory, in hex.
+pub(super) fn hash_bytes(data: &[u8]) -> String {
+    hex(Sha256::digest(data).as_slice())
+}
+
+/// SHA-256 of a file's contents in hex, and its size.
+pub(crate) fn hash_file(path: &Path) -> Result<(String, u64), String> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((hex(hasher.finalize().as_slice()), size))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
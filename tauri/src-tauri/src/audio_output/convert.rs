@@ -0,0 +1,347 @@
+use rubato::{
+    calculate_cutoff, Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType,
+    WindowFunction,
+};
+
+/// Resample and remap channels so `samples` match the device's stream config.
+pub(super) fn convert_for_device(
+    samples: Vec<f32>,
+    sample_rate: u32,
+    channels: u16,
+    device_sample_rate: u32,
+    device_channels: u16,
+) -> Result<Vec<f32>, String> {
+    eprintln!(
+        "convert_for_device: {}Hz/{}ch -> {}Hz/{}ch",
+        sample_rate, channels, device_sample_rate, device_channels
+    );
+    if sample_rate == device_sample_rate && channels == device_channels {
+        return Ok(samples);
+    }
+
+    let mut converter = FormatConverter::new(sample_rate, channels, device_sample_rate, device_channels)?;
+    let mut converted = converter.process(&samples);
+    converted.extend(converter.flush());
+    eprintln!("convert_for_device: Converted {} samples to {} samples", samples.len(), converted.len());
+    Ok(converted)
+}
+
+/// Converts interleaved audio to a device's sample rate and channel count one
+/// chunk at a time. Call `flush` after the last chunk to drain the resampler.
+pub(crate) struct FormatConverter {
+    src_rate: u32,
+    dst_rate: u32,
+    dst_channels: u16,
+    resampler: Option<SincResampler>,
+    channel_map: ChannelMap,
+}
+
+impl FormatConverter {
+    pub(crate) fn new(
+        src_rate: u32,
+        src_channels: u16,
+        dst_rate: u32,
+        dst_channels: u16,
+    ) -> Result<Self, String> {
+        let src_channels = src_channels.max(1);
+        let resampler = if src_rate != dst_rate {
+            Some(SincResampler::new(src_rate, dst_rate, src_channels as usize)?)
+        } else {
+            None
+        };
+
+        let dst_channels = dst_channels.max(1);
+        Ok(Self {
+            src_rate,
+            dst_rate,
+            dst_channels,
+            resampler,
+            channel_map: ChannelMap::new(src_channels as usize, dst_channels as usize),
+        })
+    }
+
+    /// Number of device-format samples `src_frames` source frames convert to.
+    pub(super) fn output_len(&self, src_frames: u64) -> usize {
+        let frames = src_frames * self.dst_rate as u64 / self.src_rate.max(1) as u64;
+        frames as usize * self.dst_channels as usize
+    }
+
+    pub(crate) fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        match &mut self.resampler {
+            Some(resampler) => {
+                let resampled = resampler.process(samples);
+                self.channel_map.apply(&resampled)
+            }
+            None => self.channel_map.apply(samples),
+        }
+    }
+
+    /// Output still held back by the resampler once the input has ended.
+    pub(crate) fn flush(&mut self) -> Vec<f32> {
+        match &mut self.resampler {
+            Some(resampler) => {
+                let resampled = resampler.flush();
+                self.channel_map.apply(&resampled)
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Frames fed to the sinc resampler per call.
+const RESAMPLER_CHUNK_FRAMES: usize = 1024;
+
+/// Windowed-sinc resampling of an interleaved stream. rubato works on fixed-size
+/// planar chunks, so input is buffered until a full chunk is available.
+struct SincResampler {
+    resampler: SincFixedIn<f32>,
+    channels: usize,
+    ratio: f64,
+    /// Planar input waiting for a full chunk
+    pending: Vec<Vec<f32>>,
+    input_frames: u64,
+    output_frames: u64,
+}
+
+impl SincResampler {
+    fn new(src_rate: u32, dst_rate: u32, channels: usize) -> Result<Self, String> {
+        let sinc_len = 128;
+        let window = WindowFunction::BlackmanHarris2;
+        let parameters = SincInterpolationParameters {
+            sinc_len,
+            f_cutoff: calculate_cutoff(sinc_len, window),
+            interpolation: SincInterpolationType::Linear,
+            oversampling_factor: 128,
+            window,
+        };
+        let ratio = dst_rate as f64 / src_rate as f64;
+        let resampler = SincFixedIn::new(ratio, 1.0, parameters, RESAMPLER_CHUNK_FRAMES, channels)
+            .map_err(|e| format!("Failed to create resampler: {}", e))?;
+
+        Ok(Self {
+            resampler,
+            channels,
+            ratio,
+            pending: vec![Vec::new(); channels],
+            input_frames: 0,
+            output_frames: 0,
+        })
+    }
+
+    fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in self.pending.iter_mut().zip(frame) {
+                channel.push(*sample);
+            }
+        }
+        self.input_frames += (samples.len() / self.channels) as u64;
+
+        let mut output = Vec::new();
+        while self.pending[0].len() >= self.resampler.input_frames_next() {
+            let needed = self.resampler.input_frames_next();
+            let chunk: Vec<Vec<f32>> = self
+                .pending
+                .iter_mut()
+                .map(|channel| channel.drain(..needed).collect())
+                .collect();
+            match self.resampler.process(&chunk, None) {
+                Ok(planar) => self.append_interleaved(&planar, &mut output, None),
+                Err(e) => eprintln!("Resampler error: {}", e),
+            }
+        }
+        output
+    }
+
+    fn flush(&mut self) -> Vec<f32> {
+        // Only emit as many frames as the input length calls for, trimming the
+        // zero padding rubato adds to partial chunks
+        let expected = (self.input_frames as f64 * self.ratio).round() as u64;
+        let pending = std::mem::take(&mut self.pending);
+        let mut output = Vec::new();
+        let mut input = Some(pending);
+
+        while self.output_frames < expected {
+            let remaining = (expected - self.output_frames) as usize;
+            let result = match input.take() {
+                Some(pending) => self.resampler.process_partial(Some(&pending), None),
+                None => self.resampler.process_partial::<Vec<f32>>(None, None),
+            };
+            match result {
+                Ok(planar) if !planar[0].is_empty() => {
+                    self.append_interleaved(&planar, &mut output, Some(remaining))
+                }
+                Ok(_) => break,
+                Err(e) => {
+                    eprintln!("Resampler error: {}", e);
+                    break;
+                }
+            }
+        }
+        output
+    }
+
+    /// Interleave rubato's planar output onto `output`, stopping after `limit`
+    /// frames if given.
+    fn append_interleaved(&mut self, planar: &[Vec<f32>], output: &mut Vec<f32>, limit: Option<usize>) {
+        let frames = match limit {
+            Some(limit) => planar[0].len().min(limit),
+            None => planar[0].len(),
+        };
+        for i in 0..frames {
+            for channel in planar {
+                output.push(channel[i]);
+            }
+        }
+        self.output_frames += frames as u64;
+    }
+}
+
+/// Downmix coefficient for folding a speaker into a neighbouring pair (-3 dB).
+const FOLD_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Speaker {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    Lfe,
+    BackLeft,
+    BackRight,
+    BackCenter,
+    SideLeft,
+    SideRight,
+    /// Beyond the standard layouts; only ever mapped to the same index
+    Discrete(usize),
+}
+
+/// Speaker order for a channel count, following the default WAVE/WASAPI layouts.
+fn speaker_layout(channels: usize) -> Vec<Speaker> {
+    use Speaker::*;
+    let standard: &[Speaker] = match channels {
+        1 => &[FrontCenter],
+        2 => &[FrontLeft, FrontRight],
+        3 => &[FrontLeft, FrontRight, FrontCenter],
+        4 => &[FrontLeft, FrontRight, BackLeft, BackRight],
+        5 => &[FrontLeft, FrontRight, FrontCenter, BackLeft, BackRight],
+        6 => &[FrontLeft, FrontRight, FrontCenter, Lfe, BackLeft, BackRight],
+        7 => &[FrontLeft, FrontRight, FrontCenter, Lfe, BackCenter, SideLeft, SideRight],
+        _ => &[FrontLeft, FrontRight, FrontCenter, Lfe, BackLeft, BackRight, SideLeft, SideRight],
+    };
+    (0..channels)
+        .map(|i| standard.get(i).copied().unwrap_or(Discrete(i)))
+        .collect()
+}
+
+/// A dst x src mixing matrix that remaps interleaved frames between channel
+/// layouts. Speakers missing from the destination are folded into their nearest
+/// neighbours, and rows are normalised so a full-scale downmix can't clip.
+struct ChannelMap {
+    src_channels: usize,
+    dst_channels: usize,
+    /// Row-major, one row per destination channel
+    matrix: Vec<f32>,
+    identity: bool,
+}
+
+impl ChannelMap {
+    fn new(src_channels: usize, dst_channels: usize) -> Self {
+        let matrix = if dst_channels == 1 && src_channels > 1 {
+            // Mono is the average of the stereo downmix
+            let stereo = Self::new(src_channels, 2).matrix;
+            (0..src_channels)
+                .map(|j| 0.5 * (stereo[j] + stereo[src_channels + j]))
+                .collect()
+        } else {
+            Self::speaker_matrix(src_channels, dst_channels)
+        };
+
+        Self {
+            src_channels,
+            dst_channels,
+            matrix,
+            identity: src_channels == dst_channels,
+        }
+    }
+
+    fn speaker_matrix(src_channels: usize, dst_channels: usize) -> Vec<f32> {
+        use Speaker::*;
+        let src = speaker_layout(src_channels);
+        let dst = speaker_layout(dst_channels);
+        let mut matrix = vec![0.0; dst_channels * src_channels];
+        let position = |speaker: Speaker| dst.iter().position(|s| *s == speaker);
+        let first_of = |speakers: &[Speaker]| speakers.iter().find_map(|s| position(*s));
+
+        for (j, speaker) in src.iter().enumerate() {
+            let mut send = |target: Option<usize>, gain: f32| {
+                if let Some(i) = target {
+                    matrix[i * src_channels + j] += gain;
+                }
+            };
+
+            // Mono plays on the front pair as a phantom center
+            if src_channels == 1 && dst_channels > 1 {
+                send(position(FrontLeft), 1.0);
+                send(position(FrontRight), 1.0);
+                continue;
+            }
+            if let Some(i) = position(*speaker) {
+                send(Some(i), 1.0);
+                continue;
+            }
+            match speaker {
+                FrontCenter => {
+                    send(position(FrontLeft), FOLD_GAIN);
+                    send(position(FrontRight), FOLD_GAIN);
+                }
+                BackLeft | SideLeft => {
+                    match first_of(&[SideLeft, BackLeft]) {
+                        Some(i) => send(Some(i), 1.0),
+                        None => send(position(FrontLeft), FOLD_GAIN),
+                    }
+                }
+                BackRight | SideRight => {
+                    match first_of(&[SideRight, BackRight]) {
+                        Some(i) => send(Some(i), 1.0),
+                        None => send(position(FrontRight), FOLD_GAIN),
+                    }
+                }
+                BackCenter => match (first_of(&[BackLeft, SideLeft]), first_of(&[BackRight, SideRight])) {
+                    (Some(left), Some(right)) => {
+                        send(Some(left), FOLD_GAIN);
+                        send(Some(right), FOLD_GAIN);
+                    }
+                    _ => {
+                        send(position(FrontLeft), 0.5);
+                        send(position(FrontRight), 0.5);
+                    }
+                },
+                // LFE is left out of downmixes as usual; the front pair always
+                // exists once there are two or more output channels
+                Lfe | Discrete(_) | FrontLeft | FrontRight => {}
+            }
+        }
+
+        for row in matrix.chunks_mut(src_channels) {
+            let sum: f32 = row.iter().sum();
+            if sum > 1.0 {
+                row.iter_mut().for_each(|gain| *gain /= sum);
+            }
+        }
+        matrix
+    }
+
+    fn apply(&self, samples: &[f32]) -> Vec<f32> {
+        if self.identity {
+            return samples.to_vec();
+        }
+
+        let frames = samples.len() / self.src_channels;
+        let mut mapped = Vec::with_capacity(frames * self.dst_channels);
+        for frame in samples.chunks_exact(self.src_channels) {
+            for row in self.matrix.chunks_exact(self.src_channels) {
+                mapped.push(row.iter().zip(frame).map(|(gain, sample)| gain * sample).sum());
+            }
+        }
+        mapped
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Largest chunk read for markers. `cue `, `smpl` and label lists are tiny;
+/// anything bigger is skipped rather than loaded.
+const MAX_MARKER_CHUNK_BYTES: u32 = 1 << 20;
+
+/// Named positions stored in a WAV file by audio editors: cue points from
+/// its `cue ` chunk, named by `labl` entries in a `LIST`/`adtl` chunk where
+/// there are any, and sampler loops from its `smpl` chunk. Positions are in
+/// milliseconds, in file order. Files that aren't WAV have none.
+pub(crate) fn read_wav_markers(path: &Path) -> Result<Vec<(String, u32)>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut reader = BufReader::new(file);
+    let mut header = [0u8; 12];
+    if reader.read_exact(&mut header).is_err() || &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Ok(Vec::new());
+    }
+
+    let mut sample_rate = None;
+    // (cue ID, frame) in file order
+    let mut cues: Vec<(u32, u32)> = Vec::new();
+    let mut labels: HashMap<u32, String> = HashMap::new();
+    // (start, end) frames of each loop
+    let mut loops: Vec<(u32, u32)> = Vec::new();
+    loop {
+        let mut chunk = [0u8; 8];
+        if reader.read_exact(&mut chunk).is_err() {
+            break;
+        }
+        let size = u32_at(&chunk, 4);
+        // Chunks are padded to an even length
+        let padded = size as i64 + (size % 2) as i64;
+        let id = [chunk[0], chunk[1], chunk[2], chunk[3]];
+        if !matches!(&id, b"fmt " | b"cue " | b"smpl" | b"LIST") || size > MAX_MARKER_CHUNK_BYTES {
+            if reader.seek(SeekFrom::Current(padded)).is_err() {
+                break;
+            }
+            continue;
+        }
+        let mut body = vec![0u8; padded as usize];
+        if reader.read_exact(&mut body).is_err() {
+            // A truncated file keeps whatever was read before the cut
+            break;
+        }
+        body.truncate(size as usize);
+        match &id {
+            b"fmt " if body.len() >= 8 => sample_rate = Some(u32_at(&body, 4)),
+            b"cue " => cues.extend(cue_points(&body)),
+            b"smpl" => loops.extend(sample_loops(&body)),
+            b"LIST" => labels.extend(cue_labels(&body)),
+            _ => {}
+        }
+    }
+
+    let sample_rate = match sample_rate {
+        Some(rate) if rate > 0 => rate as u64,
+        _ => return Ok(Vec::new()),
+    };
+    let to_ms = |frame: u32| (frame as u64 * 1000 / sample_rate).min(u32::MAX as u64) as u32;
+    let mut markers: Vec<(String, u32)> = cues
+        .iter()
+        .enumerate()
+        .map(|(index, (id, frame))| {
+            let name = labels
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| format!("Cue {}", index + 1));
+            (name, to_ms(*frame))
+        })
+        .collect();
+    for (index, (start, end)) in loops.iter().enumerate() {
+        markers.push((format!("Loop {} start", index + 1), to_ms(*start)));
+        markers.push((format!("Loop {} end", index + 1), to_ms(*end)));
+    }
+    Ok(markers)
+}
+
+/// (ID, sample offset) of each cue point in a `cue ` chunk.
+fn cue_points(body: &[u8]) -> Vec<(u32, u32)> {
+    if body.len() < 4 {
+        return Vec::new();
+    }
+    let count = u32_at(body, 0) as usize;
+    body[4..]
+        .chunks_exact(24)
+        .take(count)
+        .map(|point| (u32_at(point, 0), u32_at(point, 20)))
+        .collect()
+}
+
+/// (start, end) frames of each loop in a `smpl` chunk.
+fn sample_loops(body: &[u8]) -> Vec<(u32, u32)> {
+    if body.len() < 36 {
+        return Vec::new();
+    }
+    let count = u32_at(body, 28) as usize;
+    body[36..]
+        .chunks_exact(24)
+        .take(count)
+        .map(|sample_loop| (u32_at(sample_loop, 8), u32_at(sample_loop, 12)))
+        .collect()
+}
+
+/// Names of cue points from the `labl` entries of an `adtl` list.
+fn cue_labels(body: &[u8]) -> HashMap<u32, String> {
+    let mut labels = HashMap::new();
+    if body.len() < 4 || &body[0..4] != b"adtl" {
+        return labels;
+    }
+    let mut offset = 4;
+    while offset + 8 <= body.len() {
+        let size = u32_at(body, offset + 4) as usize;
+        let start = offset + 8;
+        let end = (start + size).min(body.len());
+        if &body[offset..offset + 4] == b"labl" && end >= start + 4 {
+            let text = &body[start + 4..end];
+            let text = text.split(|byte| *byte == 0).next().unwrap_or_default();
+            let name = String::from_utf8_lossy(text).trim().to_string();
+            if !name.is_empty() {
+                labels.insert(u32_at(body, start), name);
+            }
+        }
+        offset = start + size + size % 2;
+    }
+    labels
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend((body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        if body.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((body.len() as u32 + 4).to_le_bytes());
+        bytes.extend(b"WAVE");
+        bytes.extend(body);
+        bytes
+    }
+
+    fn fmt(sample_rate: u32) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend(1u16.to_le_bytes());
+        body.extend(1u16.to_le_bytes());
+        body.extend(sample_rate.to_le_bytes());
+        body.extend((sample_rate * 2).to_le_bytes());
+        body.extend(2u16.to_le_bytes());
+        body.extend(16u16.to_le_bytes());
+        chunk(b"fmt ", &body)
+    }
+
+    fn cue(points: &[(u32, u32)]) -> Vec<u8> {
+        let mut body = (points.len() as u32).to_le_bytes().to_vec();
+        for (id, frame) in points {
+            body.extend(id.to_le_bytes());
+            body.extend(0u32.to_le_bytes());
+            body.extend(b"data");
+            body.extend([0u8; 8]);
+            body.extend(frame.to_le_bytes());
+        }
+        chunk(b"cue ", &body)
+    }
+
+    fn labels(labels: &[(u32, &str)]) -> Vec<u8> {
+        let mut body = b"adtl".to_vec();
+        for (id, text) in labels {
+            let mut label = id.to_le_bytes().to_vec();
+            label.extend(text.as_bytes());
+            label.push(0);
+            body.extend(chunk(b"labl", &label));
+        }
+        chunk(b"LIST", &body)
+    }
+
+    fn smpl(loops: &[(u32, u32)]) -> Vec<u8> {
+        let mut body = vec![0u8; 36];
+        body[28..32].copy_from_slice(&(loops.len() as u32).to_le_bytes());
+        for (start, end) in loops {
+            body.extend([0u8; 8]);
+            body.extend(start.to_le_bytes());
+            body.extend(end.to_le_bytes());
+            body.extend([0u8; 8]);
+        }
+        chunk(b"smpl", &body)
+    }
+
+    fn markers(name: &str, bytes: &[u8]) -> Vec<(String, u32)> {
+        let path = std::env::temp_dir().join(format!("voicebox-cue-test-{}-{}.wav", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        let markers = read_wav_markers(&path);
+        let _ = std::fs::remove_file(&path);
+        markers.unwrap()
+    }
+
+    fn named(markers: &[(&str, u32)]) -> Vec<(String, u32)> {
+        markers.iter().map(|(name, ms)| (name.to_string(), *ms)).collect()
+    }
+
+    #[test]
+    fn reads_labelled_cues_and_loops() {
+        let bytes = wav(&[
+            fmt(48_000),
+            chunk(b"data", &[0; 8]),
+            cue(&[(1, 24_000), (7, 96_000)]),
+            labels(&[(7, "Drop")]),
+            smpl(&[(48_000, 144_000)]),
+        ]);
+        assert_eq!(
+            markers("labelled", &bytes),
+            named(&[
+                ("Cue 1", 500),
+                ("Drop", 2000),
+                ("Loop 1 start", 1000),
+                ("Loop 1 end", 3000),
+            ])
+        );
+    }
+
+    #[test]
+    fn skips_odd_sized_chunks() {
+        // An odd-sized chunk is followed by a pad byte, as is an odd-sized
+        // label
+        let bytes = wav(&[
+            chunk(b"junk", &[1, 2, 3]),
+            fmt(44_100),
+            labels(&[(2, "ab"), (3, "Verse")]),
+            cue(&[(3, 44_100), (2, 0)]),
+        ]);
+        assert_eq!(markers("odd", &bytes), named(&[("Verse", 1000), ("ab", 0)]));
+    }
+
+    #[test]
+    fn keeps_markers_read_before_a_truncated_chunk() {
+        let mut bytes = wav(&[fmt(8_000), cue(&[(1, 4_000)]), labels(&[(1, "Cut off")])]);
+        bytes.truncate(bytes.len() - 6);
+        assert_eq!(markers("truncated", &bytes), named(&[("Cue 1", 500)]));
+
+        // A chunk whose size runs past the end of the file
+        let mut bytes = wav(&[fmt(8_000)]);
+        bytes.extend(b"cue ");
+        bytes.extend(u32::MAX.to_le_bytes());
+        bytes.extend([1, 0, 0, 0]);
+        assert_eq!(markers("oversized", &bytes), Vec::new());
+    }
+
+    #[test]
+    fn ignores_malformed_marker_chunks() {
+        let mut short_cue = cue(&[(1, 8_000), (2, 16_000)]);
+        // Claims two points but only holds one
+        short_cue.truncate(short_cue.len() - 24);
+        short_cue[4..8].copy_from_slice(&(4u32 + 24).to_le_bytes());
+        let mut long_label = labels(&[(1, "Long")]);
+        // A label claiming more bytes than its list holds
+        long_label[16..20].copy_from_slice(&1000u32.to_le_bytes());
+        let bytes = wav(&[
+            fmt(8_000),
+            short_cue,
+            long_label,
+            chunk(b"cue ", &[1, 0]),
+            chunk(b"smpl", &[0; 10]),
+            chunk(b"LIST", b"INFO"),
+        ]);
+        assert_eq!(markers("malformed", &bytes), named(&[("Long", 1000)]));
+    }
+
+    #[test]
+    fn needs_a_wav_with_a_sample_rate() {
+        assert_eq!(markers("empty", &[]), Vec::new());
+        assert_eq!(markers("not-wav", b"fLaC\0\0\0\x22 not a wave file"), Vec::new());
+        assert_eq!(markers("no-fmt", &wav(&[cue(&[(1, 100)])])), Vec::new());
+        assert_eq!(markers("zero-rate", &wav(&[fmt(0), cue(&[(1, 100)])])), Vec::new());
+    }
+
+    #[test]
+    fn reports_missing_files() {
+        assert!(read_wav_markers(Path::new("/nonexistent/voicebox/marker.wav")).is_err());
+    }
+}
@@ -0,0 +1,416 @@
+use super::clip_cache::{ClipCache, DecodedClip};
+use super::http_source::HttpSource;
+use super::{LeadIn, TestToneKind};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use symphonia::core::codecs::Decoder;
+use symphonia::core::formats::FormatReader;
+use symphonia::core::io::MediaSource;
+use symphonia::core::probe::Hint;
+
+/// Decode a whole clip up front. Used where random access is needed, e.g. for
+/// scrubbing; playback streams through `ClipDecoder` instead.
+pub(super) fn decode_wav(data: &[u8]) -> Result<(Vec<f32>, u32, u16), String> {
+    let mut decoder = ClipDecoder::new(data.to_vec())?;
+    let mut samples = Vec::new();
+    while let Some(chunk) = decoder.next_chunk()? {
+        samples.extend_from_slice(&chunk);
+    }
+
+    eprintln!("decode_wav: Decoded {} samples", samples.len());
+    Ok((samples, decoder.sample_rate, decoder.channels))
+}
+
+/// Basic format of an audio file.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClipFormat {
+    pub(crate) sample_rate: u32,
+    pub(crate) channels: u16,
+    pub(crate) duration_ms: u64,
+}
+
+/// Find a file's format and length. The length comes from the container when
+/// it is reported and otherwise by decoding the whole file.
+pub(crate) fn probe_file(path: &Path) -> Result<ClipFormat, String> {
+    let mut decoder = ClipDecoder::open_file(path)?;
+    let frames = match decoder.total_frames {
+        Some(frames) => frames,
+        None => {
+            let channels = decoder.channels.max(1) as u64;
+            let mut samples = 0;
+            while let Some(chunk) = decoder.next_chunk()? {
+                samples += chunk.len() as u64;
+            }
+            samples / channels
+        }
+    };
+    Ok(ClipFormat {
+        sample_rate: decoder.sample_rate,
+        channels: decoder.channels,
+        duration_ms: frames * 1000 / decoder.sample_rate.max(1) as u64,
+    })
+}
+
+/// Frames per chunk when playing a clip from the cache.
+const CACHED_CHUNK_FRAMES: usize = 4096;
+
+/// Decodes a clip one packet at a time, so playback can start before the whole
+/// file has been decoded.
+pub(super) struct ClipDecoder {
+    source: ClipSource,
+    /// Decoded samples being collected for the clip cache
+    recording: Option<CacheRecording>,
+    pub(super) sample_rate: u32,
+    pub(super) channels: u16,
+    /// Length in frames, if the container reports it
+    pub(super) total_frames: Option<u64>,
+}
+
+enum ClipSource {
+    Stream {
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn Decoder>,
+        track_id: u32,
+    },
+    Cached {
+        samples: Arc<[f32]>,
+        position: usize,
+    },
+    /// Samples produced while the clip plays, e.g. streamed speech
+    Live {
+        chunks: mpsc::Receiver<Result<Vec<f32>, String>>,
+    },
+}
+
+struct CacheRecording {
+    cache: Arc<Mutex<ClipCache>>,
+    hash: String,
+    samples: Vec<f32>,
+}
+
+impl ClipDecoder {
+    /// Probe `data` and set up a decoder for its first audio track.
+    pub(super) fn new(data: Vec<u8>) -> Result<Self, String> {
+        eprintln!("ClipDecoder: Probing {} bytes", data.len());
+        Self::from_source(Box::new(std::io::Cursor::new(data)), Hint::new())
+    }
+
+    /// Decode straight from a file on disk, reading it as playback goes.
+    pub(super) fn open_file(path: &Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+
+        eprintln!("ClipDecoder: Probing {}", path.display());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+            hint.with_extension(extension);
+        }
+        Self::from_source(Box::new(file), hint)
+    }
+
+    /// Decode a remote HTTP(S) file while it downloads.
+    pub(super) fn open_url(url: &str) -> Result<Self, String> {
+        let source = HttpSource::open(url)?;
+
+        let mut hint = Hint::new();
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        if let Some((_, extension)) = path.rsplit_once('.') {
+            if !extension.contains('/') {
+                hint.with_extension(extension);
+            }
+        }
+        Self::from_source(Box::new(source), hint)
+    }
+
+    /// Play back a clip that has already been decoded.
+    pub(super) fn from_cache(clip: DecodedClip) -> Self {
+        let channels = clip.channels.max(1);
+        Self {
+            total_frames: Some((clip.samples.len() / channels as usize) as u64),
+            source: ClipSource::Cached {
+                samples: clip.samples,
+                position: 0,
+            },
+            recording: None,
+            sample_rate: clip.sample_rate,
+            channels,
+        }
+    }
+
+    /// Play samples as they arrive on `chunks`. The clip ends when the sender
+    /// is dropped, and fails with any error sent in place of a chunk.
+    pub(super) fn live(chunks: mpsc::Receiver<Result<Vec<f32>, String>>, sample_rate: u32, channels: u16) -> Self {
+        Self {
+            source: ClipSource::Live { chunks },
+            recording: None,
+            sample_rate,
+            channels: channels.max(1),
+            total_frames: None,
+        }
+    }
+
+    /// Add the decoded clip to `cache` under its content `hash` once it has
+    /// been decoded to the end. Nothing is cached if decoding stops early.
+    pub(super) fn cache_into(&mut self, cache: Arc<Mutex<ClipCache>>, hash: String) {
+        if let ClipSource::Stream { .. } = self.source {
+            self.recording = Some(CacheRecording {
+                cache,
+                hash,
+                samples: Vec::new(),
+            });
+        }
+    }
+
+    fn from_source(source: Box<dyn MediaSource>, hint: Hint) -> Result<Self, String> {
+        use symphonia::core::formats::FormatOptions;
+        use symphonia::core::io::MediaSourceStream;
+        use symphonia::core::meta::MetadataOptions;
+
+        let mss = MediaSourceStream::new(source, Default::default());
+
+        let format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                mss,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(|e| {
+                eprintln!("ClipDecoder: Failed to probe audio: {}", e);
+                format!("Failed to probe audio: {}", e)
+            })?
+            .format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != symphonia::core::codecs::CODEC_TYPE_NULL)
+            .ok_or_else(|| {
+                eprintln!("ClipDecoder: No audio track found");
+                "No audio track found".to_string()
+            })?;
+
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or_else(|| "No sample rate found".to_string())?;
+        let channels = track
+            .codec_params
+            .channels
+            .ok_or_else(|| "No channels found".to_string())?
+            .count() as u16;
+        let total_frames = track.codec_params.n_frames;
+        let track_id = track.id;
+
+        eprintln!(
+            "ClipDecoder: Track info - sample_rate: {}, channels: {}, frames: {:?}",
+            sample_rate, channels, total_frames
+        );
+
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &Default::default())
+            .map_err(|e| {
+                eprintln!("ClipDecoder: Failed to create decoder: {}", e);
+                format!("Failed to create decoder: {}", e)
+            })?;
+
+        Ok(Self {
+            source: ClipSource::Stream {
+                format,
+                decoder,
+                track_id,
+            },
+            recording: None,
+            sample_rate,
+            channels,
+            total_frames,
+        })
+    }
+
+    /// Decode the next packet of the track as interleaved f32 samples. Returns
+    /// `None` once the stream has ended.
+    pub(super) fn next_chunk(&mut self) -> Result<Option<Vec<f32>>, String> {
+        let chunk = match &mut self.source {
+            ClipSource::Stream {
+                format,
+                decoder,
+                track_id,
+            } => decode_packet(format.as_mut(), decoder.as_mut(), *track_id)?,
+            ClipSource::Cached { samples, position } => {
+                let end = (*position + CACHED_CHUNK_FRAMES * self.channels as usize).min(samples.len());
+                let chunk = (*position < end).then(|| samples[*position..end].to_vec());
+                *position = end;
+                chunk
+            }
+            ClipSource::Live { chunks } => match chunks.recv() {
+                Ok(chunk) => Some(chunk?),
+                Err(mpsc::RecvError) => None,
+            },
+        };
+
+        if let Some(mut recording) = self.recording.take() {
+            match &chunk {
+                Some(samples) if recording.samples.len() + samples.len() <= ClipCache::max_samples() => {
+                    recording.samples.extend_from_slice(samples);
+                    self.recording = Some(recording);
+                }
+                // Too long to cache; stop collecting
+                Some(_) => {}
+                None => recording.cache.lock().unwrap().insert(
+                    recording.hash,
+                    DecodedClip {
+                        samples: recording.samples.into(),
+                        sample_rate: self.sample_rate,
+                        channels: self.channels,
+                    },
+                ),
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+fn decode_packet(
+    format: &mut dyn FormatReader,
+    decoder: &mut dyn Decoder,
+    track_id: u32,
+) -> Result<Option<Vec<f32>>, String> {
+    use symphonia::core::audio::{AudioBufferRef, Signal};
+    use symphonia::core::conv::FromSample;
+
+    let packet = loop {
+        match format.next_packet() {
+            Ok(packet) if packet.track_id() == track_id => break packet,
+            Ok(_) => continue,
+            Err(e) => {
+                eprintln!("ClipDecoder: End of stream or error: {:?}", e);
+                return Ok(None);
+            }
+        }
+    };
+
+    let decoded = decoder
+        .decode(&packet)
+        .map_err(|e| format!("Decode error: {}", e))?;
+
+    let num_channels = decoded.spec().channels.count();
+    let num_frames = decoded.frames();
+    let mut samples = Vec::with_capacity(num_frames * num_channels);
+
+    // Interleave samples from all channels
+    for frame_idx in 0..num_frames {
+        for ch in 0..num_channels {
+            let sample_f32 = match &decoded {
+                AudioBufferRef::U8(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
+                AudioBufferRef::U16(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
+                AudioBufferRef::U24(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
+                AudioBufferRef::U32(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
+                AudioBufferRef::S8(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
+                AudioBufferRef::S16(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
+                AudioBufferRef::S24(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
+                AudioBufferRef::S32(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
+                AudioBufferRef::F32(buf) => buf.chan(ch)[frame_idx],
+                AudioBufferRef::F64(buf) => buf.chan(ch)[frame_idx] as f32,
+            };
+            samples.push(sample_f32);
+        }
+    }
+
+    Ok(Some(samples))
+}
+
+/// Render a lead-in as interleaved samples at the clip's own rate and channel count,
+/// so it can simply be played before the first packet.
+pub(super) fn render_lead_in(lead_in: LeadIn, sample_rate: u32, channels: u16) -> Result<Vec<f32>, String> {
+    const CLICK_SECS: f32 = 0.03;
+    const CLICK_GAIN: f32 = 0.5;
+    const MAX_LEAD_IN_MS: u32 = 30_000;
+
+    let channels = channels as usize;
+    let frames = match lead_in {
+        LeadIn::Silence { duration_ms } => {
+            if duration_ms > MAX_LEAD_IN_MS {
+                return Err(format!("Lead-in must last at most {} ms, got {}", MAX_LEAD_IN_MS, duration_ms));
+            }
+            (duration_ms as u64 * sample_rate as u64 / 1000) as usize
+        }
+        LeadIn::CountIn { beats, bpm } => {
+            if !(20.0..=400.0).contains(&bpm) {
+                return Err(format!("Count-in tempo must be 20-400 bpm, got {}", bpm));
+            }
+            if !(1..=32).contains(&beats) {
+                return Err(format!("Count-in must be 1-32 beats, got {}", beats));
+            }
+            if beats as f32 * 60_000.0 / bpm > MAX_LEAD_IN_MS as f32 {
+                return Err(format!("Count-in must last at most {} ms", MAX_LEAD_IN_MS));
+            }
+            let frames_per_beat = (sample_rate as f32 * 60.0 / bpm) as usize;
+            frames_per_beat * beats as usize
+        }
+    };
+
+    let mut lead = vec![0.0; frames * channels];
+
+    if let LeadIn::CountIn { beats, bpm } = lead_in {
+        let frames_per_beat = (sample_rate as f32 * 60.0 / bpm) as usize;
+        let click_frames = ((sample_rate as f32 * CLICK_SECS) as usize).min(frames_per_beat);
+
+        for beat in 0..beats as usize {
+            // Accent the downbeat so the count is easy to follow
+            let freq = if beat == 0 { 1500.0 } else { 1000.0 };
+            let start = beat * frames_per_beat;
+            for i in 0..click_frames {
+                let t = i as f32 / sample_rate as f32;
+                let envelope = 1.0 - i as f32 / click_frames as f32;
+                let value = (2.0 * std::f32::consts::PI * freq * t).sin() * envelope * CLICK_GAIN;
+                let frame = (start + i) * channels;
+                for sample in &mut lead[frame..frame + channels] {
+                    *sample = value;
+                }
+            }
+        }
+    }
+
+    Ok(lead)
+}
+
+/// Render a mono test signal of `duration_ms` at `sample_rate`, 12 dB below
+/// full scale. `frequency` is only used for the sine.
+pub(super) fn render_test_tone(
+    kind: TestToneKind,
+    frequency: f32,
+    duration_ms: u32,
+    sample_rate: u32,
+) -> Result<Vec<f32>, String> {
+    const TONE_GAIN: f32 = 0.25;
+
+    if !(20.0..=20_000.0).contains(&frequency) {
+        return Err(format!("Test tone frequency must be 20-20000 Hz, got {}", frequency));
+    }
+    if !(1..=30_000).contains(&duration_ms) {
+        return Err(format!("Test tone must last 1-30000 ms, got {}", duration_ms));
+    }
+
+    let frames = (duration_ms as u64 * sample_rate as u64 / 1000) as usize;
+    let tone = match kind {
+        TestToneKind::Sine => {
+            let step = 2.0 * std::f64::consts::PI * frequency as f64 / sample_rate as f64;
+            (0..frames)
+                .map(|i| (step * i as f64).sin() as f32 * TONE_GAIN)
+                .collect()
+        }
+        TestToneKind::Noise => {
+            // xorshift32; white noise doesn't need a better generator
+            let mut state = 0x9e37_79b9u32;
+            (0..frames)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    (state as f32 / u32::MAX as f32 * 2.0 - 1.0) * TONE_GAIN
+                })
+                .collect()
+        }
+    };
+    Ok(tone)
+}
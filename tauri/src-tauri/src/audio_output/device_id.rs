@@ -0,0 +1,161 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host};
+use std::collections::{HashMap, VecDeque};
+
+/// An audio device together with the ID the frontend addresses it by.
+pub(crate) struct IdentifiedDevice {
+    pub(crate) id: String,
+    pub(crate) platform_id: Option<String>,
+    pub(crate) name: String,
+    pub(crate) device: Device,
+}
+
+#[derive(Clone, Copy)]
+enum DeviceKind {
+    Output,
+    Input,
+}
+
+/// Enumerate output devices with IDs that survive restarts and don't collide when
+/// two devices share a name. Platform identifiers are used where the host exposes
+/// them; otherwise the ID is derived from the name, with a counter appended for
+/// duplicates.
+pub(super) fn identified_output_devices(host: &Host) -> Result<Vec<IdentifiedDevice>, String> {
+    let devices = host
+        .output_devices()
+        .map_err(|e| format!("Failed to enumerate output devices: {}", e))?;
+    Ok(identify(devices, PlatformIds::for_host(host, DeviceKind::Output)))
+}
+
+/// Enumerate input devices, with IDs made the same way as for outputs.
+pub(crate) fn identified_input_devices(host: &Host) -> Result<Vec<IdentifiedDevice>, String> {
+    let devices = host
+        .input_devices()
+        .map_err(|e| format!("Failed to enumerate input devices: {}", e))?;
+    Ok(identify(devices, PlatformIds::for_host(host, DeviceKind::Input)))
+}
+
+fn identify(devices: impl Iterator<Item = Device>, mut platform_ids: PlatformIds) -> Vec<IdentifiedDevice> {
+    let mut name_counts: HashMap<String, usize> = HashMap::new();
+    let mut result = Vec::new();
+    for device in devices {
+        let name = match device.name() {
+            Ok(name) => name,
+            Err(e) => {
+                eprintln!("Skipping device without a name: {}", e);
+                continue;
+            }
+        };
+
+        let platform_id = platform_ids.take(&name);
+        let id = match &platform_id {
+            Some(platform_id) => format!("{}:{}", platform_ids.prefix, platform_id),
+            None => {
+                let count = name_counts.entry(name.clone()).or_insert(0);
+                *count += 1;
+                match *count {
+                    1 => device_id_for_name(&name),
+                    n => format!("{}_{}", device_id_for_name(&name), n),
+                }
+            }
+        };
+
+        result.push(IdentifiedDevice {
+            id,
+            platform_id,
+            name,
+            device,
+        });
+    }
+
+    result
+}
+
+/// Fallback ID derived from the device name (cpal doesn't provide stable IDs)
+fn device_id_for_name(name: &str) -> String {
+    format!("device_{}", name.replace(' ', "_").to_lowercase())
+}
+
+/// Platform identifiers for the host's output devices, handed out per name in
+/// enumeration order so devices sharing a name each get their own.
+struct PlatformIds {
+    prefix: String,
+    by_name: HashMap<String, VecDeque<String>>,
+    /// ALSA device names are already unique PCM identifiers such as "hw:CARD=PCH,DEV=0"
+    names_are_ids: bool,
+}
+
+impl PlatformIds {
+    // Only WASAPI lists render and capture endpoints separately
+    #[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
+    fn for_host(host: &Host, kind: DeviceKind) -> Self {
+        let host_id = host.id();
+        let mut ids = Self {
+            prefix: host_id.name().to_lowercase(),
+            by_name: HashMap::new(),
+            names_are_ids: false,
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            ids.names_are_ids = host_id == cpal::HostId::Alsa;
+        }
+
+        #[cfg(target_os = "windows")]
+        if host_id == cpal::HostId::Wasapi {
+            match wasapi_endpoint_ids(kind) {
+                Ok(by_name) => ids.by_name = by_name,
+                Err(e) => eprintln!("Failed to read WASAPI endpoint IDs: {}", e),
+            }
+        }
+
+        ids
+    }
+
+    fn take(&mut self, name: &str) -> Option<String> {
+        if self.names_are_ids {
+            return Some(name.to_string());
+        }
+        self.by_name.get_mut(name).and_then(|ids| ids.pop_front())
+    }
+}
+
+/// Endpoint IDs of the active render or capture devices, grouped by friendly name (which is
+/// what cpal reports as the device name).
+#[cfg(target_os = "windows")]
+fn wasapi_endpoint_ids(kind: DeviceKind) -> Result<HashMap<String, VecDeque<String>>, String> {
+    use wasapi::{DeviceEnumerator, Direction};
+    use windows::Win32::System::Com::{CoInitializeEx, COINIT_MULTITHREADED};
+
+    // COM may already be set up on this thread by someone else; either way is fine
+    unsafe {
+        let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+    }
+
+    let direction = match kind {
+        DeviceKind::Output => Direction::Render,
+        DeviceKind::Input => Direction::Capture,
+    };
+    let collection = DeviceEnumerator::new()
+        .and_then(|enumerator| enumerator.get_device_collection(&direction))
+        .map_err(|e| format!("Failed to enumerate endpoints: {}", e))?;
+    let count = collection
+        .get_nbr_devices()
+        .map_err(|e| format!("Failed to count endpoints: {}", e))?;
+
+    let mut by_name: HashMap<String, VecDeque<String>> = HashMap::new();
+    for index in 0..count {
+        let device = collection
+            .get_device_at_index(index)
+            .map_err(|e| format!("Failed to get endpoint {}: {}", index, e))?;
+        let name = device
+            .get_friendlyname()
+            .map_err(|e| format!("Failed to get endpoint name: {}", e))?;
+        let id = device
+            .get_id()
+            .map_err(|e| format!("Failed to get endpoint ID: {}", e))?;
+        by_name.entry(name).or_default().push_back(id);
+    }
+
+    Ok(by_name)
+}
@@ -0,0 +1,66 @@
+use super::StreamProgress;
+use std::sync::atomic::Ordering;
+
+/// Playback time before drift is measured, so stream start-up jitter doesn't
+/// end up in the baseline.
+const DRIFT_WARMUP_SECS: f64 = 2.0;
+/// Weight of each new measurement; positions only move once per callback, so
+/// single readings are noisy.
+const DRIFT_SMOOTHING: f64 = 0.1;
+/// Rate change per second of drift.
+const DRIFT_CORRECTION_GAIN: f64 = 0.1;
+/// Largest rate change applied, in either direction (0.2%, far beyond the
+/// drift of real sound card clocks and well below audible pitch changes).
+const MAX_DRIFT_CORRECTION: f64 = 0.002;
+
+/// Keeps a multi-device session in sync. Each device's clock runs slightly
+/// fast or slow, so over a long clip their positions drift apart. Every stream
+/// after the first is compared with the first, and its playback rate is
+/// nudged so the gap stays where it was once playback settled.
+#[derive(Default)]
+pub(super) struct DriftCorrector {
+    /// Smoothed offset of each stream from the first, in seconds
+    offsets: Vec<f64>,
+    /// Offsets when correction started; drift is measured from these
+    baselines: Vec<Option<f64>>,
+}
+
+impl DriftCorrector {
+    /// Measure the streams and update their rates. Call periodically while the
+    /// session is playing.
+    pub(super) fn update(&mut self, streams: &[StreamProgress]) {
+        let reference = match streams.first() {
+            Some(reference) if streams.len() > 1 => reference,
+            _ => return,
+        };
+        if reference.finished.load(Ordering::Relaxed) {
+            return;
+        }
+        let reference_secs = reference.position_secs();
+        if self.offsets.len() != streams.len() {
+            self.offsets = streams
+                .iter()
+                .map(|stream| stream.position_secs() - reference_secs)
+                .collect();
+            self.baselines = vec![None; streams.len()];
+        }
+
+        for ((stream, offset), baseline) in streams
+            .iter()
+            .zip(&mut self.offsets)
+            .zip(&mut self.baselines)
+            .skip(1)
+        {
+            let measured = stream.position_secs() - reference_secs;
+            *offset += (measured - *offset) * DRIFT_SMOOTHING;
+            if reference_secs < DRIFT_WARMUP_SECS {
+                continue;
+            }
+
+            let drift = *offset - *baseline.get_or_insert(*offset);
+            let correction = (drift * DRIFT_CORRECTION_GAIN).clamp(-MAX_DRIFT_CORRECTION, MAX_DRIFT_CORRECTION);
+            // A stream that has run ahead reads the clip a little slower
+            stream.rate.set((1.0 - correction) as f32);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use serde::{Deserialize, Serialize};
+
+/// Most bands a device's EQ can have.
+pub(super) const MAX_EQ_BANDS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EqBandKind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+/// One band of a parametric EQ.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EqBand {
+    pub kind: EqBandKind,
+    pub frequency_hz: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+impl EqBand {
+    pub(super) fn validate(&self) -> Result<(), String> {
+        if !(20.0..=20_000.0).contains(&self.frequency_hz) {
+            return Err(format!("EQ frequency must be 20-20000 Hz, got {}", self.frequency_hz));
+        }
+        if !(-24.0..=24.0).contains(&self.gain_db) {
+            return Err(format!("EQ gain must be within +/-24 dB, got {}", self.gain_db));
+        }
+        if !(0.1..=10.0).contains(&self.q) {
+            return Err(format!("EQ Q must be 0.1-10, got {}", self.q));
+        }
+        Ok(())
+    }
+}
+
+/// Normalised biquad coefficients (a0 = 1).
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    /// Coefficients for `band` from the RBJ audio EQ cookbook.
+    fn for_band(band: &EqBand, sample_rate: u32) -> Self {
+        // Keep the centre frequency below Nyquist for low sample rates
+        let frequency = band.frequency_hz.min(sample_rate as f32 * 0.45) as f64;
+        let a = 10f64.powf(band.gain_db as f64 / 40.0);
+        let w0 = 2.0 * std::f64::consts::PI * frequency / sample_rate as f64;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q as f64);
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            EqBandKind::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            EqBandKind::LowShelf => {
+                let sqrt_a = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a),
+                    (a + 1.0) + (a - 1.0) * cos + sqrt_a,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - sqrt_a,
+                )
+            }
+            EqBandKind::HighShelf => {
+                let sqrt_a = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a),
+                    (a + 1.0) - (a - 1.0) * cos + sqrt_a,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - sqrt_a,
+                )
+            }
+        };
+
+        Self {
+            b0: (b0 / a0) as f32,
+            b1: (b1 / a0) as f32,
+            b2: (b2 / a0) as f32,
+            a1: (a1 / a0) as f32,
+            a2: (a2 / a0) as f32,
+        }
+    }
+}
+
+/// Transposed direct form II state for one channel of one band.
+#[derive(Clone, Copy, Default)]
+struct BiquadState {
+    z1: f32,
+    z2: f32,
+}
+
+impl BiquadState {
+    fn process(&mut self, filter: &Biquad, input: f32) -> f32 {
+        let output = filter.b0 * input + self.z1;
+        self.z1 = filter.b1 * input - filter.a1 * output + self.z2;
+        self.z2 = filter.b2 * input - filter.a2 * output;
+        output
+    }
+}
+
+/// A chain of biquads run on every channel of a device's output.
+pub(super) struct ParametricEq {
+    sample_rate: u32,
+    filters: Vec<Biquad>,
+    /// One state per band, per channel
+    states: Vec<Vec<BiquadState>>,
+}
+
+impl ParametricEq {
+    pub(super) fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            filters: Vec::new(),
+            states: Vec::new(),
+        }
+    }
+
+    /// Swap in new bands. Filter state is kept for bands that still exist so a
+    /// slider being dragged doesn't click.
+    pub(super) fn set_bands(&mut self, bands: &[EqBand]) {
+        self.filters = bands
+            .iter()
+            .map(|band| Biquad::for_band(band, self.sample_rate))
+            .collect();
+        for channel in &mut self.states {
+            channel.resize(self.filters.len(), BiquadState::default());
+        }
+    }
+
+    pub(super) fn process_frame(&mut self, frame: &mut [f32]) {
+        if self.filters.is_empty() {
+            return;
+        }
+        if self.states.len() < frame.len() {
+            self.states
+                .resize(frame.len(), vec![BiquadState::default(); self.filters.len()]);
+        }
+        for (sample, states) in frame.iter_mut().zip(&mut self.states) {
+            for (filter, state) in self.filters.iter().zip(states.iter_mut()) {
+                *sample = state.process(filter, *sample);
+            }
+        }
+    }
+}
+
+/// Delays every channel of a device's output by a whole number of frames, so a
+/// faster device can be lined up with a slower one.
+pub(super) struct DelayLine {
+    frames: usize,
+    /// Interleaved ring buffer of `frames` frames, sized on the first frame
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl DelayLine {
+    pub(super) fn new() -> Self {
+        Self {
+            frames: 0,
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Change the delay. The buffered audio is dropped, so this briefly mutes.
+    pub(super) fn set_frames(&mut self, frames: usize) {
+        self.frames = frames;
+        self.buffer.clear();
+        self.position = 0;
+    }
+
+    pub(super) fn process_frame(&mut self, frame: &mut [f32]) {
+        if self.frames == 0 {
+            return;
+        }
+        if self.buffer.is_empty() {
+            self.buffer.resize(self.frames * frame.len(), 0.0);
+        }
+        for sample in frame.iter_mut() {
+            std::mem::swap(sample, &mut self.buffer[self.position]);
+            self.position = (self.position + 1) % self.buffer.len();
+        }
+    }
+}
+
+/// Settings for the output compressor. A high ratio (20 or more) makes it
+/// behave as a limiter.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CompressorSettings {
+    pub threshold_db: f32,
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+}
+
+impl CompressorSettings {
+    pub(super) fn validate(&self) -> Result<(), String> {
+        if !(-60.0..=0.0).contains(&self.threshold_db) {
+            return Err(format!("Compressor threshold must be -60 to 0 dB, got {}", self.threshold_db));
+        }
+        if !(1.0..=100.0).contains(&self.ratio) {
+            return Err(format!("Compressor ratio must be 1-100, got {}", self.ratio));
+        }
+        if !(0.0..=500.0).contains(&self.attack_ms) {
+            return Err(format!("Compressor attack must be 0-500 ms, got {}", self.attack_ms));
+        }
+        if !(1.0..=5000.0).contains(&self.release_ms) {
+            return Err(format!("Compressor release must be 1-5000 ms, got {}", self.release_ms));
+        }
+        Ok(())
+    }
+}
+
+/// Feed-forward compressor with a peak detector linked across channels, so the
+/// stereo image doesn't shift when one side is louder.
+pub(super) struct Compressor {
+    sample_rate: u32,
+    settings: Option<CompressorSettings>,
+    attack_coeff: f32,
+    release_coeff: f32,
+    /// Current gain reduction in dB (0 or negative)
+    reduction_db: f32,
+}
+
+impl Compressor {
+    pub(super) fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            settings: None,
+            attack_coeff: 0.0,
+            release_coeff: 0.0,
+            reduction_db: 0.0,
+        }
+    }
+
+    pub(super) fn set_settings(&mut self, settings: Option<CompressorSettings>) {
+        let coeff = |ms: f32| {
+            let frames = ms * self.sample_rate as f32 / 1000.0;
+            if frames < 1.0 {
+                0.0
+            } else {
+                (-1.0 / frames).exp()
+            }
+        };
+        if let Some(settings) = &settings {
+            self.attack_coeff = coeff(settings.attack_ms);
+            self.release_coeff = coeff(settings.release_ms);
+        } else {
+            self.reduction_db = 0.0;
+        }
+        self.settings = settings;
+    }
+
+    pub(super) fn process_frame(&mut self, frame: &mut [f32]) {
+        let settings = match &self.settings {
+            Some(settings) => settings,
+            None => return,
+        };
+
+        let peak = frame.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        let level_db = 20.0 * peak.max(1e-9).log10();
+        let over_db = level_db - settings.threshold_db;
+        let target_db = if over_db > 0.0 {
+            -over_db * (1.0 - 1.0 / settings.ratio)
+        } else {
+            0.0
+        };
+
+        // Deeper reduction follows the attack time, recovery the release time
+        let coeff = if target_db < self.reduction_db {
+            self.attack_coeff
+        } else {
+            self.release_coeff
+        };
+        self.reduction_db = target_db + (self.reduction_db - target_db) * coeff;
+
+        let gain = 10f32.powf(self.reduction_db / 20.0);
+        for sample in frame.iter_mut() {
+            *sample *= gain;
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use super::clip_cache::ClipCache;
+use super::convert::FormatConverter;
+use std::sync::{Arc, Mutex};
+
+/// Turns a session's audio into one device's format and applies whatever
+/// gain the decoder thread leaves to the devices. Audio may come from the
+/// clip cache already converted, so replaying a clip on the same device
+/// skips resampling.
+pub(super) struct DeviceFeed {
+    source: FeedSource,
+    gain: f32,
+}
+
+enum FeedSource {
+    Convert {
+        converter: Box<FormatConverter>,
+        /// Converted samples being collected for the clip cache
+        recording: Option<ConvertedRecording>,
+    },
+    /// Plays the cached conversion, keeping pace with the source audio fed in
+    Cached {
+        samples: Arc<[f32]>,
+        position: usize,
+        source_frames: u64,
+        source_rate: u32,
+        source_channels: u16,
+        sample_rate: u32,
+        channels: u16,
+    },
+}
+
+struct ConvertedRecording {
+    cache: Arc<Mutex<ClipCache>>,
+    hash: String,
+    samples: Vec<f32>,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl DeviceFeed {
+    pub(super) fn convert(converter: FormatConverter, gain: f32) -> Self {
+        Self {
+            source: FeedSource::Convert {
+                converter: Box::new(converter),
+                recording: None,
+            },
+            gain,
+        }
+    }
+
+    /// Play `samples`, a clip already converted to `sample_rate` and
+    /// `channels`, in place of converting the session's audio.
+    pub(super) fn cached(
+        samples: Arc<[f32]>,
+        source_rate: u32,
+        source_channels: u16,
+        sample_rate: u32,
+        channels: u16,
+        gain: f32,
+    ) -> Self {
+        Self {
+            source: FeedSource::Cached {
+                samples,
+                position: 0,
+                source_frames: 0,
+                source_rate: source_rate.max(1),
+                source_channels: source_channels.max(1),
+                sample_rate,
+                channels: channels.max(1),
+            },
+            gain,
+        }
+    }
+
+    /// Add the converted clip to `cache` under its content `hash` once the
+    /// feed is flushed. The feed must start at the first frame of the clip.
+    pub(super) fn cache_into(&mut self, cache: Arc<Mutex<ClipCache>>, hash: String, sample_rate: u32, channels: u16) {
+        if let FeedSource::Convert { recording, .. } = &mut self.source {
+            *recording = Some(ConvertedRecording {
+                cache,
+                hash,
+                samples: Vec::new(),
+                sample_rate,
+                channels,
+            });
+        }
+    }
+
+    /// Drop whatever has been collected for the cache, e.g. because decoding
+    /// failed partway.
+    pub(super) fn stop_caching(&mut self) {
+        if let FeedSource::Convert { recording, .. } = &mut self.source {
+            *recording = None;
+        }
+    }
+
+    /// Number of device-format samples `src_frames` source frames convert to.
+    pub(super) fn output_len(&self, src_frames: u64) -> usize {
+        match &self.source {
+            FeedSource::Convert { converter, .. } => converter.output_len(src_frames),
+            FeedSource::Cached {
+                source_rate,
+                sample_rate,
+                channels,
+                ..
+            } => (src_frames * *sample_rate as u64 / *source_rate as u64) as usize * *channels as usize,
+        }
+    }
+
+    pub(super) fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        let converted = match &mut self.source {
+            FeedSource::Convert { converter, recording } => {
+                let converted = converter.process(samples);
+                record(recording, &converted);
+                converted
+            }
+            FeedSource::Cached {
+                samples: cached,
+                position,
+                source_frames,
+                source_rate,
+                source_channels,
+                sample_rate,
+                channels,
+            } => {
+                *source_frames += (samples.len() / *source_channels as usize) as u64;
+                let frames = *source_frames * *sample_rate as u64 / *source_rate as u64;
+                let end = (frames as usize * *channels as usize).min(cached.len()).max(*position);
+                let converted = cached[*position..end].to_vec();
+                *position = end;
+                converted
+            }
+        };
+        self.apply_gain(converted)
+    }
+
+    /// Output still held back once the input has ended. Adds the converted
+    /// clip to the cache if it was being collected.
+    pub(super) fn flush(&mut self) -> Vec<f32> {
+        let converted = match &mut self.source {
+            FeedSource::Convert { converter, recording } => {
+                let converted = converter.flush();
+                record(recording, &converted);
+                if let Some(recording) = recording.take() {
+                    eprintln!(
+                        "Caching clip converted to {}Hz/{}ch",
+                        recording.sample_rate, recording.channels
+                    );
+                    recording.cache.lock().unwrap().insert_converted(
+                        recording.hash,
+                        recording.samples.into(),
+                        recording.sample_rate,
+                        recording.channels,
+                    );
+                }
+                converted
+            }
+            FeedSource::Cached { samples, position, .. } => {
+                let converted = samples[*position..].to_vec();
+                *position = samples.len();
+                converted
+            }
+        };
+        self.apply_gain(converted)
+    }
+
+    fn apply_gain(&self, mut samples: Vec<f32>) -> Vec<f32> {
+        if self.gain != 1.0 {
+            samples.iter_mut().for_each(|sample| *sample *= self.gain);
+        }
+        samples
+    }
+}
+
+/// Collect `converted` for the cache, giving up once the clip is too long to
+/// cache.
+fn record(recording: &mut Option<ConvertedRecording>, converted: &[f32]) {
+    if let Some(collected) = recording {
+        if collected.samples.len() + converted.len() <= ClipCache::max_samples() {
+            collected.samples.extend_from_slice(converted);
+        } else {
+            *recording = None;
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use super::convert::FormatConverter;
+use super::decode::ClipDecoder;
+use realfft::RealFftPlanner;
+use std::path::Path;
+
+/// Rate clips are analysed at. The notes the chroma is built from are all
+/// well below its Nyquist frequency.
+const FINGERPRINT_SAMPLE_RATE: u32 = 11_025;
+/// Analysis frame and hop, about 190 ms and 46 ms.
+const FRAME_LEN: usize = 2048;
+const HOP_LEN: usize = 512;
+/// Only the start of long clips is fingerprinted, as Chromaprint does.
+const MAX_FINGERPRINT_SECONDS: u32 = 120;
+/// Range of the spectrum folded into the chroma.
+const MIN_CHROMA_HZ: f32 = 28.0;
+const MAX_CHROMA_HZ: f32 = 3520.0;
+/// Frames quieter than this are silence. Silence at either end is left
+/// out, so padding doesn't stop two copies of a clip from matching.
+const SILENCE_DBFS: f32 = -50.0;
+/// Fewest frames worth comparing, about half a second of sound.
+const MIN_FINGERPRINT_FRAMES: usize = 10;
+/// Furthest two fingerprints are slid against each other when comparing,
+/// about two seconds.
+const MAX_OFFSET_FRAMES: usize = 43;
+/// Share of the longer fingerprint that has to line up with the other.
+const MIN_OVERLAP: f32 = 0.8;
+
+/// Acoustic fingerprint of a file: one 32-bit code per frame describing how
+/// energy is spread across the twelve notes of the scale and how that
+/// changes, in the spirit of Chromaprint. It survives re-encoding, resampling
+/// and changes of level, unlike a hash of the file. `None` if the file is
+/// silent or too short to fingerprint.
+pub(crate) fn fingerprint_file(path: &Path) -> Result<Option<Vec<u32>>, String> {
+    let mut decoder = ClipDecoder::open_file(path)?;
+    let mut converter = FormatConverter::new(decoder.sample_rate, decoder.channels, FINGERPRINT_SAMPLE_RATE, 1)?;
+    let max_samples = (FINGERPRINT_SAMPLE_RATE * MAX_FINGERPRINT_SECONDS) as usize;
+    let mut samples = Vec::new();
+    while samples.len() < max_samples {
+        match decoder.next_chunk()? {
+            Some(chunk) => samples.extend(converter.process(&chunk)),
+            None => {
+                samples.extend(converter.flush());
+                break;
+            }
+        }
+    }
+    samples.truncate(max_samples);
+    fingerprint_samples(&samples)
+}
+
+/// How alike two fingerprints are, from about 0.5 for unrelated audio to 1
+/// for the same audio, at the best alignment within a couple of seconds.
+/// `None` if they're too different in length to be the same clip.
+pub(crate) fn fingerprint_similarity(a: &[u32], b: &[u32]) -> Option<f32> {
+    let min_overlap = ((a.len().max(b.len()) as f32 * MIN_OVERLAP) as usize).max(MIN_FINGERPRINT_FRAMES);
+    let mut best: Option<f32> = None;
+    for offset in -(MAX_OFFSET_FRAMES as isize)..=MAX_OFFSET_FRAMES as isize {
+        let (a_start, b_start) = if offset >= 0 {
+            (offset as usize, 0)
+        } else {
+            (0, offset.unsigned_abs())
+        };
+        if a_start >= a.len() || b_start >= b.len() {
+            continue;
+        }
+        let overlap = (a.len() - a_start).min(b.len() - b_start);
+        if overlap < min_overlap {
+            continue;
+        }
+        let errors: u32 = a[a_start..]
+            .iter()
+            .zip(&b[b_start..])
+            .map(|(x, y)| (x ^ y).count_ones())
+            .sum();
+        let similarity = 1.0 - errors as f32 / (overlap * 32) as f32;
+        best = Some(best.map_or(similarity, |best| best.max(similarity)));
+    }
+    best
+}
+
+fn fingerprint_samples(samples: &[f32]) -> Result<Option<Vec<u32>>, String> {
+    let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FRAME_LEN);
+    let window: Vec<f32> = (0..FRAME_LEN)
+        .map(|i| 0.5 - 0.5 * (std::f32::consts::PI * 2.0 * i as f32 / FRAME_LEN as f32).cos())
+        .collect();
+    // Note of the scale each FFT bin falls in, counting from A
+    let notes: Vec<Option<usize>> = (0..=FRAME_LEN / 2)
+        .map(|bin| {
+            let hz = bin as f32 * FINGERPRINT_SAMPLE_RATE as f32 / FRAME_LEN as f32;
+            (MIN_CHROMA_HZ..=MAX_CHROMA_HZ)
+                .contains(&hz)
+                .then(|| (12.0 * (hz / 440.0).log2()).round().rem_euclid(12.0) as usize)
+        })
+        .collect();
+    let mut input = fft.make_input_vec();
+    let mut spectrum = fft.make_output_vec();
+
+    // Chroma of each frame, or `None` where it is silent
+    let mut frames: Vec<Option<[f32; 12]>> = Vec::new();
+    let mut start = 0;
+    while start + FRAME_LEN <= samples.len() {
+        let frame = &samples[start..start + FRAME_LEN];
+        start += HOP_LEN;
+        let mean_square = frame.iter().map(|s| s * s).sum::<f32>() / FRAME_LEN as f32;
+        if 10.0 * mean_square.max(1e-12).log10() < SILENCE_DBFS {
+            frames.push(None);
+            continue;
+        }
+        for ((input, sample), weight) in input.iter_mut().zip(frame).zip(&window) {
+            *input = sample * weight;
+        }
+        fft.process(&mut input, &mut spectrum)
+            .map_err(|e| format!("Failed to analyse audio: {}", e))?;
+        let mut chroma = [0.0f32; 12];
+        for (value, note) in spectrum.iter().zip(&notes) {
+            if let Some(note) = note {
+                chroma[*note] += value.norm_sqr();
+            }
+        }
+        let norm = chroma.iter().map(|energy| energy * energy).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            chroma.iter_mut().for_each(|energy| *energy /= norm);
+        }
+        frames.push(Some(chroma));
+    }
+
+    let first = frames.iter().position(Option::is_some);
+    let last = frames.iter().rposition(Option::is_some);
+    let chroma: Vec<[f32; 12]> = match (first, last) {
+        (Some(first), Some(last)) => frames[first..=last].iter().map(|frame| frame.unwrap_or_default()).collect(),
+        _ => return Ok(None),
+    };
+    if chroma.len() < MIN_FINGERPRINT_FRAMES {
+        return Ok(None);
+    }
+    let codes = chroma
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| frame_code(frame, &chroma[index.saturating_sub(2)]))
+        .collect();
+    Ok(Some(codes))
+}
+
+/// 24 bits comparing each note with its neighbour and with the note a major
+/// third up, and 8 bits for whether the lower notes grew since `earlier`.
+fn frame_code(chroma: &[f32; 12], earlier: &[f32; 12]) -> u32 {
+    let mut code = 0u32;
+    for note in 0..12 {
+        code = code << 1 | (chroma[note] > chroma[(note + 1) % 12]) as u32;
+        code = code << 1 | (chroma[note] > chroma[(note + 4) % 12]) as u32;
+    }
+    for note in 0..8 {
+        code = code << 1 | (chroma[note] > earlier[note]) as u32;
+    }
+    code
+}
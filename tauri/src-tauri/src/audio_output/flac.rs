@@ -0,0 +1,406 @@
+use std::io::{Seek, SeekFrom, Write};
+
+/// Frames per FLAC frame; 4096 is what most encoders use at these rates.
+const BLOCK_FRAMES: usize = 4096;
+/// Highest Rice parameter codable with the 4-bit parameter field (15 is the escape).
+const MAX_RICE_PARAMETER: u32 = 14;
+/// Offset of the STREAMINFO body: "fLaC" plus the metadata block header.
+const STREAMINFO_OFFSET: u64 = 8;
+
+/// Minimal FLAC encoder for recording the mixdown. Each channel is coded
+/// independently with the best of the fixed predictors (orders 0-4) and a
+/// single Rice partition, falling back to verbatim samples when that is
+/// smaller. It compresses less than libFLAC but is lossless and needs no native
+/// library.
+pub(crate) struct FlacWriter<W: Write + Seek> {
+    out: W,
+    sample_rate: u32,
+    channels: usize,
+    bits_per_sample: u32,
+    /// Samples of the block being collected, one Vec per channel
+    block: Vec<Vec<i32>>,
+    frame_number: u64,
+    total_frames: u64,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    pub(super) fn new(mut out: W, sample_rate: u32, channels: u16, bits_per_sample: u32) -> Result<Self, String> {
+        if !(1..=8).contains(&channels) {
+            return Err(format!("FLAC supports 1-8 channels, got {}", channels));
+        }
+        out.write_all(b"fLaC").map_err(write_error)?;
+        // Last metadata block, type 0 (STREAMINFO), 34 bytes long
+        out.write_all(&[0x80, 0, 0, 34]).map_err(write_error)?;
+
+        let mut writer = Self {
+            out,
+            sample_rate,
+            channels: channels as usize,
+            bits_per_sample,
+            block: vec![Vec::with_capacity(BLOCK_FRAMES); channels as usize],
+            frame_number: 0,
+            total_frames: 0,
+        };
+        let info = writer.stream_info();
+        writer.out.write_all(&info).map_err(write_error)?;
+        Ok(writer)
+    }
+
+    /// Append interleaved samples in the -1.0..1.0 range.
+    pub(super) fn write_samples(&mut self, samples: &[f32]) -> Result<(), String> {
+        let scale = ((1i64 << (self.bits_per_sample - 1)) - 1) as f32;
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in self.block.iter_mut().zip(frame) {
+                channel.push((sample.clamp(-1.0, 1.0) * scale).round() as i32);
+            }
+            if self.block[0].len() == BLOCK_FRAMES {
+                self.write_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the final partial block and fill in the total length.
+    pub(super) fn finalize(mut self) -> Result<u64, String> {
+        if !self.block[0].is_empty() {
+            self.write_frame()?;
+        }
+        let info = self.stream_info();
+        self.out
+            .seek(SeekFrom::Start(STREAMINFO_OFFSET))
+            .and_then(|_| self.out.write_all(&info))
+            .and_then(|_| self.out.flush())
+            .map_err(write_error)?;
+        Ok(self.total_frames)
+    }
+
+    fn stream_info(&self) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        bits.write(BLOCK_FRAMES as u64, 16); // min block size
+        bits.write(BLOCK_FRAMES as u64, 16); // max block size
+        bits.write(0, 24); // min frame size (unknown)
+        bits.write(0, 24); // max frame size (unknown)
+        bits.write(self.sample_rate as u64, 20);
+        bits.write(self.channels as u64 - 1, 3);
+        bits.write(self.bits_per_sample as u64 - 1, 5);
+        bits.write(self.total_frames, 36);
+        // MD5 of the audio; all zeros means not computed
+        bits.write(0, 64);
+        bits.write(0, 64);
+        bits.into_bytes()
+    }
+
+    fn write_frame(&mut self) -> Result<(), String> {
+        let frames = self.block[0].len();
+        let mut bits = BitWriter::default();
+
+        bits.write(0b11_1111_1111_1110, 14); // sync code
+        bits.write(0, 1); // reserved
+        bits.write(0, 1); // fixed block size
+        let block_size_code = if frames == BLOCK_FRAMES { 0b1100 } else { 0b0111 };
+        bits.write(block_size_code, 4);
+        bits.write(0, 4); // sample rate from STREAMINFO
+        bits.write(self.channels as u64 - 1, 4); // independent channels
+        let sample_size_code = match self.bits_per_sample {
+            8 => 0b001,
+            12 => 0b010,
+            16 => 0b100,
+            20 => 0b101,
+            24 => 0b110,
+            _ => 0b000,
+        };
+        bits.write(sample_size_code, 3);
+        bits.write(0, 1); // reserved
+        bits.write_utf8(self.frame_number);
+        if block_size_code == 0b0111 {
+            bits.write(frames as u64 - 1, 16);
+        }
+        let crc = crc8(&bits.bytes);
+        bits.write(crc as u64, 8);
+
+        for channel in &self.block {
+            write_subframe(&mut bits, channel, self.bits_per_sample);
+        }
+        bits.align();
+        let crc = crc16(&bits.bytes);
+        bits.write(crc as u64, 16);
+
+        self.out.write_all(&bits.into_bytes()).map_err(write_error)?;
+        self.frame_number += 1;
+        self.total_frames += frames as u64;
+        for channel in &mut self.block {
+            channel.clear();
+        }
+        Ok(())
+    }
+}
+
+fn write_error(e: std::io::Error) -> String {
+    format!("Failed to write FLAC: {}", e)
+}
+
+/// Code one channel of a block as a FIXED subframe, or VERBATIM if prediction
+/// doesn't help (e.g. for noise).
+fn write_subframe(bits: &mut BitWriter, samples: &[i32], bits_per_sample: u32) {
+    let max_order = 4.min(samples.len().saturating_sub(1));
+    let (order, residuals) = (0..=max_order)
+        .map(|order| (order, fixed_residuals(samples, order)))
+        .min_by_key(|(_, residuals)| residuals.iter().map(|r| r.unsigned_abs()).sum::<u64>())
+        .unwrap_or((0, Vec::new()));
+    let (rice_parameter, rice_bits) = best_rice_parameter(&residuals);
+
+    let verbatim_bits = samples.len() as u64 * bits_per_sample as u64;
+    let fixed_bits = order as u64 * bits_per_sample as u64 + 2 + 4 + 4 + rice_bits;
+    if fixed_bits >= verbatim_bits {
+        bits.write(0b0000_0010, 8); // VERBATIM, no wasted bits
+        for sample in samples {
+            bits.write_signed(*sample as i64, bits_per_sample);
+        }
+        return;
+    }
+
+    bits.write(0b0001_0000 | (order as u64) << 1, 8); // FIXED of `order`, no wasted bits
+    for sample in &samples[..order] {
+        bits.write_signed(*sample as i64, bits_per_sample);
+    }
+    bits.write(0, 2); // Rice coding with 4-bit parameters
+    bits.write(0, 4); // a single partition
+    bits.write(rice_parameter as u64, 4);
+    for residual in residuals {
+        let folded = ((residual << 1) ^ (residual >> 63)) as u64;
+        bits.write_unary(folded >> rice_parameter);
+        bits.write(folded, rice_parameter);
+    }
+}
+
+/// Prediction error of the fixed polynomial predictor of `order`.
+fn fixed_residuals(samples: &[i32], order: usize) -> Vec<i64> {
+    let x = |i: usize| samples[i] as i64;
+    (order..samples.len())
+        .map(|i| match order {
+            0 => x(i),
+            1 => x(i) - x(i - 1),
+            2 => x(i) - 2 * x(i - 1) + x(i - 2),
+            3 => x(i) - 3 * x(i - 1) + 3 * x(i - 2) - x(i - 3),
+            _ => x(i) - 4 * x(i - 1) + 6 * x(i - 2) - 4 * x(i - 3) + x(i - 4),
+        })
+        .collect()
+}
+
+/// Rice parameter that codes `residuals` in the fewest bits, and that size.
+fn best_rice_parameter(residuals: &[i64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|parameter| {
+            let size = residuals
+                .iter()
+                .map(|residual| {
+                    let folded = ((residual << 1) ^ (residual >> 63)) as u64;
+                    (folded >> parameter) + 1 + parameter as u64
+                })
+                .sum::<u64>();
+            (parameter, size)
+        })
+        .min_by_key(|(_, size)| *size)
+        .unwrap_or((0, 0))
+}
+
+/// MSB-first bit packer.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    filled: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        for bit in (0..bits).rev() {
+            self.push_bit((value >> bit) & 1 == 1);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64 & ((1u64 << bits) - 1), bits);
+    }
+
+    /// `count` zeros followed by a one.
+    fn write_unary(&mut self, count: u64) {
+        for _ in 0..count {
+            self.push_bit(false);
+        }
+        self.push_bit(true);
+    }
+
+    /// Frame number in FLAC's extended UTF-8 coding.
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+        let continuation_bytes = match value {
+            0..=0x7ff => 1,
+            0x800..=0xffff => 2,
+            0x1_0000..=0x1f_ffff => 3,
+            0x20_0000..=0x3ff_ffff => 4,
+            _ => 5,
+        };
+        let first_bits = 6 - continuation_bytes;
+        let prefix = !(0xffu64 >> (continuation_bytes + 1)) & 0xff;
+        self.write(prefix | (value >> (6 * continuation_bytes)) & ((1 << first_bits) - 1), 8);
+        for byte in (0..continuation_bytes).rev() {
+            self.write(0x80 | (value >> (6 * byte)) & 0x3f, 8);
+        }
+    }
+
+    fn push_bit(&mut self, bit: bool) {
+        self.current = (self.current << 1) | bit as u8;
+        self.filled += 1;
+        if self.filled == 8 {
+            self.bytes.push(self.current);
+            self.current = 0;
+            self.filled = 0;
+        }
+    }
+
+    /// Zero-pad to the next byte boundary.
+    fn align(&mut self) {
+        while self.filled != 0 {
+            self.push_bit(false);
+        }
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, byte| {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    fn encode(samples: &[f32], sample_rate: u32, channels: u16, bits_per_sample: u32) -> Vec<u8> {
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = FlacWriter::new(&mut out, sample_rate, channels, bits_per_sample).unwrap();
+        writer.write_samples(samples).unwrap();
+        let frames = writer.finalize().unwrap();
+        assert_eq!(frames, (samples.len() / channels as usize) as u64);
+        out.into_inner()
+    }
+
+    /// Interleaved samples as the integers the writer stored, with the
+    /// stream's sample rate and channel count.
+    fn decode(bytes: Vec<u8>, bits_per_sample: u32) -> (Vec<i32>, u32, usize) {
+        let stream = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension("flac");
+        let mut format = symphonia::default::get_probe()
+            .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+            .unwrap()
+            .format;
+        let params = format.default_track().unwrap().codec_params.clone();
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .unwrap();
+        let mut samples = Vec::new();
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(_)) => break,
+                Err(e) => panic!("{}", e),
+            };
+            let decoded = decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<i32>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            // Decoded samples are scaled up to fill an i32
+            samples.extend(buffer.samples().iter().map(|sample| sample >> (32 - bits_per_sample)));
+        }
+        (samples, params.sample_rate.unwrap(), params.channels.unwrap().count())
+    }
+
+    fn quantize(samples: &[f32], bits_per_sample: u32) -> Vec<i32> {
+        let scale = ((1i64 << (bits_per_sample - 1)) - 1) as f32;
+        samples
+            .iter()
+            .map(|sample| (sample.clamp(-1.0, 1.0) * scale).round() as i32)
+            .collect()
+    }
+
+    fn sine(frames: usize, channels: usize) -> Vec<f32> {
+        (0..frames * channels)
+            .map(|i| {
+                let (frame, channel) = (i / channels, i % channels);
+                0.8 * (frame as f32 * 0.05 * (channel + 1) as f32).sin()
+            })
+            .collect()
+    }
+
+    /// Full-scale white noise from a fixed seed.
+    fn noise(len: usize) -> Vec<f32> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+            })
+            .collect()
+    }
+
+    fn assert_round_trip(samples: &[f32], sample_rate: u32, channels: u16, bits_per_sample: u32) {
+        let bytes = encode(samples, sample_rate, channels, bits_per_sample);
+        let (decoded, decoded_rate, decoded_channels) = decode(bytes, bits_per_sample);
+        assert_eq!(decoded_rate, sample_rate);
+        assert_eq!(decoded_channels, channels as usize);
+        assert_eq!(decoded, quantize(samples, bits_per_sample));
+    }
+
+    #[test]
+    fn round_trips_a_short_sine() {
+        assert_round_trip(&sine(1000, 1), 48_000, 1, 16);
+    }
+
+    #[test]
+    fn round_trips_a_full_block_and_a_partial_one() {
+        assert_round_trip(&sine(BLOCK_FRAMES + 123, 2), 44_100, 2, 16);
+    }
+
+    #[test]
+    fn round_trips_noise_as_verbatim() {
+        let samples = noise(2 * 1500);
+        let mut bits = BitWriter::default();
+        write_subframe(&mut bits, &quantize(&samples[..1500], 24), 24);
+        assert_eq!(bits.bytes[0], 0b0000_0010, "noise should be coded VERBATIM");
+
+        assert_round_trip(&samples, 48_000, 2, 24);
+    }
+}
@@ -0,0 +1,166 @@
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::Duration;
+use symphonia::core::io::MediaSource;
+
+/// Bytes fetched per range request.
+const HTTP_BLOCK_BYTES: u64 = 256 * 1024;
+
+/// A remote file read over HTTP(S). When the server supports range requests the
+/// file is fetched a block at a time and can be seeked, so decoding starts after
+/// the first block instead of the whole download. Otherwise the response body is
+/// streamed front to back.
+pub(super) struct HttpSource {
+    client: Client,
+    url: String,
+    len: Option<u64>,
+    position: u64,
+    /// Start offset and contents of the most recently fetched block
+    block_start: u64,
+    block: Vec<u8>,
+    /// Body of the initial response when the server can't serve ranges
+    stream: Option<Response>,
+}
+
+impl HttpSource {
+    pub(super) fn open(url: &str) -> Result<Self, String> {
+        // No overall timeout: without range support the body is read for as long
+        // as the clip plays
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(None)
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+        // Ask for the first block; a 206 tells us ranges work and gives the length
+        let response = client
+            .get(url)
+            .header(RANGE, format!("bytes=0-{}", HTTP_BLOCK_BYTES - 1))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Failed to fetch {}: {}", url, e))?;
+
+        let mut source = Self {
+            client,
+            url: url.to_string(),
+            len: None,
+            position: 0,
+            block_start: 0,
+            block: Vec::new(),
+            stream: None,
+        };
+
+        let supports_ranges = response.status() == StatusCode::PARTIAL_CONTENT;
+        if supports_ranges {
+            source.len = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit('/').next())
+                .and_then(|total| total.parse().ok());
+            source.block = response
+                .bytes()
+                .map_err(|e| format!("Failed to read {}: {}", url, e))?
+                .to_vec();
+        } else {
+            source.len = response
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok());
+            source.stream = Some(response);
+        }
+
+        eprintln!(
+            "HttpSource: {} ({:?} bytes, range requests: {})",
+            url, source.len, supports_ranges
+        );
+        Ok(source)
+    }
+
+    fn fetch_block(&mut self, start: u64) -> io::Result<()> {
+        let end = start + HTTP_BLOCK_BYTES - 1;
+        let response = self
+            .client
+            .get(&self.url)
+            .header(RANGE, format!("bytes={}-{}", start, end))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(io::Error::other)?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(io::Error::other("Server stopped honouring range requests"));
+        }
+
+        self.block = response
+            .bytes()
+            .map_err(io::Error::other)?
+            .to_vec();
+        self.block_start = start;
+        Ok(())
+    }
+}
+
+impl Read for HttpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(stream) = &mut self.stream {
+            let read = stream.read(buf)?;
+            self.position += read as u64;
+            return Ok(read);
+        }
+
+        if self.len.map(|len| self.position >= len).unwrap_or(false) {
+            return Ok(0);
+        }
+        let block_end = self.block_start + self.block.len() as u64;
+        if self.position < self.block_start || self.position >= block_end {
+            self.fetch_block(self.position)?;
+        }
+
+        let offset = (self.position - self.block_start) as usize;
+        let available = &self.block[offset.min(self.block.len())..];
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for HttpSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::Current(offset) => self.position as i64 + offset,
+            SeekFrom::End(offset) => match self.len {
+                Some(len) => len as i64 + offset,
+                None => {
+                    return Err(io::Error::new(io::ErrorKind::Unsupported, "Stream length is unknown"))
+                }
+            },
+        };
+        if target < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of stream"));
+        }
+
+        // Without range support only no-op seeks can be honoured
+        if self.stream.is_some() && target as u64 != self.position {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Server does not support range requests",
+            ));
+        }
+        self.position = target as u64;
+        Ok(self.position)
+    }
+}
+
+impl MediaSource for HttpSource {
+    fn is_seekable(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.len
+    }
+}
@@ -0,0 +1,168 @@
+use super::convert::FormatConverter;
+use super::mixer::Voice;
+use super::{AtomicGain, GainRamp, PAUSE_FADE_MS};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::Arc;
+
+/// Converted input buffers queued for a mixer before new ones are dropped.
+const LIVE_INPUT_BUFFER_CHUNKS: usize = 64;
+
+/// Input held back before playing starts, and again after running dry, so
+/// small timing differences between the two devices don't cause dropouts.
+const LIVE_INPUT_PREBUFFER_MS: u32 = 20;
+
+/// Most input buffered before the oldest is skipped. The input and output
+/// clocks drift apart over time, and without a cap the delay would keep
+/// growing.
+const LIVE_INPUT_MAX_BUFFER_MS: u32 = 80;
+
+/// Mixer buffers queued for an output tap before new ones are dropped.
+const OUTPUT_TAP_BUFFER_CHUNKS: usize = 64;
+
+/// Gain and stop switch shared by every mixer a live input is routed to.
+pub(crate) struct LiveInputControl {
+    gain: AtomicGain,
+    stop: AtomicBool,
+}
+
+impl LiveInputControl {
+    pub(crate) fn new(gain: f32) -> Self {
+        Self {
+            gain: AtomicGain::new(gain),
+            stop: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn set_gain(&self, gain: f32) {
+        self.gain.set(gain);
+    }
+
+    /// Fade the input out of every mix it is in.
+    pub(crate) fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The capture side of a live input routed to one device mixer. Converts to
+/// the device's format and hands buffers over without blocking.
+pub(crate) struct LiveInputFeed {
+    converter: FormatConverter,
+    tx: mpsc::SyncSender<Vec<f32>>,
+}
+
+impl LiveInputFeed {
+    /// Returns false once the mixer has let go of the input.
+    pub(crate) fn push(&mut self, samples: &[f32]) -> bool {
+        let converted = self.converter.process(samples);
+        if converted.is_empty() {
+            return true;
+        }
+        match self.tx.try_send(converted) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// A live copy of everything a device mixer plays, such as the reference an
+/// echo canceller needs. The mixer stops sending once `rx` is dropped.
+pub(crate) struct OutputTap {
+    pub(crate) rx: mpsc::Receiver<Vec<f32>>,
+    pub(crate) sample_rate: u32,
+    pub(crate) channels: u16,
+}
+
+/// Connect a tap for a mixer at `sample_rate` and `channels`. The sender goes
+/// to the mixer.
+pub(super) fn output_tap(sample_rate: u32, channels: u16) -> (mpsc::SyncSender<Vec<f32>>, OutputTap) {
+    let (tx, rx) = mpsc::sync_channel(OUTPUT_TAP_BUFFER_CHUNKS);
+    let tap = OutputTap {
+        rx,
+        sample_rate,
+        channels,
+    };
+    (tx, tap)
+}
+
+/// Plays live input into a device mix as it arrives.
+pub(super) struct LiveInputVoice {
+    rx: mpsc::Receiver<Vec<f32>>,
+    buffer: VecDeque<f32>,
+    control: Arc<LiveInputControl>,
+    ramp: GainRamp,
+    channels: usize,
+    prebuffer: usize,
+    max_buffer: usize,
+    /// False until `prebuffer` samples have arrived
+    playing: bool,
+    disconnected: bool,
+}
+
+/// Connect a voice and a feed for a mixer at `sample_rate` and `channels`.
+pub(super) fn live_input(
+    source_rate: u32,
+    source_channels: u16,
+    sample_rate: u32,
+    channels: u16,
+    control: Arc<LiveInputControl>,
+) -> Result<(LiveInputVoice, LiveInputFeed), String> {
+    let converter = FormatConverter::new(source_rate, source_channels, sample_rate, channels)?;
+    let (tx, rx) = mpsc::sync_channel(LIVE_INPUT_BUFFER_CHUNKS);
+    let channels = channels.max(1) as usize;
+    let samples_for = |ms: u32| (ms as usize * sample_rate as usize / 1000).max(1) * channels;
+    let voice = LiveInputVoice {
+        rx,
+        buffer: VecDeque::new(),
+        ramp: GainRamp::new(0.0, PAUSE_FADE_MS, sample_rate),
+        control,
+        channels,
+        prebuffer: samples_for(LIVE_INPUT_PREBUFFER_MS),
+        max_buffer: samples_for(LIVE_INPUT_MAX_BUFFER_MS),
+        playing: false,
+        disconnected: false,
+    };
+    Ok((voice, LiveInputFeed { converter, tx }))
+}
+
+impl Voice for LiveInputVoice {
+    fn mix(&mut self, out: &mut [f32]) -> bool {
+        loop {
+            match self.rx.try_recv() {
+                Ok(chunk) => self.buffer.extend(chunk),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.disconnected = true;
+                    break;
+                }
+            }
+        }
+        if self.buffer.len() > self.max_buffer {
+            // Catch up to the prebuffer level, keeping whole frames
+            let excess = self.buffer.len() - self.prebuffer;
+            self.buffer.drain(..excess - excess % self.channels);
+        }
+        if !self.playing && self.buffer.len() >= self.prebuffer {
+            self.playing = true;
+        }
+
+        let stopping = self.control.stop.load(Ordering::Relaxed) || self.disconnected;
+        let target = if stopping { 0.0 } else { self.control.gain.get() };
+        let mut gain = self.ramp.gain;
+        for frame in out.chunks_mut(self.channels) {
+            if !self.playing || self.buffer.len() < self.channels {
+                // Ran dry; fade back in once the prebuffer has refilled
+                self.playing = false;
+                self.ramp.gain = 0.0;
+                break;
+            }
+            gain = self.ramp.next(target);
+            for (sample, input) in frame.iter_mut().zip(self.buffer.drain(..self.channels)) {
+                *sample += input * gain;
+            }
+        }
+
+        !(stopping && (gain == 0.0 || !self.playing))
+    }
+}
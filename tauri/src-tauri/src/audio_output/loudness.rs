@@ -0,0 +1,173 @@
+use super::decode::ClipDecoder;
+use std::path::Path;
+
+/// Gating block length and hop for integrated loudness (ITU-R BS.1770).
+const BLOCK_MS: u64 = 400;
+const SUB_BLOCK_MS: u64 = 100;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Measures the integrated loudness of a clip in LUFS, as used by EBU R128. Feed
+/// it the whole clip, then call `integrated`.
+pub(super) struct LoudnessMeter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<[KWeightStage; 2]>,
+    sub_block_frames: usize,
+    /// Frames and per-channel sum of squares in the sub-block being filled
+    frames_in_sub_block: usize,
+    sums: Vec<f64>,
+    /// Channel-weighted mean square of every finished 100 ms sub-block
+    sub_blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub(super) fn new(sample_rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        let stages = [
+            KWeightStage::high_shelf(sample_rate as f64),
+            KWeightStage::high_pass(sample_rate as f64),
+        ];
+        Self {
+            channels,
+            weights: channel_weights(channels),
+            filters: vec![stages; channels],
+            sub_block_frames: (sample_rate as u64 * SUB_BLOCK_MS / 1000).max(1) as usize,
+            frames_in_sub_block: 0,
+            sums: vec![0.0; channels],
+            sub_blocks: Vec::new(),
+        }
+    }
+
+    pub(super) fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for ((sample, stages), sum) in frame.iter().zip(&mut self.filters).zip(&mut self.sums) {
+                let mut value = *sample as f64;
+                for stage in stages.iter_mut() {
+                    value = stage.process(value);
+                }
+                *sum += value * value;
+            }
+
+            self.frames_in_sub_block += 1;
+            if self.frames_in_sub_block == self.sub_block_frames {
+                let power = self
+                    .sums
+                    .iter()
+                    .zip(&self.weights)
+                    .map(|(sum, weight)| weight * sum / self.sub_block_frames as f64)
+                    .sum();
+                self.sub_blocks.push(power);
+                self.sums.iter_mut().for_each(|sum| *sum = 0.0);
+                self.frames_in_sub_block = 0;
+            }
+        }
+    }
+
+    /// Gated integrated loudness, or `None` if the clip is too short or silent.
+    pub(super) fn integrated(&self) -> Option<f64> {
+        let per_block = (BLOCK_MS / SUB_BLOCK_MS) as usize;
+        let blocks: Vec<f64> = self
+            .sub_blocks
+            .windows(per_block)
+            .map(|window| window.iter().sum::<f64>() / per_block as f64)
+            .filter(|power| loudness(*power) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if blocks.is_empty() {
+            return None;
+        }
+
+        let relative_gate = loudness(mean(&blocks)) + RELATIVE_GATE_LU;
+        let gated: Vec<f64> = blocks
+            .into_iter()
+            .filter(|power| loudness(*power) > relative_gate)
+            .collect();
+        if gated.is_empty() {
+            return None;
+        }
+        Some(loudness(mean(&gated)))
+    }
+}
+
+/// Integrated loudness of a whole clip, decoded separately from playback.
+pub(super) fn measure_clip(mut decoder: ClipDecoder) -> Result<Option<f64>, String> {
+    let mut meter = LoudnessMeter::new(decoder.sample_rate, decoder.channels);
+    while let Some(chunk) = decoder.next_chunk()? {
+        meter.push(&chunk);
+    }
+    Ok(meter.integrated())
+}
+
+/// Integrated loudness of a file in LUFS, or `None` if it is silent or
+/// too short to measure.
+pub(crate) fn measure_file_loudness(path: &Path) -> Result<Option<f64>, String> {
+    measure_clip(ClipDecoder::open_file(path)?)
+}
+
+fn loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.max(1e-20).log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// BS.1770 channel weights for the default WAVE layouts: surrounds count 1.41x
+/// and the LFE channel of 5.1 and up is ignored.
+fn channel_weights(channels: usize) -> Vec<f64> {
+    (0..channels)
+        .map(|channel| match (channels, channel) {
+            (4, 2..) | (5, 3..) | (6.., 4..) => 1.41,
+            (6.., 3) => 0.0,
+            _ => 1.0,
+        })
+        .collect()
+}
+
+/// One stage of the K-weighting pre-filter, with coefficients computed for the
+/// clip's sample rate.
+#[derive(Clone, Copy)]
+struct KWeightStage {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl KWeightStage {
+    /// Head-related high shelf, about +4 dB above 1.5 kHz.
+    fn high_shelf(sample_rate: f64) -> Self {
+        let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Self {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    /// RLB high-pass at about 38 Hz.
+    fn high_pass(sample_rate: f64) -> Self {
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Self {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.z[0];
+        self.z[0] = self.b[1] * input - self.a[0] * output + self.z[1];
+        self.z[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+}
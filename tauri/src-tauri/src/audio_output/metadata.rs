@@ -0,0 +1,129 @@
+use std::path::Path;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, Value};
+use symphonia::core::probe::Hint;
+
+/// Details of an audio file, read from its headers without decoding it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AudioMetadata {
+    /// Short codec name such as "mp3" or "pcm_s16le"
+    pub codec: String,
+    /// Human-readable codec name
+    pub codec_name: String,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub bits_per_sample: Option<u32>,
+    /// Missing when the container doesn't record the length
+    pub duration_ms: Option<u64>,
+    /// Average over the whole file, including container overhead
+    pub bitrate_kbps: Option<u32>,
+    pub size_bytes: u64,
+    pub tags: Vec<AudioTag>,
+}
+
+/// A tag embedded in an audio file, e.g. from ID3 or Vorbis comments.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AudioTag {
+    /// Key as written in the file
+    pub key: String,
+    /// The same key in a format-independent form such as "track_title",
+    /// when it is a well-known one
+    pub standard_key: Option<String>,
+    pub value: String,
+}
+
+pub(crate) fn read_metadata(path: &Path) -> Result<AudioMetadata, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let size_bytes = file
+        .metadata()
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .len();
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut probed = symphonia::default::get_probe()
+        .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Failed to probe audio: {}", e))?;
+
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != symphonia::core::codecs::CODEC_TYPE_NULL)
+        .ok_or_else(|| "No audio track found".to_string())?;
+    let params = &track.codec_params;
+    let (codec, codec_name) = match symphonia::default::get_codecs().get_codec(params.codec) {
+        Some(descriptor) => (descriptor.short_name.to_string(), descriptor.long_name.to_string()),
+        None => ("unknown".to_string(), "Unknown".to_string()),
+    };
+    let duration_ms = match (params.n_frames, params.sample_rate) {
+        (Some(frames), Some(sample_rate)) if sample_rate > 0 => Some(frames * 1000 / sample_rate as u64),
+        _ => None,
+    };
+    let bitrate_kbps = duration_ms
+        .filter(|&ms| ms > 0)
+        .map(|ms| (size_bytes * 8 / ms) as u32);
+
+    let mut metadata = AudioMetadata {
+        codec,
+        codec_name,
+        sample_rate: params.sample_rate,
+        channels: params.channels.map(|channels| channels.count() as u16),
+        bits_per_sample: params.bits_per_sample,
+        duration_ms,
+        bitrate_kbps,
+        size_bytes,
+        tags: Vec::new(),
+    };
+
+    // Tags can be ahead of the container, like ID3v2, or inside it, like
+    // Vorbis comments; take both
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|log| log.current()) {
+        add_tags(&mut metadata.tags, revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        add_tags(&mut metadata.tags, revision);
+    }
+    Ok(metadata)
+}
+
+fn add_tags(tags: &mut Vec<AudioTag>, revision: &MetadataRevision) {
+    for tag in revision.tags() {
+        // Cover art and other binary blobs aren't useful as text
+        if let Value::Binary(_) = tag.value {
+            continue;
+        }
+        // RIFF INFO strings keep their NUL terminator
+        let value = tag.value.to_string();
+        let value = value.trim_end_matches('\0').trim();
+        if value.is_empty() {
+            continue;
+        }
+        tags.push(AudioTag {
+            key: tag.key.clone(),
+            standard_key: tag.std_key.map(|key| snake_case(&format!("{:?}", key))),
+            value: value.to_string(),
+        });
+    }
+}
+
+/// "TrackTitle" -> "track_title"
+fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
@@ -0,0 +1,56 @@
+use std::sync::Mutex;
+
+/// Per-channel levels accumulated by a stream callback until the meter reads
+/// them. Used for output mixers and input captures alike.
+pub(crate) struct LevelMeter(Mutex<LevelAccumulator>);
+
+struct LevelAccumulator {
+    peak: Vec<f32>,
+    sum_squares: Vec<f64>,
+    frames: usize,
+}
+
+impl LevelMeter {
+    pub(crate) fn new(channels: usize) -> Self {
+        Self(Mutex::new(LevelAccumulator {
+            peak: vec![0.0; channels],
+            sum_squares: vec![0.0; channels],
+            frames: 0,
+        }))
+    }
+
+    pub(crate) fn record(&self, data: &[f32], channels: usize) {
+        // Never block the audio thread; a skipped buffer just isn't metered
+        let mut levels = match self.0.try_lock() {
+            Ok(levels) => levels,
+            Err(_) => return,
+        };
+        let levels = &mut *levels;
+        for frame in data.chunks_exact(channels) {
+            for ((sample, peak), sum) in frame.iter().zip(&mut levels.peak).zip(&mut levels.sum_squares) {
+                *peak = peak.max(sample.abs());
+                *sum += (*sample as f64) * (*sample as f64);
+            }
+        }
+        levels.frames += data.len() / channels;
+    }
+
+    pub(crate) fn take(&self) -> Option<(Vec<f32>, Vec<f32>)> {
+        let mut levels = self.0.lock().unwrap();
+        if levels.frames == 0 {
+            return None;
+        }
+        let frames = levels.frames as f64;
+        let rms = levels
+            .sum_squares
+            .iter()
+            .map(|sum| (sum / frames).sqrt() as f32)
+            .collect();
+        let peak = levels.peak.clone();
+
+        levels.peak.iter_mut().for_each(|peak| *peak = 0.0);
+        levels.sum_squares.iter_mut().for_each(|sum| *sum = 0.0);
+        levels.frames = 0;
+        Some((peak, rms))
+    }
+}
@@ -0,0 +1,711 @@
+use super::meter::LevelMeter;
+use super::record::RecordingTap;
+use super::{
+    AtomicGain, DeviceRender, GainRamp, PlaybackOptions, SessionControl, StreamProgress, DEVICE_SWAP_FADE_MS,
+    PAUSE_FADE_MS, PREVIEW_SEEK_FADE_MS,
+};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{
+    BufferSize, Device, FromSample, SampleFormat, SizedSample, Stream, StreamConfig, SupportedStreamConfig,
+};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{TryRecvError, TrySendError};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+/// A source that is summed into a device mix.
+pub(super) trait Voice: Send {
+    /// Add the next `out.len()` interleaved samples onto `out`. Returns false once
+    /// the voice has nothing left to play and can be dropped.
+    fn mix(&mut self, out: &mut [f32]) -> bool;
+
+    /// Called when a buffer this voice was mixed into went over full scale.
+    fn report_clipping(&self, _peak: f32) {}
+
+    /// Whether the other voices of the mix are lowered while this one plays.
+    fn ducks_others(&self) -> bool {
+        false
+    }
+}
+
+/// One persistent output stream per device. Every clip routed to the device is
+/// added as a voice and summed into this stream, so overlapping clips never
+/// compete for the device.
+pub(super) struct DeviceMixer {
+    pub(super) sample_rate: u32,
+    pub(super) channels: u16,
+    voice_tx: mpsc::Sender<Box<dyn Voice>>,
+    latency: Arc<OutputLatency>,
+    levels: Arc<LevelMeter>,
+    recorder: Arc<Mutex<Option<RecordingTap>>>,
+    output_taps: Arc<Mutex<Vec<mpsc::SyncSender<Vec<f32>>>>>,
+    fault: Arc<StreamFault>,
+    // Dropping the sender closes the stream
+    _stop_tx: mpsc::Sender<()>,
+}
+
+impl DeviceMixer {
+    /// Open `device` with `config` and start mixing on a dedicated thread.
+    pub(super) fn open(
+        device: Device,
+        config: SupportedStreamConfig,
+        buffer_size: BufferSize,
+        render: DeviceRender,
+    ) -> Result<Self, String> {
+        let device_name = device.name().unwrap_or_else(|_| "unknown".to_string());
+        let sample_rate = config.sample_rate().0;
+        let channels = config.channels();
+        let sample_format = config.sample_format();
+        eprintln!(
+            "DeviceMixer::open: {} - {}Hz, {} channels, format: {:?}, buffer: {:?}",
+            device_name, sample_rate, channels, sample_format, buffer_size
+        );
+
+        let (mut bus, voice_tx) = MixBus::new(channels, render);
+        let levels = bus.levels.clone();
+        let recorder = bus.recorder.clone();
+        let output_taps = bus.output_taps.clone();
+        let latency = Arc::new(OutputLatency::default());
+        let callback_latency = latency.clone();
+        let stream_config = StreamConfig {
+            channels,
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size,
+        };
+
+        let fault = Arc::new(StreamFault::default());
+        let stop_tx = spawn_stream_thread(
+            device,
+            device_name,
+            stream_config,
+            sample_format,
+            fault.clone(),
+            move |data, delay| {
+                bus.fill(data);
+                callback_latency.record(delay);
+            },
+        )?;
+
+        Ok(Self {
+            sample_rate,
+            channels,
+            voice_tx,
+            latency,
+            levels,
+            recorder,
+            output_taps,
+            fault,
+            _stop_tx: stop_tx,
+        })
+    }
+
+    /// Open a WASAPI endpoint in exclusive mode instead of going through cpal.
+    #[cfg(target_os = "windows")]
+    pub(super) fn open_exclusive(
+        endpoint_id: &str,
+        sample_rate: u32,
+        channels: u16,
+        buffer_frames: Option<u32>,
+        render: DeviceRender,
+    ) -> Result<Self, String> {
+        let (mut bus, voice_tx) = MixBus::new(channels, render);
+        let levels = bus.levels.clone();
+        let recorder = bus.recorder.clone();
+        let output_taps = bus.output_taps.clone();
+        let latency = Arc::new(OutputLatency::default());
+        let callback_latency = latency.clone();
+        let fault = Arc::new(StreamFault::default());
+        let stop_tx = super::wasapi_exclusive::spawn_exclusive_stream(
+            endpoint_id.to_string(),
+            sample_rate,
+            channels,
+            buffer_frames,
+            fault.clone(),
+            move |data, delay| {
+                bus.fill(data);
+                callback_latency.record(delay);
+            },
+        )?;
+
+        Ok(Self {
+            sample_rate,
+            channels,
+            voice_tx,
+            latency,
+            levels,
+            recorder,
+            output_taps,
+            fault,
+            _stop_tx: stop_tx,
+        })
+    }
+
+    /// Estimated time from a buffer being filled to it reaching the speakers,
+    /// once the stream has reported it.
+    pub(super) fn output_latency(&self) -> Option<Duration> {
+        self.latency.get()
+    }
+
+    /// Per-channel (peak, RMS) of everything played since the last call, or
+    /// `None` if the stream hasn't run since.
+    pub(super) fn take_levels(&self) -> Option<(Vec<f32>, Vec<f32>)> {
+        self.levels.take()
+    }
+
+    /// Copy everything this mixer plays to `tap`, or stop copying with `None`.
+    pub(super) fn set_recording_tap(&self, tap: Option<RecordingTap>) {
+        *self.recorder.lock().unwrap() = tap;
+    }
+
+    /// Send a copy of every buffer this mixer plays to `tx` until its receiver
+    /// is dropped.
+    pub(super) fn add_output_tap(&self, tx: mpsc::SyncSender<Vec<f32>>) {
+        self.output_taps.lock().unwrap().push(tx);
+    }
+
+    /// Why the stream stopped, if the device went away under it.
+    pub(super) fn fault(&self) -> Option<String> {
+        self.fault.get()
+    }
+
+    /// Start mixing `voice` into this device's output.
+    pub(super) fn add_voice(&self, voice: Box<dyn Voice>) -> Result<(), String> {
+        self.voice_tx
+            .send(voice)
+            .map_err(|_| "Device mixer is no longer running".to_string())
+    }
+}
+
+/// Latest output latency reported by a stream callback, in microseconds (0 until
+/// the first report).
+#[derive(Default)]
+struct OutputLatency(AtomicU64);
+
+impl OutputLatency {
+    fn record(&self, latency: Option<Duration>) {
+        if let Some(latency) = latency {
+            self.0.store((latency.as_micros() as u64).max(1), Ordering::Relaxed);
+        }
+    }
+
+    fn get(&self) -> Option<Duration> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+}
+
+/// The error that killed a stream, recorded from the host's error callback.
+#[derive(Default)]
+pub(super) struct StreamFault(Mutex<Option<String>>);
+
+impl StreamFault {
+    /// Record `error` unless an earlier one already was.
+    pub(super) fn set(&self, error: String) {
+        self.0.lock().unwrap().get_or_insert(error);
+    }
+
+    fn get(&self) -> Option<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Render state owned by a mixer's stream callback.
+struct MixBus {
+    voices: Vec<Box<dyn Voice>>,
+    incoming: mpsc::Receiver<Box<dyn Voice>>,
+    channels: usize,
+    render: DeviceRender,
+    levels: Arc<LevelMeter>,
+    recorder: Arc<Mutex<Option<RecordingTap>>>,
+    output_taps: Arc<Mutex<Vec<mpsc::SyncSender<Vec<f32>>>>>,
+    /// Voices that duck under others are mixed here first
+    ducked: Vec<f32>,
+}
+
+impl MixBus {
+    fn new(channels: u16, render: DeviceRender) -> (Self, mpsc::Sender<Box<dyn Voice>>) {
+        let (voice_tx, voice_rx) = mpsc::channel();
+        let channels = channels.max(1) as usize;
+        let bus = Self {
+            voices: Vec::new(),
+            incoming: voice_rx,
+            channels,
+            render,
+            levels: Arc::new(LevelMeter::new(channels)),
+            recorder: Arc::new(Mutex::new(None)),
+            output_taps: Arc::new(Mutex::new(Vec::new())),
+            ducked: Vec::new(),
+        };
+        (bus, voice_tx)
+    }
+
+    fn fill(&mut self, data: &mut [f32]) {
+        self.voices.extend(self.incoming.try_iter());
+
+        data.fill(0.0);
+        let ducking = self.voices.iter().any(|voice| voice.ducks_others());
+        self.ducked.clear();
+        self.ducked.resize(data.len(), 0.0);
+        let ducked = &mut self.ducked;
+        self.voices.retain_mut(|voice| {
+            if voice.ducks_others() {
+                voice.mix(data)
+            } else {
+                voice.mix(ducked)
+            }
+        });
+        for (frame, ducked_frame) in data.chunks_mut(self.channels).zip(self.ducked.chunks(self.channels)) {
+            let gain = self.render.duck_gain(ducking);
+            for (sample, ducked_sample) in frame.iter_mut().zip(ducked_frame) {
+                *sample += ducked_sample * gain;
+            }
+        }
+
+        let (snapshot, master_target) = self.render.begin_buffer();
+        for frame in data.chunks_mut(self.channels) {
+            self.render.apply(frame, snapshot, master_target);
+        }
+
+        self.levels.record(data, self.channels);
+        if let Ok(mut recorder) = self.recorder.try_lock() {
+            if let Some(tap) = recorder.as_ref() {
+                if !tap.send(data) {
+                    *recorder = None;
+                }
+            }
+        }
+        if let Ok(mut taps) = self.output_taps.try_lock() {
+            // A tap that falls behind misses buffers rather than stalling the mix
+            taps.retain(|tx| !matches!(tx.try_send(data.to_vec()), Err(TrySendError::Disconnected(_))));
+        }
+
+        // Measured after every gain stage, just before the samples are clamped
+        let peak = data.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        if peak > 1.0 {
+            for voice in &self.voices {
+                voice.report_clipping(peak);
+            }
+        }
+    }
+}
+
+/// Open and start a stream on a dedicated thread that keeps it alive until the
+/// returned sender is dropped. cpal streams can't be moved between threads on
+/// every platform, so they have to stay on the thread that created them.
+fn spawn_stream_thread<F>(
+    device: Device,
+    device_name: String,
+    config: StreamConfig,
+    sample_format: SampleFormat,
+    fault: Arc<StreamFault>,
+    fill: F,
+) -> Result<mpsc::Sender<()>, String>
+where
+    F: FnMut(&mut [f32], Option<Duration>) + Send + 'static,
+{
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<(), String>>();
+
+    std::thread::spawn(move || {
+        let stream = build_output_stream(&device, &config, sample_format, fault, fill).and_then(|stream| {
+            stream
+                .play()
+                .map_err(|e| format!("Failed to play stream: {}", e))
+                .map(|_| stream)
+        });
+
+        let stream = match stream {
+            Ok(stream) => {
+                eprintln!("Successfully started output stream on device: {}", device_name);
+                stream
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(format!("Failed to open device {}: {}", device_name, e)));
+                return;
+            }
+        };
+
+        let _ = ready_tx.send(Ok(()));
+        // Blocks until the owning handle is dropped
+        let _ = stop_rx.recv();
+        drop(stream);
+    });
+
+    ready_rx
+        .recv()
+        .map_err(|_| "Output stream thread exited unexpectedly".to_string())??;
+    Ok(stop_tx)
+}
+
+/// Build an output stream that pulls f32 samples from `fill` and converts them to
+/// the device's sample format. 24-bit devices are exposed by cpal as I32.
+/// `fill` is also handed the callback's output latency when the host reports it.
+fn build_output_stream<F>(
+    device: &Device,
+    stream_config: &StreamConfig,
+    sample_format: SampleFormat,
+    fault: Arc<StreamFault>,
+    mut fill: F,
+) -> Result<Stream, String>
+where
+    F: FnMut(&mut [f32], Option<Duration>) + Send + 'static,
+{
+    let stream = match sample_format {
+        SampleFormat::F32 => device.build_output_stream(
+            stream_config,
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| fill(data, callback_latency(info)),
+            stream_error(fault),
+            None,
+        ),
+        SampleFormat::F64 => build_converting_stream::<f64, F>(device, stream_config, fault, fill),
+        SampleFormat::I8 => build_converting_stream::<i8, F>(device, stream_config, fault, fill),
+        SampleFormat::I16 => build_converting_stream::<i16, F>(device, stream_config, fault, fill),
+        SampleFormat::I32 => build_converting_stream::<i32, F>(device, stream_config, fault, fill),
+        SampleFormat::I64 => build_converting_stream::<i64, F>(device, stream_config, fault, fill),
+        SampleFormat::U8 => build_converting_stream::<u8, F>(device, stream_config, fault, fill),
+        SampleFormat::U16 => build_converting_stream::<u16, F>(device, stream_config, fault, fill),
+        SampleFormat::U32 => build_converting_stream::<u32, F>(device, stream_config, fault, fill),
+        SampleFormat::U64 => build_converting_stream::<u64, F>(device, stream_config, fault, fill),
+        other => return Err(format!("Unsupported sample format: {:?}", other)),
+    };
+
+    stream.map_err(|e| format!("Failed to build stream: {}", e))
+}
+
+/// Render into an f32 scratch buffer and convert it to the device's sample type.
+fn build_converting_stream<T, F>(
+    device: &Device,
+    stream_config: &StreamConfig,
+    fault: Arc<StreamFault>,
+    mut fill: F,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+    F: FnMut(&mut [f32], Option<Duration>) + Send + 'static,
+{
+    let mut scratch = Vec::new();
+    device.build_output_stream(
+        stream_config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            scratch.resize(data.len(), 0.0);
+            fill(&mut scratch, callback_latency(info));
+            for (out, sample) in data.iter_mut().zip(&scratch) {
+                // Mixed voices can exceed full scale; clamp rather than wrap
+                *out = T::from_sample(sample.clamp(-1.0, 1.0));
+            }
+        },
+        stream_error(fault),
+        None,
+    )
+}
+
+/// How long until the buffer being filled is played, per the host's timestamps.
+fn callback_latency(info: &cpal::OutputCallbackInfo) -> Option<Duration> {
+    let timestamp = info.timestamp();
+    timestamp.playback.duration_since(&timestamp.callback)
+}
+
+/// Error callback for a stream. Losing the device is recorded in `fault` so the
+/// engine can report it; anything else is only logged.
+fn stream_error(fault: Arc<StreamFault>) -> impl FnMut(cpal::StreamError) + Send + 'static {
+    move |err| {
+        eprintln!("Playback error: {}", err);
+        if let cpal::StreamError::DeviceNotAvailable = err {
+            fault.set(err.to_string());
+        }
+    }
+}
+
+/// Plays one clip in the device's format as it arrives from the decoder thread,
+/// applying its fade-in/out and the pause and stop fades of its session. The
+/// clip is read at the stream's drift-correction rate, interpolating between
+/// frames.
+pub(super) struct PlaybackCursor {
+    source: mpsc::Receiver<Vec<f32>>,
+    chunk: Vec<f32>,
+    chunk_pos: usize,
+    rate: Arc<AtomicGain>,
+    /// The two most recently read clip frames, and how far the output is from
+    /// `previous` towards `current` (past 1.0 means the next frame is due)
+    previous: Vec<f32>,
+    current: Vec<f32>,
+    phase: f64,
+    /// Samples played so far, published after every callback for progress reports
+    position: Arc<AtomicUsize>,
+    /// Expected length, 0 if unknown
+    total_samples: usize,
+    finished: Arc<AtomicBool>,
+    detached: Arc<AtomicBool>,
+    sample_rate: u32,
+    channels: usize,
+    control: Arc<SessionControl>,
+    pause: GainRamp,
+    fade_in_frames: usize,
+    fade_out_frames: usize,
+    /// (remaining, total) frames of an in-progress stop fade
+    fade: Option<(usize, usize)>,
+    stopped: bool,
+    ended: bool,
+    ducks_others: bool,
+}
+
+impl PlaybackCursor {
+    pub(super) fn new(
+        source: mpsc::Receiver<Vec<f32>>,
+        progress: &StreamProgress,
+        control: Arc<SessionControl>,
+        options: &PlaybackOptions,
+    ) -> Self {
+        let sample_rate = progress.sample_rate;
+        let ms_to_frames = |ms: u32| (ms as u64 * sample_rate as u64 / 1000) as usize;
+        let channels = progress.channels.max(1) as usize;
+        let position = progress.position.load(Ordering::Relaxed);
+        // A device joining mid-clip ramps in like a resume instead of starting
+        // at full level
+        let initial_gain = if position > 0 || control.paused.load(Ordering::Relaxed) {
+            0.0
+        } else {
+            1.0
+        };
+        Self {
+            source,
+            chunk: Vec::new(),
+            chunk_pos: 0,
+            rate: progress.rate.clone(),
+            previous: vec![0.0; channels],
+            current: vec![0.0; channels],
+            // Due for the first frame, which then plays exactly at rate 1.0
+            phase: 2.0,
+            position: progress.position.clone(),
+            total_samples: progress.total_samples,
+            finished: progress.finished.clone(),
+            detached: progress.detached.clone(),
+            sample_rate,
+            channels,
+            control,
+            pause: GainRamp::new(initial_gain, PAUSE_FADE_MS, sample_rate),
+            fade_in_frames: ms_to_frames(options.fade_in_ms),
+            fade_out_frames: ms_to_frames(options.fade_out_ms),
+            fade: None,
+            stopped: false,
+            ended: false,
+            ducks_others: options.duck_others,
+        }
+    }
+
+    /// Next sample from the decoder, or `None` if it hasn't caught up yet or the
+    /// clip has ended.
+    fn next_sample(&mut self) -> Option<f32> {
+        while self.chunk_pos >= self.chunk.len() {
+            match self.source.try_recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.chunk_pos = 0;
+                }
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    self.ended = true;
+                    return None;
+                }
+            }
+        }
+        let sample = self.chunk[self.chunk_pos];
+        self.chunk_pos += 1;
+        Some(sample)
+    }
+
+    /// Advance to the next clip frame. Returns false, leaving the frames as they
+    /// were, if the decoder hasn't caught up yet or the clip has ended.
+    fn read_frame(&mut self) -> bool {
+        let first = match self.next_sample() {
+            Some(sample) => sample,
+            None => return false,
+        };
+        self.previous.copy_from_slice(&self.current);
+        self.current[0] = first;
+        // Chunks always hold whole frames
+        for channel in 1..self.channels {
+            self.current[channel] = self.next_sample().unwrap_or(0.0);
+        }
+        true
+    }
+
+    /// Fade-in/out gain for the frame starting at sample `idx`.
+    /// The fade-out needs the clip length, so it is skipped when that is unknown.
+    fn envelope_gain(&self, idx: usize) -> f32 {
+        let frame = idx / self.channels;
+        let remaining = (self.total_samples / self.channels).saturating_sub(frame);
+        let mut gain = 1.0;
+        if frame < self.fade_in_frames {
+            gain *= frame as f32 / self.fade_in_frames as f32;
+        }
+        if self.total_samples > 0 && remaining < self.fade_out_frames {
+            gain *= remaining as f32 / self.fade_out_frames as f32;
+        }
+        gain
+    }
+
+    /// Gain for the next frame. Once the fade has run out the cursor is stopped
+    /// and gets dropped from the mix. Detaching from a moved session fades out
+    /// the same way.
+    fn stop_gain(&mut self) -> f32 {
+        if self.stopped {
+            return 0.0;
+        }
+        let stop_requested = self.control.stop_requested.load(Ordering::Relaxed);
+        if !stop_requested && !self.detached.load(Ordering::Relaxed) {
+            return 1.0;
+        }
+
+        let (remaining, total) = *self.fade.get_or_insert_with(|| {
+            let fade_ms = if stop_requested {
+                self.control.stop_fade_ms.load(Ordering::Relaxed) as u64
+            } else {
+                DEVICE_SWAP_FADE_MS as u64
+            };
+            let frames = (fade_ms * self.sample_rate as u64 / 1000) as usize;
+            (frames, frames)
+        });
+
+        if remaining == 0 {
+            self.stopped = true;
+            return 0.0;
+        }
+
+        self.fade = Some((remaining - 1, total));
+        remaining as f32 / total as f32
+    }
+}
+
+impl Drop for PlaybackCursor {
+    // A mixer closed under the cursor still has to end its session
+    fn drop(&mut self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Voice for PlaybackCursor {
+    fn mix(&mut self, out: &mut [f32]) -> bool {
+        let paused = self.control.paused.load(Ordering::Relaxed);
+        let rate = self.rate.get() as f64;
+        let mut idx = self.position.load(Ordering::Relaxed);
+
+        for frame in out.chunks_mut(self.channels) {
+            let gain = self.stop_gain() * self.pause.next(if paused { 0.0 } else { 1.0 });
+            if self.stopped {
+                break;
+            }
+            // Hold the position once fully paused
+            if paused && gain == 0.0 {
+                continue;
+            }
+            while self.phase > 1.0 && self.read_frame() {
+                idx += self.channels;
+                self.phase -= 1.0;
+            }
+            // Underrun or end of clip: leave this frame silent
+            if self.phase > 1.0 {
+                continue;
+            }
+
+            let gain = gain * self.envelope_gain(idx.saturating_sub(self.channels));
+            let phase = self.phase as f32;
+            for ((sample, previous), current) in frame.iter_mut().zip(&self.previous).zip(&self.current) {
+                *sample += (previous + (current - previous) * phase) * gain;
+            }
+            self.phase += rate;
+        }
+
+        self.position.store(idx, Ordering::Relaxed);
+        if self.stopped || self.ended {
+            self.finished.store(true, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    fn report_clipping(&self, peak: f32) {
+        self.control.record_clipping(peak);
+    }
+
+    fn ducks_others(&self) -> bool {
+        self.ducks_others
+    }
+}
+
+/// Reads from a shared playhead that the UI can move at any time. A short fade-in
+/// after every jump keeps scrubbing free of clicks, and the same fade is used on
+/// the way out once `stop` is set.
+pub(super) struct PreviewCursor {
+    samples: Vec<f32>,
+    position: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    channels: usize,
+    fade_frames: usize,
+    fade_remaining: usize,
+    stop_fade: GainRamp,
+    /// Where the last callback left off; anything else means the playhead moved
+    expected_position: usize,
+}
+
+impl PreviewCursor {
+    pub(super) fn new(
+        samples: Vec<f32>,
+        position: Arc<AtomicUsize>,
+        stop: Arc<AtomicBool>,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Self {
+        let fade_frames = (PREVIEW_SEEK_FADE_MS as usize * sample_rate as usize / 1000).max(1);
+        Self {
+            samples,
+            position,
+            stop,
+            channels: channels.max(1) as usize,
+            fade_frames,
+            fade_remaining: fade_frames,
+            stop_fade: GainRamp::new(1.0, PREVIEW_SEEK_FADE_MS, sample_rate),
+            expected_position: usize::MAX,
+        }
+    }
+}
+
+impl Voice for PreviewCursor {
+    fn mix(&mut self, out: &mut [f32]) -> bool {
+        let start = self.position.load(Ordering::Relaxed);
+        let mut idx = start;
+        if idx != self.expected_position {
+            // Snap to a frame boundary so channels don't get swapped
+            idx -= idx % self.channels;
+            self.fade_remaining = self.fade_frames;
+        }
+
+        let stopping = self.stop.load(Ordering::Relaxed);
+        let mut stop_gain = 1.0;
+        for frame in out.chunks_mut(self.channels) {
+            stop_gain = self.stop_fade.next(if stopping { 0.0 } else { 1.0 });
+            let gain = (1.0 - self.fade_remaining as f32 / self.fade_frames as f32) * stop_gain;
+            self.fade_remaining = self.fade_remaining.saturating_sub(1);
+            for sample in frame.iter_mut() {
+                if let Some(value) = self.samples.get(idx) {
+                    *sample += value * gain;
+                    idx += 1;
+                }
+            }
+        }
+
+        // Only publish our progress if nobody seeked while we were rendering
+        if self
+            .position
+            .compare_exchange(start, idx, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            self.expected_position = idx;
+        }
+
+        // Stay in the mix at the end of the clip so the user can seek back
+        stop_gain > 0.0
+    }
+}
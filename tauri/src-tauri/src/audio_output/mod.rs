@@ -0,0 +1,2609 @@
+mod clip_cache;
+pub(crate) mod convert;
+mod cue;
+mod decode;
+pub(crate) mod device_id;
+mod drift;
+mod dsp;
+mod feed;
+mod fingerprint;
+mod flac;
+mod http_source;
+mod live_input;
+mod loudness;
+mod metadata;
+pub(crate) mod meter;
+mod mixer;
+#[cfg(feature = "mp3")]
+mod mp3;
+mod opus;
+mod peaks;
+mod playlist;
+pub(crate) mod record;
+mod silence;
+mod splice;
+mod stream_config;
+mod stretch;
+mod trim;
+#[cfg(target_os = "windows")]
+mod wasapi_exclusive;
+
+use clip_cache::{hash_bytes, ClipCache, DecodedClip};
+use convert::{convert_for_device, FormatConverter};
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host};
+use decode::ClipDecoder;
+use drift::DriftCorrector;
+use feed::DeviceFeed;
+use device_id::{identified_output_devices, IdentifiedDevice};
+use dsp::{Compressor, DelayLine, ParametricEq, MAX_EQ_BANDS};
+use live_input::{live_input, output_tap};
+use mixer::{DeviceMixer, PlaybackCursor, PreviewCursor, Voice};
+use peaks::MAX_WAVEFORM_BUCKETS;
+use playlist::Playlist;
+use record::OutputRecording;
+use stream_config::{device_capabilities, resolve_stream_config, StreamConfigStore};
+use stretch::TimeStretcher;
+use trim::{FrameRange, SilenceTrimmer};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+pub(crate) use clip_cache::hash_file;
+pub(crate) use cue::read_wav_markers;
+pub(crate) use decode::probe_file;
+pub use dsp::{CompressorSettings, EqBand};
+pub(crate) use fingerprint::{fingerprint_file, fingerprint_similarity};
+pub(crate) use live_input::{LiveInputControl, LiveInputFeed, OutputTap};
+pub(crate) use loudness::measure_file_loudness;
+pub use metadata::AudioMetadata;
+pub(crate) use metadata::read_metadata;
+pub use peaks::WaveformPeaks;
+pub use playlist::{PlaylistItem, PlaylistStatus};
+pub use silence::{SilenceOptions, SoundRegion};
+pub(crate) use silence::find_sound_regions;
+pub use splice::SpliceSegment;
+pub(crate) use splice::{cut_file, splice_files};
+pub use stream_config::{DeviceCapabilities, DeviceStreamConfig};
+pub use trim::ClipTrim;
+
+/// ID of the virtual device that plays to whatever the OS default output is,
+/// following it when the default changes.
+pub const SYSTEM_DEFAULT_DEVICE_ID: &str = "system_default";
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AudioOutputDevice {
+    pub id: String,
+    /// WASAPI endpoint ID or ALSA PCM name, when the host exposes one
+    pub platform_id: Option<String>,
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// A cpal audio host (backend) that output can be routed through.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AudioHost {
+    pub name: String,
+    pub is_default: bool,
+    pub is_active: bool,
+}
+
+/// Optional lead-in rendered before the clip itself starts.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LeadIn {
+    /// Plain silence for the given duration
+    Silence { duration_ms: u32 },
+    /// A click on every beat, the first one accented
+    CountIn { beats: u32, bpm: f32 },
+}
+
+/// Signal played by `play_test_tone`.
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TestToneKind {
+    #[default]
+    Sine,
+    Noise,
+}
+
+/// Rate test tones are rendered at; the device converter takes it from there.
+const TEST_TONE_SAMPLE_RATE: u32 = 48_000;
+/// Fade at either end of a test tone, so it doesn't click.
+const TEST_TONE_FADE_MS: u32 = 10;
+
+/// Per-playback settings that don't depend on the clip itself.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct PlaybackOptions {
+    /// Fade in from silence over the start of the clip
+    pub fade_in_ms: u32,
+    /// Fade out to silence over the end of the clip
+    pub fade_out_ms: u32,
+    /// Playback rate without changing pitch, clamped to 0.5-2.0 (default 1.0)
+    pub speed: Option<f32>,
+    /// Pitch shift without changing speed, clamped to +/-12 semitones
+    pub pitch_semitones: f32,
+    /// Normalize the clip to this integrated loudness (EBU R128, e.g. -23.0)
+    pub target_lufs: Option<f32>,
+    /// Skip silence at the start and end of the clip
+    pub trim_silence: bool,
+    /// Play only part of the clip. Library clips fall back to their saved
+    /// trim
+    pub trim: Option<ClipTrim>,
+    /// Extra gain for this playback
+    pub gain_db: f32,
+    /// Lower every other clip on the same devices to the duck level while
+    /// this one plays, e.g. for announcements
+    pub duck_others: bool,
+}
+
+impl PlaybackOptions {
+    pub(crate) fn speed(&self) -> f64 {
+        self.speed
+            .map(|speed| speed.clamp(MIN_PLAYBACK_SPEED, MAX_PLAYBACK_SPEED) as f64)
+            .unwrap_or(1.0)
+    }
+
+    /// Frequency ratio for `pitch_semitones`.
+    fn pitch_ratio(&self) -> f64 {
+        let semitones = self.pitch_semitones.clamp(-MAX_PITCH_SEMITONES, MAX_PITCH_SEMITONES);
+        2f64.powf(semitones as f64 / 12.0)
+    }
+}
+
+const MIN_PLAYBACK_SPEED: f32 = 0.5;
+const MAX_PLAYBACK_SPEED: f32 = 2.0;
+const MAX_PITCH_SEMITONES: f32 = 12.0;
+
+/// Limits on the gain loudness normalization may apply, so a near-silent clip
+/// isn't boosted into noise.
+const MIN_NORMALIZATION_DB: f64 = -30.0;
+const MAX_NORMALIZATION_DB: f64 = 12.0;
+
+/// Gain in dB that brings a clip measured at `lufs` to `target_lufs`, within
+/// the limits normalization may apply.
+pub(crate) fn normalization_db(target_lufs: f64, lufs: f64) -> f64 {
+    (target_lufs - lufs).clamp(MIN_NORMALIZATION_DB, MAX_NORMALIZATION_DB)
+}
+
+/// Identifies one call to `play_audio_to_devices` across all of its devices.
+pub type SessionId = u64;
+
+/// Transport state shared between a session and its voices.
+#[derive(Default)]
+struct SessionControl {
+    stop_requested: AtomicBool,
+    stop_fade_ms: AtomicU32,
+    paused: AtomicBool,
+    /// Highest peak above full scale since the monitor last checked, as f32
+    /// bits (0 if none)
+    clip_peak: AtomicU32,
+}
+
+impl SessionControl {
+    fn request_stop(&self, fade_ms: u32) {
+        self.stop_fade_ms.store(fade_ms, Ordering::Relaxed);
+        self.stop_requested.store(true, Ordering::Relaxed);
+    }
+
+    fn record_clipping(&self, peak: f32) {
+        // Bit patterns of positive floats sort the same way as their values
+        self.clip_peak.fetch_max(peak.abs().to_bits(), Ordering::Relaxed);
+    }
+
+    /// Peak recorded since the last call, if the output clipped.
+    fn take_clip_peak(&self) -> Option<f32> {
+        match self.clip_peak.swap(0, Ordering::Relaxed) {
+            0 => None,
+            bits => Some(f32::from_bits(bits)),
+        }
+    }
+}
+
+/// Playback position of one device stream, in interleaved samples of the
+/// device-format buffer.
+struct StreamProgress {
+    device_id: String,
+    position: Arc<AtomicUsize>,
+    /// Estimated from the container header; 0 if it doesn't report a length
+    total_samples: usize,
+    /// Set by the voice once it has played its last sample
+    finished: Arc<AtomicBool>,
+    /// Clip frames the voice reads per output frame; nudged off 1.0 to correct
+    /// clock drift against the session's other devices
+    rate: Arc<AtomicGain>,
+    /// Set when the session moves off this device; the voice fades out and
+    /// drops from the mix without ending the session
+    detached: Arc<AtomicBool>,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl StreamProgress {
+    fn samples_to_ms(&self, samples: usize) -> u64 {
+        let frames = samples / self.channels.max(1) as usize;
+        frames as u64 * 1000 / self.sample_rate.max(1) as u64
+    }
+
+    /// Playback position in seconds of clip time.
+    fn position_secs(&self) -> f64 {
+        let frames = self.position.load(Ordering::Relaxed) / self.channels.max(1) as usize;
+        frames as f64 / self.sample_rate.max(1) as f64
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PlaybackStatus {
+    pub session_id: SessionId,
+    pub position_ms: u64,
+    pub duration_ms: u64,
+    pub paused: bool,
+}
+
+/// Payload of `playback://clipping`: a device mix carrying this session went
+/// over 0 dBFS after gain and mixing.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ClippingWarning {
+    pub session_id: SessionId,
+    pub peak_dbfs: f32,
+}
+
+/// Payload of `playback://device_lost`: an open output device failed or was
+/// unplugged.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DeviceLost {
+    pub device_id: String,
+    pub error: String,
+    /// Sessions that were playing on the device
+    pub session_ids: Vec<SessionId>,
+    /// Those of them that carried on on the default device
+    pub failed_over: Vec<SessionId>,
+}
+
+/// How often sessions are checked for completion and `playback://progress`
+/// events are emitted.
+const MONITOR_INTERVAL_MS: u64 = 100;
+
+/// Report progress and clipping for every active session, keep its devices in
+/// sync, reap the ones that have played to the end and start queued clips and
+/// playlist items, until the app shuts down. Devices whose stream has failed are
+/// reported here too. Runs on its own thread so the audio callbacks
+/// never touch the event system.
+fn spawn_session_monitor(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(MONITOR_INTERVAL_MS));
+
+        let state = app.state::<AudioOutputState>();
+        report_lost_devices(&app, &state, None);
+        let sessions = &state.sessions;
+        let (active, finished, clipped) = {
+            let mut sessions = sessions.lock().unwrap();
+            for session in sessions.values_mut() {
+                if !session.control.paused.load(Ordering::Relaxed) {
+                    session.drift.update(&session.streams);
+                }
+            }
+            let clipped: Vec<ClippingWarning> = sessions
+                .iter()
+                .filter_map(|(id, session)| {
+                    session.control.take_clip_peak().map(|peak| ClippingWarning {
+                        session_id: *id,
+                        peak_dbfs: 20.0 * peak.log10(),
+                    })
+                })
+                .collect();
+            let finished_ids: Vec<SessionId> = sessions
+                .iter()
+                .filter(|(_, session)| session.is_finished())
+                .map(|(id, _)| *id)
+                .collect();
+
+            let finished: Vec<PlaybackStatus> = finished_ids
+                .into_iter()
+                .filter_map(|id| sessions.remove(&id).map(|session| session.status(id)))
+                .collect();
+            let active: Vec<PlaybackStatus> = sessions
+                .iter()
+                .map(|(id, session)| session.status(*id))
+                .collect();
+            (active, finished, clipped)
+        };
+
+        for warning in clipped {
+            eprintln!(
+                "Session {} is clipping (peak {:+.1} dBFS)",
+                warning.session_id, warning.peak_dbfs
+            );
+            if let Err(e) = app.emit("playback://clipping", &warning) {
+                eprintln!("Failed to emit playback://clipping event: {}", e);
+            }
+        }
+
+        for status in active {
+            if let Err(e) = app.emit("playback://progress", &status) {
+                eprintln!("Failed to emit playback://progress event: {}", e);
+            }
+        }
+        let finished_ids: Vec<SessionId> = finished.iter().map(|status| status.session_id).collect();
+        for status in finished {
+            eprintln!("Session {} finished playing", status.session_id);
+            if let Err(e) = app.emit("playback://finished", &status) {
+                eprintln!("Failed to emit playback://finished event: {}", e);
+            }
+        }
+
+        state.advance_queues();
+        if let Some(status) = state.advance_playlist(&finished_ids) {
+            if let Err(e) = app.emit("playlist://changed", &status) {
+                eprintln!("Failed to emit playlist://changed event: {}", e);
+            }
+        }
+    });
+}
+
+/// Close the mixers of devices that failed, or that are missing from `present`
+/// when it is given, and emit `playback://device_lost` for each.
+fn report_lost_devices(app: &AppHandle, state: &AudioOutputState, present: Option<&[AudioOutputDevice]>) {
+    for (device_id, error, mixer) in state.take_lost_devices(present) {
+        let lost = state.recover_lost_device(device_id, error);
+        // Closed only now, so its voices don't end their sessions before they
+        // have been moved
+        drop(mixer);
+        eprintln!(
+            "Lost output device {}: {} ({} session(s), {} failed over)",
+            lost.device_id,
+            lost.error,
+            lost.session_ids.len(),
+            lost.failed_over.len()
+        );
+        if let Err(e) = app.emit("playback://device_lost", &lost) {
+            eprintln!("Failed to emit playback://device_lost event: {}", e);
+        }
+    }
+}
+
+/// Levels of one device's output since the previous `playback://levels` event,
+/// per channel in dBFS.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct OutputLevel {
+    pub device_id: String,
+    pub peak_dbfs: Vec<f32>,
+    pub rms_dbfs: Vec<f32>,
+}
+
+/// How often `playback://levels` is emitted (about 30 Hz).
+pub(crate) const LEVEL_METER_INTERVAL_MS: u64 = 33;
+
+/// Lowest level reported by the meters, standing in for silence.
+const METER_FLOOR_DBFS: f32 = -120.0;
+
+/// Emit `playback://levels` with the output level of every open device mixer
+/// for the app's VU meters.
+fn spawn_level_meter(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(LEVEL_METER_INTERVAL_MS));
+
+        let levels = app.state::<AudioOutputState>().output_levels();
+        if levels.is_empty() {
+            continue;
+        }
+        if let Err(e) = app.emit("playback://levels", &levels) {
+            eprintln!("Failed to emit playback://levels event: {}", e);
+        }
+    });
+}
+
+pub(crate) fn to_dbfs(level: f32) -> f32 {
+    (20.0 * level.log10()).max(METER_FLOOR_DBFS)
+}
+
+/// How often the output device list is re-enumerated to detect hotplugging.
+const DEVICE_POLL_INTERVAL_MS: u64 = 2000;
+
+/// Emit `devices://changed` with the full device list whenever an output device
+/// is added or removed, or the default changes, and move System Default playback
+/// to the new default. cpal has no hotplug notifications, so this polls.
+fn spawn_device_watcher(app: AppHandle) {
+    std::thread::spawn(move || {
+        let state = app.state::<AudioOutputState>();
+        let mut known = state.list_output_devices().unwrap_or_default();
+        loop {
+            std::thread::sleep(Duration::from_millis(DEVICE_POLL_INTERVAL_MS));
+
+            let devices = match state.list_output_devices() {
+                Ok(devices) => devices,
+                Err(e) => {
+                    eprintln!("Device watcher: {}", e);
+                    continue;
+                }
+            };
+            if devices == known {
+                continue;
+            }
+
+            eprintln!("Output devices changed: {} -> {} device(s)", known.len(), devices.len());
+            if let Err(e) = app.emit("devices://changed", &devices) {
+                eprintln!("Failed to emit devices://changed event: {}", e);
+            }
+            report_lost_devices(&app, &state, Some(&devices));
+            known = devices;
+            state.follow_default_device();
+        }
+    });
+}
+
+/// Fade used when pausing or resuming, so the transport never clicks.
+const PAUSE_FADE_MS: u32 = 10;
+
+/// A gain that moves linearly towards its target by a fixed step per frame.
+struct GainRamp {
+    gain: f32,
+    step: f32,
+}
+
+impl GainRamp {
+    fn new(initial: f32, fade_ms: u32, sample_rate: u32) -> Self {
+        let fade_frames = (fade_ms as f32 * sample_rate as f32 / 1000.0).max(1.0);
+        Self {
+            gain: initial,
+            step: 1.0 / fade_frames,
+        }
+    }
+
+    fn next(&mut self, target: f32) -> f32 {
+        if self.gain < target {
+            self.gain = (self.gain + self.step).min(target);
+        } else if self.gain > target {
+            self.gain = (self.gain - self.step).max(target);
+        }
+        self.gain
+    }
+}
+
+/// A playing clip, made up of one voice per device mixer. Voices drop out of
+/// their mix on their own once the clip ends or the stop fade has run out.
+struct PlaybackSession {
+    control: Arc<SessionControl>,
+    source: SessionSource,
+    drift: DriftCorrector,
+    /// Sorted device IDs, used to decide whether a queue for this group is busy
+    device_group: Vec<String>,
+    /// One entry per device; they all start together so the first one is
+    /// representative for status queries
+    streams: Vec<StreamProgress>,
+}
+
+impl PlaybackSession {
+    /// True once every device stream has rendered its last sample.
+    fn is_finished(&self) -> bool {
+        !self.streams.is_empty()
+            && self
+                .streams
+                .iter()
+                .all(|stream| stream.finished.load(Ordering::Relaxed))
+    }
+
+    fn status(&self, session_id: SessionId) -> PlaybackStatus {
+        let (position_ms, duration_ms) = self
+            .streams
+            .first()
+            .map(|stream| {
+                (
+                    stream.samples_to_ms(stream.position.load(Ordering::Relaxed)),
+                    stream.samples_to_ms(stream.total_samples),
+                )
+            })
+            .unwrap_or((0, 0));
+
+        PlaybackStatus {
+            session_id,
+            position_ms,
+            duration_ms,
+            paused: self.control.paused.load(Ordering::Relaxed),
+        }
+    }
+}
+
+type SessionMap = Mutex<HashMap<SessionId, PlaybackSession>>;
+
+/// What it takes to add a device to a session that is already playing.
+#[derive(Clone)]
+struct SessionSource {
+    feeds: mpsc::Sender<FeedRequest>,
+    /// Format the decoder thread hands to the device converters, after
+    /// time-stretching and pitch shifting
+    sample_rate: u32,
+    channels: u16,
+    total_frames: Option<u64>,
+    options: PlaybackOptions,
+    /// Gain each device applies after conversion, for sessions whose decoder
+    /// thread leaves it to them
+    feed_gain: f32,
+}
+
+/// Ask the decoder thread to start feeding another device, from `from_frame`
+/// (in the session's source format) onwards.
+struct FeedRequest {
+    feed: DeviceFeed,
+    tx: mpsc::SyncSender<Vec<f32>>,
+    from_frame: u64,
+}
+
+/// A probed clip that hasn't started decoding yet.
+struct PendingClip {
+    decoder: ClipDecoder,
+    /// Rendered lead-in in the clip's own format, played before the first packet
+    lead_in: Vec<f32>,
+    /// Linear gain applied to the decoded clip (not the lead-in), from the
+    /// playback options and loudness normalization
+    gain: f32,
+    /// Trim points in source frames
+    start_frame: u64,
+    end_frame: Option<u64>,
+    /// SHA-256 of the clip's content, for clips that can be cached
+    content_hash: Option<String>,
+}
+
+impl PendingClip {
+    /// True if the whole clip plays as is, without a lead-in or trim.
+    fn is_whole(&self) -> bool {
+        self.lead_in.is_empty() && self.start_frame == 0 && self.end_frame.is_none()
+    }
+
+    /// Length including the lead-in and trim, if the container reports it.
+    fn total_frames(&self) -> Option<u64> {
+        let lead_in_frames = self.lead_in.len() / self.decoder.channels.max(1) as usize;
+        self.decoder.total_frames.map(|frames| {
+            let end = self.end_frame.map(|end| end.min(frames)).unwrap_or(frames);
+            end.saturating_sub(self.start_frame) + lead_in_frames as u64
+        })
+    }
+
+    fn duration_ms(&self) -> u64 {
+        self.total_frames().unwrap_or(0) * 1000 / self.decoder.sample_rate.max(1) as u64
+    }
+}
+
+/// Decoded chunks (about one packet each) buffered per device ahead of playback.
+const DECODE_BUFFER_CHUNKS: usize = 32;
+
+/// Recently decoded audio kept by the decoder thread, so a device joining mid-clip
+/// can start from the current position rather than from the decoder's, which
+/// runs ahead by up to `DECODE_BUFFER_CHUNKS`.
+const FEED_HISTORY_SECS: usize = 10;
+
+/// Fade out of the old device when a session moves to another one.
+const DEVICE_SWAP_FADE_MS: u32 = 20;
+
+/// Decode `clip` on its own thread and push it through every device's converter
+/// into that device's voice, cutting it to its trim points, trimming leading
+/// and trailing silence if asked and time-stretching it first if `speed`
+/// isn't 1. The bounded channels keep the decoder only a little ahead of
+/// playback; it stops early once every voice has gone away. Devices added
+/// through `requests` are fed from the audio it has kept in its history.
+fn spawn_decoder_thread(
+    clip: PendingClip,
+    speed: f64,
+    trim_silence: bool,
+    mut feeds: Vec<(DeviceFeed, mpsc::SyncSender<Vec<f32>>)>,
+    requests: mpsc::Receiver<FeedRequest>,
+) {
+    std::thread::spawn(move || {
+        let PendingClip {
+            mut decoder,
+            lead_in,
+            gain,
+            start_frame,
+            end_frame,
+            ..
+        } = clip;
+        let mut next = Some(lead_in).filter(|lead_in| !lead_in.is_empty());
+        let mut stretcher = (speed != 1.0)
+            .then(|| TimeStretcher::new(decoder.sample_rate, decoder.channels, speed));
+        let mut trimmer = trim_silence.then(|| SilenceTrimmer::new(decoder.channels));
+        let mut range = FrameRange::new(decoder.channels, start_frame, end_frame);
+        let mut history = FeedHistory::new(decoder.sample_rate, decoder.channels);
+
+        loop {
+            let samples = match next.take() {
+                Some(samples) => samples,
+                None if range.is_done() => break,
+                None => match decoder.next_chunk() {
+                    Ok(Some(samples)) => {
+                        let mut samples = range.process(samples);
+                        if let Some(trimmer) = &mut trimmer {
+                            samples = trimmer.process(&samples);
+                        }
+                        if gain != 1.0 {
+                            samples.iter_mut().for_each(|sample| *sample *= gain);
+                        }
+                        samples
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("Decoder thread: {}", e);
+                        feeds.iter_mut().for_each(|(feed, _)| feed.stop_caching());
+                        break;
+                    }
+                },
+            };
+            if samples.is_empty() {
+                continue;
+            }
+            let samples = match &mut stretcher {
+                Some(stretcher) => stretcher.process(&samples),
+                None => samples,
+            };
+
+            history.push(&samples);
+            feeds.retain_mut(|(feed, tx)| tx.send(feed.process(&samples)).is_ok());
+            // A device being swapped in must be attached before the one it
+            // replaces is found gone, or the session would end here
+            history.attach(&requests, &mut feeds);
+            if feeds.is_empty() {
+                break;
+            }
+        }
+
+        history.attach(&requests, &mut feeds);
+        let tail = stretcher.map(|mut stretcher| stretcher.flush()).unwrap_or_default();
+        for (feed, tx) in &mut feeds {
+            let mut samples = feed.process(&tail);
+            samples.extend(feed.flush());
+            let _ = tx.send(samples);
+        }
+        // Dropping the senders tells each voice the clip has ended
+    });
+}
+
+/// The last `FEED_HISTORY_SECS` of audio a decoder thread has sent out.
+struct FeedHistory {
+    samples: VecDeque<f32>,
+    /// Frame number of the first sample in `samples`
+    start_frame: u64,
+    channels: usize,
+    max_frames: usize,
+}
+
+impl FeedHistory {
+    fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            start_frame: 0,
+            channels: channels.max(1) as usize,
+            max_frames: sample_rate as usize * FEED_HISTORY_SECS,
+        }
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        self.samples.extend(samples);
+        let excess = (self.samples.len() / self.channels).saturating_sub(self.max_frames);
+        self.samples.drain(..excess * self.channels);
+        self.start_frame += excess as u64;
+    }
+
+    /// Start feeding every requested device with what it has missed since its
+    /// start frame.
+    fn attach(
+        &self,
+        requests: &mpsc::Receiver<FeedRequest>,
+        feeds: &mut Vec<(DeviceFeed, mpsc::SyncSender<Vec<f32>>)>,
+    ) {
+        for FeedRequest { mut feed, tx, from_frame } in requests.try_iter() {
+            if from_frame < self.start_frame {
+                eprintln!(
+                    "Decoder thread: {} frames requested by a new device are no longer kept",
+                    self.start_frame - from_frame
+                );
+            }
+            let skip = from_frame.saturating_sub(self.start_frame) as usize * self.channels;
+            let backlog: Vec<f32> = self.samples.iter().skip(skip).copied().collect();
+            if tx.send(feed.process(&backlog)).is_ok() {
+                feeds.push((feed, tx));
+            }
+        }
+    }
+}
+
+/// A clip waiting for its device group to become idle.
+struct QueuedPlayback {
+    id: SessionId,
+    clip: PendingClip,
+    device_ids: Vec<String>,
+    options: PlaybackOptions,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct QueuedPlaybackInfo {
+    pub id: SessionId,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PlaybackQueue {
+    pub device_ids: Vec<String>,
+    pub items: Vec<QueuedPlaybackInfo>,
+}
+
+/// Queues are keyed by the sorted set of devices they play to.
+fn device_group(device_ids: &[String]) -> Vec<String> {
+    let mut group = device_ids.to_vec();
+    group.sort();
+    group.dedup();
+    group
+}
+
+/// Request a stop on every session and forget about them. Returns how many were stopped.
+fn stop_sessions(sessions: &SessionMap, fade_ms: u32) -> usize {
+    let stopped: Vec<_> = sessions.lock().unwrap().drain().collect();
+    for (_, session) in &stopped {
+        session.control.request_stop(fade_ms);
+    }
+    stopped.len()
+}
+
+/// Fade used by stops that don't ask for one, short enough to feel instant
+/// without clicking.
+const DEFAULT_STOP_FADE_MS: u32 = 20;
+
+/// Fade applied when the sleep timer runs out.
+const SLEEP_TIMER_FADE_MS: u32 = 3000;
+
+/// Longest sleep timer, a day.
+const MAX_SLEEP_TIMER_MINUTES: f64 = 24.0 * 60.0;
+
+/// Fade used when a device is muted or unmuted.
+const DEVICE_MUTE_FADE_MS: u32 = 10;
+
+/// Per-device switches read live by that device's mixer.
+#[derive(Default)]
+struct DeviceControls {
+    invert_polarity: AtomicBool,
+    swap_channels: AtomicBool,
+    muted: AtomicBool,
+    eq_bands: Mutex<Vec<EqBand>>,
+    compressor: Mutex<Option<CompressorSettings>>,
+    /// Bumped after every EQ or compressor change so the mixer knows to reload
+    dsp_version: AtomicU64,
+    /// Extra output delay for lining this device up with others
+    delay_ms: AtomicU32,
+}
+
+#[derive(Clone, Copy)]
+struct DeviceControlSnapshot {
+    invert_polarity: bool,
+    swap_channels: bool,
+    muted: bool,
+}
+
+impl DeviceControls {
+    /// Snapshot the switches once per callback rather than once per frame.
+    fn snapshot(&self) -> DeviceControlSnapshot {
+        DeviceControlSnapshot {
+            invert_polarity: self.invert_polarity.load(Ordering::Relaxed),
+            swap_channels: self.swap_channels.load(Ordering::Relaxed),
+            muted: self.muted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Longest per-device delay offset.
+const MAX_DEVICE_DELAY_MS: u32 = 2000;
+
+/// Upper bound for the master gain (about +12 dB).
+const MAX_MASTER_GAIN: f32 = 4.0;
+
+/// Time constant for master gain smoothing; long enough to avoid zipper noise
+/// while a slider is dragged, short enough to feel immediate.
+const MASTER_GAIN_SMOOTHING_MS: f32 = 20.0;
+
+/// How far clips are lowered under a ducking playback such as TTS, and the
+/// quietest it can be set to.
+const DEFAULT_DUCK_DB: f32 = -12.0;
+const MIN_DUCK_DB: f32 = -60.0;
+
+/// Fade of clips going under and coming back up from a ducking playback.
+const DUCK_FADE_MS: u32 = 200;
+
+/// An f32 that can be shared with audio callbacks without locking.
+struct AtomicGain(AtomicU32);
+
+impl AtomicGain {
+    fn new(gain: f32) -> Self {
+        Self(AtomicU32::new(gain.to_bits()))
+    }
+
+    fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, gain: f32) {
+        self.0.store(gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Per-mixer render state for a device's controls and the master gain. Gain
+/// changes are ramped or smoothed so they never click.
+struct DeviceRender {
+    controls: Arc<DeviceControls>,
+    eq: ParametricEq,
+    compressor: Compressor,
+    dsp_version: u64,
+    delay: DelayLine,
+    delay_ms: u32,
+    sample_rate: u32,
+    mute: GainRamp,
+    master: Arc<AtomicGain>,
+    master_gain: f32,
+    master_coeff: f32,
+    /// Gain of other voices while a ducking voice plays
+    duck_level: Arc<AtomicGain>,
+    duck: GainRamp,
+}
+
+impl DeviceRender {
+    fn new(
+        controls: Arc<DeviceControls>,
+        master: Arc<AtomicGain>,
+        duck_level: Arc<AtomicGain>,
+        sample_rate: u32,
+    ) -> Self {
+        let initial = if controls.muted.load(Ordering::Relaxed) { 0.0 } else { 1.0 };
+        let smoothing_frames = MASTER_GAIN_SMOOTHING_MS * sample_rate as f32 / 1000.0;
+        Self {
+            controls,
+            eq: ParametricEq::new(sample_rate),
+            compressor: Compressor::new(sample_rate),
+            // Never a real version, so the settings are loaded on the first buffer
+            dsp_version: u64::MAX,
+            delay: DelayLine::new(),
+            delay_ms: 0,
+            sample_rate,
+            mute: GainRamp::new(initial, DEVICE_MUTE_FADE_MS, sample_rate),
+            master_gain: master.get(),
+            master,
+            master_coeff: 1.0 - (-1.0 / smoothing_frames.max(1.0)).exp(),
+            duck_level,
+            duck: GainRamp::new(1.0, DUCK_FADE_MS, sample_rate),
+        }
+    }
+
+    /// Gain for the next frame of the voices that duck under others.
+    fn duck_gain(&mut self, ducking: bool) -> f32 {
+        let target = if ducking { self.duck_level.get() } else { 1.0 };
+        self.duck.next(target)
+    }
+
+    /// Snapshot the device switches and master gain target once per callback,
+    /// picking up EQ and compressor changes if they aren't being written right now.
+    fn begin_buffer(&mut self) -> (DeviceControlSnapshot, f32) {
+        let dsp_version = self.controls.dsp_version.load(Ordering::Acquire);
+        if dsp_version != self.dsp_version {
+            if let (Ok(bands), Ok(compressor)) = (
+                self.controls.eq_bands.try_lock(),
+                self.controls.compressor.try_lock(),
+            ) {
+                self.eq.set_bands(&bands);
+                self.compressor.set_settings(*compressor);
+                self.dsp_version = dsp_version;
+            }
+        }
+        let delay_ms = self.controls.delay_ms.load(Ordering::Relaxed);
+        if delay_ms != self.delay_ms {
+            self.delay
+                .set_frames((delay_ms as u64 * self.sample_rate as u64 / 1000) as usize);
+            self.delay_ms = delay_ms;
+        }
+        (self.controls.snapshot(), self.master.get())
+    }
+
+    /// Apply EQ, master gain, mute, polarity inversion, L/R swap, the compressor
+    /// and finally the delay offset to one interleaved frame.
+    fn apply(&mut self, frame: &mut [f32], snapshot: DeviceControlSnapshot, master_target: f32) {
+        self.eq.process_frame(frame);
+        let mute_gain = self.mute.next(if snapshot.muted { 0.0 } else { 1.0 });
+        self.master_gain += (master_target - self.master_gain) * self.master_coeff;
+
+        if snapshot.swap_channels && frame.len() >= 2 {
+            frame.swap(0, 1);
+        }
+        let mut gain = mute_gain * self.master_gain;
+        if snapshot.invert_polarity {
+            gain = -gain;
+        }
+        for sample in frame.iter_mut() {
+            *sample *= gain;
+        }
+        self.compressor.process_frame(frame);
+        self.delay.process_frame(frame);
+    }
+}
+
+/// Fade applied when the preview playhead jumps, to avoid clicks while scrubbing.
+const PREVIEW_SEEK_FADE_MS: u32 = 5;
+
+/// A running scrub preview, playing as a voice on its device's mixer.
+struct PreviewHandle {
+    /// Playhead as an index into the device-format sample buffer
+    position: Arc<AtomicUsize>,
+    sample_rate: u32,
+    channels: u16,
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for PreviewHandle {
+    // Dropping the handle fades the preview out of the mix
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+pub struct AudioOutputState {
+    host: Mutex<Host>,
+    sessions: Arc<SessionMap>,
+    next_session_id: AtomicU64,
+    queues: Mutex<BTreeMap<Vec<String>, VecDeque<QueuedPlayback>>>,
+    // Dropping the sender wakes the timer thread and cancels the pending stop
+    sleep_timer: Mutex<Option<mpsc::Sender<()>>>,
+    preview: Mutex<Option<PreviewHandle>>,
+    device_controls: Mutex<HashMap<String, Arc<DeviceControls>>>,
+    master_gain: Arc<AtomicGain>,
+    /// Linear gain of clips under a ducking playback
+    duck_level: Arc<AtomicGain>,
+    /// Output streams opened so far, keyed by device ID
+    mixers: Mutex<HashMap<String, DeviceMixer>>,
+    stream_configs: Mutex<StreamConfigStore>,
+    /// Integrated loudness of clips measured so far, keyed by content SHA-256
+    loudness_cache: Mutex<HashMap<String, Option<f64>>>,
+    clip_cache: Arc<Mutex<ClipCache>>,
+    /// Mixdown recordings in progress, keyed by device ID
+    recordings: Mutex<HashMap<String, OutputRecording>>,
+    playlist: Mutex<Option<Playlist>>,
+    /// Device the System Default mixer was last opened on
+    default_output: Mutex<Option<String>>,
+    /// Move sessions to the default device when theirs is lost
+    failover: AtomicBool,
+}
+
+impl AudioOutputState {
+    pub fn new() -> Self {
+        Self {
+            host: Mutex::new(cpal::default_host()),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            next_session_id: AtomicU64::new(1),
+            queues: Mutex::new(BTreeMap::new()),
+            sleep_timer: Mutex::new(None),
+            preview: Mutex::new(None),
+            device_controls: Mutex::new(HashMap::new()),
+            master_gain: Arc::new(AtomicGain::new(1.0)),
+            duck_level: Arc::new(AtomicGain::new(10f32.powf(DEFAULT_DUCK_DB / 20.0))),
+            mixers: Mutex::new(HashMap::new()),
+            stream_configs: Mutex::new(StreamConfigStore::default()),
+            loudness_cache: Mutex::new(HashMap::new()),
+            clip_cache: Arc::new(Mutex::new(ClipCache::default())),
+            recordings: Mutex::new(HashMap::new()),
+            playlist: Mutex::new(None),
+            default_output: Mutex::new(None),
+            failover: AtomicBool::new(false),
+        }
+    }
+
+    /// Hook the engine up to the running app so it can emit events.
+    pub fn attach_app_handle(&self, app: AppHandle) {
+        match app.path().app_data_dir() {
+            Ok(dir) => {
+                *self.stream_configs.lock().unwrap() =
+                    StreamConfigStore::load(dir.join("output_devices.json"));
+            }
+            Err(e) => eprintln!("Failed to get app data dir, device configs won't be saved: {}", e),
+        }
+        spawn_session_monitor(app.clone());
+        spawn_level_meter(app.clone());
+        spawn_device_watcher(app);
+    }
+
+    /// Fade out `session_id`, or all playback if none is given, once
+    /// `minutes` have elapsed. Setting a new timer replaces any pending one.
+    pub fn set_sleep_timer(&self, app: AppHandle, minutes: f64, session_id: Option<SessionId>) -> Result<(), String> {
+        if !minutes.is_finite() || minutes <= 0.0 || minutes > MAX_SLEEP_TIMER_MINUTES {
+            return Err(format!(
+                "Sleep timer duration must be greater than zero and at most {} minutes",
+                MAX_SLEEP_TIMER_MINUTES
+            ));
+        }
+        if let Some(session_id) = session_id {
+            let queued = self
+                .queues
+                .lock()
+                .unwrap()
+                .values()
+                .any(|queue| queue.iter().any(|item| item.id == session_id));
+            if !queued && !self.sessions.lock().unwrap().contains_key(&session_id) {
+                return Err(format!("Playback session not found: {}", session_id));
+            }
+        }
+
+        let duration = Duration::from_secs_f64(minutes * 60.0);
+        let (cancel_tx, cancel_rx) = mpsc::channel::<()>();
+        // Stored before the thread starts, so even a very short timer only
+        // ever takes its own sender
+        *self.sleep_timer.lock().unwrap() = Some(cancel_tx);
+
+        std::thread::spawn(move || {
+            // Any message or a dropped sender means the timer was cancelled
+            if let Err(mpsc::RecvTimeoutError::Timeout) = cancel_rx.recv_timeout(duration) {
+                let state = app.state::<AudioOutputState>();
+                {
+                    // The sender is still in place unless the timer was
+                    // cancelled or replaced just as it ran out
+                    let mut sleep_timer = state.sleep_timer.lock().unwrap();
+                    if let Err(mpsc::TryRecvError::Disconnected) = cancel_rx.try_recv() {
+                        return;
+                    }
+                    sleep_timer.take();
+                }
+                match session_id {
+                    Some(session_id) => {
+                        eprintln!("Sleep timer elapsed, fading out session {}", session_id);
+                        if let Err(e) = state.stop_playback(session_id, Some(SLEEP_TIMER_FADE_MS)) {
+                            eprintln!("Sleep timer: {}", e);
+                        }
+                    }
+                    None => {
+                        eprintln!("Sleep timer elapsed, fading out all playback");
+                        if let Err(e) = state.stop_all_playback(Some(SLEEP_TIMER_FADE_MS)) {
+                            eprintln!("Sleep timer: {}", e);
+                        }
+                    }
+                }
+            }
+        });
+
+        eprintln!("set_sleep_timer: Playback will stop in {:.1} minutes", minutes);
+        Ok(())
+    }
+
+    pub fn cancel_sleep_timer(&self) -> Result<(), String> {
+        if self.sleep_timer.lock().unwrap().take().is_some() {
+            eprintln!("cancel_sleep_timer: Pending sleep timer cancelled");
+        }
+        Ok(())
+    }
+
+    /// Stop every active session and clear all queues, fading out over `fade_ms`
+    /// (or a short default fade).
+    pub fn stop_all_playback(&self, fade_ms: Option<u32>) -> Result<(), String> {
+        // Clear queues first so the monitor doesn't start the next item
+        self.clear_playback_queue(None)?;
+        self.playlist.lock().unwrap().take();
+        let fade_ms = fade_ms.unwrap_or(DEFAULT_STOP_FADE_MS);
+        let stopped = stop_sessions(&self.sessions, fade_ms);
+        eprintln!("stop_all_playback: Stopped {} session(s) (fade: {}ms)", stopped, fade_ms);
+        Ok(())
+    }
+
+    /// Stop a single session, leaving any other playback running. Also removes
+    /// the ID from its queue if it hasn't started yet.
+    pub fn stop_playback(&self, session_id: SessionId, fade_ms: Option<u32>) -> Result<(), String> {
+        for queue in self.queues.lock().unwrap().values_mut() {
+            if let Some(index) = queue.iter().position(|item| item.id == session_id) {
+                queue.remove(index);
+                eprintln!("stop_playback: Removed {} from its queue", session_id);
+                return Ok(());
+            }
+        }
+
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .remove(&session_id)
+            .ok_or_else(|| format!("Playback session not found: {}", session_id))?;
+        let fade_ms = fade_ms.unwrap_or(DEFAULT_STOP_FADE_MS);
+        session.control.request_stop(fade_ms);
+        eprintln!("stop_playback: Stopped session {} (fade: {}ms)", session_id, fade_ms);
+        Ok(())
+    }
+
+    /// Freeze a session at its current position. Its voices stay in the mix and
+    /// output silence until it is resumed.
+    pub fn pause_playback(&self, session_id: SessionId) -> Result<(), String> {
+        self.set_paused(session_id, true)
+    }
+
+    pub fn resume_playback(&self, session_id: SessionId) -> Result<(), String> {
+        self.set_paused(session_id, false)
+    }
+
+    /// Move a playing session from one device to another, carrying on from its
+    /// current position. The old device fades out as the new one fades in.
+    pub fn move_playback(
+        &self,
+        session_id: SessionId,
+        from_device_id: &str,
+        to_device_id: &str,
+    ) -> Result<(), String> {
+        let (control, source, position_secs) = {
+            let sessions = self.sessions.lock().unwrap();
+            let session = sessions
+                .get(&session_id)
+                .ok_or_else(|| format!("Playback session not found: {}", session_id))?;
+            if session.streams.iter().any(|stream| stream.device_id == to_device_id) {
+                return Err(format!("Session {} is already playing on {}", session_id, to_device_id));
+            }
+            let stream = session
+                .streams
+                .iter()
+                .find(|stream| stream.device_id == from_device_id)
+                .ok_or_else(|| format!("Session {} isn't playing on {}", session_id, from_device_id))?;
+            (session.control.clone(), session.source.clone(), stream.position_secs())
+        };
+
+        let progress = self
+            .join_session(control, &source, to_device_id, position_secs)
+            .map_err(|e| format!("Can't move session {}: {}", session_id, e))?;
+        self.replace_stream(session_id, from_device_id, progress)?;
+        eprintln!(
+            "move_playback: Moved session {} from {} to {} at {:.2}s",
+            session_id, from_device_id, to_device_id, position_secs
+        );
+        Ok(())
+    }
+
+    /// Whether playback on a device that is lost carries on on the System
+    /// Default output instead of stopping.
+    pub fn set_device_failover(&self, enabled: bool) {
+        self.failover.store(enabled, Ordering::Relaxed);
+        eprintln!("set_device_failover: {}", enabled);
+    }
+
+    /// Remove the mixers of devices whose stream has failed or, with `present`,
+    /// that are no longer in the device list. Returns their IDs, the reason and
+    /// the mixer.
+    fn take_lost_devices(&self, present: Option<&[AudioOutputDevice]>) -> Vec<(String, String, DeviceMixer)> {
+        let mut mixers = self.mixers.lock().unwrap();
+        let lost: Vec<(String, String)> = mixers
+            .iter()
+            .filter_map(|(device_id, mixer)| {
+                if let Some(fault) = mixer.fault() {
+                    return Some((device_id.clone(), fault));
+                }
+                let missing = present
+                    .map(|devices| !devices.iter().any(|device| &device.id == device_id))
+                    .unwrap_or(false);
+                missing.then(|| (device_id.clone(), "Device was disconnected".to_string()))
+            })
+            .collect();
+        lost.into_iter()
+            .filter_map(|(device_id, error)| {
+                let mixer = mixers.remove(&device_id)?;
+                Some((device_id, error, mixer))
+            })
+            .collect()
+    }
+
+    /// Deal with the sessions of a device whose mixer was closed by
+    /// `take_lost_devices`. With failover on they move to the System Default
+    /// output; otherwise their voices on the device are gone and they end.
+    fn recover_lost_device(&self, device_id: String, error: String) -> DeviceLost {
+        let playing: Vec<(SessionId, Arc<SessionControl>, SessionSource, f64, bool)> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(id, session)| {
+                let stream = session.streams.iter().find(|stream| stream.device_id == device_id)?;
+                let on_default = device_id != SYSTEM_DEFAULT_DEVICE_ID
+                    && session
+                        .streams
+                        .iter()
+                        .any(|stream| stream.device_id == SYSTEM_DEFAULT_DEVICE_ID);
+                Some((
+                    *id,
+                    session.control.clone(),
+                    session.source.clone(),
+                    stream.position_secs(),
+                    on_default,
+                ))
+            })
+            .collect();
+
+        let mut failed_over = Vec::new();
+        if self.failover.load(Ordering::Relaxed) {
+            for (session_id, control, source, position_secs, on_default) in &playing {
+                // Still audible on the default device through its other voice
+                if *on_default {
+                    continue;
+                }
+                let result = self
+                    .join_session(control.clone(), source, SYSTEM_DEFAULT_DEVICE_ID, *position_secs)
+                    .and_then(|progress| self.replace_stream(*session_id, &device_id, progress));
+                match result {
+                    Ok(()) => failed_over.push(*session_id),
+                    Err(e) => eprintln!("Failed to move session {} to the default device: {}", session_id, e),
+                }
+            }
+        }
+
+        DeviceLost {
+            session_ids: playing.iter().map(|(id, ..)| *id).collect(),
+            device_id,
+            error,
+            failed_over,
+        }
+    }
+
+    /// Reopen the System Default mixer if the OS default output has changed,
+    /// carrying its sessions over to the new device at their current positions.
+    fn follow_default_device(&self) {
+        let previous = match self.default_output.lock().unwrap().clone() {
+            Some(previous) => previous,
+            None => return,
+        };
+        let current = {
+            let host = self.host.lock().unwrap();
+            let devices = match identified_output_devices(&host) {
+                Ok(devices) => devices,
+                Err(e) => {
+                    eprintln!("follow_default_device: {}", e);
+                    return;
+                }
+            };
+            default_device_position(&host, &devices).map(|position| devices[position].id.clone())
+        };
+        let current = match current {
+            Some(current) if current != previous => current,
+            _ => return,
+        };
+        // Nothing open on the old default; the next playback picks up the new one
+        let old_mixer = match self.mixers.lock().unwrap().remove(SYSTEM_DEFAULT_DEVICE_ID) {
+            Some(mixer) => mixer,
+            None => return,
+        };
+
+        eprintln!("Default output changed from {} to {}, following it", previous, current);
+        if let Err(e) = self.mixer_format(SYSTEM_DEFAULT_DEVICE_ID) {
+            eprintln!("Failed to open the new default output: {}", e);
+            return;
+        }
+        self.reopen_streams(SYSTEM_DEFAULT_DEVICE_ID);
+        // Let the old voices fade out before their stream closes
+        std::thread::sleep(Duration::from_millis(DEVICE_SWAP_FADE_MS as u64 * 2));
+        drop(old_mixer);
+    }
+
+    /// Give every session playing on `device_id` a new voice on the device's
+    /// current mixer, picking up where the old voice is.
+    fn reopen_streams(&self, device_id: &str) {
+        let playing: Vec<(SessionId, Arc<SessionControl>, SessionSource, f64)> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(id, session)| {
+                let stream = session.streams.iter().find(|stream| stream.device_id == device_id)?;
+                Some((*id, session.control.clone(), session.source.clone(), stream.position_secs()))
+            })
+            .collect();
+
+        for (session_id, control, source, position_secs) in playing {
+            let result = self
+                .join_session(control, &source, device_id, position_secs)
+                .and_then(|progress| self.replace_stream(session_id, device_id, progress));
+            if let Err(e) = result {
+                eprintln!("Failed to reopen session {} on {}: {}", session_id, device_id, e);
+            }
+        }
+    }
+
+    /// Add a voice for an already playing session to `device_id`'s mixer,
+    /// starting `position_secs` into the clip.
+    fn join_session(
+        &self,
+        control: Arc<SessionControl>,
+        source: &SessionSource,
+        device_id: &str,
+        position_secs: f64,
+    ) -> Result<StreamProgress, String> {
+        let (device_sample_rate, device_channels) = self.mixer_format(device_id)?;
+        let converter = FormatConverter::new(
+            source.sample_rate,
+            source.channels,
+            device_sample_rate,
+            device_channels,
+        )?;
+        let feed = DeviceFeed::convert(converter, source.feed_gain);
+        let from_frame = (position_secs * source.sample_rate as f64).round() as u64;
+        let progress = StreamProgress {
+            device_id: device_id.to_string(),
+            position: Arc::new(AtomicUsize::new(feed.output_len(from_frame))),
+            total_samples: source
+                .total_frames
+                .map(|frames| feed.output_len(frames))
+                .unwrap_or(0),
+            finished: Arc::new(AtomicBool::new(false)),
+            rate: Arc::new(AtomicGain::new(1.0)),
+            detached: Arc::new(AtomicBool::new(false)),
+            sample_rate: device_sample_rate,
+            channels: device_channels,
+        };
+        let (tx, rx) = mpsc::sync_channel(DECODE_BUFFER_CHUNKS);
+        let cursor = PlaybackCursor::new(rx, &progress, control, &source.options);
+        source
+            .feeds
+            .send(FeedRequest {
+                feed,
+                tx,
+                from_frame,
+            })
+            .map_err(|_| "the clip has finished decoding".to_string())?;
+        self.add_voice(device_id, Box::new(cursor))?;
+        Ok(progress)
+    }
+
+    /// Swap a session's stream on `device_id` for `progress`, fading the old
+    /// voice out.
+    fn replace_stream(&self, session_id: SessionId, device_id: &str, progress: StreamProgress) -> Result<(), String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&session_id)
+            .ok_or_else(|| format!("Playback session not found: {}", session_id))?;
+        let device_ids: Vec<String> = session
+            .device_group
+            .iter()
+            .filter(|id| *id != device_id)
+            .cloned()
+            .chain(std::iter::once(progress.device_id.clone()))
+            .collect();
+        session.device_group = device_group(&device_ids);
+        if let Some(stream) = session.streams.iter_mut().find(|stream| stream.device_id == device_id) {
+            stream.detached.store(true, Ordering::Relaxed);
+            *stream = progress;
+        }
+        // Offsets were measured against the old voice
+        session.drift = DriftCorrector::default();
+        Ok(())
+    }
+
+    fn set_paused(&self, session_id: SessionId, paused: bool) -> Result<(), String> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(&session_id)
+            .ok_or_else(|| format!("Playback session not found: {}", session_id))?;
+        session.control.paused.store(paused, Ordering::Relaxed);
+        eprintln!("set_paused: Session {} paused={}", session_id, paused);
+        Ok(())
+    }
+
+    /// True once a session has played to the end or been stopped. Checks the
+    /// voices directly, so it also works without the session monitor running.
+    pub fn playback_finished(&self, session_id: SessionId) -> bool {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(&session_id)
+            .map(|session| session.is_finished())
+            .unwrap_or(true)
+    }
+
+    pub fn playback_status(&self, session_id: SessionId) -> Result<PlaybackStatus, String> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(&session_id)
+            .ok_or_else(|| format!("Playback session not found: {}", session_id))?;
+        Ok(session.status(session_id))
+    }
+
+    /// Flip the polarity of everything sent to a device, e.g. to fix phase issues
+    /// when it is mixed with the mic on a hardware desk.
+    pub fn set_device_polarity_invert(&self, device_id: &str, enabled: bool) -> Result<(), String> {
+        self.device_controls(device_id)
+            .invert_polarity
+            .store(enabled, Ordering::Relaxed);
+        eprintln!("set_device_polarity_invert: {} -> {}", device_id, enabled);
+        Ok(())
+    }
+
+    /// Silence a device with a short fade. Playback keeps advancing underneath, so
+    /// unmuting picks up wherever the clip has got to.
+    pub fn set_device_mute(&self, device_id: &str, muted: bool) -> Result<(), String> {
+        self.device_controls(device_id)
+            .muted
+            .store(muted, Ordering::Relaxed);
+        eprintln!("set_device_mute: {} -> {}", device_id, muted);
+        Ok(())
+    }
+
+    /// Swap the first two output channels of a device to work around miswired monitoring.
+    pub fn set_device_channel_swap(&self, device_id: &str, enabled: bool) -> Result<(), String> {
+        self.device_controls(device_id)
+            .swap_channels
+            .store(enabled, Ordering::Relaxed);
+        eprintln!("set_device_channel_swap: {} -> {}", device_id, enabled);
+        Ok(())
+    }
+
+    /// Set the master gain (linear, 1.0 = unity) applied to every stream, including
+    /// ones that are already playing.
+    pub fn set_master_gain(&self, gain: f32) -> Result<(), String> {
+        if !(0.0..=MAX_MASTER_GAIN).contains(&gain) {
+            return Err(format!("Master gain must be between 0 and {}", MAX_MASTER_GAIN));
+        }
+        self.master_gain.set(gain);
+        Ok(())
+    }
+
+    pub fn master_gain(&self) -> f32 {
+        self.master_gain.get()
+    }
+
+    /// Set how far clips are lowered while a playback with `duck_others`,
+    /// such as a TTS message, is on the same device. 0 dB turns ducking off.
+    pub fn set_duck_level(&self, level_db: f32) -> Result<(), String> {
+        if !(MIN_DUCK_DB..=0.0).contains(&level_db) {
+            return Err(format!("Duck level must be between {} and 0 dB", MIN_DUCK_DB));
+        }
+        self.duck_level.set(10f32.powf(level_db / 20.0));
+        Ok(())
+    }
+
+    pub fn duck_level(&self) -> f32 {
+        20.0 * self.duck_level.get().log10()
+    }
+
+    /// Replace a device's EQ bands. Takes effect on the next buffer; an empty
+    /// list turns the EQ off.
+    pub fn set_device_eq(&self, device_id: &str, bands: Vec<EqBand>) -> Result<(), String> {
+        if bands.len() > MAX_EQ_BANDS {
+            return Err(format!("At most {} EQ bands are supported", MAX_EQ_BANDS));
+        }
+        for band in &bands {
+            band.validate()?;
+        }
+
+        let controls = self.device_controls(device_id);
+        *controls.eq_bands.lock().unwrap() = bands;
+        controls.dsp_version.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn device_eq(&self, device_id: &str) -> Vec<EqBand> {
+        self.device_controls(device_id).eq_bands.lock().unwrap().clone()
+    }
+
+    /// Turn a device's compressor/limiter on with `settings`, or off with `None`.
+    pub fn set_device_compressor(
+        &self,
+        device_id: &str,
+        settings: Option<CompressorSettings>,
+    ) -> Result<(), String> {
+        if let Some(settings) = &settings {
+            settings.validate()?;
+        }
+
+        let controls = self.device_controls(device_id);
+        *controls.compressor.lock().unwrap() = settings;
+        controls.dsp_version.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn device_compressor(&self, device_id: &str) -> Option<CompressorSettings> {
+        *self.device_controls(device_id).compressor.lock().unwrap()
+    }
+
+    /// Delay everything a device plays by `delay_ms`, to line it up with
+    /// devices that have more output latency.
+    pub fn set_device_delay(&self, device_id: &str, delay_ms: u32) -> Result<(), String> {
+        if delay_ms > MAX_DEVICE_DELAY_MS {
+            return Err(format!(
+                "Device delay must be at most {} ms, got {}",
+                MAX_DEVICE_DELAY_MS, delay_ms
+            ));
+        }
+        self.device_controls(device_id)
+            .delay_ms
+            .store(delay_ms, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn device_delay(&self, device_id: &str) -> u32 {
+        self.device_controls(device_id).delay_ms.load(Ordering::Relaxed)
+    }
+
+    fn device_render(&self, device_id: &str, sample_rate: u32) -> DeviceRender {
+        DeviceRender::new(
+            self.device_controls(device_id),
+            self.master_gain.clone(),
+            self.duck_level.clone(),
+            sample_rate,
+        )
+    }
+
+    fn device_controls(&self, device_id: &str) -> Arc<DeviceControls> {
+        self.device_controls
+            .lock()
+            .unwrap()
+            .entry(device_id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Emergency stop: silence every stream and drop any pending sleep timer so
+    /// nothing comes back on its own.
+    pub fn panic_stop(&self) -> Result<(), String> {
+        eprintln!("panic_stop: Stopping all playback");
+        self.stop_all_playback(None)?;
+        self.stop_preview()?;
+        self.cancel_sleep_timer()
+    }
+
+    pub fn list_output_devices(&self) -> Result<Vec<AudioOutputDevice>, String> {
+        let host = self.host.lock().unwrap();
+        let devices = identified_output_devices(&host)?;
+        let default = default_device_position(&host, &devices);
+
+        let system_default = AudioOutputDevice {
+            id: SYSTEM_DEFAULT_DEVICE_ID.to_string(),
+            platform_id: None,
+            name: "System Default".to_string(),
+            is_default: false,
+        };
+        let result = std::iter::once(system_default)
+            .chain(devices.into_iter().enumerate().map(|(index, device)| AudioOutputDevice {
+                id: device.id,
+                platform_id: device.platform_id,
+                name: device.name,
+                is_default: default == Some(index),
+            }))
+            .collect();
+
+        Ok(result)
+    }
+
+    pub async fn play_audio_to_devices(
+        &self,
+        audio_data: Vec<u8>,
+        device_ids: Vec<String>,
+        lead_in: Option<LeadIn>,
+        options: Option<PlaybackOptions>,
+    ) -> Result<SessionId, String> {
+        eprintln!("play_audio_to_devices called with {} bytes, {} device IDs", audio_data.len(), device_ids.len());
+        let options = options.unwrap_or_default();
+        let hash = hash_bytes(&audio_data);
+        let gain = self.normalization_gain(&options, &hash, || {
+            self.open_decoder(&hash, || ClipDecoder::new(audio_data.clone()))
+        });
+        let decoder = self.open_decoder(&hash, || ClipDecoder::new(audio_data))?;
+        let mut clip = self.open_clip(decoder, lead_in, &options)?;
+        clip.gain *= gain;
+        clip.content_hash = Some(hash);
+        let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        self.start_session(session_id, clip, device_ids, options)?;
+        eprintln!("play_audio_to_devices completed successfully (session {})", session_id);
+        Ok(session_id)
+    }
+
+    /// Play a generated sine or noise burst on one device, so routing can be
+    /// checked without a sample file. Stops like any other session.
+    pub fn play_test_tone(
+        &self,
+        device_id: String,
+        frequency: f32,
+        duration_ms: u32,
+        kind: Option<TestToneKind>,
+    ) -> Result<SessionId, String> {
+        let kind = kind.unwrap_or_default();
+        let samples = decode::render_test_tone(kind, frequency, duration_ms, TEST_TONE_SAMPLE_RATE)?;
+        let decoder = ClipDecoder::from_cache(DecodedClip {
+            samples: samples.into(),
+            sample_rate: TEST_TONE_SAMPLE_RATE,
+            channels: 1,
+        });
+        let options = PlaybackOptions {
+            fade_in_ms: TEST_TONE_FADE_MS,
+            fade_out_ms: TEST_TONE_FADE_MS,
+            ..Default::default()
+        };
+        let clip = self.open_clip(decoder, None, &options)?;
+        let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        eprintln!(
+            "play_test_tone: {:?} {} Hz for {} ms on {} (session {})",
+            kind, frequency, duration_ms, device_id, session_id
+        );
+        self.start_session(session_id, clip, vec![device_id], options)?;
+        Ok(session_id)
+    }
+
+    /// Play a file from disk, decoding it as it plays rather than passing its
+    /// bytes through IPC.
+    pub fn play_file_to_devices(
+        &self,
+        path: &Path,
+        device_ids: Vec<String>,
+        lead_in: Option<LeadIn>,
+        options: Option<PlaybackOptions>,
+    ) -> Result<SessionId, String> {
+        let (hash, _) = hash_file(path)?;
+        self.play_hashed_file_to_devices(path, hash, device_ids, lead_in, options)
+    }
+
+    /// Like `play_file_to_devices`, for a file whose SHA-256 the caller
+    /// already knows, such as a library clip, so it isn't read an extra time.
+    pub fn play_hashed_file_to_devices(
+        &self,
+        path: &Path,
+        hash: String,
+        device_ids: Vec<String>,
+        lead_in: Option<LeadIn>,
+        options: Option<PlaybackOptions>,
+    ) -> Result<SessionId, String> {
+        eprintln!("play_file_to_devices called with {}, {} device IDs", path.display(), device_ids.len());
+        let options = options.unwrap_or_default();
+        let gain = self.normalization_gain(&options, &hash, || {
+            self.open_decoder(&hash, || ClipDecoder::open_file(path))
+        });
+        let decoder = self.open_decoder(&hash, || ClipDecoder::open_file(path))?;
+        let mut clip = self.open_clip(decoder, lead_in, &options)?;
+        clip.gain *= gain;
+        clip.content_hash = Some(hash);
+        let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        self.start_session(session_id, clip, device_ids, options)?;
+        eprintln!("play_file_to_devices completed successfully (session {})", session_id);
+        Ok(session_id)
+    }
+
+    /// Stream a clip from an HTTP(S) URL. Decoding starts once the first block
+    /// has downloaded; the rest is fetched as playback needs it.
+    pub async fn play_url_to_devices(
+        &self,
+        url: String,
+        device_ids: Vec<String>,
+        lead_in: Option<LeadIn>,
+        options: Option<PlaybackOptions>,
+    ) -> Result<SessionId, String> {
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(format!("Not an HTTP(S) URL: {}", url));
+        }
+        eprintln!("play_url_to_devices called with {}, {} device IDs", url, device_ids.len());
+        let options = options.unwrap_or_default();
+
+        // The probe blocks on the network, so keep it off the async runtime
+        let decoder = tauri::async_runtime::spawn_blocking(move || ClipDecoder::open_url(&url))
+            .await
+            .map_err(|e| format!("Failed to open URL: {}", e))??;
+        if options.target_lufs.is_some() {
+            // Measuring would mean downloading the whole clip before it starts
+            eprintln!("play_url_to_devices: Loudness normalization isn't supported for streams");
+        }
+        let clip = self.open_clip(decoder, lead_in, &options)?;
+        let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        self.start_session(session_id, clip, device_ids, options)?;
+        eprintln!("play_url_to_devices completed successfully (session {})", session_id);
+        Ok(session_id)
+    }
+
+    /// Play audio that is still being produced, such as streamed speech, as
+    /// it arrives on `chunks` (interleaved at `sample_rate` and `channels`).
+    /// Gaps in the stream play as silence.
+    pub(crate) fn play_live_to_devices(
+        &self,
+        chunks: mpsc::Receiver<Result<Vec<f32>, String>>,
+        sample_rate: u32,
+        channels: u16,
+        device_ids: Vec<String>,
+        options: Option<PlaybackOptions>,
+    ) -> Result<SessionId, String> {
+        eprintln!("play_live_to_devices called with {} device IDs", device_ids.len());
+        let options = options.unwrap_or_default();
+        if options.target_lufs.is_some() {
+            eprintln!("play_live_to_devices: Loudness normalization isn't supported for streams");
+        }
+        let decoder = ClipDecoder::live(chunks, sample_rate, channels);
+        let clip = self.open_clip(decoder, None, &options)?;
+        let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        self.start_session(session_id, clip, device_ids, options)?;
+        eprintln!("play_live_to_devices completed successfully (session {})", session_id);
+        Ok(session_id)
+    }
+
+    /// Play a clip on a device group, or queue it behind whatever that exact group
+    /// is already playing. The returned ID becomes the session ID once it starts.
+    pub async fn queue_audio_to_devices(
+        &self,
+        audio_data: Vec<u8>,
+        device_ids: Vec<String>,
+        lead_in: Option<LeadIn>,
+        options: Option<PlaybackOptions>,
+    ) -> Result<SessionId, String> {
+        eprintln!("queue_audio_to_devices called with {} bytes, {} device IDs", audio_data.len(), device_ids.len());
+        let options = options.unwrap_or_default();
+        let hash = hash_bytes(&audio_data);
+        let gain = self.normalization_gain(&options, &hash, || {
+            self.open_decoder(&hash, || ClipDecoder::new(audio_data.clone()))
+        });
+        let decoder = self.open_decoder(&hash, || ClipDecoder::new(audio_data))?;
+        let mut clip = self.open_clip(decoder, lead_in, &options)?;
+        clip.gain *= gain;
+        clip.content_hash = Some(hash);
+        let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        let group = device_group(&device_ids);
+
+        let busy = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .any(|session| session.device_group == group);
+        let mut queues = self.queues.lock().unwrap();
+        let waiting = queues.get(&group).map(|queue| !queue.is_empty()).unwrap_or(false);
+
+        if busy || waiting {
+            eprintln!("queue_audio_to_devices: Group {:?} is busy, queueing {}", group, session_id);
+            queues.entry(group).or_default().push_back(QueuedPlayback {
+                id: session_id,
+                clip,
+                device_ids,
+                options,
+            });
+            return Ok(session_id);
+        }
+        drop(queues);
+
+        self.start_session(session_id, clip, device_ids, options)?;
+        Ok(session_id)
+    }
+
+    /// Start playing `items` in order on `device_ids`, replacing any playlist
+    /// that is already playing.
+    pub fn play_playlist(
+        &self,
+        items: Vec<PlaylistItem>,
+        device_ids: Vec<String>,
+        options: Option<PlaybackOptions>,
+        start_index: Option<usize>,
+    ) -> Result<PlaylistStatus, String> {
+        let index = start_index.unwrap_or(0);
+        if index >= items.len() {
+            return Err(format!("Playlist has no item {} ({} items)", index, items.len()));
+        }
+
+        let mut current = self.playlist.lock().unwrap();
+        if let Some(session) = current.take().and_then(|playlist| playlist.session) {
+            let _ = self.stop_playback(session, None);
+        }
+        let mut playlist = Playlist {
+            items,
+            device_ids,
+            options: options.unwrap_or_default(),
+            index,
+            session: None,
+            next_at: None,
+        };
+        self.start_playlist_item(&mut playlist)?;
+        let status = playlist.status();
+        *current = Some(playlist);
+        Ok(status)
+    }
+
+    /// Skip to the next playlist item, or finish the playlist after the last one.
+    pub fn playlist_next(&self) -> Result<PlaylistStatus, String> {
+        self.jump_playlist(|index| index + 1)
+    }
+
+    /// Go back to the previous playlist item, or restart the first one.
+    pub fn playlist_previous(&self) -> Result<PlaylistStatus, String> {
+        self.jump_playlist(|index| index.saturating_sub(1))
+    }
+
+    pub fn stop_playlist(&self) -> Result<(), String> {
+        let playlist = self
+            .playlist
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| "No playlist is playing".to_string())?;
+        if let Some(session) = playlist.session {
+            let _ = self.stop_playback(session, None);
+        }
+        eprintln!("stop_playlist: Stopped at item {}", playlist.index);
+        Ok(())
+    }
+
+    pub fn playlist_status(&self) -> Option<PlaylistStatus> {
+        self.playlist.lock().unwrap().as_ref().map(Playlist::status)
+    }
+
+    fn jump_playlist(&self, next_index: impl FnOnce(usize) -> usize) -> Result<PlaylistStatus, String> {
+        let mut current = self.playlist.lock().unwrap();
+        let playlist = current
+            .as_mut()
+            .ok_or_else(|| "No playlist is playing".to_string())?;
+        if let Some(session) = playlist.session.take() {
+            let _ = self.stop_playback(session, None);
+        }
+
+        playlist.index = next_index(playlist.index);
+        if playlist.index >= playlist.items.len() {
+            let status = playlist.finished_status();
+            *current = None;
+            return Ok(status);
+        }
+        match self.start_playlist_item(playlist) {
+            Ok(()) => Ok(playlist.status()),
+            Err(e) => {
+                *current = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// Start the item at `playlist.index`, skipping forward past items that
+    /// fail to play.
+    fn start_playlist_item(&self, playlist: &mut Playlist) -> Result<(), String> {
+        playlist.next_at = None;
+        while playlist.index < playlist.items.len() {
+            let path = Path::new(&playlist.items[playlist.index].path).to_path_buf();
+            match self.play_file_to_devices(
+                &path,
+                playlist.device_ids.clone(),
+                None,
+                Some(playlist.item_options()),
+            ) {
+                Ok(session) => {
+                    playlist.session = Some(session);
+                    return Ok(());
+                }
+                Err(e) => {
+                    eprintln!("Skipping playlist item {} ({}): {}", playlist.index, path.display(), e);
+                    playlist.index += 1;
+                }
+            }
+        }
+        Err("No playable items left in the playlist".to_string())
+    }
+
+    /// Move the playlist on once its current item has played out and the gap
+    /// after it has passed. A session stopped from outside the playlist ends
+    /// it. Returns the new status if anything changed.
+    fn advance_playlist(&self, finished: &[SessionId]) -> Option<PlaylistStatus> {
+        let mut current = self.playlist.lock().unwrap();
+        let playlist = current.as_mut()?;
+
+        if let Some(session) = playlist.session {
+            if finished.contains(&session) {
+                let gap_ms = playlist.items[playlist.index].gap_ms as u64;
+                playlist.session = None;
+                playlist.next_at = Some(Instant::now() + Duration::from_millis(gap_ms));
+            } else if !self.sessions.lock().unwrap().contains_key(&session) {
+                eprintln!("advance_playlist: Session {} was stopped, ending the playlist", session);
+                let status = playlist.finished_status();
+                *current = None;
+                return Some(status);
+            } else {
+                return None;
+            }
+        }
+
+        match playlist.next_at {
+            Some(next_at) if next_at <= Instant::now() => {}
+            _ => return None,
+        }
+        playlist.index += 1;
+        if playlist.index < playlist.items.len() && self.start_playlist_item(playlist).is_ok() {
+            return Some(playlist.status());
+        }
+        eprintln!("advance_playlist: Playlist finished");
+        let status = playlist.finished_status();
+        *current = None;
+        Some(status)
+    }
+
+    /// Snapshot of every non-empty queue, in play order.
+    pub fn playback_queues(&self) -> Vec<PlaybackQueue> {
+        self.queues
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, queue)| !queue.is_empty())
+            .map(|(group, queue)| PlaybackQueue {
+                device_ids: group.clone(),
+                items: queue
+                    .iter()
+                    .map(|item| QueuedPlaybackInfo {
+                        id: item.id,
+                        duration_ms: (item.clip.duration_ms() as f64 / item.options.speed()) as u64,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Move a queued item to `index` within its queue (clamped to the end).
+    pub fn move_queued_playback(&self, id: SessionId, index: usize) -> Result<(), String> {
+        let mut queues = self.queues.lock().unwrap();
+        for queue in queues.values_mut() {
+            if let Some(current) = queue.iter().position(|item| item.id == id) {
+                let item = queue.remove(current).expect("index from position");
+                let index = index.min(queue.len());
+                queue.insert(index, item);
+                return Ok(());
+            }
+        }
+        Err(format!("Queued playback not found: {}", id))
+    }
+
+    /// Drop queued items for one device group, or for every group when `device_ids`
+    /// is `None`. Whatever is currently playing is left alone.
+    pub fn clear_playback_queue(&self, device_ids: Option<Vec<String>>) -> Result<(), String> {
+        let mut queues = self.queues.lock().unwrap();
+        match device_ids {
+            Some(device_ids) => {
+                queues.remove(&device_group(&device_ids));
+            }
+            None => queues.clear(),
+        }
+        Ok(())
+    }
+
+    /// Start the next queued item for every group that has gone idle.
+    fn advance_queues(&self) {
+        let busy: HashSet<Vec<String>> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .map(|session| session.device_group.clone())
+            .collect();
+
+        let ready: Vec<QueuedPlayback> = {
+            let mut queues = self.queues.lock().unwrap();
+            let ready = queues
+                .iter_mut()
+                .filter(|(group, _)| !busy.contains(*group))
+                .filter_map(|(_, queue)| queue.pop_front())
+                .collect();
+            queues.retain(|_, queue| !queue.is_empty());
+            ready
+        };
+
+        for item in ready {
+            eprintln!("advance_queues: Starting queued playback {}", item.id);
+            if let Err(e) = self.start_session(item.id, item.clip, item.device_ids, item.options) {
+                eprintln!("advance_queues: Failed to start queued playback {}: {}", item.id, e);
+            }
+        }
+    }
+
+    /// Decoder for a clip, served from the decoded-clip cache when its content
+    /// `hash` is in it. Otherwise the clip is opened with `open` and cached once
+    /// fully decoded.
+    fn open_decoder(
+        &self,
+        hash: &str,
+        open: impl FnOnce() -> Result<ClipDecoder, String>,
+    ) -> Result<ClipDecoder, String> {
+        if let Some(clip) = self.clip_cache.lock().unwrap().get(hash) {
+            eprintln!("Playing clip from the decoded-clip cache");
+            return Ok(ClipDecoder::from_cache(clip));
+        }
+        let mut decoder = open()?;
+        decoder.cache_into(self.clip_cache.clone(), hash.to_string());
+        Ok(decoder)
+    }
+
+    fn open_clip(
+        &self,
+        decoder: ClipDecoder,
+        lead_in: Option<LeadIn>,
+        options: &PlaybackOptions,
+    ) -> Result<PendingClip, String> {
+        let (start_frame, end_frame) = match options.trim {
+            Some(trim) => {
+                trim.validate()?;
+                trim.frames(decoder.sample_rate)
+            }
+            None => (0, None),
+        };
+
+        let lead_in = match lead_in {
+            Some(lead_in) => {
+                let samples = decode::render_lead_in(lead_in, decoder.sample_rate, decoder.channels)?;
+                eprintln!("Prepending {} lead-in samples ({:?})", samples.len(), lead_in);
+                samples
+            }
+            None => Vec::new(),
+        };
+
+        Ok(PendingClip {
+            decoder,
+            lead_in,
+            gain: 10f32.powf(options.gain_db / 20.0),
+            start_frame,
+            end_frame,
+            content_hash: None,
+        })
+    }
+
+    /// Gain that brings a clip to `options.target_lufs`, or 1.0 if no target is
+    /// set. `hash` identifies the clip's content; `open` gives a fresh decoder
+    /// for measuring it when the loudness isn't cached yet.
+    fn normalization_gain(
+        &self,
+        options: &PlaybackOptions,
+        hash: &str,
+        open: impl FnOnce() -> Result<ClipDecoder, String>,
+    ) -> f32 {
+        let target = match options.target_lufs {
+            Some(target) => target as f64,
+            None => return 1.0,
+        };
+
+        let cached = self.loudness_cache.lock().unwrap().get(hash).copied();
+        let measured = match cached {
+            Some(measured) => measured,
+            None => match open().and_then(loudness::measure_clip) {
+                Ok(measured) => {
+                    self.loudness_cache.lock().unwrap().insert(hash.to_string(), measured);
+                    measured
+                }
+                Err(e) => {
+                    eprintln!("Failed to measure loudness, playing unnormalized: {}", e);
+                    return 1.0;
+                }
+            },
+        };
+
+        match measured {
+            Some(lufs) => {
+                let gain_db = normalization_db(target, lufs);
+                eprintln!("Clip loudness {:.1} LUFS, applying {:+.1} dB", lufs, gain_db);
+                10f64.powf(gain_db / 20.0) as f32
+            }
+            // Silent or shorter than one gating block: nothing to normalize
+            None => 1.0,
+        }
+    }
+
+    /// Add a voice for `clip` to the mixer of every requested device, start
+    /// decoding and register the session. Devices that can't be found or opened
+    /// are skipped.
+    fn start_session(
+        &self,
+        session_id: SessionId,
+        mut clip: PendingClip,
+        device_ids: Vec<String>,
+        options: PlaybackOptions,
+    ) -> Result<(), String> {
+        eprintln!("Requested device IDs: {:?}", device_ids);
+
+        let control = Arc::new(SessionControl::default());
+        let group = device_group(&device_ids);
+        // Pitch shifting resamples the clip as if it were recorded at a higher or
+        // lower rate, and the time-stretch makes up the length difference
+        let pitch = options.pitch_ratio();
+        let stretch = options.speed() / pitch;
+        let source_rate = (clip.decoder.sample_rate as f64 * pitch).round() as u32;
+        let total_frames = clip
+            .total_frames()
+            .map(|frames| (frames as f64 / stretch).round() as u64);
+        // A whole clip at its own pitch and speed converts the same way every
+        // time, so each device's conversion can be cached. The gain is applied
+        // after conversion so the cached audio doesn't depend on it.
+        let cache_hash = clip
+            .content_hash
+            .clone()
+            .filter(|_| clip.is_whole() && pitch == 1.0 && stretch == 1.0 && !options.trim_silence);
+        let feed_gain = match cache_hash {
+            Some(_) => std::mem::replace(&mut clip.gain, 1.0),
+            None => 1.0,
+        };
+        let mut streams = Vec::with_capacity(group.len());
+        let mut feeds = Vec::with_capacity(group.len());
+        for device_id in &group {
+            let (device_sample_rate, device_channels) = match self.mixer_format(device_id) {
+                Ok(format) => format,
+                Err(e) => {
+                    eprintln!("Skipping device {}: {}", device_id, e);
+                    continue;
+                }
+            };
+
+            let feed = match self.device_feed(
+                cache_hash.as_deref(),
+                source_rate,
+                clip.decoder.channels,
+                device_sample_rate,
+                device_channels,
+                feed_gain,
+            ) {
+                Ok(feed) => feed,
+                Err(e) => {
+                    eprintln!("Skipping device {}: {}", device_id, e);
+                    continue;
+                }
+            };
+            let progress = StreamProgress {
+                device_id: device_id.clone(),
+                position: Arc::new(AtomicUsize::new(0)),
+                total_samples: total_frames.map(|frames| feed.output_len(frames)).unwrap_or(0),
+                finished: Arc::new(AtomicBool::new(false)),
+                rate: Arc::new(AtomicGain::new(1.0)),
+                detached: Arc::new(AtomicBool::new(false)),
+                sample_rate: device_sample_rate,
+                channels: device_channels,
+            };
+            let (tx, rx) = mpsc::sync_channel(DECODE_BUFFER_CHUNKS);
+            let cursor = PlaybackCursor::new(rx, &progress, control.clone(), &options);
+
+            if let Err(e) = self.add_voice(device_id, Box::new(cursor)) {
+                eprintln!("Skipping device {}: {}", device_id, e);
+                continue;
+            }
+            streams.push(progress);
+            feeds.push((feed, tx));
+        }
+
+        if streams.is_empty() {
+            eprintln!("ERROR: No matching devices found");
+            return Err("No matching devices found".to_string());
+        }
+
+        eprintln!("Playing session {} on {} device(s)", session_id, streams.len());
+        let (requests_tx, requests) = mpsc::channel();
+        let source = SessionSource {
+            feeds: requests_tx,
+            sample_rate: source_rate,
+            channels: clip.decoder.channels,
+            total_frames,
+            options: options.clone(),
+            feed_gain,
+        };
+        spawn_decoder_thread(clip, stretch, options.trim_silence, feeds, requests);
+        self.sessions.lock().unwrap().insert(
+            session_id,
+            PlaybackSession {
+                control,
+                source,
+                drift: DriftCorrector::default(),
+                streams,
+                device_group: group,
+            },
+        );
+        Ok(())
+    }
+
+    /// Feed for one device of a new session. With a `hash`, the device plays
+    /// the clip's cached conversion to its format, or fills the cache with it.
+    fn device_feed(
+        &self,
+        hash: Option<&str>,
+        source_rate: u32,
+        source_channels: u16,
+        sample_rate: u32,
+        channels: u16,
+        gain: f32,
+    ) -> Result<DeviceFeed, String> {
+        // Nothing to save when the device already matches the clip
+        let hash = hash.filter(|_| (source_rate, source_channels) != (sample_rate, channels));
+        if let Some(hash) = hash {
+            if let Some(samples) = self.clip_cache.lock().unwrap().get_converted(hash, sample_rate, channels) {
+                eprintln!("Playing clip converted to {}Hz/{}ch from the cache", sample_rate, channels);
+                return Ok(DeviceFeed::cached(samples, source_rate, source_channels, sample_rate, channels, gain));
+            }
+        }
+        let converter = FormatConverter::new(source_rate, source_channels, sample_rate, channels)?;
+        let mut feed = DeviceFeed::convert(converter, gain);
+        if let Some(hash) = hash {
+            feed.cache_into(self.clip_cache.clone(), hash.to_string(), sample_rate, channels);
+        }
+        Ok(feed)
+    }
+
+    /// Sample rate and channel count of a device's mixer, opening the mixer on
+    /// first use.
+    fn mixer_format(&self, device_id: &str) -> Result<(u32, u16), String> {
+        let mut mixers = self.mixers.lock().unwrap();
+        if let Some(mixer) = mixers.get(device_id) {
+            return Ok((mixer.sample_rate, mixer.channels));
+        }
+
+        let device = self.find_identified_device(device_id)?;
+        if device_id == SYSTEM_DEFAULT_DEVICE_ID {
+            eprintln!("System Default output is {}", device.id);
+            *self.default_output.lock().unwrap() = Some(device.id.clone());
+        }
+        let requested = self.stream_configs.lock().unwrap().get(device_id);
+        let (config, buffer_size) = resolve_stream_config(&device.device, &requested)?;
+
+        #[cfg(target_os = "windows")]
+        if requested.exclusive {
+            if let Some(endpoint_id) = &device.platform_id {
+                let buffer_frames = match buffer_size {
+                    cpal::BufferSize::Fixed(frames) => Some(frames),
+                    cpal::BufferSize::Default => None,
+                };
+                match DeviceMixer::open_exclusive(
+                    endpoint_id,
+                    config.sample_rate().0,
+                    config.channels(),
+                    buffer_frames,
+                    self.device_render(device_id, config.sample_rate().0),
+                ) {
+                    Ok(mixer) => {
+                        let format = (mixer.sample_rate, mixer.channels);
+                        mixers.insert(device_id.to_string(), mixer);
+                        return Ok(format);
+                    }
+                    Err(e) => eprintln!("Exclusive mode unavailable for {}, using shared mode: {}", device_id, e),
+                }
+            }
+        }
+
+        let render = self.device_render(device_id, config.sample_rate().0);
+        let mixer = DeviceMixer::open(device.device, config, buffer_size, render)?;
+        let format = (mixer.sample_rate, mixer.channels);
+        mixers.insert(device_id.to_string(), mixer);
+        Ok(format)
+    }
+
+    /// Route output through ASIO instead of the platform's default host. Only
+    /// available in Windows builds with the `asio` feature.
+    pub fn set_asio_enabled(&self, enabled: bool) -> Result<(), String> {
+        self.set_optional_host("ASIO", enabled)
+    }
+
+    /// Route output through JACK (or PipeWire's JACK server) so VoiceBox shows
+    /// up as a JACK client. Only available in Linux builds with the `jack` feature.
+    pub fn set_jack_enabled(&self, enabled: bool) -> Result<(), String> {
+        self.set_optional_host("JACK", enabled)
+    }
+
+    fn set_optional_host(&self, name: &str, enabled: bool) -> Result<(), String> {
+        if enabled {
+            self.set_output_host(name).map(|_| ())
+        } else {
+            self.switch_host(cpal::default_host().id())
+        }
+    }
+
+    /// Hosts usable on this machine, e.g. WASAPI and ASIO on Windows or ALSA and
+    /// JACK on Linux, depending on build features.
+    pub fn list_output_hosts(&self) -> Vec<AudioHost> {
+        let active = self.host.lock().unwrap().id();
+        let default = cpal::default_host().id();
+        cpal::available_hosts()
+            .into_iter()
+            .map(|id| AudioHost {
+                name: id.name().to_string(),
+                is_default: id == default,
+                is_active: id == active,
+            })
+            .collect()
+    }
+
+    /// Switch output to the host called `name` and return its devices.
+    pub fn set_output_host(&self, name: &str) -> Result<Vec<AudioOutputDevice>, String> {
+        let host_id = cpal::available_hosts()
+            .into_iter()
+            .find(|id| id.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("{} support is not available in this build", name))?;
+        self.switch_host(host_id)?;
+        self.list_output_devices()
+    }
+
+    /// Make `host_id` the host every device is enumerated and opened on. Open
+    /// streams belong to the old host, so all playback is stopped first.
+    fn switch_host(&self, host_id: cpal::HostId) -> Result<(), String> {
+        if self.host.lock().unwrap().id() == host_id {
+            return Ok(());
+        }
+        let host = cpal::host_from_id(host_id)
+            .map_err(|e| format!("Failed to open {} host: {}", host_id.name(), e))?;
+
+        self.stop_all_playback(None)?;
+        self.stop_preview()?;
+        self.mixers.lock().unwrap().clear();
+        *self.host.lock().unwrap() = host;
+        eprintln!("switch_host: Now using the {} host", host_id.name());
+        Ok(())
+    }
+
+    /// Estimated output latency in milliseconds for every device with an open
+    /// stream, once its stream has reported one.
+    pub fn output_latencies(&self) -> HashMap<String, f64> {
+        self.mixers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(id, mixer)| {
+                mixer
+                    .output_latency()
+                    .map(|latency| (id.clone(), latency.as_secs_f64() * 1000.0))
+            })
+            .collect()
+    }
+
+    /// Levels of every open device mixer since the last call.
+    fn output_levels(&self) -> Vec<OutputLevel> {
+        self.mixers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(device_id, mixer)| {
+                mixer.take_levels().map(|(peak, rms)| OutputLevel {
+                    device_id: device_id.clone(),
+                    peak_dbfs: peak.into_iter().map(to_dbfs).collect(),
+                    rms_dbfs: rms.into_iter().map(to_dbfs).collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// Mix live input (such as the microphone) captured at `source_rate` and
+    /// `source_channels` into a device. Returns the feed the capture pushes into.
+    pub(crate) fn add_live_input(
+        &self,
+        device_id: &str,
+        source_rate: u32,
+        source_channels: u16,
+        control: Arc<LiveInputControl>,
+    ) -> Result<LiveInputFeed, String> {
+        let (sample_rate, channels) = self.mixer_format(device_id)?;
+        let (voice, feed) = live_input(source_rate, source_channels, sample_rate, channels, control)?;
+        match self.mixers.lock().unwrap().get(device_id) {
+            Some(mixer) => mixer.add_voice(Box::new(voice))?,
+            None => return Err(format!("Device mixer closed: {}", device_id)),
+        }
+        Ok(feed)
+    }
+
+    /// Copy everything a device plays, after all of its gain and processing,
+    /// to a channel the caller reads at its own pace.
+    pub(crate) fn tap_output(&self, device_id: &str) -> Result<OutputTap, String> {
+        let (sample_rate, channels) = self.mixer_format(device_id)?;
+        let (tx, tap) = output_tap(sample_rate, channels);
+        match self.mixers.lock().unwrap().get(device_id) {
+            Some(mixer) => mixer.add_output_tap(tx),
+            None => return Err(format!("Device mixer closed: {}", device_id)),
+        }
+        Ok(tap)
+    }
+
+    /// Record everything sent to a device into a WAV, FLAC or Opus file, after all of
+    /// the device's gain and processing.
+    pub fn start_output_recording(&self, device_id: &str, path: &Path) -> Result<(), String> {
+        let mut recordings = self.recordings.lock().unwrap();
+        if recordings.contains_key(device_id) {
+            return Err(format!("Device {} is already being recorded", device_id));
+        }
+
+        let (sample_rate, channels) = self.mixer_format(device_id)?;
+        let (recording, tap) = OutputRecording::start(path, sample_rate, channels)?;
+        match self.mixers.lock().unwrap().get(device_id) {
+            Some(mixer) => mixer.set_recording_tap(Some(tap)),
+            None => return Err(format!("Device mixer closed: {}", device_id)),
+        }
+        recordings.insert(device_id.to_string(), recording);
+        Ok(())
+    }
+
+    /// Stop recording a device and finish writing the file.
+    pub fn stop_output_recording(&self, device_id: &str) -> Result<(), String> {
+        let recording = match self.recordings.lock().unwrap().remove(device_id) {
+            Some(recording) => recording,
+            None => return Err(format!("Device {} is not being recorded", device_id)),
+        };
+        // A mixer that was closed in the meantime has already dropped its tap
+        if let Some(mixer) = self.mixers.lock().unwrap().get(device_id) {
+            mixer.set_recording_tap(None);
+        }
+        recording.finish()
+    }
+
+    /// Sample rates, channel counts and formats `device_id` can be opened with.
+    pub fn output_device_capabilities(&self, device_id: &str) -> Result<DeviceCapabilities, String> {
+        device_capabilities(&self.find_output_device(device_id)?)
+    }
+
+    pub fn get_output_device_config(&self, device_id: &str) -> DeviceStreamConfig {
+        self.stream_configs.lock().unwrap().get(device_id)
+    }
+
+    /// Validate and save a stream config for a device. Its stream is reopened
+    /// with the new config, which cuts off anything playing on it.
+    pub fn set_output_device_config(
+        &self,
+        device_id: &str,
+        config: DeviceStreamConfig,
+    ) -> Result<(), String> {
+        let device = self.find_output_device(device_id)?;
+        resolve_stream_config(&device, &config)?;
+        self.stream_configs.lock().unwrap().set(device_id, config)?;
+
+        let was_open = self.mixers.lock().unwrap().remove(device_id).is_some();
+        if was_open {
+            self.mixer_format(device_id)?;
+            eprintln!("set_output_device_config: Reopened {} with the new config", device_id);
+        }
+        Ok(())
+    }
+
+    /// Decode a file into `buckets` min/max pairs for drawing its waveform. The
+    /// decoded clip is cached, so playing it afterwards starts immediately.
+    pub fn waveform_peaks(&self, path: &Path, buckets: usize) -> Result<WaveformPeaks, String> {
+        if buckets == 0 || buckets > MAX_WAVEFORM_BUCKETS {
+            return Err(format!(
+                "Waveform resolution must be 1-{} buckets, got {}",
+                MAX_WAVEFORM_BUCKETS, buckets
+            ));
+        }
+        let (hash, _) = hash_file(path)?;
+        let decoder = self.open_decoder(&hash, || ClipDecoder::open_file(path))?;
+        peaks::waveform_peaks(decoder, buckets)
+    }
+
+    /// Hand `voice` to a device's mixer. The voice must already be in the format
+    /// reported by `mixer_format`.
+    fn add_voice(&self, device_id: &str, voice: Box<dyn Voice>) -> Result<(), String> {
+        self.mixers
+            .lock()
+            .unwrap()
+            .get(device_id)
+            .ok_or_else(|| format!("No mixer open for device: {}", device_id))?
+            .add_voice(voice)
+    }
+
+    fn find_output_device(&self, device_id: &str) -> Result<Device, String> {
+        self.find_identified_device(device_id).map(|device| device.device)
+    }
+
+    /// Look up a device by ID. `SYSTEM_DEFAULT_DEVICE_ID` resolves to the
+    /// current default output.
+    fn find_identified_device(&self, device_id: &str) -> Result<IdentifiedDevice, String> {
+        let host = self.host.lock().unwrap();
+        let mut devices = identified_output_devices(&host)?;
+        if device_id == SYSTEM_DEFAULT_DEVICE_ID {
+            let position = default_device_position(&host, &devices)
+                .ok_or_else(|| "No default output device".to_string())?;
+            return Ok(devices.swap_remove(position));
+        }
+        devices
+            .into_iter()
+            .find(|device| device.id == device_id)
+            .ok_or_else(|| format!("Output device not found: {}", device_id))
+    }
+
+    /// Start previewing a clip on a single (monitor) device from `position_ms`.
+    /// Replaces any preview that is already running.
+    pub fn start_preview(
+        &self,
+        audio_data: Vec<u8>,
+        device_id: String,
+        position_ms: u32,
+    ) -> Result<(), String> {
+        self.stop_preview()?;
+
+        let (samples, sample_rate, channels) = decode::decode_wav(&audio_data)?;
+        self.play_preview(samples, sample_rate, channels, device_id, position_ms)
+    }
+
+    /// Audition part of a file on a single (monitor) device, e.g. while
+    /// adjusting a clip's trim: `duration_ms` from `start_ms`, or to the end
+    /// of the file. Replaces any preview that is already running.
+    /// `seek_preview` positions count from `start_ms`.
+    pub fn preview_file(
+        &self,
+        path: &Path,
+        start_ms: u32,
+        duration_ms: Option<u32>,
+        device_id: String,
+    ) -> Result<(), String> {
+        if duration_ms == Some(0) {
+            return Err("Preview duration must be more than 0 ms".to_string());
+        }
+        let trim = ClipTrim {
+            start_ms,
+            end_ms: duration_ms.map(|duration_ms| start_ms.saturating_add(duration_ms)),
+        };
+        self.stop_preview()?;
+
+        // Only the segment is decoded, unless the whole clip is already cached
+        let (hash, _) = hash_file(path)?;
+        let mut decoder = self.open_decoder(&hash, || ClipDecoder::open_file(path))?;
+        let (start, end) = trim.frames(decoder.sample_rate);
+        let mut range = FrameRange::new(decoder.channels, start, end);
+        let mut samples = Vec::new();
+        while !range.is_done() {
+            match decoder.next_chunk()? {
+                Some(chunk) => samples.extend(range.process(chunk)),
+                None => break,
+            }
+        }
+        if samples.is_empty() {
+            return Err(format!("{} ends before {}ms", path.display(), start_ms));
+        }
+        self.play_preview(samples, decoder.sample_rate, decoder.channels, device_id, 0)
+    }
+
+    fn play_preview(
+        &self,
+        samples: Vec<f32>,
+        sample_rate: u32,
+        channels: u16,
+        device_id: String,
+        position_ms: u32,
+    ) -> Result<(), String> {
+        let (device_sample_rate, device_channels) = self.mixer_format(&device_id)?;
+        let prepared = convert_for_device(
+            samples,
+            sample_rate,
+            channels,
+            device_sample_rate,
+            device_channels,
+        )?;
+
+        let position = Arc::new(AtomicUsize::new(sample_index_for_ms(
+            position_ms,
+            device_sample_rate,
+            device_channels,
+        )));
+        let stop = Arc::new(AtomicBool::new(false));
+        let cursor = PreviewCursor::new(
+            prepared,
+            position.clone(),
+            stop.clone(),
+            device_sample_rate,
+            device_channels,
+        );
+        self.add_voice(&device_id, Box::new(cursor))?;
+
+        eprintln!("play_preview: Previewing on {} from {}ms", device_id, position_ms);
+        *self.preview.lock().unwrap() = Some(PreviewHandle {
+            position,
+            sample_rate: device_sample_rate,
+            channels: device_channels,
+            stop,
+        });
+        Ok(())
+    }
+
+    /// Move the preview playhead, e.g. while the user drags across the waveform.
+    pub fn seek_preview(&self, position_ms: u32) -> Result<(), String> {
+        let preview = self.preview.lock().unwrap();
+        let preview = preview.as_ref().ok_or("No preview is playing")?;
+        preview.position.store(
+            sample_index_for_ms(position_ms, preview.sample_rate, preview.channels),
+            Ordering::Relaxed,
+        );
+        Ok(())
+    }
+
+    pub fn stop_preview(&self) -> Result<(), String> {
+        if self.preview.lock().unwrap().take().is_some() {
+            eprintln!("stop_preview: Preview stopped");
+        }
+        Ok(())
+    }
+}
+
+/// Position of the host's default output in `devices`. cpal only tells us the
+/// default's name; with duplicates, the first match is the best guess.
+fn default_device_position(host: &Host, devices: &[IdentifiedDevice]) -> Option<usize> {
+    let name = host.default_output_device()?.name().ok()?;
+    devices.iter().position(|device| device.name == name)
+}
+
+fn sample_index_for_ms(position_ms: u32, sample_rate: u32, channels: u16) -> usize {
+    let frame = position_ms as u64 * sample_rate as u64 / 1000;
+    frame as usize * channels as usize
+}
+
+impl Default for AudioOutputState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
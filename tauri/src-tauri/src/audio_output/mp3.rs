@@ -0,0 +1,78 @@
+use mp3lame_encoder::{Bitrate, Builder, Encoder, FlushNoGap, InterleavedPcm, MonoPcm, Quality};
+use std::io::Write;
+
+/// Constant bitrate of written files. LAME picks the nearest valid rate for
+/// MPEG-2 sample rates, which top out lower.
+const MP3_BITRATE: Bitrate = Bitrate::Kbps192;
+
+/// MP3 encoder backed by LAME, built from source by the `mp3` feature. MP3
+/// carries at most two channels.
+pub(crate) struct Mp3Writer<W: Write> {
+    out: W,
+    encoder: Encoder,
+    channels: usize,
+    /// Input converted to 16-bit, reused between writes
+    pcm: Vec<i16>,
+    encoded: Vec<u8>,
+}
+
+impl<W: Write> Mp3Writer<W> {
+    pub(super) fn new(out: W, sample_rate: u32, channels: u16) -> Result<Self, String> {
+        if !(1..=2).contains(&channels) {
+            return Err(format!("MP3 supports mono or stereo, got {} channels", channels));
+        }
+        let mut builder = Builder::new().ok_or("Failed to create MP3 encoder")?;
+        builder
+            .set_num_channels(channels as u8)
+            .map_err(|e| format!("Failed to set MP3 channels: {:?}", e))?;
+        builder
+            .set_sample_rate(sample_rate)
+            .map_err(|e| format!("MP3 doesn't support {} Hz: {:?}", sample_rate, e))?;
+        builder
+            .set_brate(MP3_BITRATE)
+            .map_err(|e| format!("Failed to set MP3 bitrate: {:?}", e))?;
+        builder
+            .set_quality(Quality::Good)
+            .map_err(|e| format!("Failed to set MP3 quality: {:?}", e))?;
+        let encoder = builder
+            .build()
+            .map_err(|e| format!("Failed to create MP3 encoder: {:?}", e))?;
+        Ok(Self {
+            out,
+            encoder,
+            channels: channels as usize,
+            pcm: Vec::new(),
+            encoded: Vec::new(),
+        })
+    }
+
+    /// Append interleaved samples in the -1.0..1.0 range.
+    pub(super) fn write_samples(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.pcm.clear();
+        self.pcm
+            .extend(samples.iter().map(|sample| (sample.clamp(-1.0, 1.0) * 32767.0).round() as i16));
+        self.encoded.clear();
+        let result = if self.channels == 1 {
+            self.encoder.encode_to_vec(MonoPcm(&self.pcm), &mut self.encoded)
+        } else {
+            self.encoder.encode_to_vec(InterleavedPcm(&self.pcm), &mut self.encoded)
+        };
+        result.map_err(|e| format!("Failed to encode MP3: {:?}", e))?;
+        self.out.write_all(&self.encoded).map_err(write_error)
+    }
+
+    /// Encode what LAME still holds and return the underlying writer.
+    pub(super) fn finalize(mut self) -> Result<W, String> {
+        self.encoded.clear();
+        self.encoder
+            .flush_to_vec::<FlushNoGap>(&mut self.encoded)
+            .map_err(|e| format!("Failed to finish MP3: {:?}", e))?;
+        self.out.write_all(&self.encoded).map_err(write_error)?;
+        self.out.flush().map_err(write_error)?;
+        Ok(self.out)
+    }
+}
+
+fn write_error(e: std::io::Error) -> String {
+    format!("Failed to write MP3: {}", e)
+}
@@ -0,0 +1,154 @@
+use audiopus::coder::Encoder;
+use audiopus::{Application, Channels, SampleRate};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::io::Write;
+
+/// Length of each Opus packet.
+const FRAME_MS: usize = 20;
+/// Largest packet libopus is asked to produce; its own recommended ceiling.
+const MAX_PACKET_BYTES: usize = 4000;
+/// Ogg granule positions always count 48 kHz samples, whatever the input rate.
+const GRANULE_RATE: u32 = 48_000;
+/// Serial number of the single logical stream in the file.
+const STREAM_SERIAL: u32 = 1;
+
+/// Opus encoder writing an Ogg Opus file (RFC 7845). libopus only takes 8, 12,
+/// 16, 24 or 48 kHz input and at most two channels here, so callers convert
+/// first.
+pub(crate) struct OpusWriter<W: Write> {
+    packets: PacketWriter<W>,
+    encoder: Encoder,
+    channels: usize,
+    /// Samples per channel in one packet at the input rate
+    frame_len: usize,
+    /// Input samples to 48 kHz granule units
+    granule_scale: u64,
+    /// Encoder delay in samples per channel at the input rate
+    delay: usize,
+    /// Interleaved input not yet making up a whole packet
+    pending: Vec<f32>,
+    /// Encoded packet held back until we know whether it is the last
+    last_packet: Option<Vec<u8>>,
+    packets_written: u64,
+    frames_in: u64,
+}
+
+impl<W: Write> OpusWriter<W> {
+    pub(super) fn new(out: W, sample_rate: u32, channels: u16) -> Result<Self, String> {
+        let rate = SampleRate::try_from(sample_rate as i32)
+            .map_err(|_| format!("Opus supports 8, 12, 16, 24 or 48 kHz, got {} Hz", sample_rate))?;
+        let layout = Channels::try_from(channels as i32)
+            .map_err(|_| format!("Opus recordings must be mono or stereo, got {} channels", channels))?;
+        let encoder = Encoder::new(rate, layout, Application::Audio)
+            .map_err(|e| format!("Failed to create Opus encoder: {}", e))?;
+
+        let granule_scale = (GRANULE_RATE / sample_rate) as u64;
+        let lookahead = encoder
+            .lookahead()
+            .map_err(|e| format!("Failed to read Opus encoder delay: {}", e))?;
+        let mut writer = Self {
+            packets: PacketWriter::new(out),
+            encoder,
+            channels: channels as usize,
+            frame_len: sample_rate as usize * FRAME_MS / 1000,
+            granule_scale,
+            delay: lookahead as usize,
+            pending: Vec::new(),
+            last_packet: None,
+            packets_written: 0,
+            frames_in: 0,
+        };
+        writer.write_headers(sample_rate)?;
+        Ok(writer)
+    }
+
+    /// Append interleaved samples in the -1.0..1.0 range.
+    pub(super) fn write_samples(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.frames_in += (samples.len() / self.channels) as u64;
+        self.pending.extend_from_slice(samples);
+        self.encode_pending()
+    }
+
+    /// Flush the encoder's delay and the final partial packet with silence, and
+    /// end the stream. The padding is cut off again by the last granule
+    /// position.
+    pub(super) fn finalize(mut self) -> Result<u64, String> {
+        let packet_samples = self.frame_len * self.channels;
+        let padded = self.pending.len() + self.delay * self.channels;
+        self.pending
+            .resize(padded.div_ceil(packet_samples).max(1) * packet_samples, 0.0);
+        self.encode_pending()?;
+
+        if let Some(packet) = self.last_packet.take() {
+            let granule = self.pre_skip() + self.frames_in * self.granule_scale;
+            self.write_packet(packet, PacketWriteEndInfo::EndStream, granule)?;
+        }
+        self.packets.inner_mut().flush().map_err(write_error)?;
+        Ok(self.frames_in)
+    }
+
+    /// Encoder delay in granule units, which players trim from the start.
+    fn pre_skip(&self) -> u64 {
+        self.delay as u64 * self.granule_scale
+    }
+
+    fn encode_pending(&mut self) -> Result<(), String> {
+        let packet_samples = self.frame_len * self.channels;
+        let whole = self.pending.len() / packet_samples * packet_samples;
+        let input: Vec<f32> = self.pending.drain(..whole).collect();
+        for frame in input.chunks_exact(packet_samples) {
+            self.encode(frame)?;
+        }
+        Ok(())
+    }
+
+    fn write_headers(&mut self, sample_rate: u32) -> Result<(), String> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // version
+        head.push(self.channels as u8);
+        head.extend_from_slice(&(self.pre_skip() as u16).to_le_bytes());
+        head.extend_from_slice(&sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // mono/stereo channel mapping
+
+        let vendor = concat!("voicebox ", env!("CARGO_PKG_VERSION"));
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+
+        // Each header has to sit on a page of its own
+        for header in [head, tags] {
+            self.write_packet(header, PacketWriteEndInfo::EndPage, 0)?;
+        }
+        Ok(())
+    }
+
+    fn encode(&mut self, frame: &[f32]) -> Result<(), String> {
+        let mut packet = vec![0u8; MAX_PACKET_BYTES];
+        let len = self
+            .encoder
+            .encode_float(frame, &mut packet)
+            .map_err(|e| format!("Opus encoding failed: {}", e))?;
+        packet.truncate(len);
+
+        // A page's granule position is the number of samples decoded by the
+        // end of it, pre-skip included
+        if let Some(previous) = self.last_packet.replace(packet) {
+            self.packets_written += 1;
+            let granule = self.packets_written * self.frame_len as u64 * self.granule_scale;
+            self.write_packet(previous, PacketWriteEndInfo::NormalPacket, granule)?;
+        }
+        Ok(())
+    }
+
+    fn write_packet(&mut self, packet: Vec<u8>, end: PacketWriteEndInfo, granule: u64) -> Result<(), String> {
+        self.packets
+            .write_packet(packet.into_boxed_slice(), STREAM_SERIAL, end, granule)
+            .map_err(write_error)
+    }
+}
+
+fn write_error(e: std::io::Error) -> String {
+    format!("Failed to write Opus: {}", e)
+}
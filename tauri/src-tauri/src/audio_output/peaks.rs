@@ -0,0 +1,58 @@
+use super::decode::ClipDecoder;
+
+/// Frames summarised by each intermediate peak while decoding. The requested
+/// resolution is built from these once the clip length is known.
+const FINE_BUCKET_FRAMES: usize = 64;
+
+/// Most buckets a caller may ask for.
+pub(super) const MAX_WAVEFORM_BUCKETS: usize = 100_000;
+
+/// Downsampled waveform for drawing: the lowest and highest sample in each
+/// bucket, across all channels.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WaveformPeaks {
+    pub duration_ms: u64,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+/// Decode a whole clip and reduce it to `buckets` min/max pairs.
+pub(super) fn waveform_peaks(mut decoder: ClipDecoder, buckets: usize) -> Result<WaveformPeaks, String> {
+    let channels = decoder.channels.max(1) as usize;
+    let mut fine: Vec<(f32, f32)> = Vec::new();
+    let mut frames = 0usize;
+
+    while let Some(chunk) = decoder.next_chunk()? {
+        for frame in chunk.chunks_exact(channels) {
+            if frames == fine.len() * FINE_BUCKET_FRAMES {
+                fine.push((f32::MAX, f32::MIN));
+            }
+            if let Some((min, max)) = fine.last_mut() {
+                for sample in frame {
+                    *min = min.min(*sample);
+                    *max = max.max(*sample);
+                }
+            }
+            frames += 1;
+        }
+    }
+
+    let (mut min, mut max) = (Vec::with_capacity(buckets), Vec::with_capacity(buckets));
+    if !fine.is_empty() {
+        for bucket in 0..buckets {
+            let start = bucket * fine.len() / buckets;
+            let end = ((bucket + 1) * fine.len() / buckets).max(start + 1);
+            let (low, high) = fine[start..end]
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(low, high), (min, max)| (low.min(*min), high.max(*max)));
+            min.push(low);
+            max.push(high);
+        }
+    }
+
+    Ok(WaveformPeaks {
+        duration_ms: frames as u64 * 1000 / decoder.sample_rate.max(1) as u64,
+        min,
+        max,
+    })
+}
@@ -0,0 +1,62 @@
+use super::{PlaybackOptions, SessionId};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// One clip in a playlist.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistItem {
+    pub path: String,
+    /// Gain for this item on top of the playlist's options
+    #[serde(default)]
+    pub gain_db: f32,
+    /// Silence after this item before the next one starts
+    #[serde(default)]
+    pub gap_ms: u32,
+}
+
+/// Payload of `playlist://changed` and the playlist commands.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaylistStatus {
+    pub index: usize,
+    pub len: usize,
+    /// Session playing the current item; `None` during a gap or once finished
+    pub session_id: Option<SessionId>,
+    pub finished: bool,
+}
+
+/// The playlist the engine is working through. Only one plays at a time.
+pub(super) struct Playlist {
+    pub(super) items: Vec<PlaylistItem>,
+    pub(super) device_ids: Vec<String>,
+    pub(super) options: PlaybackOptions,
+    pub(super) index: usize,
+    pub(super) session: Option<SessionId>,
+    /// When the gap after the current item is over
+    pub(super) next_at: Option<Instant>,
+}
+
+impl Playlist {
+    pub(super) fn status(&self) -> PlaylistStatus {
+        PlaylistStatus {
+            index: self.index,
+            len: self.items.len(),
+            session_id: self.session,
+            finished: false,
+        }
+    }
+
+    pub(super) fn finished_status(&self) -> PlaylistStatus {
+        PlaylistStatus {
+            session_id: None,
+            finished: true,
+            ..self.status()
+        }
+    }
+
+    /// Options for playing the current item.
+    pub(super) fn item_options(&self) -> PlaybackOptions {
+        let mut options = self.options.clone();
+        options.gain_db += self.items[self.index].gain_db;
+        options
+    }
+}
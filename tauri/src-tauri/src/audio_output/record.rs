@@ -0,0 +1,327 @@
+use super::convert::FormatConverter;
+use super::decode::{ClipDecoder, ClipFormat};
+use super::flac::FlacWriter;
+#[cfg(feature = "mp3")]
+use super::mp3::Mp3Writer;
+use super::opus::OpusWriter;
+use super::stretch::TimeStretcher;
+use super::PlaybackOptions;
+use hound::{WavSpec, WavWriter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// Bit depth of recorded files.
+const RECORDING_BITS: u32 = 24;
+
+/// Mixer buffers queued for the writer thread before new ones are dropped.
+const RECORD_BUFFER_CHUNKS: usize = 512;
+
+/// The mixer's end of a recording: copies each rendered buffer to the writer.
+pub(super) struct RecordingTap {
+    tx: mpsc::SyncSender<Vec<f32>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl RecordingTap {
+    /// Queue a buffer without blocking. Returns false once the writer has gone.
+    pub(super) fn send(&self, data: &[f32]) -> bool {
+        match self.tx.try_send(data.to_vec()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// File format of a recording, chosen by the path's extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RecordingFormat {
+    Wav,
+    Flac,
+    Opus,
+    #[cfg(feature = "mp3")]
+    Mp3,
+}
+
+impl RecordingFormat {
+    pub(crate) fn from_path(path: &Path) -> Result<Self, String> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("wav") => Ok(RecordingFormat::Wav),
+            Some("flac") => Ok(RecordingFormat::Flac),
+            Some("opus") => Ok(RecordingFormat::Opus),
+            #[cfg(feature = "mp3")]
+            Some("mp3") => Ok(RecordingFormat::Mp3),
+            #[cfg(not(feature = "mp3"))]
+            Some("mp3") => Err("MP3 files need a build with the mp3 feature".to_string()),
+            _ => Err(format!(
+                "Recordings must be .wav, .flac, .opus or .mp3 files, got {}",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// Writes interleaved f32 audio to a file in any of the recording formats.
+pub(crate) enum RecordingEncoder {
+    Wav(WavWriter<BufWriter<File>>),
+    Flac(FlacWriter<BufWriter<File>>),
+    Opus(OpusWriter<BufWriter<File>>),
+    #[cfg(feature = "mp3")]
+    Mp3(Mp3Writer<BufWriter<File>>),
+}
+
+impl RecordingEncoder {
+    pub(crate) fn create(path: &Path, sample_rate: u32, channels: u16) -> Result<Self, String> {
+        match RecordingFormat::from_path(path)? {
+            RecordingFormat::Wav => {
+                let spec = WavSpec {
+                    channels,
+                    sample_rate,
+                    bits_per_sample: RECORDING_BITS as u16,
+                    sample_format: hound::SampleFormat::Int,
+                };
+                WavWriter::create(path, spec)
+                    .map(RecordingEncoder::Wav)
+                    .map_err(|e| format!("Failed to create {}: {}", path.display(), e))
+            }
+            RecordingFormat::Flac => {
+                let file = File::create(path)
+                    .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+                FlacWriter::new(BufWriter::new(file), sample_rate, channels, RECORDING_BITS)
+                    .map(RecordingEncoder::Flac)
+            }
+            RecordingFormat::Opus => {
+                let file = File::create(path)
+                    .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+                OpusWriter::new(BufWriter::new(file), sample_rate, channels).map(RecordingEncoder::Opus)
+            }
+            #[cfg(feature = "mp3")]
+            RecordingFormat::Mp3 => {
+                let file = File::create(path)
+                    .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+                Mp3Writer::new(BufWriter::new(file), sample_rate, channels).map(RecordingEncoder::Mp3)
+            }
+        }
+    }
+
+    pub(crate) fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        match self {
+            RecordingEncoder::Wav(writer) => {
+                let scale = ((1i32 << (RECORDING_BITS - 1)) - 1) as f32;
+                for sample in samples {
+                    writer
+                        .write_sample((sample.clamp(-1.0, 1.0) * scale).round() as i32)
+                        .map_err(|e| format!("Failed to write WAV: {}", e))?;
+                }
+                Ok(())
+            }
+            RecordingEncoder::Flac(writer) => writer.write_samples(samples),
+            RecordingEncoder::Opus(writer) => writer.write_samples(samples),
+            #[cfg(feature = "mp3")]
+            RecordingEncoder::Mp3(writer) => writer.write_samples(samples),
+        }
+    }
+
+    pub(crate) fn finalize(self) -> Result<(), String> {
+        match self {
+            RecordingEncoder::Wav(writer) => writer
+                .finalize()
+                .map_err(|e| format!("Failed to finalize WAV: {}", e)),
+            RecordingEncoder::Flac(writer) => writer.finalize().map(|_| ()),
+            RecordingEncoder::Opus(writer) => writer.finalize().map(|_| ()),
+            #[cfg(feature = "mp3")]
+            RecordingEncoder::Mp3(writer) => writer.finalize().map(|_| ()),
+        }
+    }
+}
+
+/// Write a stream of audio to a file in the format of `path`'s extension,
+/// applying the speed, pitch and gain of `options` the same way playback
+/// does. Returns the number of frames written. A partial file is removed if
+/// the stream fails.
+pub(crate) fn export_stream(
+    chunks: mpsc::Receiver<Result<Vec<f32>, String>>,
+    sample_rate: u32,
+    channels: u16,
+    options: &PlaybackOptions,
+    path: &Path,
+) -> Result<u64, String> {
+    let mut encoder = RecordingEncoder::create(path, sample_rate, channels)?;
+    let result = (|| {
+        // As on playback, pitch resamples the audio as if it were recorded at
+        // another rate, and the stretch makes up the length difference
+        let pitch = options.pitch_ratio();
+        let stretch = options.speed() / pitch;
+        let mut stretcher = (stretch != 1.0).then(|| TimeStretcher::new(sample_rate, channels, stretch));
+        let source_rate = (sample_rate as f64 * pitch).round() as u32;
+        let mut converter = FormatConverter::new(source_rate, channels, sample_rate, channels)?;
+        let gain = 10f32.powf(options.gain_db / 20.0);
+        let mut samples_written = 0u64;
+        let mut write = |samples: Vec<f32>, flush: bool| -> Result<(), String> {
+            let samples = match &mut stretcher {
+                Some(stretcher) if flush => {
+                    let mut samples = stretcher.process(&samples);
+                    samples.extend(stretcher.flush());
+                    samples
+                }
+                Some(stretcher) => stretcher.process(&samples),
+                None => samples,
+            };
+            let mut samples = converter.process(&samples);
+            if flush {
+                samples.extend(converter.flush());
+            }
+            for sample in &mut samples {
+                *sample *= gain;
+            }
+            encoder.write(&samples)?;
+            samples_written += samples.len() as u64;
+            Ok(())
+        };
+        for chunk in chunks {
+            write(chunk?, false)?;
+        }
+        write(Vec::new(), true)?;
+        Ok(samples_written / channels.max(1) as u64)
+    })();
+
+    match result {
+        Ok(frames) => {
+            encoder.finalize()?;
+            Ok(frames)
+        }
+        Err(e) => {
+            drop(encoder);
+            let _ = std::fs::remove_file(path);
+            Err(e)
+        }
+    }
+}
+
+/// Decode `source` and write it to `target`, in the format of the target's
+/// extension, resampled to `sample_rate`. Channels are kept. `cancelled` is
+/// checked between packets to give up early. A partial file is removed on
+/// failure.
+pub(crate) fn transcode_file(
+    source: &Path,
+    target: &Path,
+    sample_rate: u32,
+    cancelled: impl Fn() -> bool,
+) -> Result<ClipFormat, String> {
+    let mut decoder = ClipDecoder::open_file(source)?;
+    let channels = decoder.channels;
+    let mut encoder = RecordingEncoder::create(target, sample_rate, channels)?;
+    let result = (|| {
+        let mut converter = FormatConverter::new(decoder.sample_rate, channels, sample_rate, channels)?;
+        let mut samples_written = 0u64;
+        while let Some(chunk) = decoder.next_chunk()? {
+            if cancelled() {
+                return Err("Conversion was cancelled".to_string());
+            }
+            let samples = converter.process(&chunk);
+            encoder.write(&samples)?;
+            samples_written += samples.len() as u64;
+        }
+        let samples = converter.flush();
+        encoder.write(&samples)?;
+        samples_written += samples.len() as u64;
+        Ok(samples_written / channels.max(1) as u64)
+    })();
+
+    let result = match result {
+        Ok(frames) => encoder.finalize().map(|()| frames),
+        Err(e) => {
+            drop(encoder);
+            Err(e)
+        }
+    };
+    match result {
+        Ok(frames) => Ok(ClipFormat {
+            sample_rate,
+            channels,
+            duration_ms: frames * 1000 / sample_rate.max(1) as u64,
+        }),
+        Err(e) => {
+            let _ = std::fs::remove_file(target);
+            Err(e)
+        }
+    }
+}
+
+/// A device's mixdown being written to a WAV, FLAC or Opus file (chosen by the
+/// path's extension) on its own thread, so the audio callback never touches
+/// the disk.
+pub(super) struct OutputRecording {
+    path: PathBuf,
+    dropped: Arc<AtomicUsize>,
+    writer: JoinHandle<Result<u64, String>>,
+}
+
+impl OutputRecording {
+    /// Create the file and start the writer. Recording runs until the returned
+    /// tap is dropped.
+    pub(super) fn start(
+        path: &Path,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<(Self, RecordingTap), String> {
+        let mut encoder = RecordingEncoder::create(path, sample_rate, channels)?;
+        let (tx, rx) = mpsc::sync_channel::<Vec<f32>>(RECORD_BUFFER_CHUNKS);
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let writer = std::thread::spawn(move || {
+            let mut samples_written = 0u64;
+            for buffer in rx {
+                encoder.write(&buffer)?;
+                samples_written += buffer.len() as u64;
+            }
+            encoder.finalize()?;
+            Ok(samples_written / channels.max(1) as u64)
+        });
+
+        eprintln!("Recording output to {}", path.display());
+        let tap = RecordingTap {
+            tx,
+            dropped: dropped.clone(),
+        };
+        Ok((
+            Self {
+                path: path.to_path_buf(),
+                dropped,
+                writer,
+            },
+            tap,
+        ))
+    }
+
+    /// Wait for the writer to flush and close the file. The tap must have been
+    /// dropped first.
+    pub(super) fn finish(self) -> Result<(), String> {
+        let frames = self
+            .writer
+            .join()
+            .map_err(|_| "Recording writer thread panicked".to_string())??;
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            eprintln!(
+                "Recording to {} fell behind and skipped {} buffer(s)",
+                self.path.display(),
+                dropped
+            );
+        }
+        eprintln!("Finished recording {} frames to {}", frames, self.path.display());
+        Ok(())
+    }
+}
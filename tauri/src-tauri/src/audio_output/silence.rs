@@ -0,0 +1,107 @@
+use super::decode::ClipDecoder;
+use std::path::Path;
+
+/// Length of the windows a recording's level is measured over.
+const LEVEL_WINDOW_MS: u32 = 10;
+/// Thresholds the user can pick, in dBFS.
+const MIN_THRESHOLD_DB: f32 = -90.0;
+const MAX_THRESHOLD_DB: f32 = -10.0;
+
+/// How `find_sound_regions` tells sound from silence.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(default)]
+pub struct SilenceOptions {
+    /// Windows quieter than this, in dBFS RMS, are silence
+    pub threshold_db: f32,
+    /// Shortest silence that separates two regions; shorter pauses, e.g.
+    /// between words, are kept inside a region
+    pub min_silence_ms: u32,
+    /// Regions shorter than this are dropped as clicks and bumps
+    pub min_region_ms: u32,
+    /// Silence kept before and after each region, so it doesn't start or
+    /// end abruptly
+    pub padding_ms: u32,
+}
+
+impl Default for SilenceOptions {
+    fn default() -> Self {
+        Self {
+            threshold_db: -45.0,
+            min_silence_ms: 700,
+            min_region_ms: 250,
+            padding_ms: 100,
+        }
+    }
+}
+
+/// A stretch of sound in a recording, in milliseconds from its start.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct SoundRegion {
+    pub start_ms: u32,
+    pub end_ms: u32,
+}
+
+/// The stretches of sound in a file, separated by silent gaps, in order.
+pub(crate) fn find_sound_regions(path: &Path, options: &SilenceOptions) -> Result<Vec<SoundRegion>, String> {
+    if !(MIN_THRESHOLD_DB..=MAX_THRESHOLD_DB).contains(&options.threshold_db) {
+        return Err(format!(
+            "Silence threshold must be {} to {} dBFS, got {}",
+            MIN_THRESHOLD_DB, MAX_THRESHOLD_DB, options.threshold_db
+        ));
+    }
+    let mut decoder = ClipDecoder::open_file(path)?;
+    let channels = decoder.channels.max(1) as usize;
+    let window_frames = (decoder.sample_rate * LEVEL_WINDOW_MS / 1000).max(1) as usize;
+    let threshold = 10f32.powf(options.threshold_db / 10.0);
+
+    // Whether each window is louder than the threshold
+    let mut loud = Vec::new();
+    let (mut sum, mut frames) = (0.0f32, 0usize);
+    while let Some(chunk) = decoder.next_chunk()? {
+        for frame in chunk.chunks_exact(channels) {
+            sum += frame.iter().map(|sample| sample * sample).sum::<f32>() / channels as f32;
+            frames += 1;
+            if frames == window_frames {
+                loud.push(sum / frames as f32 > threshold);
+                (sum, frames) = (0.0, 0);
+            }
+        }
+    }
+    if frames > 0 {
+        loud.push(sum / frames as f32 > threshold);
+    }
+    let duration_ms = loud.len() as u32 * LEVEL_WINDOW_MS;
+    Ok(regions_from_levels(&loud, duration_ms, options))
+}
+
+fn regions_from_levels(loud: &[bool], duration_ms: u32, options: &SilenceOptions) -> Vec<SoundRegion> {
+    // Runs of loud windows, joined across pauses shorter than the minimum
+    // silence
+    let mut regions: Vec<SoundRegion> = Vec::new();
+    for (index, _) in loud.iter().enumerate().filter(|(_, loud)| **loud) {
+        let start_ms = index as u32 * LEVEL_WINDOW_MS;
+        let end_ms = start_ms + LEVEL_WINDOW_MS;
+        match regions.last_mut() {
+            Some(last) if start_ms - last.end_ms < options.min_silence_ms => last.end_ms = end_ms,
+            _ => regions.push(SoundRegion { start_ms, end_ms }),
+        }
+    }
+    regions.retain(|region| region.end_ms - region.start_ms >= options.min_region_ms);
+
+    // Pad into the silence around each region, giving each at most half of
+    // the gap it shares with its neighbour
+    let unpadded = regions.clone();
+    for (index, region) in regions.iter_mut().enumerate() {
+        let earliest = match index.checked_sub(1) {
+            Some(previous) => (unpadded[previous].end_ms + region.start_ms) / 2,
+            None => 0,
+        };
+        let latest = match unpadded.get(index + 1) {
+            Some(next) => (region.end_ms + next.start_ms) / 2,
+            None => duration_ms,
+        };
+        region.start_ms = region.start_ms.saturating_sub(options.padding_ms).max(earliest);
+        region.end_ms = (region.end_ms + options.padding_ms).min(latest);
+    }
+    regions
+}
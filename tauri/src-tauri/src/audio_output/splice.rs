@@ -0,0 +1,152 @@
+use super::convert::FormatConverter;
+use super::decode::{ClipDecoder, ClipFormat};
+use super::record::RecordingEncoder;
+use super::trim::{ClipTrim, FrameRange};
+use std::path::Path;
+
+/// Fade at each join between segments, so cutting mid-waveform doesn't
+/// click.
+const SPLICE_DECLICK_MS: u32 = 5;
+
+/// A stretch of an audio file to put in a spliced file.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SpliceSegment {
+    pub path: String,
+    /// Part of the file to take; all of it by default
+    #[serde(flatten)]
+    pub trim: ClipTrim,
+}
+
+/// Write `segments` one after another to `target`, in the format of its
+/// extension. The result has the sample rate and channels of the first
+/// segment's file; other files are converted to match. A partial file is
+/// removed on failure.
+pub(crate) fn splice_files(segments: &[SpliceSegment], target: &Path) -> Result<ClipFormat, String> {
+    if segments.is_empty() {
+        return Err("Nothing to splice".to_string());
+    }
+    for segment in segments {
+        segment.trim.validate()?;
+        // Written while the sources are still being read
+        if Path::new(&segment.path) == target {
+            return Err(format!("Can't splice into {}, which is one of the segments", target.display()));
+        }
+    }
+
+    let first = ClipDecoder::open_file(Path::new(&segments[0].path))?;
+    let (sample_rate, channels) = (first.sample_rate, first.channels.max(1));
+    let mut encoder = RecordingEncoder::create(target, sample_rate, channels)?;
+    let result = (|| {
+        let mut first = Some(first);
+        let mut frames_written = 0u64;
+        for (index, segment) in segments.iter().enumerate() {
+            let decoder = match first.take() {
+                Some(decoder) => decoder,
+                None => ClipDecoder::open_file(Path::new(&segment.path))?,
+            };
+            let mut samples = read_segment(decoder, &segment.trim, sample_rate, channels)?;
+            if samples.is_empty() {
+                return Err(format!("{} ends before {}ms", segment.path, segment.trim.start_ms));
+            }
+            declick(
+                &mut samples,
+                channels as usize,
+                sample_rate,
+                index > 0,
+                index + 1 < segments.len(),
+            );
+            encoder.write(&samples)?;
+            frames_written += (samples.len() / channels as usize) as u64;
+        }
+        Ok(frames_written)
+    })();
+
+    let result = match result {
+        Ok(frames) => encoder.finalize().map(|()| frames),
+        Err(e) => {
+            drop(encoder);
+            Err(e)
+        }
+    };
+    match result {
+        Ok(frames) => Ok(ClipFormat {
+            sample_rate,
+            channels,
+            duration_ms: frames * 1000 / sample_rate as u64,
+        }),
+        Err(e) => {
+            let _ = std::fs::remove_file(target);
+            Err(e)
+        }
+    }
+}
+
+/// The trimmed part of a file, converted to the spliced file's format.
+fn read_segment(mut decoder: ClipDecoder, trim: &ClipTrim, sample_rate: u32, channels: u16) -> Result<Vec<f32>, String> {
+    let (start, end) = trim.frames(decoder.sample_rate);
+    let mut range = FrameRange::new(decoder.channels, start, end);
+    let mut converter = FormatConverter::new(decoder.sample_rate, decoder.channels, sample_rate, channels)?;
+    let mut samples = Vec::new();
+    while !range.is_done() {
+        match decoder.next_chunk()? {
+            Some(chunk) => samples.extend(converter.process(&range.process(chunk))),
+            None => break,
+        }
+    }
+    samples.extend(converter.flush());
+    Ok(samples)
+}
+
+/// Fade the start and/or end of a segment over `SPLICE_DECLICK_MS`.
+fn declick(samples: &mut [f32], channels: usize, sample_rate: u32, fade_in: bool, fade_out: bool) {
+    let frames = samples.len() / channels;
+    let fade_frames = ((SPLICE_DECLICK_MS * sample_rate / 1000) as usize).min(frames / 2).max(1);
+    for i in 0..fade_frames {
+        let gain = i as f32 / fade_frames as f32;
+        if fade_in {
+            samples[i * channels..(i + 1) * channels]
+                .iter_mut()
+                .for_each(|sample| *sample *= gain);
+        }
+        if fade_out {
+            let frame = frames - 1 - i;
+            samples[frame * channels..(frame + 1) * channels]
+                .iter_mut()
+                .for_each(|sample| *sample *= gain);
+        }
+    }
+}
+
+/// Write `source` to `target` without the part from `start_ms` to `end_ms`.
+pub(crate) fn cut_file(source: &Path, start_ms: u32, end_ms: u32, target: &Path) -> Result<ClipFormat, String> {
+    let cut = ClipTrim {
+        start_ms,
+        end_ms: Some(end_ms),
+    };
+    cut.validate()?;
+    let duration_ms = super::probe_file(source)?.duration_ms;
+    let path = source.to_string_lossy().into_owned();
+    let mut segments = Vec::new();
+    if start_ms > 0 {
+        segments.push(SpliceSegment {
+            path: path.clone(),
+            trim: ClipTrim {
+                start_ms: 0,
+                end_ms: Some(start_ms),
+            },
+        });
+    }
+    if (end_ms as u64) < duration_ms {
+        segments.push(SpliceSegment {
+            path,
+            trim: ClipTrim {
+                start_ms: end_ms,
+                end_ms: None,
+            },
+        });
+    }
+    if segments.is_empty() {
+        return Err("Cutting that would leave nothing of the clip".to_string());
+    }
+    splice_files(&segments, target)
+}
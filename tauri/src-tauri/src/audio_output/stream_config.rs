@@ -0,0 +1,219 @@
+use cpal::traits::DeviceTrait;
+use cpal::{BufferSize, Device, SampleFormat, SupportedBufferSize, SupportedStreamConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// User overrides for how a device's output stream is opened. Unset fields use
+/// the device's default config.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceStreamConfig {
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// Buffer size in frames
+    pub buffer_size: Option<u32>,
+    /// Target buffer latency in milliseconds (e.g. 5, 10 or 20), converted to a
+    /// fixed buffer size. An explicit `buffer_size` takes precedence.
+    pub latency_ms: Option<u32>,
+    /// Windows only: open the device in WASAPI exclusive mode, falling back to
+    /// shared mode if it's busy or rejects the format
+    pub exclusive: bool,
+}
+
+impl DeviceStreamConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Sample rates offered when a device supports a continuous range.
+const COMMON_SAMPLE_RATES: [u32; 8] = [22_050, 32_000, 44_100, 48_000, 88_200, 96_000, 176_400, 192_000];
+
+/// One range of configs a device can be opened with, as reported by the driver.
+#[derive(Debug, Clone, Serialize)]
+pub struct SupportedOutputConfig {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    /// e.g. "f32" or "i16"
+    pub sample_format: String,
+    /// Buffer size range in frames, when the driver reports one
+    pub min_buffer_size: Option<u32>,
+    pub max_buffer_size: Option<u32>,
+}
+
+/// What a device supports, so the settings UI only offers valid options.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceCapabilities {
+    pub default_sample_rate: u32,
+    pub default_channels: u16,
+    /// Common rates that at least one config supports, plus the default
+    pub sample_rates: Vec<u32>,
+    pub channel_counts: Vec<u16>,
+    pub configs: Vec<SupportedOutputConfig>,
+}
+
+pub(super) fn device_capabilities(device: &Device) -> Result<DeviceCapabilities, String> {
+    let default = device
+        .default_output_config()
+        .map_err(|e| format!("Failed to get default config: {}", e))?;
+    let configs: Vec<SupportedOutputConfig> = device
+        .supported_output_configs()
+        .map_err(|e| format!("Failed to get supported configs: {}", e))?
+        .map(|range| {
+            let (min_buffer_size, max_buffer_size) = match range.buffer_size() {
+                SupportedBufferSize::Range { min, max } => (Some(*min), Some(*max)),
+                SupportedBufferSize::Unknown => (None, None),
+            };
+            SupportedOutputConfig {
+                channels: range.channels(),
+                min_sample_rate: range.min_sample_rate().0,
+                max_sample_rate: range.max_sample_rate().0,
+                sample_format: range.sample_format().to_string(),
+                min_buffer_size,
+                max_buffer_size,
+            }
+        })
+        .collect();
+
+    let mut sample_rates: Vec<u32> = COMMON_SAMPLE_RATES
+        .iter()
+        .copied()
+        .chain(std::iter::once(default.sample_rate().0))
+        .filter(|rate| {
+            configs
+                .iter()
+                .any(|config| (config.min_sample_rate..=config.max_sample_rate).contains(rate))
+        })
+        .collect();
+    sample_rates.sort_unstable();
+    sample_rates.dedup();
+    let mut channel_counts: Vec<u16> = configs.iter().map(|config| config.channels).collect();
+    channel_counts.sort_unstable();
+    channel_counts.dedup();
+
+    Ok(DeviceCapabilities {
+        default_sample_rate: default.sample_rate().0,
+        default_channels: default.channels(),
+        sample_rates,
+        channel_counts,
+        configs,
+    })
+}
+
+/// Pick the supported config closest to `requested`, or explain why the device
+/// can't be opened that way.
+pub(super) fn resolve_stream_config(
+    device: &Device,
+    requested: &DeviceStreamConfig,
+) -> Result<(SupportedStreamConfig, BufferSize), String> {
+    let default = device
+        .default_output_config()
+        .map_err(|e| format!("Failed to get default config: {}", e))?;
+
+    let config = if requested.sample_rate.is_none() && requested.channels.is_none() {
+        default
+    } else {
+        let sample_rate = requested.sample_rate.unwrap_or(default.sample_rate().0);
+        let channels = requested.channels.unwrap_or(default.channels());
+        let matching: Vec<_> = device
+            .supported_output_configs()
+            .map_err(|e| format!("Failed to get supported configs: {}", e))?
+            .filter(|range| {
+                range.channels() == channels
+                    && range.min_sample_rate().0 <= sample_rate
+                    && sample_rate <= range.max_sample_rate().0
+            })
+            .collect();
+
+        // Keep the device's native sample format if it can, then prefer f32
+        let range = matching
+            .iter()
+            .find(|range| range.sample_format() == default.sample_format())
+            .or_else(|| matching.iter().find(|range| range.sample_format() == SampleFormat::F32))
+            .or_else(|| matching.first())
+            .ok_or_else(|| {
+                format!("Device does not support {}Hz with {} channel(s)", sample_rate, channels)
+            })?;
+        range.with_sample_rate(cpal::SampleRate(sample_rate))
+    };
+
+    let buffer_size = match (requested.buffer_size, requested.latency_ms) {
+        (Some(0), _) => return Err("Buffer size must be greater than zero".to_string()),
+        (Some(frames), _) => {
+            if let SupportedBufferSize::Range { min, max } = config.buffer_size() {
+                if frames < *min || frames > *max {
+                    return Err(format!(
+                        "Buffer size {} is outside the supported range of {}-{} frames",
+                        frames, min, max
+                    ));
+                }
+            }
+            BufferSize::Fixed(frames)
+        }
+        (None, Some(0)) => return Err("Latency target must be greater than zero".to_string()),
+        (None, Some(latency_ms)) => {
+            let frames = (config.sample_rate().0 as u64 * latency_ms as u64 / 1000).max(1) as u32;
+            // Get as close to the target as the driver allows
+            let frames = match config.buffer_size() {
+                SupportedBufferSize::Range { min, max } => frames.clamp(*min, *max),
+                SupportedBufferSize::Unknown => frames,
+            };
+            BufferSize::Fixed(frames)
+        }
+        (None, None) => BufferSize::Default,
+    };
+
+    Ok((config, buffer_size))
+}
+
+/// Per-device stream configs, persisted as JSON in the app data directory.
+#[derive(Default)]
+pub(super) struct StreamConfigStore {
+    path: Option<PathBuf>,
+    devices: HashMap<String, DeviceStreamConfig>,
+}
+
+impl StreamConfigStore {
+    /// Load saved configs from `path`. A missing or unreadable file starts empty.
+    pub(super) fn load(path: PathBuf) -> Self {
+        let devices = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Ignoring invalid device config file {:?}: {}", path, e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path: Some(path),
+            devices,
+        }
+    }
+
+    pub(super) fn get(&self, device_id: &str) -> DeviceStreamConfig {
+        self.devices.get(device_id).cloned().unwrap_or_default()
+    }
+
+    /// Store `config` for a device and write the file. A default config removes
+    /// the entry.
+    pub(super) fn set(&mut self, device_id: &str, config: DeviceStreamConfig) -> Result<(), String> {
+        if config.is_default() {
+            self.devices.remove(device_id);
+        } else {
+            self.devices.insert(device_id.to_string(), config);
+        }
+
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+        let contents = serde_json::to_string_pretty(&self.devices)
+            .map_err(|e| format!("Failed to serialize device configs: {}", e))?;
+        std::fs::write(path, contents).map_err(|e| format!("Failed to save device configs: {}", e))
+    }
+}
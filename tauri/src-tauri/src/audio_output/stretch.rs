@@ -0,0 +1,167 @@
+/// Length of each analysis window.
+const WSOLA_WINDOW_MS: f64 = 25.0;
+/// How far either side of its ideal position a window may move to line up with
+/// the previous one.
+const WSOLA_SEARCH_MS: f64 = 8.0;
+
+/// Changes playback speed without changing pitch using WSOLA (waveform-similarity
+/// overlap-add). Windows are read from the input at `speed` times the rate they
+/// are written, and each one is nudged to the offset where it best continues the
+/// previous window so the overlap-add doesn't smear or phase-cancel.
+pub(super) struct TimeStretcher {
+    channels: usize,
+    speed: f64,
+    window_frames: usize,
+    hop_frames: usize,
+    search_frames: usize,
+    /// Hann window over `window_frames`; 50% overlap makes it sum to one
+    window: Vec<f32>,
+    /// Interleaved input not yet consumed; `input[0]` is frame `input_start`
+    input: Vec<f32>,
+    input_start: usize,
+    input_frames: u64,
+    /// Where the next window should ideally be taken from, in input frames
+    ideal_position: f64,
+    /// Where the previous window was actually taken from
+    previous_position: Option<usize>,
+    /// Second half of the previous window, waiting to be added to the next one
+    overlap: Vec<f32>,
+    output_frames: u64,
+}
+
+impl TimeStretcher {
+    pub(super) fn new(sample_rate: u32, channels: u16, speed: f64) -> Self {
+        let ms_to_frames = |ms: f64| (sample_rate as f64 * ms / 1000.0) as usize;
+        let hop_frames = (ms_to_frames(WSOLA_WINDOW_MS) / 2).max(1);
+        let window_frames = hop_frames * 2;
+        let window = (0..window_frames)
+            .map(|i| {
+                let phase = std::f64::consts::PI * 2.0 * i as f64 / window_frames as f64;
+                (0.5 - 0.5 * phase.cos()) as f32
+            })
+            .collect();
+        let channels = channels.max(1) as usize;
+
+        Self {
+            channels,
+            speed,
+            window_frames,
+            hop_frames,
+            search_frames: ms_to_frames(WSOLA_SEARCH_MS),
+            window,
+            input: Vec::new(),
+            input_start: 0,
+            input_frames: 0,
+            ideal_position: 0.0,
+            previous_position: None,
+            overlap: vec![0.0; hop_frames * channels],
+            output_frames: 0,
+        }
+    }
+
+    pub(super) fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.input.extend_from_slice(samples);
+        self.input_frames += (samples.len() / self.channels) as u64;
+
+        let mut output = Vec::new();
+        while self.has_input_for_next_window() {
+            self.next_window(&mut output);
+        }
+        output
+    }
+
+    /// Output still owed once the input has ended.
+    pub(super) fn flush(&mut self) -> Vec<f32> {
+        let expected = (self.input_frames as f64 / self.speed).round() as u64;
+        let padding = (self.window_frames + self.search_frames * 2) * self.channels;
+        self.input.resize(self.input.len() + padding, 0.0);
+
+        let mut output = Vec::new();
+        while self.output_frames < expected && self.has_input_for_next_window() {
+            self.next_window(&mut output);
+        }
+        if self.output_frames < expected {
+            output.extend_from_slice(&self.overlap);
+            self.output_frames += self.hop_frames as u64;
+        }
+
+        // Drop whatever the zero padding produced past the real end
+        let excess = self.output_frames.saturating_sub(expected) as usize * self.channels;
+        output.truncate(output.len().saturating_sub(excess));
+        output
+    }
+
+    fn available_end(&self) -> usize {
+        self.input_start + self.input.len() / self.channels
+    }
+
+    fn has_input_for_next_window(&self) -> bool {
+        let ideal = self.ideal_position.round() as usize;
+        let mut needed = ideal + self.search_frames + self.window_frames;
+        if let Some(previous) = self.previous_position {
+            needed = needed.max(previous + self.hop_frames + self.window_frames);
+        }
+        self.available_end() >= needed
+    }
+
+    fn next_window(&mut self, output: &mut Vec<f32>) {
+        let ideal = self.ideal_position.round() as usize;
+        let position = match self.previous_position {
+            Some(previous) => self.best_offset(ideal, previous + self.hop_frames),
+            None => ideal,
+        };
+
+        let first = self.previous_position.is_none();
+        let start = (position - self.input_start) * self.channels;
+        for frame in 0..self.window_frames {
+            // The very first window isn't faded in, so the clip starts at full level
+            let gain = if first && frame < self.hop_frames { 1.0 } else { self.window[frame] };
+            for channel in 0..self.channels {
+                let sample = self.input[start + frame * self.channels + channel] * gain;
+                let idx = (frame % self.hop_frames) * self.channels + channel;
+                if frame < self.hop_frames {
+                    output.push(self.overlap[idx] + sample);
+                } else {
+                    self.overlap[idx] = sample;
+                }
+            }
+        }
+        self.output_frames += self.hop_frames as u64;
+
+        self.previous_position = Some(position);
+        self.ideal_position += self.hop_frames as f64 * self.speed;
+
+        // Keep only what the next search can still reach
+        let keep_from = (self.ideal_position.round() as usize)
+            .saturating_sub(self.search_frames)
+            .min(position + self.hop_frames)
+            .max(self.input_start);
+        let drop = (keep_from - self.input_start) * self.channels;
+        self.input.drain(..drop);
+        self.input_start = keep_from;
+    }
+
+    /// Start frame within `ideal` ± the search range whose first half best matches
+    /// the natural continuation of the previous window at `target`.
+    fn best_offset(&self, ideal: usize, target: usize) -> usize {
+        let from = ideal.saturating_sub(self.search_frames).max(self.input_start);
+        let to = ideal + self.search_frames;
+        let mono = |frame: usize| -> f32 {
+            let start = (frame - self.input_start) * self.channels;
+            self.input[start..start + self.channels].iter().sum()
+        };
+
+        let mut best = ideal.max(from);
+        let mut best_score = f32::MIN;
+        for candidate in from..=to {
+            let score: f32 = (0..self.hop_frames)
+                .map(|i| mono(candidate + i) * mono(target + i))
+                .sum();
+            if score > best_score {
+                best_score = score;
+                best = candidate;
+            }
+        }
+        best
+    }
+}
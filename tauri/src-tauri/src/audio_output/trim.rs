@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+/// Frames with every sample at or below this level count as silence (-60 dBFS).
+const SILENCE_THRESHOLD: f32 = 0.001;
+
+/// Drops silence from the start and end of a clip as it streams through. Quiet
+/// stretches are held back until something audible follows them, so silence in
+/// the middle of a clip is kept and only the trailing run is lost at the end.
+pub(super) struct SilenceTrimmer {
+    channels: usize,
+    started: bool,
+    held: Vec<f32>,
+}
+
+impl SilenceTrimmer {
+    pub(super) fn new(channels: u16) -> Self {
+        Self {
+            channels: channels.max(1) as usize,
+            started: false,
+            held: Vec::new(),
+        }
+    }
+
+    pub(super) fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        let mut output = Vec::with_capacity(samples.len());
+        for frame in samples.chunks_exact(self.channels) {
+            let silent = frame.iter().all(|sample| sample.abs() <= SILENCE_THRESHOLD);
+            if silent {
+                if self.started {
+                    self.held.extend_from_slice(frame);
+                }
+                continue;
+            }
+
+            self.started = true;
+            output.append(&mut self.held);
+            output.extend_from_slice(frame);
+        }
+        output
+    }
+}
+
+/// In and out points for playing part of a clip, in milliseconds of the source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipTrim {
+    pub start_ms: u32,
+    /// Play to the end of the clip if unset
+    pub end_ms: Option<u32>,
+}
+
+impl ClipTrim {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match self.end_ms {
+            Some(end_ms) if end_ms <= self.start_ms => Err(format!(
+                "Trim end ({} ms) must be after its start ({} ms)",
+                end_ms, self.start_ms
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// (start, end) in frames at `sample_rate`.
+    pub(super) fn frames(&self, sample_rate: u32) -> (u64, Option<u64>) {
+        let to_frames = |ms: u32| ms as u64 * sample_rate as u64 / 1000;
+        (to_frames(self.start_ms), self.end_ms.map(to_frames))
+    }
+}
+
+/// Passes through only the frames of a clip between its trim points.
+pub(super) struct FrameRange {
+    channels: usize,
+    start: u64,
+    end: Option<u64>,
+    /// Source frames seen so far
+    position: u64,
+}
+
+impl FrameRange {
+    pub(super) fn new(channels: u16, start: u64, end: Option<u64>) -> Self {
+        Self {
+            channels: channels.max(1) as usize,
+            start,
+            end,
+            position: 0,
+        }
+    }
+
+    pub(super) fn process(&mut self, samples: Vec<f32>) -> Vec<f32> {
+        let frames = (samples.len() / self.channels) as u64;
+        let chunk_start = self.position;
+        self.position += frames;
+
+        let from = self.start.saturating_sub(chunk_start).min(frames);
+        let to = match self.end {
+            Some(end) => end.saturating_sub(chunk_start).min(frames),
+            None => frames,
+        };
+        if from == 0 && to == frames {
+            return samples;
+        }
+        samples[from.min(to) as usize * self.channels..to as usize * self.channels].to_vec()
+    }
+
+    /// True once the out point has been passed.
+    pub(super) fn is_done(&self) -> bool {
+        self.end.map(|end| self.position >= end).unwrap_or(false)
+    }
+}
@@ -0,0 +1,276 @@
+use super::mixer::StreamFault;
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+use wasapi::*;
+use windows::Win32::System::Com::{CoInitializeEx, CoUninitialize, COINIT_MULTITHREADED};
+
+/// Sample encodings tried for an exclusive-mode stream, best first. Exclusive
+/// mode bypasses the Windows mixer, so the device has to accept the format as is.
+#[derive(Clone, Copy, Debug)]
+enum ExclusiveFormat {
+    F32,
+    I32,
+    I24,
+    I16,
+}
+
+const EXCLUSIVE_FORMATS: [ExclusiveFormat; 4] = [
+    ExclusiveFormat::F32,
+    ExclusiveFormat::I32,
+    ExclusiveFormat::I24,
+    ExclusiveFormat::I16,
+];
+
+impl ExclusiveFormat {
+    fn wave_format(self, sample_rate: u32, channels: u16) -> WaveFormat {
+        let (store_bits, valid_bits, sample_type) = match self {
+            ExclusiveFormat::F32 => (32, 32, SampleType::Float),
+            ExclusiveFormat::I32 => (32, 32, SampleType::Int),
+            ExclusiveFormat::I24 => (24, 24, SampleType::Int),
+            ExclusiveFormat::I16 => (16, 16, SampleType::Int),
+        };
+        WaveFormat::new(
+            store_bits,
+            valid_bits,
+            &sample_type,
+            sample_rate as usize,
+            channels as usize,
+            None,
+        )
+    }
+
+    /// Encode f32 samples as little-endian bytes in this format.
+    fn encode(self, samples: &[f32], bytes: &mut Vec<u8>) {
+        bytes.clear();
+        for sample in samples {
+            let sample = sample.clamp(-1.0, 1.0);
+            match self {
+                ExclusiveFormat::F32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+                ExclusiveFormat::I32 => bytes
+                    .extend_from_slice(&((sample as f64 * i32::MAX as f64) as i32).to_le_bytes()),
+                ExclusiveFormat::I24 => {
+                    let value = (sample * 8_388_607.0) as i32;
+                    bytes.extend_from_slice(&value.to_le_bytes()[..3]);
+                }
+                ExclusiveFormat::I16 => {
+                    bytes.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes())
+                }
+            }
+        }
+    }
+}
+
+/// An initialized exclusive-mode client, ready to start.
+struct ExclusiveStream {
+    client: AudioClient,
+    render_client: AudioRenderClient,
+    event: Handle,
+    format: ExclusiveFormat,
+}
+
+impl ExclusiveStream {
+    fn open(
+        endpoint_id: &str,
+        sample_rate: u32,
+        channels: u16,
+        buffer_frames: Option<u32>,
+    ) -> Result<Self, String> {
+        let device = find_endpoint(endpoint_id)?;
+
+        let mut last_error = String::from("no formats tried");
+        for format in EXCLUSIVE_FORMATS {
+            // A client can only be initialized once, so every attempt needs a new one
+            let mut client = device
+                .get_iaudioclient()
+                .map_err(|e| format!("Failed to get audio client: {}", e))?;
+            let (default_period, min_period) = client
+                .get_device_period()
+                .map_err(|e| format!("Failed to get device period: {}", e))?;
+            let period_hns = match buffer_frames {
+                Some(frames) => (frames as i64 * 10_000_000 / sample_rate as i64).max(min_period),
+                None => default_period,
+            };
+
+            let wave_format = format.wave_format(sample_rate, channels);
+            let mode = StreamMode::EventsExclusive { period_hns };
+            if let Err(e) = client.initialize_client(&wave_format, &Direction::Render, &mode) {
+                last_error = format!("{:?}: {}", format, e);
+                continue;
+            }
+
+            let event = client
+                .set_get_eventhandle()
+                .map_err(|e| format!("Failed to set event handle: {}", e))?;
+            let render_client = client
+                .get_audiorenderclient()
+                .map_err(|e| format!("Failed to get render client: {}", e))?;
+            return Ok(Self {
+                client,
+                render_client,
+                event,
+                format,
+            });
+        }
+
+        Err(format!(
+            "Device rejected every exclusive-mode format (last error: {})",
+            last_error
+        ))
+    }
+
+    /// Write the next buffer, pulling `frames` frames from `fill`.
+    fn write<F>(
+        &self,
+        frames: usize,
+        channels: usize,
+        sample_rate: u32,
+        fill: &mut F,
+        scratch: &mut Vec<f32>,
+        bytes: &mut Vec<u8>,
+    ) -> Result<(), String>
+    where
+        F: FnMut(&mut [f32], Option<Duration>),
+    {
+        // Exclusive mode plays straight from this buffer, so its length is the latency
+        let latency = Duration::from_secs_f64(frames as f64 / sample_rate.max(1) as f64);
+        scratch.resize(frames * channels, 0.0);
+        fill(scratch, Some(latency));
+        self.format.encode(scratch, bytes);
+        self.render_client
+            .write_to_device(frames, bytes, None)
+            .map_err(|e| format!("Failed to write to device: {}", e))
+    }
+}
+
+fn find_endpoint(endpoint_id: &str) -> Result<Device, String> {
+    let collection = DeviceEnumerator::new()
+        .and_then(|enumerator| enumerator.get_device_collection(&Direction::Render))
+        .map_err(|e| format!("Failed to enumerate endpoints: {}", e))?;
+    let count = collection
+        .get_nbr_devices()
+        .map_err(|e| format!("Failed to count endpoints: {}", e))?;
+
+    for index in 0..count {
+        if let Ok(device) = collection.get_device_at_index(index) {
+            if device.get_id().map(|id| id == endpoint_id).unwrap_or(false) {
+                return Ok(device);
+            }
+        }
+    }
+    Err(format!("Endpoint not found: {}", endpoint_id))
+}
+
+/// Open an exclusive-mode stream on its own thread, with the same contract as
+/// the cpal stream threads: it runs until the returned sender is dropped. Fails
+/// if the device is busy or accepts none of our formats, so the caller can fall
+/// back to shared mode.
+pub(super) fn spawn_exclusive_stream<F>(
+    endpoint_id: String,
+    sample_rate: u32,
+    channels: u16,
+    buffer_frames: Option<u32>,
+    fault: Arc<StreamFault>,
+    mut fill: F,
+) -> Result<mpsc::Sender<()>, String>
+where
+    F: FnMut(&mut [f32], Option<Duration>) + Send + 'static,
+{
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<(), String>>();
+
+    // WASAPI COM objects are not Send, so everything happens on this thread
+    std::thread::spawn(move || {
+        unsafe {
+            let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
+            if hr.is_err() {
+                let _ = ready_tx.send(Err(format!("Failed to initialize COM: {:?}", hr)));
+                return;
+            }
+        }
+        let _com_guard = scopeguard::guard((), |_| unsafe {
+            CoUninitialize();
+        });
+
+        let channels_usize = channels.max(1) as usize;
+        let mut scratch = Vec::new();
+        let mut bytes = Vec::new();
+        let stream = ExclusiveStream::open(&endpoint_id, sample_rate, channels, buffer_frames)
+            .and_then(|stream| {
+                // Exclusive mode wants the first buffer filled before the stream starts
+                let frames = stream
+                    .client
+                    .get_available_space_in_frames()
+                    .map_err(|e| format!("Failed to get buffer size: {}", e))?;
+                stream.write(
+                    frames as usize,
+                    channels_usize,
+                    sample_rate,
+                    &mut |data: &mut [f32], _: Option<Duration>| data.fill(0.0),
+                    &mut scratch,
+                    &mut bytes,
+                )?;
+                stream
+                    .client
+                    .start_stream()
+                    .map_err(|e| format!("Failed to start stream: {}", e))?;
+                Ok(stream)
+            });
+
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+                return;
+            }
+        };
+        eprintln!(
+            "Started exclusive-mode stream ({:?}) on {}",
+            stream.format, endpoint_id
+        );
+        let _ = ready_tx.send(Ok(()));
+
+        loop {
+            match stop_rx.try_recv() {
+                Err(TryRecvError::Empty) => {}
+                // A message or a dropped handle both mean stop
+                _ => break,
+            }
+            if stream.event.wait_for_event(1000).is_err() {
+                eprintln!(
+                    "Exclusive-mode stream on {} stopped responding",
+                    endpoint_id
+                );
+                fault.set("Exclusive-mode stream stopped responding".to_string());
+                break;
+            }
+
+            let result = stream
+                .client
+                .get_available_space_in_frames()
+                .map_err(|e| format!("Failed to get buffer space: {}", e))
+                .and_then(|frames| {
+                    stream.write(
+                        frames as usize,
+                        channels_usize,
+                        sample_rate,
+                        &mut fill,
+                        &mut scratch,
+                        &mut bytes,
+                    )
+                });
+            if let Err(e) = result {
+                eprintln!("Playback error: {}", e);
+                fault.set(e);
+                break;
+            }
+        }
+
+        stream.client.stop_stream().ok();
+    });
+
+    ready_rx
+        .recv()
+        .map_err(|_| "Exclusive-mode stream thread exited unexpectedly".to_string())??;
+    Ok(stop_tx)
+}
@@ -0,0 +1,103 @@
+use crate::audio_output::{AudioOutputDevice, AudioOutputState, PlaybackOptions, SYSTEM_DEFAULT_DEVICE_ID};
+use std::path::Path;
+use std::time::Duration;
+
+const USAGE: &str = "Usage:
+  voicebox list-devices
+  voicebox play <file> [--device <id or name>]... [--gain-db <dB>] [--speed <rate>]
+
+Without --device, play uses the system default output. With no subcommand the
+app starts as usual.";
+
+/// How often `play` checks whether playback has ended.
+const POLL_INTERVAL_MS: u64 = 100;
+
+/// Run the headless subcommand named by `args` (without the program name), for
+/// scripting and debugging the audio engine without opening the window.
+/// Returns the process exit code, or `None` if there is no subcommand and the
+/// app should start normally. Release builds on Windows get no console, so
+/// there the output only shows up when redirected.
+pub fn run(args: &[String]) -> Option<i32> {
+    let result = match args.first().map(String::as_str) {
+        Some("list-devices") => list_devices(),
+        Some("play") => play(&args[1..]),
+        Some("help") | Some("--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => return None,
+    };
+
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Some(1)
+        }
+    }
+}
+
+fn list_devices() -> Result<(), String> {
+    for device in AudioOutputState::new().list_output_devices()? {
+        let default = if device.is_default { " (default)" } else { "" };
+        println!("{}\t{}{}", device.id, device.name, default);
+    }
+    Ok(())
+}
+
+fn play(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut devices = Vec::new();
+    let mut options = PlaybackOptions::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value\n\n{}", name, USAGE))
+        };
+        match arg.as_str() {
+            "--device" => devices.push(value("--device")?),
+            "--gain-db" => {
+                let gain = value("--gain-db")?;
+                options.gain_db = gain.parse().map_err(|_| format!("Invalid gain: {}", gain))?;
+            }
+            "--speed" => {
+                let speed = value("--speed")?;
+                options.speed = Some(speed.parse().map_err(|_| format!("Invalid speed: {}", speed))?);
+            }
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}\n\n{}", arg, USAGE)),
+        }
+    }
+    let path = path.ok_or_else(|| format!("No file given\n\n{}", USAGE))?;
+
+    let state = AudioOutputState::new();
+    let available = state.list_output_devices()?;
+    let device_ids = if devices.is_empty() {
+        vec![SYSTEM_DEFAULT_DEVICE_ID.to_string()]
+    } else {
+        devices
+            .iter()
+            .map(|device| resolve_device(&available, device))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    println!("Playing {} on {}", path, device_ids.join(", "));
+    let session_id = state.play_file_to_devices(Path::new(&path), device_ids, None, Some(options))?;
+    while !state.playback_finished(session_id) {
+        std::thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    }
+    Ok(())
+}
+
+/// Match a device by ID, or else by name ignoring case.
+fn resolve_device(devices: &[AudioOutputDevice], wanted: &str) -> Result<String, String> {
+    devices
+        .iter()
+        .find(|device| device.id == wanted)
+        .or_else(|| devices.iter().find(|device| device.name.eq_ignore_ascii_case(wanted)))
+        .map(|device| device.id.clone())
+        .ok_or_else(|| format!("No output device with ID or name {:?}; see `voicebox list-devices`", wanted))
+}
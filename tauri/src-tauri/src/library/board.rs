@@ -0,0 +1,79 @@
+use super::ClipId;
+use std::collections::HashSet;
+
+pub type ProfileId = i64;
+
+/// A named soundboard layout: which clips are on the board, the hotkeys
+/// that fire them and where they play.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BoardProfile {
+    pub id: ProfileId,
+    pub name: String,
+    /// Devices the board's clips play on unless a slot says otherwise
+    pub output_device_ids: Vec<String>,
+    pub slots: Vec<BoardSlot>,
+    /// Whether this is the profile in use
+    pub active: bool,
+}
+
+/// A clip placed on a board.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BoardSlot {
+    /// Place on the board, counting from 0
+    pub position: u32,
+    pub clip_id: ClipId,
+    /// Accelerator that plays the slot, e.g. "CmdOrCtrl+Shift+1"
+    #[serde(default)]
+    pub hotkey: Option<String>,
+    /// Devices to play on instead of the profile's
+    #[serde(default)]
+    pub device_ids: Option<Vec<String>>,
+}
+
+/// Changes to a profile. Fields left unset are kept.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct BoardProfileUpdate {
+    pub name: Option<String>,
+    pub output_device_ids: Option<Vec<String>>,
+    /// Replaces all of the profile's slots
+    pub slots: Option<Vec<BoardSlot>>,
+}
+
+/// Payload of `board://profile_changed`, emitted when another profile is
+/// switched to or the active one is edited or deleted.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ActiveProfileChanged {
+    pub profile: Option<BoardProfile>,
+}
+
+pub(super) fn clean_profile_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Profile name is empty".to_string());
+    }
+    Ok(name.to_string())
+}
+
+/// Sort slots by position and check no two share a position or hotkey.
+pub(super) fn validate_slots(slots: &mut [BoardSlot]) -> Result<(), String> {
+    slots.sort_by_key(|slot| slot.position);
+    if let Some(pair) = slots.windows(2).find(|pair| pair[0].position == pair[1].position) {
+        return Err(format!("Two clips are in board position {}", pair[0].position));
+    }
+    let mut hotkeys = HashSet::new();
+    for slot in slots.iter_mut() {
+        let hotkey = match slot.hotkey.as_deref().map(str::trim) {
+            Some(hotkey) if !hotkey.is_empty() => hotkey.to_string(),
+            _ => {
+                slot.hotkey = None;
+                continue;
+            }
+        };
+        if !hotkeys.insert(hotkey.to_lowercase()) {
+            return Err(format!("Hotkey {} is used by more than one clip", hotkey));
+        }
+        slot.hotkey = Some(hotkey);
+    }
+    Ok(())
+}
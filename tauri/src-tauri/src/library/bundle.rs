@@ -0,0 +1,183 @@
+use super::board::BoardProfile;
+use super::{ClipMarker, ClipPlayback, LibraryClip};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Version of the bundle layout. Bundles from a later version are refused.
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+/// Name of the manifest inside a bundle.
+const MANIFEST_NAME: &str = "board.json";
+
+/// Everything in a bundle apart from the audio, stored as `board.json`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(super) struct BundleManifest {
+    pub(super) format_version: u32,
+    pub(super) profile: BundleProfile,
+    pub(super) clips: Vec<BundleClip>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(super) struct BundleProfile {
+    pub(super) name: String,
+    pub(super) output_device_ids: Vec<String>,
+    pub(super) slots: Vec<BundleSlot>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(super) struct BundleSlot {
+    pub(super) position: u32,
+    /// Index into the manifest's clips
+    pub(super) clip: usize,
+    pub(super) hotkey: Option<String>,
+    pub(super) device_ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(super) struct BundleClip {
+    /// Name of the audio file inside the bundle
+    pub(super) file: String,
+    pub(super) name: String,
+    pub(super) tags: Vec<String>,
+    pub(super) favorite: bool,
+    pub(super) playback: ClipPlayback,
+    /// Missing from bundles made before clips had markers
+    #[serde(default)]
+    pub(super) markers: Vec<ClipMarker>,
+    #[serde(default)]
+    pub(super) exclusive_group: Option<String>,
+    #[serde(default)]
+    pub(super) hold_to_play: bool,
+}
+
+/// Write a profile and the clips on it to a zip archive at `path`. Audio is
+/// stored as it is in the library, uncompressed since it already is. A
+/// partial file is removed on failure.
+pub(super) fn write_bundle(path: &Path, profile: &BoardProfile, clips: &[LibraryClip]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let result = (|| {
+        let mut zip = ZipWriter::new(BufWriter::new(file));
+        let mut bundle_clips = Vec::with_capacity(clips.len());
+        for (index, clip) in clips.iter().enumerate() {
+            let extension = Path::new(&clip.path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("audio");
+            let name = format!("clips/{}.{}", index, extension);
+            let mut audio = File::open(&clip.path).map_err(|e| format!("Failed to open {}: {}", clip.path, e))?;
+            let options = SimpleFileOptions::default()
+                .compression_method(CompressionMethod::Stored)
+                .large_file(true);
+            zip.start_file(name.as_str(), options).map_err(zip_error)?;
+            std::io::copy(&mut audio, &mut zip).map_err(|e| format!("Failed to add {}: {}", clip.path, e))?;
+            bundle_clips.push(BundleClip {
+                file: name,
+                name: clip.name.clone(),
+                tags: clip.tags.clone(),
+                favorite: clip.favorite,
+                playback: clip.playback,
+                markers: clip.markers.clone(),
+                exclusive_group: clip.exclusive_group.clone(),
+                hold_to_play: clip.hold_to_play,
+            });
+        }
+
+        let slots = profile
+            .slots
+            .iter()
+            .filter_map(|slot| {
+                let clip = clips.iter().position(|clip| clip.id == slot.clip_id)?;
+                Some(BundleSlot {
+                    position: slot.position,
+                    clip,
+                    hotkey: slot.hotkey.clone(),
+                    device_ids: slot.device_ids.clone(),
+                })
+            })
+            .collect();
+        let manifest = BundleManifest {
+            format_version: BUNDLE_FORMAT_VERSION,
+            profile: BundleProfile {
+                name: profile.name.clone(),
+                output_device_ids: profile.output_device_ids.clone(),
+                slots,
+            },
+            clips: bundle_clips,
+        };
+        let manifest = serde_json::to_vec_pretty(&manifest)
+            .map_err(|e| format!("Failed to serialize board: {}", e))?;
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file(MANIFEST_NAME, options).map_err(zip_error)?;
+        zip.write_all(&manifest).map_err(|e| format!("Failed to write board: {}", e))?;
+        zip.finish()
+            .map_err(zip_error)?
+            .flush()
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+/// A bundle opened for importing.
+pub(super) struct BundleReader {
+    archive: ZipArchive<BufReader<File>>,
+    pub(super) manifest: BundleManifest,
+}
+
+impl BundleReader {
+    pub(super) fn open(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut archive = ZipArchive::new(BufReader::new(file))
+            .map_err(|e| format!("{} isn't a board bundle: {}", path.display(), e))?;
+        let mut contents = String::new();
+        archive
+            .by_name(MANIFEST_NAME)
+            .map_err(|_| format!("{} isn't a board bundle: it has no {}", path.display(), MANIFEST_NAME))?
+            .read_to_string(&mut contents)
+            .map_err(|e| format!("Failed to read board: {}", e))?;
+        let manifest: BundleManifest =
+            serde_json::from_str(&contents).map_err(|e| format!("Invalid board in bundle: {}", e))?;
+        if manifest.format_version > BUNDLE_FORMAT_VERSION {
+            return Err(format!(
+                "Bundle was made by a newer version of VoiceBox (format {}, expected at most {})",
+                manifest.format_version, BUNDLE_FORMAT_VERSION
+            ));
+        }
+        if let Some(slot) = manifest.profile.slots.iter().find(|slot| slot.clip >= manifest.clips.len()) {
+            return Err(format!("Invalid board in bundle: position {} has no clip", slot.position));
+        }
+        Ok(Self { archive, manifest })
+    }
+
+    /// Copy the audio of the clip at `index` to `target`. Only files named
+    /// by the manifest are read, and never to a path taken from the archive.
+    pub(super) fn extract_clip(&mut self, index: usize, target: &Path) -> Result<(), String> {
+        let name = &self.manifest.clips[index].file;
+        let mut entry = self
+            .archive
+            .by_name(name)
+            .map_err(|e| format!("Bundle is missing {}: {}", name, e))?;
+        let mut out = File::create(target).map_err(|e| format!("Failed to create {}: {}", target.display(), e))?;
+        std::io::copy(&mut entry, &mut out).map_err(|e| format!("Failed to extract {}: {}", name, e))?;
+        Ok(())
+    }
+
+    /// Extension of the clip's file, for naming it where it is extracted to.
+    pub(super) fn clip_extension(&self, index: usize) -> String {
+        Path::new(&self.manifest.clips[index].file)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .filter(|ext| ext.chars().all(|c| c.is_ascii_alphanumeric()))
+            .unwrap_or("audio")
+            .to_string()
+    }
+}
+
+fn zip_error(e: zip::result::ZipError) -> String {
+    format!("Failed to write bundle: {}", e)
+}
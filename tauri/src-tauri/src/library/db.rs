@@ -0,0 +1,1116 @@
+use super::board::{BoardProfile, BoardSlot, ProfileId};
+use super::schedule::{ScheduleId, ScheduledPlay};
+use super::stats::{ClipPeriodPlays, HourPlays, PlayStats, PlayStatsQuery, SourcePlays};
+use super::triggers::{TriggerEvent, TriggerSource};
+use super::{ClipId, ClipMarker, ClipPlayback, ClipQuery, ClipSort, LibraryClip, TagCount, WatchFolder};
+use crate::audio_output::ClipTrim;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Schema changes, applied in order. The database's `user_version` records
+/// how many have been applied, so only add to the end of this list.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE clips (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        name TEXT NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        sample_rate INTEGER NOT NULL,
+        channels INTEGER NOT NULL,
+        size_bytes INTEGER NOT NULL,
+        hash TEXT NOT NULL,
+        added_at_ms INTEGER NOT NULL
+    );
+    CREATE INDEX clips_hash ON clips (hash);
+    CREATE TABLE clip_tags (
+        clip_id INTEGER NOT NULL REFERENCES clips (id) ON DELETE CASCADE,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (clip_id, tag)
+    );
+    CREATE INDEX clip_tags_tag ON clip_tags (tag);
+", "
+    CREATE TABLE watch_folders (
+        path TEXT PRIMARY KEY,
+        recursive INTEGER NOT NULL
+    );
+", "
+    ALTER TABLE clips ADD COLUMN source_hash TEXT;
+    CREATE INDEX clips_source_hash ON clips (source_hash);
+", "
+    ALTER TABLE clips ADD COLUMN favorite INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE clips ADD COLUMN play_count INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE clips ADD COLUMN last_played_at_ms INTEGER;
+", "
+    ALTER TABLE clips ADD COLUMN loudness_lufs REAL;
+    ALTER TABLE clips ADD COLUMN normalization_db REAL;
+    CREATE TABLE settings (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+", "
+    ALTER TABLE clips ADD COLUMN gain_db REAL NOT NULL DEFAULT 0;
+    ALTER TABLE clips ADD COLUMN trim_start_ms INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE clips ADD COLUMN trim_end_ms INTEGER;
+    ALTER TABLE clips ADD COLUMN fade_in_ms INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE clips ADD COLUMN fade_out_ms INTEGER NOT NULL DEFAULT 0;
+", "
+    CREATE TABLE board_profiles (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        output_device_ids TEXT NOT NULL
+    );
+    CREATE TABLE board_slots (
+        profile_id INTEGER NOT NULL REFERENCES board_profiles (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        clip_id INTEGER NOT NULL REFERENCES clips (id) ON DELETE CASCADE,
+        hotkey TEXT,
+        device_ids TEXT,
+        PRIMARY KEY (profile_id, position)
+    );
+    CREATE INDEX board_slots_clip ON board_slots (clip_id);
+", "
+    ALTER TABLE clips ADD COLUMN fingerprint BLOB;
+", "
+    CREATE TABLE clip_markers (
+        clip_id INTEGER NOT NULL REFERENCES clips (id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        position_ms INTEGER NOT NULL,
+        PRIMARY KEY (clip_id, name)
+    );
+", "
+    ALTER TABLE clips ADD COLUMN exclusive_group TEXT;
+", "
+    ALTER TABLE clips ADD COLUMN hold_to_play INTEGER NOT NULL DEFAULT 0;
+", "
+    CREATE TABLE scheduled_plays (
+        id INTEGER PRIMARY KEY,
+        clip_id INTEGER NOT NULL REFERENCES clips (id) ON DELETE CASCADE,
+        at_ms INTEGER NOT NULL,
+        device_ids TEXT NOT NULL
+    );
+    CREATE INDEX scheduled_plays_at ON scheduled_plays (at_ms);
+", "
+    CREATE TABLE clip_triggers (
+        id INTEGER PRIMARY KEY,
+        clip_id INTEGER NOT NULL REFERENCES clips (id) ON DELETE CASCADE,
+        at_ms INTEGER NOT NULL,
+        source TEXT NOT NULL,
+        device_ids TEXT NOT NULL
+    );
+    CREATE INDEX clip_triggers_at ON clip_triggers (at_ms);
+"];
+
+const CLIP_COLUMNS: &str = "id, path, name, duration_ms, sample_rate, channels, size_bytes, hash, added_at_ms, \
+     source_hash, favorite, play_count, last_played_at_ms, loudness_lufs, normalization_db, gain_db, \
+     trim_start_ms, trim_end_ms, fade_in_ms, fade_out_ms, exclusive_group, \
+     hold_to_play";
+
+/// A clip as analysed on import, before it has an ID.
+pub(super) struct NewClip {
+    pub(super) path: String,
+    pub(super) name: String,
+    pub(super) duration_ms: u64,
+    pub(super) sample_rate: u32,
+    pub(super) channels: u16,
+    pub(super) size_bytes: u64,
+    pub(super) hash: String,
+    pub(super) added_at_ms: u64,
+    pub(super) source_hash: Option<String>,
+    pub(super) loudness_lufs: Option<f64>,
+    pub(super) normalization_db: Option<f64>,
+}
+
+/// The SQLite database behind the clip library.
+pub(super) struct LibraryDb {
+    conn: Connection,
+}
+
+impl LibraryDb {
+    pub(super) fn open(path: &Path) -> Result<Self, String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create library directory: {}", e))?;
+        }
+        let conn = Connection::open(path)
+            .map_err(|e| format!("Failed to open library {}: {}", path.display(), e))?;
+        Self::init(conn)
+    }
+
+    /// A library that only lasts as long as the app, for when there is
+    /// nowhere to save one.
+    pub(super) fn open_in_memory() -> Result<Self, String> {
+        Self::init(Connection::open_in_memory().map_err(db_error)?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, String> {
+        conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;")
+            .map_err(db_error)?;
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(db_error)?;
+        if version > MIGRATIONS.len() {
+            return Err(format!(
+                "Library was created by a newer version of VoiceBox (schema {}, expected at most {})",
+                version,
+                MIGRATIONS.len()
+            ));
+        }
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let batch = format!("BEGIN; {} PRAGMA user_version = {}; COMMIT;", migration, index + 1);
+            conn.execute_batch(&batch)
+                .map_err(|e| format!("Failed to upgrade library to schema {}: {}", index + 1, e))?;
+        }
+        Ok(Self { conn })
+    }
+
+    /// Add a clip, or refresh the analysis of one already in the library at
+    /// the same path. Its name, tags, date added and play history are kept.
+    pub(super) fn upsert(&self, clip: &NewClip) -> Result<ClipId, String> {
+        self.conn
+            .query_row(
+                "INSERT INTO clips (path, name, duration_ms, sample_rate, channels, size_bytes, hash, added_at_ms,
+                                    source_hash, loudness_lufs, normalization_db)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                 ON CONFLICT (path) DO UPDATE SET
+                     duration_ms = excluded.duration_ms,
+                     sample_rate = excluded.sample_rate,
+                     channels = excluded.channels,
+                     size_bytes = excluded.size_bytes,
+                     hash = excluded.hash,
+                     fingerprint = CASE WHEN hash = excluded.hash THEN fingerprint END,
+                     loudness_lufs = excluded.loudness_lufs,
+                     normalization_db = excluded.normalization_db
+                 RETURNING id",
+                params![
+                    clip.path,
+                    clip.name,
+                    clip.duration_ms as i64,
+                    clip.sample_rate,
+                    clip.channels,
+                    clip.size_bytes as i64,
+                    clip.hash,
+                    clip.added_at_ms as i64,
+                    clip.source_hash,
+                    clip.loudness_lufs,
+                    clip.normalization_db,
+                ],
+                |row| row.get(0),
+            )
+            .map_err(db_error)
+    }
+
+    /// Point a clip at a converted copy of its file. Only the details of the
+    /// file are taken from `file`; name, tags, history and playback settings
+    /// are kept.
+    pub(super) fn replace_file(&self, id: ClipId, file: &NewClip) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE clips SET path = ?2, duration_ms = ?3, sample_rate = ?4, channels = ?5, size_bytes = ?6,
+                                  hash = ?7, source_hash = ?8
+                 WHERE id = ?1",
+                params![
+                    id,
+                    file.path,
+                    file.duration_ms as i64,
+                    file.sample_rate,
+                    file.channels,
+                    file.size_bytes as i64,
+                    file.hash,
+                    file.source_hash,
+                ],
+            )
+            .map_err(db_error)?;
+        found(id, changed)
+    }
+
+    pub(super) fn get(&self, id: ClipId) -> Result<Option<LibraryClip>, String> {
+        let clip = self
+            .conn
+            .query_row(
+                &format!("SELECT {} FROM clips WHERE id = ?1", CLIP_COLUMNS),
+                [id],
+                clip_from_row,
+            )
+            .optional()
+            .map_err(db_error)?;
+        match clip {
+            Some(clip) => Ok(Some(self.with_details(clip)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Clips matching every filter in `query`.
+    pub(super) fn search(&self, query: &ClipQuery) -> Result<Vec<LibraryClip>, String> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(text) = query.text.as_deref().map(str::trim).filter(|text| !text.is_empty()) {
+            let pattern = format!("%{}%", escape_like(text));
+            conditions.push(
+                "(name LIKE ? ESCAPE '\\' OR EXISTS (
+                    SELECT 1 FROM clip_tags WHERE clip_id = clips.id AND tag LIKE ? ESCAPE '\\'))",
+            );
+            values.push(Value::Text(pattern.clone()));
+            values.push(Value::Text(pattern));
+        }
+        for tag in &query.tags {
+            conditions.push("EXISTS (SELECT 1 FROM clip_tags WHERE clip_id = clips.id AND tag = ?)");
+            values.push(Value::Text(tag.clone()));
+        }
+        if query.favorites_only {
+            conditions.push("favorite = 1");
+        }
+        if query.played_only {
+            conditions.push("play_count > 0");
+        }
+        if let Some(min) = query.min_duration_ms {
+            conditions.push("duration_ms >= ?");
+            values.push(Value::Integer(min as i64));
+        }
+        if let Some(max) = query.max_duration_ms {
+            conditions.push("duration_ms <= ?");
+            values.push(Value::Integer(max as i64));
+        }
+
+        let mut sql = format!("SELECT {} FROM clips", CLIP_COLUMNS);
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        let column = match query.sort {
+            ClipSort::Name => "name COLLATE NOCASE",
+            ClipSort::Added => "added_at_ms",
+            ClipSort::Duration => "duration_ms",
+            ClipSort::PlayCount => "play_count",
+            ClipSort::LastPlayed => "last_played_at_ms",
+        };
+        let direction = if query.descending { "DESC" } else { "ASC" };
+        sql.push_str(&format!(" ORDER BY {} {}, id {}", column, direction, direction));
+        // SQLite needs a LIMIT to take an OFFSET; -1 means no limit
+        sql.push_str(" LIMIT ? OFFSET ?");
+        values.push(Value::Integer(query.limit.map_or(-1, |limit| limit as i64)));
+        values.push(Value::Integer(query.offset as i64));
+
+        let mut statement = self.conn.prepare(&sql).map_err(db_error)?;
+        let clips = statement
+            .query_map(params_from_iter(values), clip_from_row)
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        clips.into_iter().map(|clip| self.with_details(clip)).collect()
+    }
+
+    /// Clips whose file, or the file they were converted from, is
+    /// byte-for-byte the same as a file with `hash`.
+    pub(super) fn find_by_hash(&self, hash: &str) -> Result<Vec<LibraryClip>, String> {
+        let mut statement = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {} FROM clips WHERE hash = ?1 OR source_hash = ?1 ORDER BY id",
+                CLIP_COLUMNS
+            ))
+            .map_err(db_error)?;
+        let clips = statement
+            .query_map([hash], clip_from_row)
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        clips.into_iter().map(|clip| self.with_details(clip)).collect()
+    }
+
+    pub(super) fn rename(&self, id: ClipId, name: &str) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute("UPDATE clips SET name = ?2 WHERE id = ?1", params![id, name])
+            .map_err(db_error)?;
+        found(id, changed)
+    }
+
+    pub(super) fn set_favorite(&self, id: ClipId, favorite: bool) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute("UPDATE clips SET favorite = ?2 WHERE id = ?1", params![id, favorite])
+            .map_err(db_error)?;
+        found(id, changed)
+    }
+
+    pub(super) fn set_exclusive_group(&self, id: ClipId, group: Option<&str>) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute("UPDATE clips SET exclusive_group = ?2 WHERE id = ?1", params![id, group])
+            .map_err(db_error)?;
+        found(id, changed)
+    }
+
+    pub(super) fn set_hold_to_play(&self, id: ClipId, hold_to_play: bool) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute("UPDATE clips SET hold_to_play = ?2 WHERE id = ?1", params![id, hold_to_play])
+            .map_err(db_error)?;
+        found(id, changed)
+    }
+
+    /// Count a play of a clip and add it to the trigger log.
+    pub(super) fn record_play(
+        &mut self,
+        id: ClipId,
+        played_at_ms: u64,
+        source: TriggerSource,
+        device_ids: &[String],
+    ) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(db_error)?;
+        let changed = tx
+            .execute(
+                "UPDATE clips SET play_count = play_count + 1, last_played_at_ms = ?2 WHERE id = ?1",
+                params![id, played_at_ms as i64],
+            )
+            .map_err(db_error)?;
+        found(id, changed)?;
+        tx.execute(
+            "INSERT INTO clip_triggers (clip_id, at_ms, source, device_ids) VALUES (?1, ?2, ?3, ?4)",
+            params![id, played_at_ms as i64, source.name(), to_json(device_ids)?],
+        )
+        .map_err(db_error)?;
+        tx.commit().map_err(db_error)
+    }
+
+    /// Clips started from `since_ms` up to `until_ms`, in the order they
+    /// started.
+    pub(super) fn triggers(&self, since_ms: u64, until_ms: u64) -> Result<Vec<TriggerEvent>, String> {
+        let mut statement = self
+            .conn
+            .prepare_cached(
+                "SELECT t.id, t.clip_id, c.name, t.at_ms, t.source, t.device_ids FROM clip_triggers t
+                 JOIN clips c ON c.id = t.clip_id WHERE t.at_ms >= ?1 AND t.at_ms <= ?2 ORDER BY t.at_ms, t.id",
+            )
+            .map_err(db_error)?;
+        let triggers = statement
+            .query_map(params![since_ms as i64, until_ms.min(i64::MAX as u64) as i64], trigger_from_row)
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        Ok(triggers)
+    }
+
+    pub(super) fn set_loudness(
+        &self,
+        id: ClipId,
+        loudness_lufs: Option<f64>,
+        normalization_db: Option<f64>,
+    ) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE clips SET loudness_lufs = ?2, normalization_db = ?3 WHERE id = ?1",
+                params![id, loudness_lufs, normalization_db],
+            )
+            .map_err(db_error)?;
+        found(id, changed)
+    }
+
+    /// Work out every measured clip's normalization gain again, e.g. for a
+    /// new target. `gain` gets a clip's loudness in LUFS.
+    pub(super) fn update_normalization(&mut self, gain: impl Fn(f64) -> Option<f64>) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(db_error)?;
+        {
+            let mut select = tx
+                .prepare("SELECT id, loudness_lufs FROM clips WHERE loudness_lufs IS NOT NULL")
+                .map_err(db_error)?;
+            let measured = select
+                .query_map([], |row| Ok((row.get::<_, ClipId>(0)?, row.get::<_, f64>(1)?)))
+                .map_err(db_error)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(db_error)?;
+            let mut update = tx
+                .prepare("UPDATE clips SET normalization_db = ?2 WHERE id = ?1")
+                .map_err(db_error)?;
+            for (id, lufs) in measured {
+                update.execute(params![id, gain(lufs)]).map_err(db_error)?;
+            }
+        }
+        tx.commit().map_err(db_error)
+    }
+
+    /// IDs and paths of clips whose loudness hasn't been measured.
+    pub(super) fn unmeasured_clips(&self) -> Result<Vec<(ClipId, String)>, String> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT id, path FROM clips WHERE loudness_lufs IS NULL ORDER BY id")
+            .map_err(db_error)?;
+        let clips = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error);
+        clips
+    }
+
+    /// Store a clip's fingerprint. An empty one marks a clip that was
+    /// analysed but is too short or quiet to have one.
+    pub(super) fn set_fingerprint(&self, id: ClipId, fingerprint: &[u32]) -> Result<(), String> {
+        let blob: Vec<u8> = fingerprint.iter().flat_map(|code| code.to_le_bytes()).collect();
+        let changed = self
+            .conn
+            .execute("UPDATE clips SET fingerprint = ?2 WHERE id = ?1", params![id, blob])
+            .map_err(db_error)?;
+        found(id, changed)
+    }
+
+    pub(super) fn fingerprint(&self, id: ClipId) -> Result<Option<Vec<u32>>, String> {
+        let blob: Option<Vec<u8>> = self
+            .conn
+            .query_row("SELECT fingerprint FROM clips WHERE id = ?1", [id], |row| row.get(0))
+            .optional()
+            .map_err(db_error)?
+            .flatten();
+        Ok(blob.filter(|blob| !blob.is_empty()).map(|blob| fingerprint_from_blob(&blob)))
+    }
+
+    /// IDs and fingerprints of clips between `min_ms` and `max_ms` long
+    /// that have one.
+    pub(super) fn fingerprints_between(&self, min_ms: u64, max_ms: u64) -> Result<Vec<(ClipId, Vec<u32>)>, String> {
+        let mut statement = self
+            .conn
+            .prepare(
+                "SELECT id, fingerprint FROM clips
+                 WHERE length(fingerprint) > 0 AND duration_ms BETWEEN ?1 AND ?2 ORDER BY id",
+            )
+            .map_err(db_error)?;
+        let clips = statement
+            .query_map(params![min_ms as i64, max_ms as i64], |row| {
+                Ok((row.get(0)?, fingerprint_from_blob(&row.get::<_, Vec<u8>>(1)?)))
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error);
+        clips
+    }
+
+    /// IDs and paths of clips that haven't been fingerprinted.
+    pub(super) fn unfingerprinted_clips(&self) -> Result<Vec<(ClipId, String)>, String> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT id, path FROM clips WHERE fingerprint IS NULL ORDER BY id")
+            .map_err(db_error)?;
+        let clips = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error);
+        clips
+    }
+
+    /// Every board profile, by name. None of them are marked active.
+    pub(super) fn board_profiles(&self) -> Result<Vec<BoardProfile>, String> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT id, name, output_device_ids FROM board_profiles ORDER BY name COLLATE NOCASE")
+            .map_err(db_error)?;
+        let profiles = statement
+            .query_map([], profile_from_row)
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        profiles.into_iter().map(|profile| self.with_slots(profile)).collect()
+    }
+
+    pub(super) fn board_profile(&self, id: ProfileId) -> Result<Option<BoardProfile>, String> {
+        let profile = self
+            .conn
+            .query_row(
+                "SELECT id, name, output_device_ids FROM board_profiles WHERE id = ?1",
+                [id],
+                profile_from_row,
+            )
+            .optional()
+            .map_err(db_error)?;
+        match profile {
+            Some(profile) => Ok(Some(self.with_slots(profile)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(super) fn create_board_profile(
+        &mut self,
+        name: &str,
+        output_device_ids: &[String],
+        slots: &[BoardSlot],
+    ) -> Result<ProfileId, String> {
+        let tx = self.conn.transaction().map_err(db_error)?;
+        profile_name_free(&tx, name, None)?;
+        tx.execute(
+            "INSERT INTO board_profiles (name, output_device_ids) VALUES (?1, ?2)",
+            params![name, to_json(output_device_ids)?],
+        )
+        .map_err(db_error)?;
+        let id = tx.last_insert_rowid();
+        insert_slots(&tx, id, slots)?;
+        tx.commit().map_err(db_error)?;
+        Ok(id)
+    }
+
+    pub(super) fn update_board_profile(&mut self, profile: &BoardProfile) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(db_error)?;
+        profile_name_free(&tx, &profile.name, Some(profile.id))?;
+        let changed = tx
+            .execute(
+                "UPDATE board_profiles SET name = ?2, output_device_ids = ?3 WHERE id = ?1",
+                params![profile.id, profile.name, to_json(&profile.output_device_ids)?],
+            )
+            .map_err(db_error)?;
+        if changed == 0 {
+            return Err(format!("No board profile {}", profile.id));
+        }
+        tx.execute("DELETE FROM board_slots WHERE profile_id = ?1", [profile.id])
+            .map_err(db_error)?;
+        insert_slots(&tx, profile.id, &profile.slots)?;
+        tx.commit().map_err(db_error)
+    }
+
+    pub(super) fn delete_board_profile(&self, id: ProfileId) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute("DELETE FROM board_profiles WHERE id = ?1", [id])
+            .map_err(db_error)?;
+        if changed == 0 {
+            return Err(format!("No board profile {}", id));
+        }
+        Ok(())
+    }
+
+    fn with_slots(&self, mut profile: BoardProfile) -> Result<BoardProfile, String> {
+        let mut statement = self
+            .conn
+            .prepare_cached(
+                "SELECT position, clip_id, hotkey, device_ids FROM board_slots
+                 WHERE profile_id = ?1 ORDER BY position",
+            )
+            .map_err(db_error)?;
+        let slots = statement
+            .query_map([profile.id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get::<_, Option<String>>(3)?))
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        profile.slots = slots
+            .into_iter()
+            .map(|(position, clip_id, hotkey, device_ids)| {
+                Ok(BoardSlot {
+                    position,
+                    clip_id,
+                    hotkey,
+                    device_ids: device_ids.as_deref().map(from_json).transpose()?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(profile)
+    }
+
+    pub(super) fn add_scheduled_play(&self, clip_id: ClipId, at_ms: u64, device_ids: &[String]) -> Result<ScheduleId, String> {
+        self.conn
+            .execute(
+                "INSERT INTO scheduled_plays (clip_id, at_ms, device_ids) VALUES (?1, ?2, ?3)",
+                params![clip_id, at_ms as i64, to_json(device_ids)?],
+            )
+            .map_err(db_error)?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Plays waiting to happen, soonest first.
+    pub(super) fn scheduled_plays(&self) -> Result<Vec<ScheduledPlay>, String> {
+        let mut statement = self
+            .conn
+            .prepare_cached(
+                "SELECT s.id, s.clip_id, c.name, s.at_ms, s.device_ids FROM scheduled_plays s
+                 JOIN clips c ON c.id = s.clip_id ORDER BY s.at_ms, s.id",
+            )
+            .map_err(db_error)?;
+        let plays = statement
+            .query_map([], scheduled_play_from_row)
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        Ok(plays)
+    }
+
+    pub(super) fn delete_scheduled_play(&self, id: ScheduleId) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute("DELETE FROM scheduled_plays WHERE id = ?1", [id])
+            .map_err(db_error)?;
+        if changed == 0 {
+            return Err(format!("No scheduled play {}", id));
+        }
+        Ok(())
+    }
+
+    /// Remove and return the plays due by `now_ms`, soonest first.
+    pub(super) fn take_due_plays(&mut self, now_ms: u64) -> Result<Vec<ScheduledPlay>, String> {
+        let tx = self.conn.transaction().map_err(db_error)?;
+        let plays = {
+            let mut statement = tx
+                .prepare_cached(
+                    "SELECT s.id, s.clip_id, c.name, s.at_ms, s.device_ids FROM scheduled_plays s
+                     JOIN clips c ON c.id = s.clip_id WHERE s.at_ms <= ?1 ORDER BY s.at_ms, s.id",
+                )
+                .map_err(db_error)?;
+            let plays = statement
+                .query_map([now_ms as i64], scheduled_play_from_row)
+                .map_err(db_error)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(db_error)?;
+            plays
+        };
+        if plays.is_empty() {
+            return Ok(plays);
+        }
+        tx.execute("DELETE FROM scheduled_plays WHERE at_ms <= ?1", [now_ms as i64])
+            .map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(plays)
+    }
+
+    /// Plays in the trigger log counted per clip and period, per hour of the
+    /// day and per trigger source.
+    pub(super) fn play_stats(&self, query: &PlayStatsQuery) -> Result<PlayStats, String> {
+        let since_ms = query.since_ms.unwrap_or(0).min(i64::MAX as u64) as i64;
+        let until_ms = query.until_ms.unwrap_or(u64::MAX).min(i64::MAX as u64) as i64;
+        let offset_ms = query.utc_offset_minutes as i64 * 60_000;
+        let (span_ms, shift_ms) = query.period.span_ms();
+
+        let total_plays: u64 = self
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM clip_triggers WHERE at_ms >= ?1 AND at_ms <= ?2",
+                params![since_ms, until_ms],
+                |row| row.get(0),
+            )
+            .map_err(db_error)?;
+
+        let mut statement = self
+            .conn
+            .prepare_cached(
+                "SELECT t.clip_id, c.name, ((t.at_ms + ?3) / ?4) * ?4 - ?3 AS period, COUNT(*) AS plays
+                 FROM clip_triggers t JOIN clips c ON c.id = t.clip_id
+                 WHERE t.at_ms >= ?1 AND t.at_ms <= ?2
+                 GROUP BY t.clip_id, period ORDER BY period, plays DESC, t.clip_id",
+            )
+            .map_err(db_error)?;
+        let per_clip = statement
+            .query_map(params![since_ms, until_ms, offset_ms + shift_ms, span_ms], |row| {
+                Ok(ClipPeriodPlays {
+                    clip_id: row.get(0)?,
+                    clip_name: row.get(1)?,
+                    period_start_ms: row.get::<_, i64>(2)?.max(0) as u64,
+                    plays: row.get(3)?,
+                })
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+
+        let mut statement = self
+            .conn
+            .prepare_cached(
+                "SELECT ((at_ms + ?3) / 3600000) % 24 AS hour, COUNT(*) AS plays FROM clip_triggers
+                 WHERE at_ms >= ?1 AND at_ms <= ?2 GROUP BY hour ORDER BY plays DESC, hour",
+            )
+            .map_err(db_error)?;
+        let busiest_hours = statement
+            .query_map(params![since_ms, until_ms, offset_ms], |row| {
+                Ok(HourPlays {
+                    hour: row.get(0)?,
+                    plays: row.get(1)?,
+                })
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+
+        let mut statement = self
+            .conn
+            .prepare_cached(
+                "SELECT source, COUNT(*) AS plays FROM clip_triggers
+                 WHERE at_ms >= ?1 AND at_ms <= ?2 GROUP BY source ORDER BY plays DESC, source",
+            )
+            .map_err(db_error)?;
+        let sources = statement
+            .query_map(params![since_ms, until_ms], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?
+            .into_iter()
+            // Written by `record_play`, so only unknown if edited by hand
+            .filter_map(|(source, plays)| {
+                Some(SourcePlays {
+                    source: TriggerSource::from_name(&source)?,
+                    plays,
+                })
+            })
+            .collect();
+
+        Ok(PlayStats {
+            total_plays,
+            per_clip,
+            busiest_hours,
+            sources,
+        })
+    }
+
+    /// A value saved with `set_setting`, if there is one.
+    pub(super) fn setting<T: serde::de::DeserializeOwned>(&self, key: &str) -> Result<Option<T>, String> {
+        let value: Option<String> = self
+            .conn
+            .query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| row.get(0))
+            .optional()
+            .map_err(db_error)?;
+        match value {
+            Some(value) => serde_json::from_str(&value)
+                .map(Some)
+                .map_err(|e| format!("Invalid {} setting in library: {}", key, e)),
+            None => Ok(None),
+        }
+    }
+
+    pub(super) fn set_setting<T: serde::Serialize>(&self, key: &str, value: &T) -> Result<(), String> {
+        let value = serde_json::to_string(value).map_err(|e| format!("Failed to serialize {}: {}", key, e))?;
+        self.conn
+            .execute(
+                "INSERT INTO settings (key, value) VALUES (?1, ?2)
+                 ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            )
+            .map_err(db_error)?;
+        Ok(())
+    }
+
+    pub(super) fn set_playback(&self, id: ClipId, playback: &ClipPlayback) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE clips SET gain_db = ?2, trim_start_ms = ?3, trim_end_ms = ?4, fade_in_ms = ?5, fade_out_ms = ?6
+                 WHERE id = ?1",
+                params![
+                    id,
+                    playback.gain_db,
+                    playback.trim.start_ms,
+                    playback.trim.end_ms,
+                    playback.fade_in_ms,
+                    playback.fade_out_ms,
+                ],
+            )
+            .map_err(db_error)?;
+        found(id, changed)
+    }
+
+    /// Replace all of a clip's tags.
+    pub(super) fn set_tags(&mut self, id: ClipId, tags: &[String]) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(db_error)?;
+        let exists = tx
+            .query_row("SELECT 1 FROM clips WHERE id = ?1", [id], |_| Ok(()))
+            .optional()
+            .map_err(db_error)?;
+        found(id, exists.map_or(0, |_| 1))?;
+        tx.execute("DELETE FROM clip_tags WHERE clip_id = ?1", [id])
+            .map_err(db_error)?;
+        for tag in tags {
+            tx.execute(
+                "INSERT OR IGNORE INTO clip_tags (clip_id, tag) VALUES (?1, ?2)",
+                params![id, tag],
+            )
+            .map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)
+    }
+
+    /// Replace all of a clip's markers.
+    pub(super) fn set_markers(&mut self, id: ClipId, markers: &[ClipMarker]) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(db_error)?;
+        let exists = tx
+            .query_row("SELECT 1 FROM clips WHERE id = ?1", [id], |_| Ok(()))
+            .optional()
+            .map_err(db_error)?;
+        found(id, exists.map_or(0, |_| 1))?;
+        tx.execute("DELETE FROM clip_markers WHERE clip_id = ?1", [id])
+            .map_err(db_error)?;
+        for marker in markers {
+            tx.execute(
+                "INSERT INTO clip_markers (clip_id, name, position_ms) VALUES (?1, ?2, ?3)",
+                params![id, marker.name, marker.position_ms],
+            )
+            .map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)
+    }
+
+    /// Add tags to a clip, keeping the ones it has.
+    pub(super) fn add_tags(&self, id: ClipId, tags: &[String]) -> Result<(), String> {
+        let mut statement = self
+            .conn
+            .prepare_cached("INSERT OR IGNORE INTO clip_tags (clip_id, tag) VALUES (?1, ?2)")
+            .map_err(db_error)?;
+        for tag in tags {
+            statement.execute(params![id, tag]).map_err(db_error)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn remove(&self, id: ClipId) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute("DELETE FROM clips WHERE id = ?1", [id])
+            .map_err(db_error)?;
+        found(id, changed)
+    }
+
+    /// Every tag in use, with how many clips have it, most used first.
+    pub(super) fn tags(&self) -> Result<Vec<TagCount>, String> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT tag, COUNT(*) FROM clip_tags GROUP BY tag ORDER BY COUNT(*) DESC, tag")
+            .map_err(db_error)?;
+        let tags = statement
+            .query_map([], |row| {
+                Ok(TagCount {
+                    tag: row.get(0)?,
+                    clips: row.get::<_, i64>(1)? as u64,
+                })
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error);
+        tags
+    }
+
+    /// Paths of every clip in the library.
+    pub(super) fn clip_paths(&self) -> Result<HashSet<String>, String> {
+        let mut statement = self.conn.prepare_cached("SELECT path FROM clips").map_err(db_error)?;
+        let paths = statement
+            .query_map([], |row| row.get(0))
+            .map_err(db_error)?
+            .collect::<Result<HashSet<_>, _>>()
+            .map_err(db_error);
+        paths
+    }
+
+    pub(super) fn watch_folders(&self) -> Result<Vec<WatchFolder>, String> {
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT path, recursive FROM watch_folders ORDER BY path")
+            .map_err(db_error)?;
+        let folders = statement
+            .query_map([], |row| {
+                Ok(WatchFolder {
+                    path: row.get(0)?,
+                    recursive: row.get(1)?,
+                })
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error);
+        folders
+    }
+
+    /// Watch a folder, or change whether an already watched one is watched
+    /// recursively.
+    pub(super) fn add_watch_folder(&self, folder: &WatchFolder) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO watch_folders (path, recursive) VALUES (?1, ?2)
+                 ON CONFLICT (path) DO UPDATE SET recursive = excluded.recursive",
+                params![folder.path, folder.recursive],
+            )
+            .map_err(db_error)?;
+        Ok(())
+    }
+
+    pub(super) fn remove_watch_folder(&self, path: &str) -> Result<(), String> {
+        let changed = self
+            .conn
+            .execute("DELETE FROM watch_folders WHERE path = ?1", [path])
+            .map_err(db_error)?;
+        if changed == 0 {
+            return Err(format!("{} isn't being watched", path));
+        }
+        Ok(())
+    }
+
+    /// Fill in a clip's tags and markers.
+    fn with_details(&self, mut clip: LibraryClip) -> Result<LibraryClip, String> {
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT tag FROM clip_tags WHERE clip_id = ?1 ORDER BY tag")
+            .map_err(db_error)?;
+        clip.tags = statement
+            .query_map([clip.id], |row| row.get(0))
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT name, position_ms FROM clip_markers WHERE clip_id = ?1 ORDER BY position_ms, name")
+            .map_err(db_error)?;
+        clip.markers = statement
+            .query_map([clip.id], |row| {
+                Ok(ClipMarker {
+                    name: row.get(0)?,
+                    position_ms: row.get(1)?,
+                })
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        Ok(clip)
+    }
+}
+
+fn clip_from_row(row: &Row) -> rusqlite::Result<LibraryClip> {
+    Ok(LibraryClip {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        name: row.get(2)?,
+        tags: Vec::new(),
+        markers: Vec::new(),
+        duration_ms: row.get::<_, i64>(3)? as u64,
+        sample_rate: row.get(4)?,
+        channels: row.get(5)?,
+        size_bytes: row.get::<_, i64>(6)? as u64,
+        hash: row.get(7)?,
+        added_at_ms: row.get::<_, i64>(8)? as u64,
+        source_hash: row.get(9)?,
+        favorite: row.get(10)?,
+        play_count: row.get::<_, i64>(11)? as u64,
+        last_played_at_ms: row.get::<_, Option<i64>>(12)?.map(|ms| ms as u64),
+        loudness_lufs: row.get(13)?,
+        normalization_db: row.get(14)?,
+        playback: ClipPlayback {
+            gain_db: row.get(15)?,
+            trim: ClipTrim {
+                start_ms: row.get(16)?,
+                end_ms: row.get(17)?,
+            },
+            fade_in_ms: row.get(18)?,
+            fade_out_ms: row.get(19)?,
+        },
+        exclusive_group: row.get(20)?,
+        hold_to_play: row.get(21)?,
+    })
+}
+
+fn profile_from_row(row: &Row) -> rusqlite::Result<BoardProfile> {
+    let output_device_ids: String = row.get(2)?;
+    Ok(BoardProfile {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        // Written by `to_json`, so only unreadable if edited by hand
+        output_device_ids: from_json(&output_device_ids).unwrap_or_default(),
+        slots: Vec::new(),
+        active: false,
+    })
+}
+
+fn scheduled_play_from_row(row: &Row) -> rusqlite::Result<ScheduledPlay> {
+    let device_ids: String = row.get(4)?;
+    Ok(ScheduledPlay {
+        id: row.get(0)?,
+        clip_id: row.get(1)?,
+        clip_name: row.get(2)?,
+        at_ms: row.get(3)?,
+        // Written by `to_json`, so only unreadable if edited by hand
+        device_ids: from_json(&device_ids).unwrap_or_default(),
+    })
+}
+
+fn trigger_from_row(row: &Row) -> rusqlite::Result<TriggerEvent> {
+    let source: String = row.get(4)?;
+    let device_ids: String = row.get(5)?;
+    Ok(TriggerEvent {
+        id: row.get(0)?,
+        clip_id: row.get(1)?,
+        clip_name: row.get(2)?,
+        at_ms: row.get(3)?,
+        // Both written by this file, so only unreadable if edited by hand
+        source: TriggerSource::from_name(&source).unwrap_or(TriggerSource::External),
+        device_ids: from_json(&device_ids).unwrap_or_default(),
+    })
+}
+
+fn profile_name_free(conn: &Connection, name: &str, except: Option<ProfileId>) -> Result<(), String> {
+    let taken = conn
+        .query_row(
+            "SELECT 1 FROM board_profiles WHERE name = ?1 COLLATE NOCASE AND id IS NOT ?2",
+            params![name, except],
+            |_| Ok(()),
+        )
+        .optional()
+        .map_err(db_error)?;
+    match taken {
+        Some(()) => Err(format!("There is already a board profile named {}", name)),
+        None => Ok(()),
+    }
+}
+
+fn insert_slots(conn: &Connection, profile_id: ProfileId, slots: &[BoardSlot]) -> Result<(), String> {
+    let mut statement = conn
+        .prepare_cached(
+            "INSERT INTO board_slots (profile_id, position, clip_id, hotkey, device_ids)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )
+        .map_err(db_error)?;
+    for slot in slots {
+        let device_ids = slot.device_ids.as_deref().map(to_json).transpose()?;
+        statement
+            .execute(params![profile_id, slot.position, slot.clip_id, slot.hotkey, device_ids])
+            .map_err(|e| match e.sqlite_error_code() {
+                Some(rusqlite::ErrorCode::ConstraintViolation) => {
+                    format!("No clip {} in the library", slot.clip_id)
+                }
+                _ => db_error(e),
+            })?;
+    }
+    Ok(())
+}
+
+/// Fingerprints are stored as little-endian 32-bit codes.
+fn fingerprint_from_blob(blob: &[u8]) -> Vec<u32> {
+    blob.chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+fn to_json(device_ids: &[String]) -> Result<String, String> {
+    serde_json::to_string(device_ids).map_err(|e| format!("Failed to serialize device IDs: {}", e))
+}
+
+fn from_json(device_ids: &str) -> Result<Vec<String>, String> {
+    serde_json::from_str(device_ids).map_err(|e| format!("Invalid device IDs in library: {}", e))
+}
+
+/// Escape `%`, `_` and the escape character itself so `text` matches
+/// literally inside a LIKE pattern.
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn found(id: ClipId, changed: usize) -> Result<(), String> {
+    if changed == 0 {
+        return Err(format!("No clip {} in the library", id));
+    }
+    Ok(())
+}
+
+fn db_error(e: rusqlite::Error) -> String {
+    format!("Library database error: {}", e)
+}
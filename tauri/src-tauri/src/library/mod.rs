@@ -0,0 +1,1553 @@
+mod board;
+mod bundle;
+mod db;
+mod schedule;
+mod stats;
+mod transcode;
+mod triggers;
+mod watch;
+
+use crate::audio_output::{
+    hash_file, AudioOutputState, ClipTrim, LeadIn, PlaybackOptions, SessionId, SilenceOptions, SoundRegion,
+    SpliceSegment,
+};
+use bundle::BundleReader;
+use db::{LibraryDb, NewClip};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager};
+
+pub use board::{BoardProfile, BoardProfileUpdate, BoardSlot, ProfileId};
+pub use schedule::{ScheduleId, ScheduledPlay};
+pub use stats::{PlayStats, PlayStatsQuery};
+pub use transcode::TranscodeRequest;
+pub use triggers::{TriggerEvent, TriggerSource};
+pub use watch::WatchFolder;
+
+pub type ClipId = i64;
+
+/// Format clips are converted to when copied into the library.
+const LIBRARY_SAMPLE_RATE: u32 = 48_000;
+const LIBRARY_EXTENSION: &str = "flac";
+
+/// Settings key of `NormalizeSettings`.
+const NORMALIZE_SETTINGS_KEY: &str = "normalize";
+/// Settings key of the ID of the board profile in use.
+const ACTIVE_PROFILE_KEY: &str = "active_board_profile";
+/// Loudness targets the user can pick, in LUFS.
+const MIN_TARGET_LUFS: f32 = -40.0;
+const MAX_TARGET_LUFS: f32 = -5.0;
+/// Range of a clip's own gain.
+const MIN_CLIP_GAIN_DB: f32 = -40.0;
+const MAX_CLIP_GAIN_DB: f32 = 12.0;
+/// Fingerprint similarity from which two clips count as the same sound.
+/// Re-encoded copies score 0.85 and up, unrelated clips under 0.75.
+const SIMILAR_CLIP_THRESHOLD: f32 = 0.8;
+/// Fade out of the clip that was playing when another in its exclusive
+/// group starts.
+const EXCLUSIVE_GROUP_FADE_MS: u32 = 100;
+/// Fade out of a hold-to-play clip when its key is let go.
+const HOLD_RELEASE_FADE_MS: u32 = 50;
+
+/// Numbers the files imports work on before they're done, so imports
+/// running at the same time never share one.
+static NEXT_TEMP_FILE: AtomicU64 = AtomicU64::new(0);
+
+/// A clip tracked by the library.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LibraryClip {
+    pub id: ClipId,
+    pub path: String,
+    pub name: String,
+    pub tags: Vec<String>,
+    /// In order of position
+    pub markers: Vec<ClipMarker>,
+    pub duration_ms: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub size_bytes: u64,
+    /// SHA-256 of the file, in hex
+    pub hash: String,
+    /// When the clip was imported, in milliseconds since the Unix epoch
+    pub added_at_ms: u64,
+    /// SHA-256 of the file it was converted from, for clips copied into the
+    /// library
+    pub source_hash: Option<String>,
+    pub favorite: bool,
+    /// Times the clip has been played
+    pub play_count: u64,
+    pub last_played_at_ms: Option<u64>,
+    /// Integrated loudness, measured on import while normalization is on
+    pub loudness_lufs: Option<f64>,
+    /// Gain applied on playback to bring the clip to the normalization
+    /// target, if normalization is on
+    pub normalization_db: Option<f64>,
+    pub playback: ClipPlayback,
+    /// Playing the clip stops any other clip in the same group, as on a
+    /// cart wall
+    pub exclusive_group: Option<String>,
+    /// The clip only plays while its key is held, fading out on release
+    pub hold_to_play: bool,
+}
+
+/// How a clip is played, as adjusted by the user. Applied every time the
+/// clip is played from the library.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ClipPlayback {
+    pub gain_db: f32,
+    pub trim: ClipTrim,
+    pub fade_in_ms: u32,
+    pub fade_out_ms: u32,
+}
+
+impl ClipPlayback {
+    fn validate(&self) -> Result<(), String> {
+        if !(MIN_CLIP_GAIN_DB..=MAX_CLIP_GAIN_DB).contains(&self.gain_db) {
+            return Err(format!(
+                "Clip gain must be {} to {} dB, got {}",
+                MIN_CLIP_GAIN_DB, MAX_CLIP_GAIN_DB, self.gain_db
+            ));
+        }
+        self.trim.validate()
+    }
+
+    /// Fill in `options` from these settings. Trim and fades given for one
+    /// playback take precedence; gains add up.
+    fn apply(&self, options: &mut PlaybackOptions) {
+        options.gain_db += self.gain_db;
+        if options.trim.is_none() && self.trim != ClipTrim::default() {
+            options.trim = Some(self.trim);
+        }
+        if options.fade_in_ms == 0 {
+            options.fade_in_ms = self.fade_in_ms;
+        }
+        if options.fade_out_ms == 0 {
+            options.fade_out_ms = self.fade_out_ms;
+        }
+    }
+}
+
+/// A named position in a clip, e.g. where the beat drops.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ClipMarker {
+    pub name: String,
+    /// From the start of the clip's file
+    pub position_ms: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipSort {
+    #[default]
+    Name,
+    Added,
+    Duration,
+    PlayCount,
+    LastPlayed,
+}
+
+/// Filters for searching the library. Every filter that is set has to
+/// match.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct ClipQuery {
+    /// Part of the clip's name or one of its tags, ignoring case
+    pub text: Option<String>,
+    /// Tags the clip must all have
+    pub tags: Vec<String>,
+    pub min_duration_ms: Option<u64>,
+    pub max_duration_ms: Option<u64>,
+    pub favorites_only: bool,
+    /// Only clips that have been played at least once
+    pub played_only: bool,
+    pub sort: ClipSort,
+    pub descending: bool,
+    pub limit: Option<u32>,
+    pub offset: u32,
+}
+
+/// A tag and how many clips have it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub clips: u64,
+}
+
+/// Changes to a clip's details. Fields left unset are kept.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct ClipUpdate {
+    pub name: Option<String>,
+    /// Replaces all of the clip's tags
+    pub tags: Option<Vec<String>>,
+    pub favorite: Option<bool>,
+    pub playback: Option<ClipPlayback>,
+    /// Replaces all of the clip's markers
+    pub markers: Option<Vec<ClipMarker>>,
+    /// An empty name takes the clip out of its group
+    pub exclusive_group: Option<String>,
+    pub hold_to_play: Option<bool>,
+}
+
+/// Loudness normalization of library clips. While it is on, clips are
+/// measured as they're imported and played at `target_lufs`.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct NormalizeSettings {
+    pub enabled: bool,
+    pub target_lufs: f32,
+}
+
+impl Default for NormalizeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target_lufs: -16.0,
+        }
+    }
+}
+
+impl NormalizeSettings {
+    /// Playback gain for a clip measured at `lufs`, if normalization is on.
+    fn gain_db(&self, lufs: f64) -> Option<f64> {
+        self.enabled
+            .then(|| crate::audio_output::normalization_db(self.target_lufs as f64, lufs))
+    }
+}
+
+/// What became of a file given to `import_files`.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ImportOutcome {
+    Imported { clip: LibraryClip },
+    /// The library already has this file; nothing was copied
+    Duplicate { clip: LibraryClip },
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ImportResult {
+    /// Path of the file as given
+    pub source: String,
+    #[serde(flatten)]
+    pub outcome: ImportOutcome,
+}
+
+/// One of the clips a recording was split into by `split_on_silence`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SplitClip {
+    /// Where the clip was in the recording
+    pub region: SoundRegion,
+    #[serde(flatten)]
+    pub outcome: ImportOutcome,
+}
+
+/// A clip that sounds the same as another, going by their fingerprints.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SimilarClip {
+    pub clip: LibraryClip,
+    /// How alike the two are, up to 1
+    pub similarity: f32,
+}
+
+/// Payload of `library://similar_clips_found`, emitted when an imported
+/// clip sounds like clips the library already has.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SimilarClipsFound {
+    pub clip: LibraryClip,
+    pub similar: Vec<SimilarClip>,
+}
+
+/// Result of `import_board`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BoardImport {
+    pub profile: BoardProfile,
+    /// Clips added to the library
+    pub imported_clips: usize,
+    /// Clips the library already had, which were used as they are
+    pub duplicate_clips: usize,
+}
+
+/// The user's clip library: files they've imported, with names and tags to
+/// find them by.
+pub struct LibraryState {
+    db: Mutex<Option<LibraryDb>>,
+    /// Where files copied into the library are kept
+    clips_dir: Mutex<Option<PathBuf>>,
+    normalize: Mutex<NormalizeSettings>,
+    app_handle: Mutex<Option<AppHandle>>,
+    /// Cancels the batch conversion in progress, if any
+    transcode_job: Mutex<Option<Arc<AtomicBool>>>,
+    /// Session last started for each exclusive group
+    group_sessions: Mutex<HashMap<String, SessionId>>,
+    /// Sessions of hold-to-play clips whose key is down
+    held_sessions: Mutex<HashMap<ClipId, SessionId>>,
+    /// When the app started, where the trigger log of this session begins
+    started_at_ms: u64,
+    /// Stops the trigger replay in progress, if any
+    replay_job: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+impl LibraryState {
+    pub fn new() -> Self {
+        Self {
+            db: Mutex::new(None),
+            clips_dir: Mutex::new(None),
+            normalize: Mutex::new(NormalizeSettings::default()),
+            app_handle: Mutex::new(None),
+            transcode_job: Mutex::new(None),
+            group_sessions: Mutex::new(HashMap::new()),
+            held_sessions: Mutex::new(HashMap::new()),
+            started_at_ms: now_ms(),
+            replay_job: Mutex::new(None),
+        }
+    }
+
+    /// Open the library in the app's data directory, creating it on first
+    /// run.
+    pub fn attach_app_handle(&self, app: AppHandle) {
+        *self.app_handle.lock().unwrap() = Some(app.clone());
+        let db = match app.path().app_data_dir() {
+            Ok(dir) => {
+                *self.clips_dir.lock().unwrap() = Some(dir.join("library"));
+                LibraryDb::open(&dir.join("library.db"))
+            }
+            Err(e) => Err(format!("Failed to get app data dir, library won't be saved: {}", e)),
+        };
+        let db = match db {
+            Ok(db) => db,
+            Err(e) => {
+                eprintln!("{}", e);
+                match LibraryDb::open_in_memory() {
+                    Ok(db) => db,
+                    Err(e) => {
+                        eprintln!("Failed to create library: {}", e);
+                        return;
+                    }
+                }
+            }
+        };
+        match db.setting(NORMALIZE_SETTINGS_KEY) {
+            Ok(Some(settings)) => *self.normalize.lock().unwrap() = settings,
+            Ok(None) => {}
+            Err(e) => eprintln!("{}", e),
+        }
+        *self.db.lock().unwrap() = Some(db);
+        watch::spawn_folder_watcher(app.clone());
+        schedule::spawn_scheduler(app);
+    }
+
+    fn with_db<T>(&self, f: impl FnOnce(&mut LibraryDb) -> Result<T, String>) -> Result<T, String> {
+        match self.db.lock().unwrap().as_mut() {
+            Some(db) => f(db),
+            None => Err("Library isn't available".to_string()),
+        }
+    }
+
+    /// Analyse a file and add it to the library, named after the file unless
+    /// `name` is given. Importing a file that is already in the library
+    /// refreshes its analysis and adds any new tags.
+    pub fn import_clip(&self, path: &Path, name: Option<String>, tags: Vec<String>) -> Result<LibraryClip, String> {
+        let path = std::fs::canonicalize(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let name = match name {
+            Some(name) => clean_name(&name)?,
+            None => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        let tags = clean_tags(tags);
+        let format = crate::audio_output::probe_file(&path)?;
+        let (hash, size_bytes) = hash_file(&path)?;
+        let (loudness_lufs, normalization_db) = self.measure_loudness(&path)?;
+        let markers = wav_markers(&path, format.duration_ms);
+        let clip = NewClip {
+            path: path.to_string_lossy().into_owned(),
+            name,
+            duration_ms: format.duration_ms,
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            size_bytes,
+            hash,
+            added_at_ms: now_ms(),
+            source_hash: None,
+            loudness_lufs,
+            normalization_db,
+        };
+
+        let clip = self.with_db(|db| {
+            let id = db.upsert(&clip)?;
+            db.add_tags(id, &tags)?;
+            add_file_markers(db, id, &markers)?;
+            db.get(id)?.ok_or_else(|| format!("No clip {} in the library", id))
+        })?;
+        self.check_similar(&clip);
+        Ok(clip)
+    }
+
+    /// Copy files into the library, converting them to 48 kHz FLAC. A file
+    /// whose contents are already in the library, as an original or as the
+    /// source of a converted copy, isn't copied again; it gets `tags` added
+    /// instead.
+    pub fn import_files(&self, paths: Vec<PathBuf>, tags: Vec<String>) -> Vec<ImportResult> {
+        let tags = clean_tags(tags);
+        paths
+            .into_iter()
+            .map(|path| {
+                let outcome = match self.import_copy(&path, &tags) {
+                    Ok(outcome) => outcome,
+                    Err(error) => {
+                        eprintln!("Failed to import {}: {}", path.display(), error);
+                        ImportOutcome::Failed { error }
+                    }
+                };
+                ImportResult {
+                    source: path.to_string_lossy().into_owned(),
+                    outcome,
+                }
+            })
+            .collect()
+    }
+
+    fn import_copy(&self, source: &Path, tags: &[String]) -> Result<ImportOutcome, String> {
+        let (source_hash, _) = hash_file(source)?;
+        let existing = self.with_db(|db| {
+            let clip = match db.find_by_hash(&source_hash)?.into_iter().next() {
+                Some(clip) => clip,
+                None => return Ok(None),
+            };
+            db.add_tags(clip.id, tags)?;
+            db.get(clip.id)
+        })?;
+        if let Some(clip) = existing {
+            return Ok(ImportOutcome::Duplicate { clip });
+        }
+
+        let dir = self.clips_dir()?;
+        // Converted under a temporary name so an interrupted import never
+        // leaves a file that looks finished
+        let partial = temp_file_path(&dir, &format!("{}.part", source_hash), LIBRARY_EXTENSION);
+        let target = dir.join(format!("{}.{}", source_hash, LIBRARY_EXTENSION));
+        let format = crate::audio_output::record::transcode_file(source, &partial, LIBRARY_SAMPLE_RATE, || false)?;
+        std::fs::rename(&partial, &target).map_err(|e| {
+            let _ = std::fs::remove_file(&partial);
+            format!("Failed to move {} into the library: {}", partial.display(), e)
+        })?;
+        let (hash, size_bytes) = hash_file(&target)?;
+        let (loudness_lufs, normalization_db) = self.measure_loudness(&target)?;
+        // Cue points are lost in conversion, so they're read from the source
+        let markers = wav_markers(source, format.duration_ms);
+        let clip = NewClip {
+            path: target.to_string_lossy().into_owned(),
+            name: source
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            duration_ms: format.duration_ms,
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            size_bytes,
+            hash,
+            added_at_ms: now_ms(),
+            source_hash: Some(source_hash),
+            loudness_lufs,
+            normalization_db,
+        };
+
+        let clip = self.with_db(|db| {
+            let id = db.upsert(&clip)?;
+            db.add_tags(id, tags)?;
+            add_file_markers(db, id, &markers)?;
+            db.get(id)?.ok_or_else(|| format!("No clip {} in the library", id))
+        })?;
+        eprintln!("Imported {} as {}", source.display(), clip.path);
+        self.check_similar(&clip);
+        Ok(ImportOutcome::Imported { clip })
+    }
+
+    /// The folder files copied into the library are kept in, created if
+    /// need be.
+    fn clips_dir(&self) -> Result<PathBuf, String> {
+        let dir = match self.clips_dir.lock().unwrap().clone() {
+            Some(dir) => dir,
+            None => return Err("Library folder isn't available".to_string()),
+        };
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create library folder: {}", e))?;
+        Ok(dir)
+    }
+
+    /// Split a long recording at its silent gaps and copy each stretch of
+    /// sound into the library as its own clip, named after the recording and
+    /// numbered in order.
+    pub fn split_on_silence(
+        &self,
+        path: &Path,
+        options: &SilenceOptions,
+        tags: Vec<String>,
+    ) -> Result<Vec<SplitClip>, String> {
+        let regions = crate::audio_output::find_sound_regions(path, options)?;
+        if regions.is_empty() {
+            return Err(format!("No sound found in {}", path.display()));
+        }
+        let tags = clean_tags(tags);
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let width = regions.len().to_string().len();
+
+        let mut clips = Vec::with_capacity(regions.len());
+        for (index, region) in regions.into_iter().enumerate() {
+            let name = format!("{} {:0width$}", stem, index + 1, width = width);
+            let segment = SpliceSegment {
+                path: path.to_string_lossy().into_owned(),
+                trim: ClipTrim {
+                    start_ms: region.start_ms,
+                    end_ms: Some(region.end_ms),
+                },
+            };
+            let part = temp_file_path(&std::env::temp_dir(), "voicebox-split", LIBRARY_EXTENSION);
+            let outcome = crate::audio_output::splice_files(&[segment], &part)
+                .and_then(|_| self.import_copy(&part, &tags));
+            let _ = std::fs::remove_file(&part);
+            let outcome = match outcome {
+                Ok(ImportOutcome::Imported { clip }) => {
+                    let update = ClipUpdate {
+                        name: Some(name),
+                        ..Default::default()
+                    };
+                    match self.update_clip(clip.id, update) {
+                        Ok(clip) => ImportOutcome::Imported { clip },
+                        Err(error) => ImportOutcome::Failed { error },
+                    }
+                }
+                Ok(outcome) => outcome,
+                Err(error) => {
+                    eprintln!("Failed to import {} of {}: {}", name, path.display(), error);
+                    ImportOutcome::Failed { error }
+                }
+            };
+            clips.push(SplitClip { region, outcome });
+        }
+        eprintln!("Split {} into {} clips", path.display(), clips.len());
+        Ok(clips)
+    }
+
+    /// Loudness of a file being imported and the gain that normalizes it,
+    /// if normalization is on. Not measured otherwise, to keep imports
+    /// quick.
+    fn measure_loudness(&self, path: &Path) -> Result<(Option<f64>, Option<f64>), String> {
+        let settings = *self.normalize.lock().unwrap();
+        if !settings.enabled {
+            return Ok((None, None));
+        }
+        let lufs = crate::audio_output::measure_file_loudness(path)?;
+        Ok((lufs, lufs.and_then(|lufs| settings.gain_db(lufs))))
+    }
+
+    pub fn normalize_settings(&self) -> NormalizeSettings {
+        *self.normalize.lock().unwrap()
+    }
+
+    /// Change normalization and update the gain of every measured clip to
+    /// match. Clips imported while it was off can be measured afterwards
+    /// with `analyze_loudness`.
+    pub fn set_normalize_settings(&self, settings: NormalizeSettings) -> Result<(), String> {
+        if !(MIN_TARGET_LUFS..=MAX_TARGET_LUFS).contains(&settings.target_lufs) {
+            return Err(format!(
+                "Target loudness must be {} to {} LUFS, got {}",
+                MIN_TARGET_LUFS, MAX_TARGET_LUFS, settings.target_lufs
+            ));
+        }
+        self.with_db(|db| {
+            db.set_setting(NORMALIZE_SETTINGS_KEY, &settings)?;
+            db.update_normalization(|lufs| settings.gain_db(lufs))
+        })?;
+        *self.normalize.lock().unwrap() = settings;
+        Ok(())
+    }
+
+    /// Measure the loudness of every clip that hasn't been, so normalization
+    /// covers the whole library. Returns how many clips were measured.
+    pub fn analyze_loudness(&self) -> Result<usize, String> {
+        if !self.normalize.lock().unwrap().enabled {
+            return Err("Loudness normalization is off".to_string());
+        }
+        let clips = self.with_db(|db| db.unmeasured_clips())?;
+        let mut measured = 0;
+        for (id, path) in clips {
+            // Measured without holding the database, which other commands
+            // need meanwhile
+            let (lufs, gain_db) = match self.measure_loudness(Path::new(&path)) {
+                Ok(loudness) => loudness,
+                Err(e) => {
+                    eprintln!("Failed to measure loudness of {}: {}", path, e);
+                    continue;
+                }
+            };
+            if lufs.is_some() {
+                self.with_db(|db| db.set_loudness(id, lufs, gain_db))?;
+                measured += 1;
+            }
+        }
+        Ok(measured)
+    }
+
+    /// Fingerprint a newly imported clip and warn with a
+    /// `library://similar_clips_found` event if it sounds like clips already
+    /// in the library. Failures are only logged; the import stands.
+    fn check_similar(&self, clip: &LibraryClip) {
+        let similar = self
+            .fingerprint_clip(clip.id, Path::new(&clip.path))
+            .and_then(|_| self.similar_clips(clip.id));
+        let similar = match similar {
+            Ok(similar) => similar,
+            Err(e) => {
+                eprintln!("Failed to compare {} with the library: {}", clip.path, e);
+                return;
+            }
+        };
+        if similar.is_empty() {
+            return;
+        }
+        eprintln!("{} sounds like {} clip(s) already in the library", clip.name, similar.len());
+        let app = match self.app_handle.lock().unwrap().clone() {
+            Some(app) => app,
+            None => return,
+        };
+        let event = SimilarClipsFound {
+            clip: clip.clone(),
+            similar,
+        };
+        if let Err(e) = app.emit("library://similar_clips_found", &event) {
+            eprintln!("Failed to emit library://similar_clips_found event: {}", e);
+        }
+    }
+
+    /// Work out and store a clip's fingerprint. Returns whether it has one;
+    /// very short or silent clips don't.
+    fn fingerprint_clip(&self, id: ClipId, path: &Path) -> Result<bool, String> {
+        let fingerprint = crate::audio_output::fingerprint_file(path)?.unwrap_or_default();
+        self.with_db(|db| db.set_fingerprint(id, &fingerprint))?;
+        Ok(!fingerprint.is_empty())
+    }
+
+    /// Clips that sound the same as the clip with `id` even though their
+    /// files differ, e.g. the same sound in another format, most alike
+    /// first. Empty if the clip hasn't been fingerprinted.
+    pub fn similar_clips(&self, id: ClipId) -> Result<Vec<SimilarClip>, String> {
+        let clip = self.get_clip(id)?;
+        let fingerprints = self.with_db(|db| match db.fingerprint(id)? {
+            Some(fingerprint) => {
+                let candidates = db.fingerprints_between(clip.duration_ms / 2, clip.duration_ms.saturating_mul(2))?;
+                Ok(Some((fingerprint, candidates)))
+            }
+            None => Ok(None),
+        })?;
+        let (fingerprint, candidates) = match fingerprints {
+            Some(fingerprints) => fingerprints,
+            None => return Ok(Vec::new()),
+        };
+
+        // Compared without holding the database, which other commands need
+        // meanwhile
+        let mut matches: Vec<(ClipId, f32)> = candidates
+            .into_iter()
+            .filter(|(other, _)| *other != id)
+            .filter_map(|(other, codes)| {
+                crate::audio_output::fingerprint_similarity(&fingerprint, &codes)
+                    .filter(|similarity| *similarity >= SIMILAR_CLIP_THRESHOLD)
+                    .map(|similarity| (other, similarity))
+            })
+            .collect();
+        matches.sort_by(|a, b| b.1.total_cmp(&a.1));
+        self.with_db(|db| {
+            let mut similar = Vec::with_capacity(matches.len());
+            for (other, similarity) in matches {
+                if let Some(clip) = db.get(other)? {
+                    similar.push(SimilarClip { clip, similarity });
+                }
+            }
+            Ok(similar)
+        })
+    }
+
+    /// Fingerprint every clip that hasn't been, such as ones imported before
+    /// fingerprints were kept. Returns how many clips got one.
+    pub fn fingerprint_library(&self) -> Result<usize, String> {
+        let clips = self.with_db(|db| db.unfingerprinted_clips())?;
+        let mut fingerprinted = 0;
+        for (id, path) in clips {
+            match self.fingerprint_clip(id, Path::new(&path)) {
+                Ok(true) => fingerprinted += 1,
+                Ok(false) => {}
+                Err(e) => eprintln!("Failed to fingerprint {}: {}", path, e),
+            }
+        }
+        Ok(fingerprinted)
+    }
+
+    pub fn get_clip(&self, id: ClipId) -> Result<LibraryClip, String> {
+        self.with_db(|db| db.get(id)?.ok_or_else(|| format!("No clip {} in the library", id)))
+    }
+
+    pub fn search(&self, mut query: ClipQuery) -> Result<Vec<LibraryClip>, String> {
+        query.tags = clean_tags(query.tags);
+        self.with_db(|db| db.search(&query))
+    }
+
+    /// Clips with exactly the same file contents as the clip with `id`, or
+    /// converted from the same file, not counting itself.
+    pub fn duplicates(&self, id: ClipId) -> Result<Vec<LibraryClip>, String> {
+        self.with_db(|db| {
+            let clip = db.get(id)?.ok_or_else(|| format!("No clip {} in the library", id))?;
+            let mut clips = db.find_by_hash(&clip.hash)?;
+            if let Some(source_hash) = &clip.source_hash {
+                clips.extend(db.find_by_hash(source_hash)?);
+            }
+            clips.sort_by_key(|other| other.id);
+            clips.dedup_by_key(|other| other.id);
+            clips.retain(|other| other.id != id);
+            Ok(clips)
+        })
+    }
+
+    pub fn update_clip(&self, id: ClipId, update: ClipUpdate) -> Result<LibraryClip, String> {
+        let name = update.name.as_deref().map(clean_name).transpose()?;
+        if let Some(playback) = &update.playback {
+            playback.validate()?;
+        }
+        let tags = update.tags.map(clean_tags);
+        let markers = update.markers.map(clean_markers).transpose()?;
+        let exclusive_group = update.exclusive_group.map(|group| group.trim().to_string());
+        self.with_db(|db| {
+            if let Some(name) = &name {
+                db.rename(id, name)?;
+            }
+            if let Some(tags) = &tags {
+                db.set_tags(id, tags)?;
+            }
+            if let Some(favorite) = update.favorite {
+                db.set_favorite(id, favorite)?;
+            }
+            if let Some(playback) = &update.playback {
+                db.set_playback(id, playback)?;
+            }
+            if let Some(markers) = &markers {
+                let clip = db.get(id)?.ok_or_else(|| format!("No clip {} in the library", id))?;
+                if let Some(marker) = markers.iter().find(|marker| marker.position_ms as u64 > clip.duration_ms) {
+                    return Err(format!("Marker {} is past the end of the clip", marker.name));
+                }
+                db.set_markers(id, markers)?;
+            }
+            if let Some(group) = &exclusive_group {
+                db.set_exclusive_group(id, Some(group.as_str()).filter(|group| !group.is_empty()))?;
+            }
+            if let Some(hold_to_play) = update.hold_to_play {
+                db.set_hold_to_play(id, hold_to_play)?;
+            }
+            db.get(id)?.ok_or_else(|| format!("No clip {} in the library", id))
+        })
+    }
+
+    /// The trim a clip is played with unless one is given, if it has one.
+    pub fn get_clip_trim(&self, id: ClipId) -> Result<Option<ClipTrim>, String> {
+        let trim = self.get_clip(id)?.playback.trim;
+        Ok((trim != ClipTrim::default()).then_some(trim))
+    }
+
+    /// Save the trim a clip is played with unless one is given, or clear it
+    /// with `None`.
+    pub fn set_clip_trim(&self, id: ClipId, trim: Option<ClipTrim>) -> Result<LibraryClip, String> {
+        let trim = trim.unwrap_or_default();
+        trim.validate()?;
+        self.with_db(|db| {
+            let clip = db.get(id)?.ok_or_else(|| format!("No clip {} in the library", id))?;
+            db.set_playback(id, &ClipPlayback { trim, ..clip.playback })?;
+            db.get(id)?.ok_or_else(|| format!("No clip {} in the library", id))
+        })
+    }
+
+    /// Play a library clip from disk with its saved gain, trim and fades,
+    /// and count it in the clip's play history and trigger log. A clip in an exclusive
+    /// group fades out the last one started from that group. A
+    /// hold-to-play clip that is still held keeps playing rather than
+    /// starting again, so key repeat doesn't retrigger it.
+    pub fn play_clip(
+        &self,
+        output: &AudioOutputState,
+        id: ClipId,
+        device_ids: Vec<String>,
+        lead_in: Option<LeadIn>,
+        options: Option<PlaybackOptions>,
+        source: TriggerSource,
+    ) -> Result<SessionId, String> {
+        let clip = self.get_clip(id)?;
+        let mut held_sessions = self.held_sessions.lock().unwrap();
+        if clip.hold_to_play {
+            if let Some(&session_id) = held_sessions.get(&id) {
+                if output.playback_status(session_id).is_ok() {
+                    return Ok(session_id);
+                }
+            }
+        }
+        // A loudness target given for this playback replaces the stored gain
+        let mut options = options.unwrap_or_default();
+        if let (None, Some(gain_db)) = (options.target_lufs, clip.normalization_db) {
+            options.gain_db += gain_db as f32;
+        }
+        clip.playback.apply(&mut options);
+        let options = Some(options);
+        let logged_device_ids = device_ids.clone();
+        let path = Path::new(&clip.path);
+        let session_id = match &clip.exclusive_group {
+            Some(group) => {
+                // Held while starting, so two clips triggered at once can't
+                // both keep playing
+                let mut group_sessions = self.group_sessions.lock().unwrap();
+                if let Some(previous) = group_sessions.remove(group) {
+                    // Fails if it has already finished
+                    let _ = output.stop_playback(previous, Some(EXCLUSIVE_GROUP_FADE_MS));
+                }
+                let session_id =
+                    output.play_hashed_file_to_devices(path, clip.hash.clone(), device_ids, lead_in, options)?;
+                group_sessions.insert(group.clone(), session_id);
+                session_id
+            }
+            None => output.play_hashed_file_to_devices(path, clip.hash.clone(), device_ids, lead_in, options)?,
+        };
+        if clip.hold_to_play {
+            held_sessions.insert(id, session_id);
+        }
+        drop(held_sessions);
+        if let Err(e) = self.with_db(|db| db.record_play(id, now_ms(), source, &logged_device_ids)) {
+            eprintln!("Failed to record play of clip {}: {}", id, e);
+        }
+        Ok(session_id)
+    }
+
+    /// Play a library clip from one of its markers, as `play_clip` does
+    /// otherwise. It still stops at the end of its trim, if that is after
+    /// the marker.
+    pub fn play_clip_from_marker(
+        &self,
+        output: &AudioOutputState,
+        id: ClipId,
+        marker: &str,
+        device_ids: Vec<String>,
+        options: Option<PlaybackOptions>,
+    ) -> Result<SessionId, String> {
+        let clip = self.get_clip(id)?;
+        let position_ms = clip
+            .markers
+            .iter()
+            .find(|candidate| candidate.name == marker)
+            .map(|marker| marker.position_ms)
+            .ok_or_else(|| format!("Clip {} has no marker named {}", clip.name, marker))?;
+        let mut options = options.unwrap_or_default();
+        let end_ms = options
+            .trim
+            .unwrap_or(clip.playback.trim)
+            .end_ms
+            .filter(|end_ms| *end_ms > position_ms);
+        options.trim = Some(ClipTrim {
+            start_ms: position_ms,
+            end_ms,
+        });
+        self.play_clip(output, id, device_ids, None, Some(options), TriggerSource::Marker)
+    }
+
+    /// The key playing a clip was let go. A hold-to-play clip fades out;
+    /// any other clip plays on.
+    pub fn release_clip(&self, output: &AudioOutputState, id: ClipId) -> Result<(), String> {
+        let session_id = self.held_sessions.lock().unwrap().remove(&id);
+        if let Some(session_id) = session_id {
+            // Fails if the clip has already finished
+            let _ = output.stop_playback(session_id, Some(HOLD_RELEASE_FADE_MS));
+        }
+        Ok(())
+    }
+
+    /// Play a clip once at `at_ms`, in milliseconds since the Unix epoch.
+    /// The schedule is kept in the library, so it survives a restart.
+    pub fn schedule_play(&self, clip_id: ClipId, at_ms: u64, device_ids: Vec<String>) -> Result<ScheduledPlay, String> {
+        if at_ms <= now_ms() {
+            return Err("Scheduled time has already passed".to_string());
+        }
+        if device_ids.is_empty() {
+            return Err("No output devices to play on".to_string());
+        }
+        self.with_db(|db| {
+            db.get(clip_id)?.ok_or_else(|| format!("No clip {} in the library", clip_id))?;
+            let id = db.add_scheduled_play(clip_id, at_ms, &device_ids)?;
+            db.scheduled_plays()?
+                .into_iter()
+                .find(|play| play.id == id)
+                .ok_or_else(|| format!("No scheduled play {}", id))
+        })
+    }
+
+    /// Plays waiting to happen, soonest first.
+    pub fn scheduled_plays(&self) -> Result<Vec<ScheduledPlay>, String> {
+        self.with_db(|db| db.scheduled_plays())
+    }
+
+    pub fn cancel_scheduled_play(&self, id: ScheduleId) -> Result<(), String> {
+        self.with_db(|db| db.delete_scheduled_play(id))
+    }
+
+    /// Count a play of a clip that was played some other way, e.g. by path.
+    pub fn record_play(&self, id: ClipId) -> Result<(), String> {
+        self.with_db(|db| db.record_play(id, now_ms(), TriggerSource::External, &[]))
+    }
+
+    /// How often clips were played, counted in the database from the
+    /// trigger log.
+    pub fn play_stats(&self, query: PlayStatsQuery) -> Result<PlayStats, String> {
+        query.validate()?;
+        self.with_db(|db| db.play_stats(&query))
+    }
+
+    /// Clips started from `since_ms`, or else since the app started, up to
+    /// `until_ms` or now.
+    pub fn trigger_log(&self, since_ms: Option<u64>, until_ms: Option<u64>) -> Result<Vec<TriggerEvent>, String> {
+        let since_ms = since_ms.unwrap_or(self.started_at_ms);
+        self.with_db(|db| db.triggers(since_ms, until_ms.unwrap_or(u64::MAX)))
+    }
+
+    /// Start the logged triggers from `since_ms` up to `until_ms` again,
+    /// spaced as they were, on `device_ids` or else their own devices.
+    /// Plays that were only counted can't be replayed and are left out.
+    /// Ends with a `library://replay_finished` event; only one replay runs
+    /// at a time. Returns how many clips will be played.
+    pub fn replay_triggers(
+        &self,
+        since_ms: Option<u64>,
+        until_ms: Option<u64>,
+        device_ids: Option<Vec<String>>,
+    ) -> Result<usize, String> {
+        if device_ids.as_ref().is_some_and(|device_ids| device_ids.is_empty()) {
+            return Err("No output devices to play on".to_string());
+        }
+        let mut events = self.trigger_log(since_ms, until_ms)?;
+        events.retain(|event| !event.device_ids.is_empty());
+        if events.is_empty() {
+            return Err("No triggers to replay".to_string());
+        }
+        let app = match self.app_handle.lock().unwrap().clone() {
+            Some(app) => app,
+            None => return Err("Library isn't available".to_string()),
+        };
+        let mut job = self.replay_job.lock().unwrap();
+        if job.is_some() {
+            return Err("Triggers are already being replayed".to_string());
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        *job = Some(cancel.clone());
+        let count = events.len();
+        eprintln!("Replaying {} triggers", count);
+        triggers::spawn_replay(app, events, device_ids, cancel);
+        Ok(count)
+    }
+
+    /// Stop the trigger replay in progress. Clips it already started keep
+    /// playing.
+    pub fn stop_replay(&self) -> Result<(), String> {
+        match self.replay_job.lock().unwrap().as_ref() {
+            Some(cancel) => {
+                cancel.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+            None => Err("No triggers are being replayed".to_string()),
+        }
+    }
+
+    fn end_replay(&self, cancel: &Arc<AtomicBool>) {
+        let mut job = self.replay_job.lock().unwrap();
+        if job.as_ref().is_some_and(|current| Arc::ptr_eq(current, cancel)) {
+            *job = None;
+        }
+    }
+
+    /// The clips played most often, most played first.
+    pub fn most_played(&self, limit: u32) -> Result<Vec<LibraryClip>, String> {
+        self.search(ClipQuery {
+            played_only: true,
+            sort: ClipSort::PlayCount,
+            descending: true,
+            limit: Some(limit),
+            ..Default::default()
+        })
+    }
+
+    /// The clips played most recently, latest first.
+    pub fn recently_played(&self, limit: u32) -> Result<Vec<LibraryClip>, String> {
+        self.search(ClipQuery {
+            played_only: true,
+            sort: ClipSort::LastPlayed,
+            descending: true,
+            limit: Some(limit),
+            ..Default::default()
+        })
+    }
+
+    pub fn board_profiles(&self) -> Result<Vec<BoardProfile>, String> {
+        self.with_db(|db| {
+            let active = active_profile_id(db)?;
+            let mut profiles = db.board_profiles()?;
+            for profile in &mut profiles {
+                profile.active = Some(profile.id) == active;
+            }
+            Ok(profiles)
+        })
+    }
+
+    pub fn active_board_profile(&self) -> Result<Option<BoardProfile>, String> {
+        self.with_db(|db| match active_profile_id(db)? {
+            Some(id) => Ok(db.board_profile(id)?.map(|profile| BoardProfile { active: true, ..profile })),
+            None => Ok(None),
+        })
+    }
+
+    /// Create an empty profile. The first profile is switched to.
+    pub fn create_board_profile(&self, name: &str) -> Result<BoardProfile, String> {
+        let name = board::clean_profile_name(name)?;
+        self.save_new_profile(&name, &[], &[])
+    }
+
+    /// Copy a profile's clips, hotkeys and routing into a new profile, named
+    /// "<name> copy" unless `name` is given.
+    pub fn duplicate_board_profile(&self, id: ProfileId, name: Option<String>) -> Result<BoardProfile, String> {
+        let source = self.board_profile(id)?;
+        let name = match name {
+            Some(name) => board::clean_profile_name(&name)?,
+            None => format!("{} copy", source.name),
+        };
+        self.save_new_profile(&name, &source.output_device_ids, &source.slots)
+    }
+
+    fn save_new_profile(&self, name: &str, output_device_ids: &[String], slots: &[BoardSlot]) -> Result<BoardProfile, String> {
+        let (profile, switched) = self.with_db(|db| {
+            let id = db.create_board_profile(name, output_device_ids, slots)?;
+            let switched = active_profile_id(db)?.is_none();
+            if switched {
+                db.set_setting(ACTIVE_PROFILE_KEY, &Some(id))?;
+            }
+            let profile = db.board_profile(id)?.ok_or_else(|| format!("No board profile {}", id))?;
+            Ok((BoardProfile { active: switched, ..profile }, switched))
+        })?;
+        if switched {
+            self.emit_active_profile(Some(&profile));
+        }
+        Ok(profile)
+    }
+
+    pub fn update_board_profile(&self, id: ProfileId, update: BoardProfileUpdate) -> Result<BoardProfile, String> {
+        let mut profile = self.board_profile(id)?;
+        if let Some(name) = update.name {
+            profile.name = board::clean_profile_name(&name)?;
+        }
+        if let Some(output_device_ids) = update.output_device_ids {
+            profile.output_device_ids = output_device_ids;
+        }
+        if let Some(mut slots) = update.slots {
+            board::validate_slots(&mut slots)?;
+            profile.slots = slots;
+        }
+        self.with_db(|db| db.update_board_profile(&profile))?;
+        if profile.active {
+            self.emit_active_profile(Some(&profile));
+        }
+        Ok(profile)
+    }
+
+    /// Make a profile the one in use.
+    pub fn switch_board_profile(&self, id: ProfileId) -> Result<BoardProfile, String> {
+        let profile = self.with_db(|db| {
+            let profile = db.board_profile(id)?.ok_or_else(|| format!("No board profile {}", id))?;
+            db.set_setting(ACTIVE_PROFILE_KEY, &Some(id))?;
+            Ok(BoardProfile { active: true, ..profile })
+        })?;
+        eprintln!("Switched to board profile {}", profile.name);
+        self.emit_active_profile(Some(&profile));
+        Ok(profile)
+    }
+
+    /// Delete a profile. Its clips stay in the library. Deleting the profile
+    /// in use leaves no profile active.
+    pub fn delete_board_profile(&self, id: ProfileId) -> Result<(), String> {
+        let was_active = self.with_db(|db| {
+            let was_active = active_profile_id(db)? == Some(id);
+            db.delete_board_profile(id)?;
+            if was_active {
+                db.set_setting(ACTIVE_PROFILE_KEY, &None::<ProfileId>)?;
+            }
+            Ok(was_active)
+        })?;
+        if was_active {
+            self.emit_active_profile(None);
+        }
+        Ok(())
+    }
+
+    /// Play the clip at `position` on the active board, on the slot's own
+    /// devices or else the profile's.
+    pub fn play_board_slot(
+        &self,
+        output: &AudioOutputState,
+        position: u32,
+        options: Option<PlaybackOptions>,
+    ) -> Result<SessionId, String> {
+        let profile = self
+            .active_board_profile()?
+            .ok_or_else(|| "No board profile is active".to_string())?;
+        let slot = profile
+            .slots
+            .iter()
+            .find(|slot| slot.position == position)
+            .ok_or_else(|| format!("Board position {} is empty", position))?;
+        let device_ids = slot
+            .device_ids
+            .clone()
+            .unwrap_or_else(|| profile.output_device_ids.clone());
+        if device_ids.is_empty() {
+            return Err(format!("Board profile {} has no output devices", profile.name));
+        }
+        self.play_clip(output, slot.clip_id, device_ids, None, options, TriggerSource::Board)
+    }
+
+    /// The hotkey of the slot at `position` on the active board was let go.
+    pub fn release_board_slot(&self, output: &AudioOutputState, position: u32) -> Result<(), String> {
+        let profile = self
+            .active_board_profile()?
+            .ok_or_else(|| "No board profile is active".to_string())?;
+        let slot = profile
+            .slots
+            .iter()
+            .find(|slot| slot.position == position)
+            .ok_or_else(|| format!("Board position {} is empty", position))?;
+        self.release_clip(output, slot.clip_id)
+    }
+
+    /// Write a profile and its clips to a single archive that
+    /// `import_board` can load on another machine. Returns how many clips
+    /// were written.
+    pub fn export_board(&self, id: ProfileId, path: &Path) -> Result<usize, String> {
+        let profile = self.board_profile(id)?;
+        let clips = self.with_db(|db| {
+            let mut clips: Vec<LibraryClip> = Vec::new();
+            for slot in &profile.slots {
+                if clips.iter().any(|clip| clip.id == slot.clip_id) {
+                    continue;
+                }
+                match db.get(slot.clip_id)? {
+                    Some(clip) => clips.push(clip),
+                    None => return Err(format!("No clip {} in the library", slot.clip_id)),
+                }
+            }
+            Ok(clips)
+        })?;
+        bundle::write_bundle(path, &profile, &clips)?;
+        eprintln!("Exported board {} with {} clips to {}", profile.name, clips.len(), path.display());
+        Ok(clips.len())
+    }
+
+    /// Load a board exported by `export_board` as a new profile. Its clips
+    /// are imported like dropped files, so ones already in the library are
+    /// reused. Routing to devices this machine doesn't have is dropped.
+    pub fn import_board(&self, output: &AudioOutputState, path: &Path) -> Result<BoardImport, String> {
+        let mut reader = BundleReader::open(path)?;
+        let manifest = reader.manifest.clone();
+        let devices: HashSet<String> = output
+            .list_output_devices()?
+            .into_iter()
+            .map(|device| device.id)
+            .collect();
+
+        let mut clip_ids = Vec::with_capacity(manifest.clips.len());
+        let (mut imported_clips, mut duplicate_clips) = (0, 0);
+        for (index, bundled) in manifest.clips.iter().enumerate() {
+            let extracted = temp_file_path(&std::env::temp_dir(), "voicebox-bundle", &reader.clip_extension(index));
+            let tags = clean_tags(bundled.tags.clone());
+            let outcome = reader
+                .extract_clip(index, &extracted)
+                .and_then(|()| self.import_copy(&extracted, &tags));
+            let _ = std::fs::remove_file(&extracted);
+            let clip = match outcome? {
+                ImportOutcome::Imported { clip } => {
+                    imported_clips += 1;
+                    let update = ClipUpdate {
+                        name: Some(bundled.name.clone()),
+                        tags: None,
+                        favorite: Some(bundled.favorite),
+                        playback: Some(bundled.playback),
+                        markers: Some(bundled.markers.clone()),
+                        exclusive_group: bundled.exclusive_group.clone(),
+                        hold_to_play: Some(bundled.hold_to_play),
+                    };
+                    self.update_clip(clip.id, update)?
+                }
+                ImportOutcome::Duplicate { clip } => {
+                    duplicate_clips += 1;
+                    clip
+                }
+                ImportOutcome::Failed { error } => return Err(error),
+            };
+            clip_ids.push(clip.id);
+        }
+
+        let known = |ids: &[String]| -> Vec<String> { ids.iter().filter(|id| devices.contains(*id)).cloned().collect() };
+        let mut output_device_ids = known(&manifest.profile.output_device_ids);
+        if output_device_ids.is_empty() && !manifest.profile.output_device_ids.is_empty() {
+            output_device_ids.push(crate::audio_output::SYSTEM_DEFAULT_DEVICE_ID.to_string());
+        }
+        let mut slots: Vec<BoardSlot> = manifest
+            .profile
+            .slots
+            .iter()
+            .map(|slot| BoardSlot {
+                position: slot.position,
+                clip_id: clip_ids[slot.clip],
+                hotkey: slot.hotkey.clone(),
+                device_ids: slot.device_ids.as_deref().map(known).filter(|ids| !ids.is_empty()),
+            })
+            .collect();
+        board::validate_slots(&mut slots)?;
+
+        let name = self.free_profile_name(&board::clean_profile_name(&manifest.profile.name)?)?;
+        let profile = self.save_new_profile(&name, &output_device_ids, &slots)?;
+        eprintln!(
+            "Imported board {} from {}: {} new clips, {} already in the library",
+            profile.name,
+            path.display(),
+            imported_clips,
+            duplicate_clips
+        );
+        Ok(BoardImport {
+            profile,
+            imported_clips,
+            duplicate_clips,
+        })
+    }
+
+    /// `name`, or "<name> (2)", "<name> (3)"... if a profile already has it.
+    fn free_profile_name(&self, name: &str) -> Result<String, String> {
+        let taken: HashSet<String> = self
+            .with_db(|db| db.board_profiles())?
+            .into_iter()
+            .map(|profile| profile.name.to_lowercase())
+            .collect();
+        let mut candidate = name.to_string();
+        let mut number = 2;
+        while taken.contains(&candidate.to_lowercase()) {
+            candidate = format!("{} ({})", name, number);
+            number += 1;
+        }
+        Ok(candidate)
+    }
+
+    fn board_profile(&self, id: ProfileId) -> Result<BoardProfile, String> {
+        self.with_db(|db| {
+            let active = active_profile_id(db)? == Some(id);
+            let profile = db.board_profile(id)?.ok_or_else(|| format!("No board profile {}", id))?;
+            Ok(BoardProfile { active, ..profile })
+        })
+    }
+
+    fn emit_active_profile(&self, profile: Option<&BoardProfile>) {
+        let app = match self.app_handle.lock().unwrap().clone() {
+            Some(app) => app,
+            None => return,
+        };
+        let event = board::ActiveProfileChanged {
+            profile: profile.cloned(),
+        };
+        if let Err(e) = app.emit("board://profile_changed", &event) {
+            eprintln!("Failed to emit board://profile_changed event: {}", e);
+        }
+    }
+
+    /// Convert library clips to another format and sample rate in the
+    /// background, reporting progress with `library://transcode_progress`
+    /// and `library://transcode_finished` events. Only one batch runs at a
+    /// time.
+    pub fn start_transcode(&self, request: TranscodeRequest) -> Result<(), String> {
+        request.validate()?;
+        let app = match self.app_handle.lock().unwrap().clone() {
+            Some(app) => app,
+            None => return Err("Library isn't available".to_string()),
+        };
+        let mut job = self.transcode_job.lock().unwrap();
+        if job.is_some() {
+            return Err("Clips are already being converted".to_string());
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        *job = Some(cancel.clone());
+        eprintln!(
+            "Converting {} clips to {} at {} Hz",
+            request.clip_ids.len(),
+            request.format.extension(),
+            request.sample_rate
+        );
+        transcode::spawn_transcode_job(app, request, cancel);
+        Ok(())
+    }
+
+    /// Stop the batch conversion in progress. The clip being converted is
+    /// left as it was; clips already converted stay converted.
+    pub fn cancel_transcode(&self) -> Result<(), String> {
+        match self.transcode_job.lock().unwrap().as_ref() {
+            Some(cancel) => {
+                cancel.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+            None => Err("No clips are being converted".to_string()),
+        }
+    }
+
+    fn end_transcode(&self, cancel: &Arc<AtomicBool>) {
+        let mut job = self.transcode_job.lock().unwrap();
+        if job.as_ref().is_some_and(|current| Arc::ptr_eq(current, cancel)) {
+            *job = None;
+        }
+    }
+
+    /// Convert one clip's file into the library folder and play the clip
+    /// from the copy. Clips imported where they are, e.g. from watched
+    /// folders, are skipped: their files belong to the user, and a watched
+    /// folder would import the original again.
+    fn transcode_clip(
+        &self,
+        id: ClipId,
+        format: transcode::TranscodeFormat,
+        sample_rate: u32,
+        cancel: &AtomicBool,
+    ) -> Result<transcode::TranscodeOutcome, String> {
+        use transcode::TranscodeOutcome;
+
+        let clip = self.get_clip(id)?;
+        let dir = self.clips_dir()?;
+        let source = PathBuf::from(&clip.path);
+        if !source.starts_with(&dir) {
+            return Ok(TranscodeOutcome::Skipped {
+                reason: "Only clips copied into the library can be converted".to_string(),
+            });
+        }
+        let extension = format.extension();
+        let same_format = source
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(extension));
+        if same_format && clip.sample_rate == sample_rate {
+            return Ok(TranscodeOutcome::Skipped {
+                reason: "Already in that format".to_string(),
+            });
+        }
+
+        let partial = dir.join(format!("{}.part.{}", clip.hash, extension));
+        let converted = crate::audio_output::record::transcode_file(&source, &partial, sample_rate, || {
+            cancel.load(Ordering::Relaxed)
+        })?;
+        let (hash, size_bytes) = match hash_file(&partial) {
+            Ok(hashed) => hashed,
+            Err(e) => {
+                let _ = std::fs::remove_file(&partial);
+                return Err(e);
+            }
+        };
+        let target = dir.join(format!("{}.{}", hash, extension));
+        if target.exists() {
+            let _ = std::fs::remove_file(&partial);
+            return Err(format!("The library already has a clip converted to {}", target.display()));
+        }
+        std::fs::rename(&partial, &target).map_err(|e| {
+            let _ = std::fs::remove_file(&partial);
+            format!("Failed to move {} into the library: {}", partial.display(), e)
+        })?;
+
+        let file = NewClip {
+            path: target.to_string_lossy().into_owned(),
+            name: clip.name.clone(),
+            duration_ms: converted.duration_ms,
+            sample_rate: converted.sample_rate,
+            channels: converted.channels,
+            size_bytes,
+            hash,
+            added_at_ms: clip.added_at_ms,
+            // Keep recognising the file the clip first came from
+            source_hash: Some(clip.source_hash.clone().unwrap_or_else(|| clip.hash.clone())),
+            loudness_lufs: clip.loudness_lufs,
+            normalization_db: clip.normalization_db,
+        };
+        let updated = self.with_db(|db| {
+            db.replace_file(id, &file)?;
+            db.get(id)?.ok_or_else(|| format!("No clip {} in the library", id))
+        });
+        let updated = match updated {
+            Ok(updated) => updated,
+            Err(e) => {
+                let _ = std::fs::remove_file(&target);
+                return Err(e);
+            }
+        };
+        if let Err(e) = std::fs::remove_file(&source) {
+            eprintln!("Failed to delete {}: {}", source.display(), e);
+        }
+        eprintln!("Converted {} to {}", clip.path, updated.path);
+        Ok(TranscodeOutcome::Converted {
+            clip: Box::new(updated),
+        })
+    }
+
+    /// Forget a clip. Files copied into the library are deleted with it;
+    /// files imported where they were are left alone.
+    pub fn remove_clip(&self, id: ClipId) -> Result<(), String> {
+        let clip = self.with_db(|db| {
+            let clip = db.get(id)?;
+            db.remove(id)?;
+            Ok(clip)
+        })?;
+        let clips_dir = self.clips_dir.lock().unwrap().clone();
+        if let (Some(clip), Some(dir)) = (clip, clips_dir) {
+            let path = Path::new(&clip.path);
+            if path.starts_with(&dir) {
+                if let Err(e) = std::fs::remove_file(path) {
+                    eprintln!("Failed to delete {}: {}", path.display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tags(&self) -> Result<Vec<TagCount>, String> {
+        self.with_db(|db| db.tags())
+    }
+
+    pub fn watch_folders(&self) -> Result<Vec<WatchFolder>, String> {
+        self.with_db(|db| db.watch_folders())
+    }
+
+    /// Start watching a folder. Audio files already in it are imported too.
+    pub fn add_watch_folder(&self, path: &Path, recursive: bool) -> Result<WatchFolder, String> {
+        let path = std::fs::canonicalize(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        if !path.is_dir() {
+            return Err(format!("{} isn't a folder", path.display()));
+        }
+        let folder = WatchFolder {
+            path: path.to_string_lossy().into_owned(),
+            recursive,
+        };
+        self.with_db(|db| db.add_watch_folder(&folder))?;
+        Ok(folder)
+    }
+
+    /// Stop watching a folder. Clips already imported from it are kept.
+    pub fn remove_watch_folder(&self, path: &str) -> Result<(), String> {
+        self.with_db(|db| db.remove_watch_folder(path))
+    }
+}
+
+fn active_profile_id(db: &LibraryDb) -> Result<Option<ProfileId>, String> {
+    Ok(db.setting::<Option<ProfileId>>(ACTIVE_PROFILE_KEY)?.flatten())
+}
+
+/// A path in `dir` for a file no other import is using.
+fn temp_file_path(dir: &Path, prefix: &str, extension: &str) -> PathBuf {
+    let number = NEXT_TEMP_FILE.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!("{}-{}-{}.{}", prefix, std::process::id(), number, extension))
+}
+
+/// Markers in the order of their positions, with tidy names that differ.
+fn clean_markers(markers: Vec<ClipMarker>) -> Result<Vec<ClipMarker>, String> {
+    let mut names = HashSet::new();
+    let mut cleaned = Vec::with_capacity(markers.len());
+    for marker in markers {
+        let name = marker.name.trim().to_string();
+        if name.is_empty() {
+            return Err("Marker name is empty".to_string());
+        }
+        if !names.insert(name.clone()) {
+            return Err(format!("Two markers are named {}", name));
+        }
+        cleaned.push(ClipMarker { name, ..marker });
+    }
+    cleaned.sort_by_key(|marker| marker.position_ms);
+    Ok(cleaned)
+}
+
+/// Markers stored in a WAV file being imported, within its `duration_ms`.
+/// Names repeated in the file get a number. Unreadable markers are only
+/// logged, since they don't stop the audio from being imported.
+fn wav_markers(path: &Path, duration_ms: u64) -> Vec<ClipMarker> {
+    let markers = match crate::audio_output::read_wav_markers(path) {
+        Ok(markers) => markers,
+        Err(e) => {
+            eprintln!("Failed to read markers from {}: {}", path.display(), e);
+            return Vec::new();
+        }
+    };
+    let mut names = HashSet::new();
+    let mut cleaned = Vec::with_capacity(markers.len());
+    for (name, position_ms) in markers {
+        if position_ms as u64 > duration_ms {
+            continue;
+        }
+        let mut unique = name.clone();
+        let mut number = 2;
+        while !names.insert(unique.clone()) {
+            unique = format!("{} ({})", name, number);
+            number += 1;
+        }
+        cleaned.push(ClipMarker {
+            name: unique,
+            position_ms,
+        });
+    }
+    cleaned.sort_by_key(|marker| marker.position_ms);
+    cleaned
+}
+
+/// Give a newly imported clip the markers from its file, unless it already
+/// has markers of its own from an earlier import.
+fn add_file_markers(db: &mut LibraryDb, id: ClipId, markers: &[ClipMarker]) -> Result<(), String> {
+    if markers.is_empty() {
+        return Ok(());
+    }
+    match db.get(id)? {
+        Some(clip) if clip.markers.is_empty() => db.set_markers(id, markers),
+        _ => Ok(()),
+    }
+}
+
+fn clean_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Clip name is empty".to_string());
+    }
+    Ok(name.to_string())
+}
+
+/// Tags are matched without regard to case or surrounding space, so they're
+/// stored trimmed and lowercased.
+fn clean_tags(tags: Vec<String>) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
@@ -0,0 +1,78 @@
+use super::{now_ms, ClipId, LibraryState, TriggerSource};
+use crate::audio_output::{AudioOutputState, SessionId};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+
+pub type ScheduleId = i64;
+
+/// How often the scheduler checks for plays that have come due.
+const SCHEDULE_POLL_MS: u64 = 250;
+
+/// Plays this overdue, e.g. because the app was closed at the time, are
+/// dropped rather than played late.
+const MAX_SCHEDULE_LATENESS_MS: u64 = 60_000;
+
+/// A clip set to play once at a given time.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ScheduledPlay {
+    pub id: ScheduleId,
+    pub clip_id: ClipId,
+    pub clip_name: String,
+    /// When to play, in milliseconds since the Unix epoch
+    pub at_ms: u64,
+    pub device_ids: Vec<String>,
+}
+
+/// Payload of `library://scheduled_play`, emitted when a scheduled play
+/// comes due and is taken off the schedule.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ScheduledPlayFired {
+    pub schedule: ScheduledPlay,
+    pub session_id: Option<SessionId>,
+    /// Why the clip didn't play, if it didn't
+    pub error: Option<String>,
+}
+
+/// Play scheduled clips as they come due until the app shuts down.
+pub(super) fn spawn_scheduler(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(SCHEDULE_POLL_MS));
+
+        let library = app.state::<LibraryState>();
+        let now = now_ms();
+        let due = match library.with_db(|db| db.take_due_plays(now)) {
+            Ok(due) => due,
+            Err(_) => continue,
+        };
+        for schedule in due {
+            let result = if now - schedule.at_ms > MAX_SCHEDULE_LATENESS_MS {
+                Err(format!("Missed by {} s", (now - schedule.at_ms) / 1000))
+            } else {
+                let output = app.state::<AudioOutputState>();
+                library.play_clip(
+                    &output,
+                    schedule.clip_id,
+                    schedule.device_ids.clone(),
+                    None,
+                    None,
+                    TriggerSource::Schedule,
+                )
+            };
+            if let Err(e) = &result {
+                eprintln!("Scheduled play {} of clip {} failed: {}", schedule.id, schedule.clip_name, e);
+            }
+            let (session_id, error) = match result {
+                Ok(session_id) => (Some(session_id), None),
+                Err(e) => (None, Some(e)),
+            };
+            let event = ScheduledPlayFired {
+                schedule,
+                session_id,
+                error,
+            };
+            if let Err(e) = app.emit("library://scheduled_play", &event) {
+                eprintln!("Failed to emit library://scheduled_play event: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,88 @@
+use super::{ClipId, TriggerSource};
+
+/// Furthest a clock can be from UTC.
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
+/// Length of the periods plays are counted in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsPeriod {
+    #[default]
+    Day,
+    /// Monday to Sunday
+    Week,
+}
+
+impl StatsPeriod {
+    /// Length of the period, and how far the Unix epoch (a Thursday) is
+    /// from the start of one.
+    pub(super) fn span_ms(self) -> (i64, i64) {
+        const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+        match self {
+            StatsPeriod::Day => (DAY_MS, 0),
+            StatsPeriod::Week => (7 * DAY_MS, 3 * DAY_MS),
+        }
+    }
+}
+
+/// Which plays to count and how to group them.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct PlayStatsQuery {
+    /// Plays from this time on, in milliseconds since the Unix epoch; from
+    /// the start of the log if unset
+    pub since_ms: Option<u64>,
+    pub until_ms: Option<u64>,
+    pub period: StatsPeriod,
+    /// Minutes the user's clock is ahead of UTC, so days and hours follow
+    /// local time
+    pub utc_offset_minutes: i32,
+}
+
+impl PlayStatsQuery {
+    pub(super) fn validate(&self) -> Result<(), String> {
+        if self.utc_offset_minutes.abs() > MAX_UTC_OFFSET_MINUTES {
+            return Err(format!(
+                "UTC offset must be within {} hours",
+                MAX_UTC_OFFSET_MINUTES / 60
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Plays of one clip in one day or week.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ClipPeriodPlays {
+    pub clip_id: ClipId,
+    pub clip_name: String,
+    /// Local midnight the period starts at, in milliseconds since the Unix
+    /// epoch
+    pub period_start_ms: u64,
+    pub plays: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HourPlays {
+    /// Local hour of the day, 0-23
+    pub hour: u32,
+    pub plays: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SourcePlays {
+    pub source: TriggerSource,
+    pub plays: u64,
+}
+
+/// Counts over the trigger log for a stats view.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PlayStats {
+    pub total_plays: u64,
+    /// By period, then most played first
+    pub per_clip: Vec<ClipPeriodPlays>,
+    /// Hours with any plays, busiest first
+    pub busiest_hours: Vec<HourPlays>,
+    /// Most used first
+    pub sources: Vec<SourcePlays>,
+}
@@ -0,0 +1,137 @@
+use super::{ClipId, LibraryClip, LibraryState};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Sample rates clips can be converted to.
+const MIN_TRANSCODE_SAMPLE_RATE: u32 = 8_000;
+const MAX_TRANSCODE_SAMPLE_RATE: u32 = 192_000;
+
+/// Formats library clips can be converted to. Opus isn't offered since
+/// the library can't play it back.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscodeFormat {
+    Wav,
+    Flac,
+    Mp3,
+}
+
+impl TranscodeFormat {
+    pub(super) fn extension(self) -> &'static str {
+        match self {
+            TranscodeFormat::Wav => "wav",
+            TranscodeFormat::Flac => "flac",
+            TranscodeFormat::Mp3 => "mp3",
+        }
+    }
+}
+
+/// A batch conversion to start with `start_transcode`.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TranscodeRequest {
+    pub clip_ids: Vec<ClipId>,
+    pub format: TranscodeFormat,
+    pub sample_rate: u32,
+}
+
+impl TranscodeRequest {
+    pub(super) fn validate(&self) -> Result<(), String> {
+        if self.clip_ids.is_empty() {
+            return Err("No clips to convert".to_string());
+        }
+        if !(MIN_TRANSCODE_SAMPLE_RATE..=MAX_TRANSCODE_SAMPLE_RATE).contains(&self.sample_rate) {
+            return Err(format!(
+                "Sample rate must be {} to {} Hz, got {}",
+                MIN_TRANSCODE_SAMPLE_RATE, MAX_TRANSCODE_SAMPLE_RATE, self.sample_rate
+            ));
+        }
+        // Catches formats this build can't write, such as MP3 without the
+        // mp3 feature, before any clip is touched
+        let probe = std::path::Path::new("clip").with_extension(self.format.extension());
+        crate::audio_output::record::RecordingFormat::from_path(&probe).map(|_| ())
+    }
+}
+
+/// What became of one clip in a batch conversion.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TranscodeOutcome {
+    /// The clip now plays from the converted file
+    Converted { clip: Box<LibraryClip> },
+    Skipped { reason: String },
+    Failed { error: String },
+}
+
+/// Payload of `library://transcode_progress`, emitted as each clip is done.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TranscodeProgress {
+    pub clip_id: ClipId,
+    /// Clips done so far, including this one
+    pub completed: usize,
+    pub total: usize,
+    #[serde(flatten)]
+    pub outcome: TranscodeOutcome,
+}
+
+/// Payload of `library://transcode_finished`, emitted when a batch ends,
+/// whether it ran to completion or was cancelled.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct TranscodeFinished {
+    pub converted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub cancelled: bool,
+}
+
+/// Convert the requested clips one at a time on a background thread,
+/// reporting each as it is done. Setting `cancel` stops the clip being
+/// converted and the rest of the batch.
+pub(super) fn spawn_transcode_job(app: AppHandle, request: TranscodeRequest, cancel: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        let library = app.state::<LibraryState>();
+        let total = request.clip_ids.len();
+        let mut finished = TranscodeFinished::default();
+        for (index, &clip_id) in request.clip_ids.iter().enumerate() {
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            let outcome = match library.transcode_clip(clip_id, request.format, request.sample_rate, &cancel) {
+                Ok(outcome) => outcome,
+                // Cancelling mid-clip isn't a failure of the clip
+                Err(_) if cancel.load(Ordering::Relaxed) => break,
+                Err(error) => {
+                    eprintln!("Failed to convert clip {}: {}", clip_id, error);
+                    TranscodeOutcome::Failed { error }
+                }
+            };
+            match outcome {
+                TranscodeOutcome::Converted { .. } => finished.converted += 1,
+                TranscodeOutcome::Skipped { .. } => finished.skipped += 1,
+                TranscodeOutcome::Failed { .. } => finished.failed += 1,
+            }
+            let progress = TranscodeProgress {
+                clip_id,
+                completed: index + 1,
+                total,
+                outcome,
+            };
+            if let Err(e) = app.emit("library://transcode_progress", &progress) {
+                eprintln!("Failed to emit library://transcode_progress event: {}", e);
+            }
+        }
+
+        finished.cancelled = cancel.load(Ordering::Relaxed);
+        library.end_transcode(&cancel);
+        eprintln!(
+            "Batch conversion {}: {} converted, {} skipped, {} failed",
+            if finished.cancelled { "cancelled" } else { "finished" },
+            finished.converted,
+            finished.skipped,
+            finished.failed
+        );
+        if let Err(e) = app.emit("library://transcode_finished", &finished) {
+            eprintln!("Failed to emit library://transcode_finished event: {}", e);
+        }
+    });
+}
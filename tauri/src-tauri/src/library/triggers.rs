@@ -0,0 +1,119 @@
+use super::{ClipId, LibraryState};
+use crate::audio_output::AudioOutputState;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Longest a replay sleeps before checking whether it was stopped.
+const REPLAY_POLL_MS: u64 = 50;
+
+/// What started a clip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerSource {
+    /// Played by ID, e.g. from the library view
+    Manual,
+    Marker,
+    Board,
+    Schedule,
+    Replay,
+    /// Played some other way and counted with `record_clip_play`
+    External,
+}
+
+impl TriggerSource {
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            TriggerSource::Manual => "manual",
+            TriggerSource::Marker => "marker",
+            TriggerSource::Board => "board",
+            TriggerSource::Schedule => "schedule",
+            TriggerSource::Replay => "replay",
+            TriggerSource::External => "external",
+        }
+    }
+
+    pub(super) fn from_name(name: &str) -> Option<Self> {
+        [
+            TriggerSource::Manual,
+            TriggerSource::Marker,
+            TriggerSource::Board,
+            TriggerSource::Schedule,
+            TriggerSource::Replay,
+            TriggerSource::External,
+        ]
+        .into_iter()
+        .find(|source| source.name() == name)
+    }
+}
+
+/// A clip being started, as kept in the trigger log.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TriggerEvent {
+    pub id: i64,
+    pub clip_id: ClipId,
+    pub clip_name: String,
+    /// In milliseconds since the Unix epoch
+    pub at_ms: u64,
+    pub source: TriggerSource,
+    /// Empty for plays counted with `record_clip_play`, which can't be
+    /// replayed
+    pub device_ids: Vec<String>,
+}
+
+/// Payload of `library://replay_finished`, emitted when a replay ends,
+/// whether it ran to completion or was stopped.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ReplayFinished {
+    pub played: usize,
+    pub failed: usize,
+    pub cancelled: bool,
+}
+
+/// Fire `events` again on a background thread, spaced as they were
+/// originally. `device_ids` replaces each event's own devices if given.
+pub(super) fn spawn_replay(
+    app: AppHandle,
+    events: Vec<TriggerEvent>,
+    device_ids: Option<Vec<String>>,
+    cancel: Arc<AtomicBool>,
+) {
+    std::thread::spawn(move || {
+        let library = app.state::<LibraryState>();
+        let output = app.state::<AudioOutputState>();
+        let mut finished = ReplayFinished::default();
+        let start = Instant::now();
+        let first_ms = events.first().map_or(0, |event| event.at_ms);
+        for event in events {
+            let due = Duration::from_millis(event.at_ms - first_ms);
+            while !cancel.load(Ordering::Relaxed) && start.elapsed() < due {
+                let wait = due - start.elapsed();
+                std::thread::sleep(wait.min(Duration::from_millis(REPLAY_POLL_MS)));
+            }
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            let device_ids = device_ids.clone().unwrap_or(event.device_ids);
+            match library.play_clip(&output, event.clip_id, device_ids, None, None, TriggerSource::Replay) {
+                Ok(_) => finished.played += 1,
+                Err(e) => {
+                    eprintln!("Failed to replay clip {}: {}", event.clip_name, e);
+                    finished.failed += 1;
+                }
+            }
+        }
+
+        finished.cancelled = cancel.load(Ordering::Relaxed);
+        library.end_replay(&cancel);
+        eprintln!(
+            "Trigger replay {}: {} played, {} failed",
+            if finished.cancelled { "stopped" } else { "finished" },
+            finished.played,
+            finished.failed
+        );
+        if let Err(e) = app.emit("library://replay_finished", &finished) {
+            eprintln!("Failed to emit library://replay_finished event: {}", e);
+        }
+    });
+}
@@ -0,0 +1,152 @@
+use super::{LibraryClip, LibraryState};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// How often watched folders are scanned for new files.
+const WATCH_POLL_INTERVAL_MS: u64 = 2000;
+
+/// Extensions of files picked up from watched folders.
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "wav", "wave", "mp3", "flac", "ogg", "oga", "m4a", "aac", "aif", "aiff", "caf",
+];
+
+/// A folder whose new audio files are added to the library.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WatchFolder {
+    pub path: String,
+    /// Also watch the folders inside it
+    pub recursive: bool,
+}
+
+/// Payload of `library://clip_imported`, emitted when a file found in a
+/// watched folder has been added to the library.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WatchedImport {
+    pub folder: String,
+    pub clip: LibraryClip,
+}
+
+/// Payload of `library://import_failed`, emitted when a file found in a
+/// watched folder couldn't be added. It isn't tried again until it changes.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WatchedImportError {
+    pub folder: String,
+    pub path: String,
+    pub error: String,
+}
+
+/// Size and modification time of a file, to tell whether it is still being
+/// written between scans.
+type FileStamp = (u64, Option<SystemTime>);
+
+/// Scan the watched folders for audio files that aren't in the library yet
+/// and import them. A file is only imported once it is unchanged across two
+/// scans, so recordings and downloads aren't read half-written.
+pub(super) fn spawn_folder_watcher(app: AppHandle) {
+    std::thread::spawn(move || {
+        let library = app.state::<LibraryState>();
+        let mut waiting: HashMap<PathBuf, FileStamp> = HashMap::new();
+        let mut failed: HashMap<PathBuf, FileStamp> = HashMap::new();
+        loop {
+            std::thread::sleep(Duration::from_millis(WATCH_POLL_INTERVAL_MS));
+
+            let folders = match library.watch_folders() {
+                Ok(folders) => folders,
+                Err(_) => continue,
+            };
+            if folders.is_empty() {
+                waiting.clear();
+                continue;
+            }
+            let known = match library.with_db(|db| db.clip_paths()) {
+                Ok(known) => known,
+                Err(e) => {
+                    eprintln!("Folder watcher: {}", e);
+                    continue;
+                }
+            };
+
+            let mut still_waiting = HashMap::new();
+            for folder in &folders {
+                for (path, stamp) in audio_files(Path::new(&folder.path), folder.recursive) {
+                    if known.contains(path.to_string_lossy().as_ref()) || failed.get(&path) == Some(&stamp) {
+                        continue;
+                    }
+                    if waiting.get(&path) != Some(&stamp) {
+                        still_waiting.insert(path, stamp);
+                        continue;
+                    }
+
+                    failed.remove(&path);
+                    match library.import_clip(&path, None, Vec::new()) {
+                        Ok(clip) => {
+                            eprintln!("Imported {} from watched folder {}", path.display(), folder.path);
+                            let event = WatchedImport {
+                                folder: folder.path.clone(),
+                                clip,
+                            };
+                            emit(&app, "library://clip_imported", &event);
+                        }
+                        Err(error) => {
+                            eprintln!("Failed to import {}: {}", path.display(), error);
+                            let event = WatchedImportError {
+                                folder: folder.path.clone(),
+                                path: path.to_string_lossy().into_owned(),
+                                error,
+                            };
+                            emit(&app, "library://import_failed", &event);
+                            failed.insert(path, stamp);
+                        }
+                    }
+                }
+            }
+            waiting = still_waiting;
+        }
+    });
+}
+
+/// Audio files in `dir`, skipping hidden files and symlinks.
+fn audio_files(dir: &Path, recursive: bool) -> Vec<(PathBuf, FileStamp)> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                if recursive {
+                    dirs.push(path);
+                }
+            } else if file_type.is_file() && is_audio_file(&path) {
+                if let Ok(metadata) = entry.metadata() {
+                    files.push((path, (metadata.len(), metadata.modified().ok())));
+                }
+            }
+        }
+    }
+    files
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known)))
+}
+
+fn emit<S: serde::Serialize + Clone>(app: &AppHandle, event: &str, payload: &S) {
+    if let Err(e) = app.emit(event, payload) {
+        eprintln!("Failed to emit {} event: {}", event, e);
+    }
+}
@@ -2,8 +2,13 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod audio_capture;
+mod audio_input;
 mod audio_output;
+mod cli;
+mod library;
+mod tts;
 
+use std::collections::HashMap;
 use std::sync::Mutex;
 use tauri::{command, State, Manager, WindowEvent, Emitter, Listener, RunEvent};
 use tauri_plugin_shell::ShellExt;
@@ -18,6 +23,18 @@ struct ServerState {
     keep_running_on_close: Mutex<bool>,
 }
 
+/// Run file and decoding work on the blocking pool, so it holds up neither
+/// the main thread nor the async runtime's workers.
+async fn run_blocking<T, F>(task: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(task)
+        .await
+        .map_err(|e| format!("Background task failed: {}", e))?
+}
+
 #[command]
 async fn start_server(
     app: tauri::AppHandle,
@@ -569,6 +586,144 @@ async fn stop_system_audio_capture(
     audio_capture::stop_capture(&state).await
 }
 
+#[command]
+fn list_audio_input_devices(
+    state: State<'_, audio_input::AudioInputState>,
+) -> Result<Vec<audio_input::AudioInputDevice>, String> {
+    state.list_input_devices()
+}
+
+#[command]
+fn start_mic_capture(
+    state: State<'_, audio_input::AudioInputState>,
+    device_id: Option<String>,
+    max_duration_secs: u32,
+) -> Result<(), String> {
+    state.start_mic_capture(device_id, max_duration_secs)
+}
+
+#[command]
+fn stop_mic_capture(state: State<'_, audio_input::AudioInputState>) -> Result<String, String> {
+    state.stop_mic_capture()
+}
+
+#[command]
+fn start_mic_recording(
+    state: State<'_, audio_input::AudioInputState>,
+    device_id: Option<String>,
+    path: String,
+    sample_rate: Option<u32>,
+) -> Result<(), String> {
+    state.start_mic_recording(device_id, std::path::Path::new(&path), sample_rate)
+}
+
+#[command]
+fn stop_mic_recording(state: State<'_, audio_input::AudioInputState>) -> Result<(), String> {
+    state.stop_mic_recording()
+}
+
+#[command]
+fn start_mic_monitor(
+    input: State<'_, audio_input::AudioInputState>,
+    output: State<'_, audio_output::AudioOutputState>,
+    device_id: Option<String>,
+    output_device_ids: Vec<String>,
+    gain: f32,
+) -> Result<(), String> {
+    input.start_mic_monitor(&output, device_id, output_device_ids, gain)
+}
+
+#[command]
+fn set_mic_monitor_gain(state: State<'_, audio_input::AudioInputState>, gain: f32) -> Result<(), String> {
+    state.set_mic_monitor_gain(gain)
+}
+
+#[command]
+fn stop_mic_monitor(state: State<'_, audio_input::AudioInputState>) -> Result<(), String> {
+    state.stop_mic_monitor()
+}
+
+#[command]
+fn start_voice_detection(
+    state: State<'_, audio_input::AudioInputState>,
+    device_id: Option<String>,
+) -> Result<(), String> {
+    state.start_voice_detection(device_id)
+}
+
+#[command]
+fn stop_voice_detection(state: State<'_, audio_input::AudioInputState>) -> Result<(), String> {
+    state.stop_voice_detection()
+}
+
+#[command]
+fn start_lip_sync(
+    state: State<'_, audio_input::AudioInputState>,
+    device_id: Option<String>,
+) -> Result<(), String> {
+    state.start_lip_sync(device_id)
+}
+
+#[command]
+fn stop_lip_sync(state: State<'_, audio_input::AudioInputState>) -> Result<(), String> {
+    state.stop_lip_sync()
+}
+
+#[command]
+fn set_noise_suppression(
+    state: State<'_, audio_input::AudioInputState>,
+    enabled: bool,
+    strength: f32,
+) -> Result<(), String> {
+    state.set_noise_suppression(enabled, strength)
+}
+
+#[command]
+fn set_push_to_talk(
+    state: State<'_, audio_input::AudioInputState>,
+    enabled: bool,
+    release_tail_ms: u32,
+) -> Result<(), String> {
+    state.set_push_to_talk(enabled, release_tail_ms)
+}
+
+#[command]
+fn set_push_to_talk_held(state: State<'_, audio_input::AudioInputState>, held: bool) {
+    state.set_push_to_talk_held(held)
+}
+
+#[command]
+fn set_auto_gain(
+    state: State<'_, audio_input::AudioInputState>,
+    enabled: bool,
+    target_dbfs: f32,
+    max_gain_db: f32,
+) -> Result<(), String> {
+    state.set_auto_gain(enabled, target_dbfs, max_gain_db)
+}
+
+#[command]
+fn set_echo_cancellation(
+    input: State<'_, audio_input::AudioInputState>,
+    output: State<'_, audio_output::AudioOutputState>,
+    output_device_id: Option<String>,
+) -> Result<(), String> {
+    input.set_echo_cancellation(&output, output_device_id)
+}
+
+#[command]
+fn start_input_meter(
+    state: State<'_, audio_input::AudioInputState>,
+    device_id: Option<String>,
+) -> Result<(), String> {
+    state.start_input_meter(device_id)
+}
+
+#[command]
+fn stop_input_meter(state: State<'_, audio_input::AudioInputState>) -> Result<(), String> {
+    state.stop_input_meter()
+}
+
 #[command]
 fn is_system_audio_supported() -> bool {
     audio_capture::is_supported()
@@ -586,109 +741,1240 @@ async fn play_audio_to_devices(
     state: State<'_, audio_output::AudioOutputState>,
     audio_data: Vec<u8>,
     device_ids: Vec<String>,
+    lead_in: Option<audio_output::LeadIn>,
+    options: Option<audio_output::PlaybackOptions>,
+) -> Result<audio_output::SessionId, String> {
+    state.play_audio_to_devices(audio_data, device_ids, lead_in, options).await
+}
+
+#[command]
+fn play_file_to_devices(
+    state: State<'_, audio_output::AudioOutputState>,
+    path: String,
+    device_ids: Vec<String>,
+    lead_in: Option<audio_output::LeadIn>,
+    options: Option<audio_output::PlaybackOptions>,
+) -> Result<audio_output::SessionId, String> {
+    state.play_file_to_devices(std::path::Path::new(&path), device_ids, lead_in, options)
+}
+
+#[command]
+async fn play_url_to_devices(
+    state: State<'_, audio_output::AudioOutputState>,
+    url: String,
+    device_ids: Vec<String>,
+    lead_in: Option<audio_output::LeadIn>,
+    options: Option<audio_output::PlaybackOptions>,
+) -> Result<audio_output::SessionId, String> {
+    state.play_url_to_devices(url, device_ids, lead_in, options).await
+}
+
+#[command]
+async fn queue_audio_to_devices(
+    state: State<'_, audio_output::AudioOutputState>,
+    audio_data: Vec<u8>,
+    device_ids: Vec<String>,
+    lead_in: Option<audio_output::LeadIn>,
+    options: Option<audio_output::PlaybackOptions>,
+) -> Result<audio_output::SessionId, String> {
+    state.queue_audio_to_devices(audio_data, device_ids, lead_in, options).await
+}
+
+#[command]
+fn play_playlist(
+    state: State<'_, audio_output::AudioOutputState>,
+    items: Vec<audio_output::PlaylistItem>,
+    device_ids: Vec<String>,
+    options: Option<audio_output::PlaybackOptions>,
+    start_index: Option<usize>,
+) -> Result<audio_output::PlaylistStatus, String> {
+    state.play_playlist(items, device_ids, options, start_index)
+}
+
+#[command]
+fn playlist_next(
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Result<audio_output::PlaylistStatus, String> {
+    state.playlist_next()
+}
+
+#[command]
+fn playlist_previous(
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Result<audio_output::PlaylistStatus, String> {
+    state.playlist_previous()
+}
+
+#[command]
+fn stop_playlist(state: State<'_, audio_output::AudioOutputState>) -> Result<(), String> {
+    state.stop_playlist()
+}
+
+#[command]
+fn get_playlist_status(
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Option<audio_output::PlaylistStatus> {
+    state.playlist_status()
+}
+
+#[command]
+fn get_playback_queues(
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Vec<audio_output::PlaybackQueue> {
+    state.playback_queues()
+}
+
+#[command]
+fn move_queued_playback(
+    state: State<'_, audio_output::AudioOutputState>,
+    id: audio_output::SessionId,
+    index: usize,
+) -> Result<(), String> {
+    state.move_queued_playback(id, index)
+}
+
+#[command]
+fn clear_playback_queue(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_ids: Option<Vec<String>>,
+) -> Result<(), String> {
+    state.clear_playback_queue(device_ids)
+}
+
+#[command]
+fn play_test_tone(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    frequency: f32,
+    duration_ms: u32,
+    kind: Option<audio_output::TestToneKind>,
+) -> Result<audio_output::SessionId, String> {
+    state.play_test_tone(device_id, frequency, duration_ms, kind)
+}
+
+#[command]
+fn stop_playback(
+    state: State<'_, audio_output::AudioOutputState>,
+    session_id: audio_output::SessionId,
+    fade_ms: Option<u32>,
 ) -> Result<(), String> {
-    state.play_audio_to_devices(audio_data, device_ids).await
+    state.stop_playback(session_id, fade_ms)
+}
+
+#[command]
+fn pause_playback(
+    state: State<'_, audio_output::AudioOutputState>,
+    session_id: audio_output::SessionId,
+) -> Result<(), String> {
+    state.pause_playback(session_id)
+}
+
+#[command]
+fn resume_playback(
+    state: State<'_, audio_output::AudioOutputState>,
+    session_id: audio_output::SessionId,
+) -> Result<(), String> {
+    state.resume_playback(session_id)
+}
+
+#[command]
+fn move_playback(
+    state: State<'_, audio_output::AudioOutputState>,
+    session_id: audio_output::SessionId,
+    from_device_id: String,
+    to_device_id: String,
+) -> Result<(), String> {
+    state.move_playback(session_id, &from_device_id, &to_device_id)
+}
+
+#[command]
+fn set_device_failover(state: State<'_, audio_output::AudioOutputState>, enabled: bool) {
+    state.set_device_failover(enabled)
+}
+
+#[command]
+fn get_playback_status(
+    state: State<'_, audio_output::AudioOutputState>,
+    session_id: audio_output::SessionId,
+) -> Result<audio_output::PlaybackStatus, String> {
+    state.playback_status(session_id)
 }
 
 #[command]
 fn stop_audio_playback(
     state: State<'_, audio_output::AudioOutputState>,
+    fade_ms: Option<u32>,
 ) -> Result<(), String> {
-    state.stop_all_playback()
+    state.stop_all_playback(fade_ms)
 }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    tauri::Builder::default()
-        .plugin(tauri_plugin_dialog::init())
-        .plugin(tauri_plugin_fs::init())
-        .plugin(tauri_plugin_shell::init())
-        .manage(ServerState {
-            child: Mutex::new(None),
-            server_pid: Mutex::new(None),
-            keep_running_on_close: Mutex::new(false),
-        })
-        .manage(audio_capture::AudioCaptureState::new())
-        .manage(audio_output::AudioOutputState::new())
-        .setup(|app| {
-            #[cfg(desktop)]
-            {
-                app.handle().plugin(tauri_plugin_updater::Builder::new().build())?;
-                app.handle().plugin(tauri_plugin_process::init())?;
-            }
+#[command]
+fn set_device_polarity_invert(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    enabled: bool,
+) -> Result<(), String> {
+    state.set_device_polarity_invert(&device_id, enabled)
+}
 
-            // Hide title bar icon on Windows
-            #[cfg(windows)]
-            {
-                use windows::Win32::Foundation::HWND;
-                use windows::Win32::UI::WindowsAndMessaging::{SetClassLongPtrW, GCLP_HICON, GCLP_HICONSM};
-                
-                if let Some((_, window)) = app.webview_windows().iter().next() {
-                    if let Ok(hwnd) = window.hwnd() {
-                        let hwnd = HWND(hwnd.0);
-                        unsafe {
-                            // Set both small and regular icons to NULL to hide the title bar icon
-                            SetClassLongPtrW(hwnd, GCLP_HICON, 0);
-                            SetClassLongPtrW(hwnd, GCLP_HICONSM, 0);
-                        }
-                    }
-                }
-            }
+#[command]
+fn set_master_gain(
+    state: State<'_, audio_output::AudioOutputState>,
+    gain: f32,
+) -> Result<(), String> {
+    state.set_master_gain(gain)
+}
 
-            Ok(())
-        })
-        .invoke_handler(tauri::generate_handler![
-            start_server,
-            stop_server,
-            set_keep_server_running,
-            start_system_audio_capture,
-            stop_system_audio_capture,
-            is_system_audio_supported,
-            list_audio_output_devices,
-            play_audio_to_devices,
-            stop_audio_playback
-        ])
-        .on_window_event(|window, event| {
-            if let WindowEvent::CloseRequested { api, .. } = event {
-                // Prevent automatic close
-                api.prevent_close();
+#[command]
+fn get_master_gain(state: State<'_, audio_output::AudioOutputState>) -> f32 {
+    state.master_gain()
+}
 
-                // Emit event to frontend to check setting and stop server if needed
-                let app_handle = window.app_handle();
+#[command]
+fn set_duck_level(state: State<'_, audio_output::AudioOutputState>, level_db: f32) -> Result<(), String> {
+    state.set_duck_level(level_db)
+}
 
-                if let Err(e) = app_handle.emit("window-close-requested", ()) {
-                    eprintln!("Failed to emit window-close-requested event: {}", e);
-                    // If event emission fails, allow close anyway
-                    window.close().ok();
-                    return;
-                }
+#[command]
+fn get_duck_level(state: State<'_, audio_output::AudioOutputState>) -> f32 {
+    state.duck_level()
+}
 
-                // Set up listener for frontend response
-                let window_for_close = window.clone();
-                let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+#[command]
+fn set_device_mute(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    muted: bool,
+) -> Result<(), String> {
+    state.set_device_mute(&device_id, muted)
+}
 
-                // Listen for response from frontend using window's listen method
-                let listener_id = window.listen("window-close-allowed", move |_| {
-                    // Frontend has checked setting and stopped server if needed
-                    // Signal that we can close
-                    let _ = tx.send(());
-                });
+#[command]
+fn set_device_channel_swap(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    enabled: bool,
+) -> Result<(), String> {
+    state.set_device_channel_swap(&device_id, enabled)
+}
 
-                // Wait for frontend response or timeout
-                tokio::spawn(async move {
-                    tokio::select! {
-                        _ = rx.recv() => {
-                            // Frontend responded, close window
-                            window_for_close.close().ok();
-                        }
-                        _ = tokio::time::sleep(tokio::time::Duration::from_secs(5)) => {
-                            // Timeout - close anyway
-                            eprintln!("Window close timeout, closing anyway");
-                            window_for_close.close().ok();
-                        }
-                    }
-                    // Clean up listener
-                    window_for_close.unlisten(listener_id);
+#[command]
+fn list_audio_output_hosts(
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Vec<audio_output::AudioHost> {
+    state.list_output_hosts()
+}
+
+#[command]
+fn set_audio_output_host(
+    state: State<'_, audio_output::AudioOutputState>,
+    host: String,
+) -> Result<Vec<audio_output::AudioOutputDevice>, String> {
+    state.set_output_host(&host)
+}
+
+#[command]
+fn set_asio_output_enabled(
+    state: State<'_, audio_output::AudioOutputState>,
+    enabled: bool,
+) -> Result<(), String> {
+    state.set_asio_enabled(enabled)
+}
+
+#[command]
+fn set_jack_output_enabled(
+    state: State<'_, audio_output::AudioOutputState>,
+    enabled: bool,
+) -> Result<(), String> {
+    state.set_jack_enabled(enabled)
+}
+
+#[command]
+fn get_output_latencies(
+    state: State<'_, audio_output::AudioOutputState>,
+) -> std::collections::HashMap<String, f64> {
+    state.output_latencies()
+}
+
+#[command]
+fn get_supported_output_configs(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+) -> Result<audio_output::DeviceCapabilities, String> {
+    state.output_device_capabilities(&device_id)
+}
+
+#[command]
+fn get_output_device_config(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+) -> audio_output::DeviceStreamConfig {
+    state.get_output_device_config(&device_id)
+}
+
+#[command]
+fn set_output_device_config(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    config: audio_output::DeviceStreamConfig,
+) -> Result<(), String> {
+    state.set_output_device_config(&device_id, config)
+}
+
+#[command]
+fn get_clip_trim(
+    state: State<'_, library::LibraryState>,
+    id: library::ClipId,
+) -> Result<Option<audio_output::ClipTrim>, String> {
+    state.get_clip_trim(id)
+}
+
+#[command]
+fn set_clip_trim(
+    state: State<'_, library::LibraryState>,
+    id: library::ClipId,
+    trim: Option<audio_output::ClipTrim>,
+) -> Result<library::LibraryClip, String> {
+    state.set_clip_trim(id, trim)
+}
+
+#[command]
+async fn get_waveform_peaks(
+    app: tauri::AppHandle,
+    path: String,
+    buckets: usize,
+) -> Result<audio_output::WaveformPeaks, String> {
+    run_blocking(move || {
+        app.state::<audio_output::AudioOutputState>()
+            .waveform_peaks(std::path::Path::new(&path), buckets)
+    })
+    .await
+}
+
+#[command]
+fn get_audio_metadata(path: String) -> Result<audio_output::AudioMetadata, String> {
+    audio_output::read_metadata(std::path::Path::new(&path))
+}
+
+#[command]
+async fn splice_audio_files(segments: Vec<audio_output::SpliceSegment>, output_path: String) -> Result<u64, String> {
+    run_blocking(move || {
+        audio_output::splice_files(&segments, std::path::Path::new(&output_path)).map(|format| format.duration_ms)
+    })
+    .await
+}
+
+#[command]
+async fn cut_audio_file(path: String, start_ms: u32, end_ms: u32, output_path: String) -> Result<u64, String> {
+    run_blocking(move || {
+        audio_output::cut_file(std::path::Path::new(&path), start_ms, end_ms, std::path::Path::new(&output_path))
+            .map(|format| format.duration_ms)
+    })
+    .await
+}
+
+#[command]
+async fn find_sound_regions(
+    path: String,
+    options: Option<audio_output::SilenceOptions>,
+) -> Result<Vec<audio_output::SoundRegion>, String> {
+    run_blocking(move || audio_output::find_sound_regions(std::path::Path::new(&path), &options.unwrap_or_default()))
+        .await
+}
+
+#[command]
+fn set_device_delay(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    delay_ms: u32,
+) -> Result<(), String> {
+    state.set_device_delay(&device_id, delay_ms)
+}
+
+#[command]
+fn get_device_delay(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+) -> u32 {
+    state.device_delay(&device_id)
+}
+
+#[command]
+fn start_output_recording(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    path: String,
+) -> Result<(), String> {
+    state.start_output_recording(&device_id, std::path::Path::new(&path))
+}
+
+#[command]
+fn stop_output_recording(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+) -> Result<(), String> {
+    state.stop_output_recording(&device_id)
+}
+
+#[command]
+fn set_device_eq(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    bands: Vec<audio_output::EqBand>,
+) -> Result<(), String> {
+    state.set_device_eq(&device_id, bands)
+}
+
+#[command]
+fn get_device_eq(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+) -> Vec<audio_output::EqBand> {
+    state.device_eq(&device_id)
+}
+
+#[command]
+fn set_device_compressor(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    settings: Option<audio_output::CompressorSettings>,
+) -> Result<(), String> {
+    state.set_device_compressor(&device_id, settings)
+}
+
+#[command]
+fn get_device_compressor(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+) -> Option<audio_output::CompressorSettings> {
+    state.device_compressor(&device_id)
+}
+
+#[command]
+async fn start_audio_preview(
+    state: State<'_, audio_output::AudioOutputState>,
+    audio_data: Vec<u8>,
+    device_id: String,
+    position_ms: u32,
+) -> Result<(), String> {
+    state.start_preview(audio_data, device_id, position_ms)
+}
+
+#[command]
+async fn preview_audio_file(
+    app: tauri::AppHandle,
+    path: String,
+    start_ms: u32,
+    duration_ms: Option<u32>,
+    device_id: String,
+) -> Result<(), String> {
+    run_blocking(move || {
+        app.state::<audio_output::AudioOutputState>()
+            .preview_file(std::path::Path::new(&path), start_ms, duration_ms, device_id)
+    })
+    .await
+}
+
+#[command]
+fn seek_audio_preview(
+    state: State<'_, audio_output::AudioOutputState>,
+    position_ms: u32,
+) -> Result<(), String> {
+    state.seek_preview(position_ms)
+}
+
+#[command]
+fn stop_audio_preview(
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Result<(), String> {
+    state.stop_preview()
+}
+
+#[command]
+fn panic_stop_playback(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+    input: State<'_, audio_input::AudioInputState>,
+    tts: State<'_, tts::TtsState>,
+) -> Result<(), String> {
+    // Cleared first so the queue doesn't start its next message
+    tts.clear_queue(&state);
+    // Only fails if the mic wasn't being monitored
+    let _ = input.stop_mic_monitor();
+    state.panic_stop()?;
+    if let Err(e) = app.emit("playback://panic", ()) {
+        eprintln!("Failed to emit playback://panic event: {}", e);
+    }
+    Ok(())
+}
+
+#[command]
+fn set_playback_sleep_timer(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+    minutes: f64,
+    session_id: Option<audio_output::SessionId>,
+) -> Result<(), String> {
+    state.set_sleep_timer(app, minutes, session_id)
+}
+
+#[command]
+fn cancel_playback_sleep_timer(
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Result<(), String> {
+    state.cancel_sleep_timer()
+}
+
+#[command]
+fn configure_tts_provider(
+    state: State<'_, tts::TtsState>,
+    provider: tts::TtsProviderKind,
+    config: Option<tts::TtsProviderConfig>,
+) -> Result<(), String> {
+    state.configure_provider(provider, config)
+}
+
+#[command]
+fn list_tts_providers(state: State<'_, tts::TtsState>) -> Vec<tts::TtsProviderStatus> {
+    state.list_providers()
+}
+
+#[command]
+async fn list_tts_voices(
+    state: State<'_, tts::TtsState>,
+    provider: tts::TtsProviderKind,
+) -> Result<Vec<tts::TtsVoice>, String> {
+    state.list_voices(provider).await
+}
+
+#[command]
+async fn speak_text(
+    tts: State<'_, tts::TtsState>,
+    output: State<'_, audio_output::AudioOutputState>,
+    request: tts::TtsRequest,
+    device_ids: Vec<String>,
+    options: Option<audio_output::PlaybackOptions>,
+) -> Result<audio_output::SessionId, String> {
+    tts.speak(&output, request, device_ids, options).await
+}
+
+#[command]
+async fn export_speech(
+    state: State<'_, tts::TtsState>,
+    request: tts::TtsRequest,
+    path: String,
+    options: Option<audio_output::PlaybackOptions>,
+) -> Result<tts::SpeechExport, String> {
+    state.export(request, path, options).await
+}
+
+#[command]
+fn enqueue_speech(
+    state: State<'_, tts::TtsState>,
+    request: tts::TtsRequest,
+    device_ids: Vec<String>,
+    options: Option<audio_output::PlaybackOptions>,
+    priority: Option<tts::TtsPriority>,
+) -> Result<u64, String> {
+    state.enqueue(request, device_ids, options, priority.unwrap_or_default())
+}
+
+#[command]
+fn skip_speech(
+    tts: State<'_, tts::TtsState>,
+    output: State<'_, audio_output::AudioOutputState>,
+) -> Result<(), String> {
+    tts.skip(&output)
+}
+
+#[command]
+fn clear_speech_queue(
+    tts: State<'_, tts::TtsState>,
+    output: State<'_, audio_output::AudioOutputState>,
+) {
+    tts.clear_queue(&output)
+}
+
+#[command]
+fn get_speech_queue(state: State<'_, tts::TtsState>) -> tts::SpeechQueueStatus {
+    state.queue_status()
+}
+
+#[command]
+fn detect_text_language(state: State<'_, tts::TtsState>, text: String) -> Option<tts::DetectedLanguage> {
+    state.detect_language(&text)
+}
+
+#[command]
+fn get_language_voices(state: State<'_, tts::TtsState>) -> HashMap<String, tts::LanguageVoice> {
+    state.language_voices()
+}
+
+#[command]
+fn set_language_voice(
+    state: State<'_, tts::TtsState>,
+    language: String,
+    voice: Option<tts::LanguageVoice>,
+) -> Result<(), String> {
+    state.set_language_voice(&language, voice)
+}
+
+#[command]
+fn get_pronunciations(state: State<'_, tts::TtsState>) -> Vec<tts::PronunciationEntry> {
+    state.pronunciations()
+}
+
+#[command]
+fn set_pronunciation(
+    state: State<'_, tts::TtsState>,
+    entry: tts::PronunciationEntry,
+) -> Result<(), String> {
+    state.set_pronunciation(entry)
+}
+
+#[command]
+fn remove_pronunciation(state: State<'_, tts::TtsState>, term: String) -> Result<(), String> {
+    state.remove_pronunciation(&term)
+}
+
+#[command]
+fn follow_text_file(state: State<'_, tts::TtsState>, file: tts::FollowedFile) -> Result<tts::FollowedFile, String> {
+    state.follow_file(file)
+}
+
+#[command]
+fn unfollow_text_file(state: State<'_, tts::TtsState>, path: String) -> Result<(), String> {
+    state.unfollow_file(&path)
+}
+
+#[command]
+fn get_followed_text_files(state: State<'_, tts::TtsState>) -> Vec<tts::FollowedFile> {
+    state.followed_files()
+}
+
+#[command]
+fn get_tts_feeds(state: State<'_, tts::TtsState>) -> tts::FeedSettings {
+    state.feed_settings()
+}
+
+#[command]
+fn set_tts_feed(state: State<'_, tts::TtsState>, feed: tts::Feed) -> Result<(), String> {
+    state.set_feed(feed)
+}
+
+#[command]
+fn remove_tts_feed(state: State<'_, tts::TtsState>, url: String) -> Result<(), String> {
+    state.remove_feed(&url)
+}
+
+#[command]
+fn set_tts_feed_interval(state: State<'_, tts::TtsState>, interval_secs: u64) -> Result<(), String> {
+    state.set_feed_interval(interval_secs)
+}
+
+#[command]
+fn get_tts_templates(state: State<'_, tts::TtsState>) -> Vec<tts::TtsTemplate> {
+    state.templates()
+}
+
+#[command]
+fn set_tts_template(state: State<'_, tts::TtsState>, template: tts::TtsTemplate) -> Result<(), String> {
+    state.set_template(template)
+}
+
+#[command]
+fn remove_tts_template(state: State<'_, tts::TtsState>, name: String) -> Result<(), String> {
+    state.remove_template(&name)
+}
+
+#[command]
+fn render_tts_template(
+    state: State<'_, tts::TtsState>,
+    name: String,
+    values: HashMap<String, String>,
+) -> Result<String, String> {
+    state.render_template(&name, &values)
+}
+
+#[command]
+async fn import_library_clip(
+    app: tauri::AppHandle,
+    path: String,
+    name: Option<String>,
+    tags: Option<Vec<String>>,
+) -> Result<library::LibraryClip, String> {
+    run_blocking(move || {
+        app.state::<library::LibraryState>()
+            .import_clip(std::path::Path::new(&path), name, tags.unwrap_or_default())
+    })
+    .await
+}
+
+#[command]
+async fn import_library_files(
+    app: tauri::AppHandle,
+    paths: Vec<String>,
+    tags: Option<Vec<String>>,
+) -> Result<Vec<library::ImportResult>, String> {
+    let paths = paths.into_iter().map(std::path::PathBuf::from).collect();
+    run_blocking(move || Ok(app.state::<library::LibraryState>().import_files(paths, tags.unwrap_or_default()))).await
+}
+
+#[command]
+fn get_library_clip(state: State<'_, library::LibraryState>, id: library::ClipId) -> Result<library::LibraryClip, String> {
+    state.get_clip(id)
+}
+
+#[command]
+fn search_library(
+    state: State<'_, library::LibraryState>,
+    query: Option<library::ClipQuery>,
+) -> Result<Vec<library::LibraryClip>, String> {
+    state.search(query.unwrap_or_default())
+}
+
+#[command]
+fn update_library_clip(
+    state: State<'_, library::LibraryState>,
+    id: library::ClipId,
+    update: library::ClipUpdate,
+) -> Result<library::LibraryClip, String> {
+    state.update_clip(id, update)
+}
+
+#[command]
+fn play_library_clip(
+    library: State<'_, library::LibraryState>,
+    output: State<'_, audio_output::AudioOutputState>,
+    id: library::ClipId,
+    device_ids: Vec<String>,
+    lead_in: Option<audio_output::LeadIn>,
+    options: Option<audio_output::PlaybackOptions>,
+) -> Result<audio_output::SessionId, String> {
+    library.play_clip(&output, id, device_ids, lead_in, options, library::TriggerSource::Manual)
+}
+
+#[command]
+fn play_library_clip_from_marker(
+    library: State<'_, library::LibraryState>,
+    output: State<'_, audio_output::AudioOutputState>,
+    id: library::ClipId,
+    marker: String,
+    device_ids: Vec<String>,
+    options: Option<audio_output::PlaybackOptions>,
+) -> Result<audio_output::SessionId, String> {
+    library.play_clip_from_marker(&output, id, &marker, device_ids, options)
+}
+
+#[command]
+fn release_library_clip(
+    library: State<'_, library::LibraryState>,
+    output: State<'_, audio_output::AudioOutputState>,
+    id: library::ClipId,
+) -> Result<(), String> {
+    library.release_clip(&output, id)
+}
+
+#[command]
+fn record_clip_play(state: State<'_, library::LibraryState>, id: library::ClipId) -> Result<(), String> {
+    state.record_play(id)
+}
+
+#[command]
+fn get_play_stats(
+    state: State<'_, library::LibraryState>,
+    query: Option<library::PlayStatsQuery>,
+) -> Result<library::PlayStats, String> {
+    state.play_stats(query.unwrap_or_default())
+}
+
+#[command]
+fn get_trigger_log(
+    state: State<'_, library::LibraryState>,
+    since_ms: Option<u64>,
+    until_ms: Option<u64>,
+) -> Result<Vec<library::TriggerEvent>, String> {
+    state.trigger_log(since_ms, until_ms)
+}
+
+#[command]
+fn replay_triggers(
+    state: State<'_, library::LibraryState>,
+    since_ms: Option<u64>,
+    until_ms: Option<u64>,
+    device_ids: Option<Vec<String>>,
+) -> Result<usize, String> {
+    state.replay_triggers(since_ms, until_ms, device_ids)
+}
+
+#[command]
+fn stop_trigger_replay(state: State<'_, library::LibraryState>) -> Result<(), String> {
+    state.stop_replay()
+}
+
+#[command]
+fn get_most_played_clips(
+    state: State<'_, library::LibraryState>,
+    limit: Option<u32>,
+) -> Result<Vec<library::LibraryClip>, String> {
+    state.most_played(limit.unwrap_or(20))
+}
+
+#[command]
+fn get_recently_played_clips(
+    state: State<'_, library::LibraryState>,
+    limit: Option<u32>,
+) -> Result<Vec<library::LibraryClip>, String> {
+    state.recently_played(limit.unwrap_or(20))
+}
+
+#[command]
+fn get_normalize_settings(state: State<'_, library::LibraryState>) -> library::NormalizeSettings {
+    state.normalize_settings()
+}
+
+#[command]
+fn set_normalize_settings(
+    state: State<'_, library::LibraryState>,
+    settings: library::NormalizeSettings,
+) -> Result<(), String> {
+    state.set_normalize_settings(settings)
+}
+
+#[command]
+async fn analyze_library_loudness(app: tauri::AppHandle) -> Result<usize, String> {
+    run_blocking(move || app.state::<library::LibraryState>().analyze_loudness()).await
+}
+
+#[command]
+async fn split_recording_on_silence(
+    app: tauri::AppHandle,
+    path: String,
+    options: Option<audio_output::SilenceOptions>,
+    tags: Option<Vec<String>>,
+) -> Result<Vec<library::SplitClip>, String> {
+    run_blocking(move || {
+        app.state::<library::LibraryState>().split_on_silence(
+            std::path::Path::new(&path),
+            &options.unwrap_or_default(),
+            tags.unwrap_or_default(),
+        )
+    })
+    .await
+}
+
+#[command]
+fn start_library_transcode(
+    state: State<'_, library::LibraryState>,
+    request: library::TranscodeRequest,
+) -> Result<(), String> {
+    state.start_transcode(request)
+}
+
+#[command]
+fn cancel_library_transcode(state: State<'_, library::LibraryState>) -> Result<(), String> {
+    state.cancel_transcode()
+}
+
+#[command]
+fn get_board_profiles(state: State<'_, library::LibraryState>) -> Result<Vec<library::BoardProfile>, String> {
+    state.board_profiles()
+}
+
+#[command]
+fn get_active_board_profile(
+    state: State<'_, library::LibraryState>,
+) -> Result<Option<library::BoardProfile>, String> {
+    state.active_board_profile()
+}
+
+#[command]
+fn create_board_profile(
+    state: State<'_, library::LibraryState>,
+    name: String,
+) -> Result<library::BoardProfile, String> {
+    state.create_board_profile(&name)
+}
+
+#[command]
+fn duplicate_board_profile(
+    state: State<'_, library::LibraryState>,
+    id: library::ProfileId,
+    name: Option<String>,
+) -> Result<library::BoardProfile, String> {
+    state.duplicate_board_profile(id, name)
+}
+
+#[command]
+fn update_board_profile(
+    state: State<'_, library::LibraryState>,
+    id: library::ProfileId,
+    update: library::BoardProfileUpdate,
+) -> Result<library::BoardProfile, String> {
+    state.update_board_profile(id, update)
+}
+
+#[command]
+fn switch_board_profile(
+    state: State<'_, library::LibraryState>,
+    id: library::ProfileId,
+) -> Result<library::BoardProfile, String> {
+    state.switch_board_profile(id)
+}
+
+#[command]
+fn delete_board_profile(state: State<'_, library::LibraryState>, id: library::ProfileId) -> Result<(), String> {
+    state.delete_board_profile(id)
+}
+
+#[command]
+async fn export_board_profile(app: tauri::AppHandle, id: library::ProfileId, path: String) -> Result<usize, String> {
+    run_blocking(move || app.state::<library::LibraryState>().export_board(id, std::path::Path::new(&path))).await
+}
+
+#[command]
+async fn import_board_profile(app: tauri::AppHandle, path: String) -> Result<library::BoardImport, String> {
+    run_blocking(move || {
+        let output = app.state::<audio_output::AudioOutputState>();
+        app.state::<library::LibraryState>()
+            .import_board(&output, std::path::Path::new(&path))
+    })
+    .await
+}
+
+#[command]
+fn schedule_clip_play(
+    state: State<'_, library::LibraryState>,
+    clip_id: library::ClipId,
+    at_ms: u64,
+    device_ids: Vec<String>,
+) -> Result<library::ScheduledPlay, String> {
+    state.schedule_play(clip_id, at_ms, device_ids)
+}
+
+#[command]
+fn get_scheduled_plays(state: State<'_, library::LibraryState>) -> Result<Vec<library::ScheduledPlay>, String> {
+    state.scheduled_plays()
+}
+
+#[command]
+fn cancel_scheduled_play(state: State<'_, library::LibraryState>, id: library::ScheduleId) -> Result<(), String> {
+    state.cancel_scheduled_play(id)
+}
+
+#[command]
+fn play_board_slot(
+    library: State<'_, library::LibraryState>,
+    output: State<'_, audio_output::AudioOutputState>,
+    position: u32,
+    options: Option<audio_output::PlaybackOptions>,
+) -> Result<audio_output::SessionId, String> {
+    library.play_board_slot(&output, position, options)
+}
+
+#[command]
+fn release_board_slot(
+    library: State<'_, library::LibraryState>,
+    output: State<'_, audio_output::AudioOutputState>,
+    position: u32,
+) -> Result<(), String> {
+    library.release_board_slot(&output, position)
+}
+
+#[command]
+fn remove_library_clip(state: State<'_, library::LibraryState>, id: library::ClipId) -> Result<(), String> {
+    state.remove_clip(id)
+}
+
+#[command]
+fn get_library_tags(state: State<'_, library::LibraryState>) -> Result<Vec<library::TagCount>, String> {
+    state.tags()
+}
+
+#[command]
+fn find_duplicate_clips(
+    state: State<'_, library::LibraryState>,
+    id: library::ClipId,
+) -> Result<Vec<library::LibraryClip>, String> {
+    state.duplicates(id)
+}
+
+#[command]
+async fn find_similar_clips(app: tauri::AppHandle, id: library::ClipId) -> Result<Vec<library::SimilarClip>, String> {
+    run_blocking(move || app.state::<library::LibraryState>().similar_clips(id)).await
+}
+
+#[command]
+async fn fingerprint_library_clips(app: tauri::AppHandle) -> Result<usize, String> {
+    run_blocking(move || app.state::<library::LibraryState>().fingerprint_library()).await
+}
+
+#[command]
+fn get_watch_folders(state: State<'_, library::LibraryState>) -> Result<Vec<library::WatchFolder>, String> {
+    state.watch_folders()
+}
+
+#[command]
+fn add_watch_folder(
+    state: State<'_, library::LibraryState>,
+    path: String,
+    recursive: Option<bool>,
+) -> Result<library::WatchFolder, String> {
+    state.add_watch_folder(std::path::Path::new(&path), recursive.unwrap_or(true))
+}
+
+#[command]
+fn remove_watch_folder(state: State<'_, library::LibraryState>, path: String) -> Result<(), String> {
+    state.remove_watch_folder(&path)
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_fs::init())
+        .plugin(tauri_plugin_shell::init())
+        .manage(ServerState {
+            child: Mutex::new(None),
+            server_pid: Mutex::new(None),
+            keep_running_on_close: Mutex::new(false),
+        })
+        .manage(audio_capture::AudioCaptureState::new())
+        .manage(audio_output::AudioOutputState::new())
+        .manage(audio_input::AudioInputState::new())
+        .manage(tts::TtsState::new())
+        .manage(library::LibraryState::new())
+        .setup(|app| {
+            #[cfg(desktop)]
+            {
+                app.handle().plugin(tauri_plugin_updater::Builder::new().build())?;
+                app.handle().plugin(tauri_plugin_process::init())?;
+            }
+
+            app.state::<audio_output::AudioOutputState>()
+                .attach_app_handle(app.handle().clone());
+            app.state::<audio_input::AudioInputState>()
+                .attach_app_handle(app.handle().clone());
+            app.state::<tts::TtsState>()
+                .attach_app_handle(app.handle().clone());
+            app.state::<library::LibraryState>()
+                .attach_app_handle(app.handle().clone());
+
+            // Hide title bar icon on Windows
+            #[cfg(windows)]
+            {
+                use windows::Win32::Foundation::HWND;
+                use windows::Win32::UI::WindowsAndMessaging::{SetClassLongPtrW, GCLP_HICON, GCLP_HICONSM};
+                
+                if let Some((_, window)) = app.webview_windows().iter().next() {
+                    if let Ok(hwnd) = window.hwnd() {
+                        let hwnd = HWND(hwnd.0);
+                        unsafe {
+                            // Set both small and regular icons to NULL to hide the title bar icon
+                            SetClassLongPtrW(hwnd, GCLP_HICON, 0);
+                            SetClassLongPtrW(hwnd, GCLP_HICONSM, 0);
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            start_server,
+            stop_server,
+            set_keep_server_running,
+            start_system_audio_capture,
+            stop_system_audio_capture,
+            list_audio_input_devices,
+            start_mic_capture,
+            stop_mic_capture,
+            start_mic_recording,
+            stop_mic_recording,
+            start_mic_monitor,
+            set_mic_monitor_gain,
+            stop_mic_monitor,
+            start_voice_detection,
+            stop_voice_detection,
+            start_lip_sync,
+            stop_lip_sync,
+            set_noise_suppression,
+            set_push_to_talk,
+            set_push_to_talk_held,
+            set_auto_gain,
+            set_echo_cancellation,
+            start_input_meter,
+            stop_input_meter,
+            is_system_audio_supported,
+            list_audio_output_devices,
+            play_audio_to_devices,
+            play_file_to_devices,
+            play_url_to_devices,
+            stop_audio_playback,
+            play_test_tone,
+            stop_playback,
+            queue_audio_to_devices,
+            get_playback_queues,
+            play_playlist,
+            playlist_next,
+            playlist_previous,
+            stop_playlist,
+            get_playlist_status,
+            move_queued_playback,
+            clear_playback_queue,
+            pause_playback,
+            resume_playback,
+            move_playback,
+            set_device_failover,
+            get_playback_status,
+            panic_stop_playback,
+            start_audio_preview,
+            preview_audio_file,
+            seek_audio_preview,
+            stop_audio_preview,
+            set_device_polarity_invert,
+            set_device_channel_swap,
+            set_device_eq,
+            get_device_eq,
+            set_device_compressor,
+            get_device_compressor,
+            list_audio_output_hosts,
+            set_audio_output_host,
+            set_asio_output_enabled,
+            set_jack_output_enabled,
+            get_output_latencies,
+            get_supported_output_configs,
+            get_output_device_config,
+            set_output_device_config,
+            get_clip_trim,
+            set_clip_trim,
+            get_waveform_peaks,
+            get_audio_metadata,
+            splice_audio_files,
+            cut_audio_file,
+            find_sound_regions,
+            set_device_delay,
+            get_device_delay,
+            start_output_recording,
+            stop_output_recording,
+            set_device_mute,
+            set_master_gain,
+            get_master_gain,
+            set_duck_level,
+            get_duck_level,
+            set_playback_sleep_timer,
+            cancel_playback_sleep_timer,
+            configure_tts_provider,
+            list_tts_providers,
+            list_tts_voices,
+            speak_text,
+            export_speech,
+            enqueue_speech,
+            skip_speech,
+            clear_speech_queue,
+            get_speech_queue,
+            detect_text_language,
+            get_language_voices,
+            set_language_voice,
+            get_pronunciations,
+            set_pronunciation,
+            remove_pronunciation,
+            get_tts_templates,
+            set_tts_template,
+            remove_tts_template,
+            render_tts_template,
+            follow_text_file,
+            unfollow_text_file,
+            get_followed_text_files,
+            get_tts_feeds,
+            set_tts_feed,
+            remove_tts_feed,
+            set_tts_feed_interval,
+            import_library_clip,
+            import_library_files,
+            get_library_clip,
+            search_library,
+            update_library_clip,
+            play_library_clip,
+            play_library_clip_from_marker,
+            release_library_clip,
+            record_clip_play,
+            get_play_stats,
+            get_trigger_log,
+            replay_triggers,
+            stop_trigger_replay,
+            get_most_played_clips,
+            get_recently_played_clips,
+            get_normalize_settings,
+            set_normalize_settings,
+            analyze_library_loudness,
+            split_recording_on_silence,
+            start_library_transcode,
+            cancel_library_transcode,
+            get_board_profiles,
+            get_active_board_profile,
+            create_board_profile,
+            duplicate_board_profile,
+            update_board_profile,
+            switch_board_profile,
+            delete_board_profile,
+            export_board_profile,
+            import_board_profile,
+            schedule_clip_play,
+            get_scheduled_plays,
+            cancel_scheduled_play,
+            play_board_slot,
+            release_board_slot,
+            remove_library_clip,
+            get_library_tags,
+            find_duplicate_clips,
+            find_similar_clips,
+            fingerprint_library_clips,
+            get_watch_folders,
+            add_watch_folder,
+            remove_watch_folder
+        ])
+        .on_window_event(|window, event| {
+            if let WindowEvent::CloseRequested { api, .. } = event {
+                // Prevent automatic close
+                api.prevent_close();
+
+                // Emit event to frontend to check setting and stop server if needed
+                let app_handle = window.app_handle();
+
+                if let Err(e) = app_handle.emit("window-close-requested", ()) {
+                    eprintln!("Failed to emit window-close-requested event: {}", e);
+                    // If event emission fails, allow close anyway
+                    window.close().ok();
+                    return;
+                }
+
+                // Set up listener for frontend response
+                let window_for_close = window.clone();
+                let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+
+                // Listen for response from frontend using window's listen method
+                let listener_id = window.listen("window-close-allowed", move |_| {
+                    // Frontend has checked setting and stopped server if needed
+                    // Signal that we can close
+                    let _ = tx.send(());
+                });
+
+                // Wait for frontend response or timeout
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = rx.recv() => {
+                            // Frontend responded, close window
+                            window_for_close.close().ok();
+                        }
+                        _ = tokio::time::sleep(tokio::time::Duration::from_secs(5)) => {
+                            // Timeout - close anyway
+                            eprintln!("Window close timeout, closing anyway");
+                            window_for_close.close().ok();
+                        }
+                    }
+                    // Clean up listener
+                    window_for_close.unlisten(listener_id);
                 });
             }
         })
@@ -824,5 +2110,9 @@ pub fn run() {
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
     run();
 }
@@ -0,0 +1,109 @@
+use super::ssml::{escape_xml, SsmlDocument};
+use super::{send, stream_pcm, SpeechSink, TtsProvider, TtsProviderConfig, TtsProviderKind, TtsRequest, TtsVoice};
+use reqwest::blocking::Client;
+
+const AZURE_OUTPUT_FORMAT: &str = "raw-24khz-16bit-mono-pcm";
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Voice {
+    short_name: String,
+    display_name: String,
+    locale: String,
+    gender: String,
+}
+
+pub(super) struct Azure {
+    client: Client,
+    api_key: String,
+    region: String,
+}
+
+impl Azure {
+    pub(super) fn new(client: Client, config: TtsProviderConfig) -> Result<Self, String> {
+        let region = match config.region {
+            Some(region) if !region.trim().is_empty() => region.trim().to_string(),
+            _ => return Err("Azure AI Speech needs the region of the Speech resource".to_string()),
+        };
+        Ok(Self {
+            client,
+            api_key: config.api_key,
+            region,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("https://{}.tts.speech.microsoft.com/cognitiveservices/{}", self.region, path)
+    }
+}
+
+impl TtsProvider for Azure {
+    fn list_voices(&self) -> Result<Vec<TtsVoice>, String> {
+        let request = self
+            .client
+            .get(self.url("voices/list"))
+            .header("Ocp-Apim-Subscription-Key", &self.api_key);
+        let voices: Vec<Voice> = send(request, TtsProviderKind::Azure)?
+            .json()
+            .map_err(|e| format!("Failed to read Azure voices: {}", e))?;
+        Ok(voices
+            .into_iter()
+            .map(|voice| TtsVoice {
+                id: voice.short_name,
+                name: voice.display_name,
+                language: Some(voice.locale),
+                gender: Some(voice.gender),
+            })
+            .collect())
+    }
+
+    /// Azure takes SSML natively, so the whole document is passed on.
+    fn stream(&self, request: &TtsRequest, document: &SsmlDocument, sink: &mut dyn SpeechSink) -> Result<(), String> {
+        let request = self
+            .client
+            .post(self.url("v1"))
+            .header("Ocp-Apim-Subscription-Key", &self.api_key)
+            .header("Content-Type", "application/ssml+xml")
+            .header("X-Microsoft-OutputFormat", AZURE_OUTPUT_FORMAT)
+            .header("User-Agent", "voicebox")
+            .body(ssml_document(&request.voice_id, &with_prosody(request, document.to_markup())));
+        stream_pcm(send(request, TtsProviderKind::Azure)?, TtsProviderKind::Azure, sink)?;
+        Ok(())
+    }
+
+    fn native_rate(&self, _rate: f32) -> bool {
+        true
+    }
+
+    fn native_pitch(&self) -> bool {
+        true
+    }
+}
+
+/// Wrap markup in a `<prosody>` for the request's rate and pitch, if it sets
+/// them.
+fn with_prosody(request: &TtsRequest, markup: String) -> String {
+    let mut attributes = String::new();
+    if let Some(rate) = request.rate {
+        attributes.push_str(&format!(" rate=\"{:+.0}%\"", (rate - 1.0) * 100.0));
+    }
+    if let Some(semitones) = request.pitch_semitones {
+        attributes.push_str(&format!(" pitch=\"{:+.1}st\"", semitones));
+    }
+    if attributes.is_empty() {
+        return markup;
+    }
+    format!("<prosody{}>{}</prosody>", attributes, markup)
+}
+
+/// Wrap markup in the document Azure expects for `voice`.
+fn ssml_document(voice: &str, markup: &str) -> String {
+    // Voice names start with their locale, e.g. "en-US-JennyNeural"
+    let locale = voice.splitn(3, '-').take(2).collect::<Vec<_>>().join("-");
+    format!(
+        "<speak version='1.0' xmlns='http://www.w3.org/2001/10/synthesis' xml:lang='{}'><voice name='{}'>{}</voice></speak>",
+        escape_xml(&locale),
+        escape_xml(voice),
+        markup
+    )
+}
@@ -0,0 +1,222 @@
+use super::ssml::{Segment, SsmlDocument};
+use super::{
+    send, PcmReader, SpeechSink, TtsProvider, TtsProviderConfig, TtsProviderKind, TtsRequest, TtsVoice,
+    WordBoundary, TTS_SAMPLE_RATE,
+};
+use base64::{engine::general_purpose, Engine as _};
+use reqwest::blocking::Client;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+
+const ELEVENLABS_API: &str = "https://api.elevenlabs.io/v1";
+const ELEVENLABS_DEFAULT_MODEL: &str = "eleven_multilingual_v2";
+
+/// Longest pause a single `<break>` tag may ask ElevenLabs for.
+const ELEVENLABS_MAX_BREAK_MS: u32 = 3000;
+
+/// Speeds ElevenLabs voices accept; faster or slower is time-stretched.
+const ELEVENLABS_MIN_SPEED: f32 = 0.7;
+const ELEVENLABS_MAX_SPEED: f32 = 1.2;
+
+#[derive(serde::Deserialize)]
+struct VoiceList {
+    voices: Vec<Voice>,
+}
+
+#[derive(serde::Deserialize)]
+struct Voice {
+    voice_id: String,
+    name: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+/// One line of the timestamped stream: a piece of audio and the timing of the
+/// characters it speaks.
+#[derive(serde::Deserialize)]
+struct TimestampedChunk {
+    audio_base64: Option<String>,
+    alignment: Option<Alignment>,
+}
+
+#[derive(serde::Deserialize)]
+struct Alignment {
+    characters: Vec<String>,
+    character_start_times_seconds: Vec<f64>,
+    character_end_times_seconds: Vec<f64>,
+}
+
+pub(super) struct ElevenLabs {
+    client: Client,
+    api_key: String,
+    model: String,
+}
+
+impl ElevenLabs {
+    pub(super) fn new(client: Client, config: TtsProviderConfig) -> Self {
+        Self {
+            client,
+            api_key: config.api_key,
+            model: config.model.unwrap_or_else(|| ELEVENLABS_DEFAULT_MODEL.to_string()),
+        }
+    }
+}
+
+impl TtsProvider for ElevenLabs {
+    fn list_voices(&self) -> Result<Vec<TtsVoice>, String> {
+        let request = self
+            .client
+            .get(format!("{}/voices", ELEVENLABS_API))
+            .header("xi-api-key", &self.api_key);
+        let list: VoiceList = send(request, TtsProviderKind::ElevenLabs)?
+            .json()
+            .map_err(|e| format!("Failed to read ElevenLabs voices: {}", e))?;
+        Ok(list
+            .voices
+            .into_iter()
+            .map(|voice| TtsVoice {
+                id: voice.voice_id,
+                name: voice.name,
+                // Only some voices are labelled with a language
+                language: voice.labels.get("language").cloned(),
+                gender: voice.labels.get("gender").cloned(),
+            })
+            .collect())
+    }
+
+    /// ElevenLabs reads `<break>` tags in the text; the rest of the SSML is
+    /// flattened to plain text. The timestamped stream is used so words can be
+    /// followed during playback.
+    fn stream(&self, request: &TtsRequest, document: &SsmlDocument, sink: &mut dyn SpeechSink) -> Result<(), String> {
+        let mut body = serde_json::json!({
+            "text": text_with_breaks(document),
+            "model_id": self.model,
+        });
+        if let Some(rate) = request.rate {
+            body["voice_settings"] = serde_json::json!({ "speed": rate });
+        }
+        let request = self
+            .client
+            .post(format!(
+                "{}/text-to-speech/{}/stream/with-timestamps",
+                ELEVENLABS_API, request.voice_id
+            ))
+            .query(&[("output_format", format!("pcm_{}", TTS_SAMPLE_RATE))])
+            .header("xi-api-key", &self.api_key)
+            .json(&body);
+        let response = send(request, TtsProviderKind::ElevenLabs)?;
+
+        let mut pcm = PcmReader::default();
+        let mut words = WordAligner::default();
+        let mut frames = 0u64;
+        for line in BufReader::new(response).lines() {
+            let line = line.map_err(|e| format!("Failed to read ElevenLabs audio: {}", e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let chunk: TimestampedChunk =
+                serde_json::from_str(&line).map_err(|e| format!("Invalid ElevenLabs response: {}", e))?;
+            if let Some(alignment) = chunk.alignment {
+                words.push(&alignment, frames * 1000 / TTS_SAMPLE_RATE as u64, sink);
+            }
+            if let Some(audio) = chunk.audio_base64 {
+                let bytes = general_purpose::STANDARD
+                    .decode(audio)
+                    .map_err(|e| format!("Invalid ElevenLabs audio: {}", e))?;
+                let samples = pcm.samples(&bytes);
+                frames += samples.len() as u64;
+                if !samples.is_empty() && !sink.audio(samples) {
+                    return Ok(());
+                }
+            }
+        }
+        words.finish_word(sink);
+        Ok(())
+    }
+
+    fn native_rate(&self, rate: f32) -> bool {
+        (ELEVENLABS_MIN_SPEED..=ELEVENLABS_MAX_SPEED).contains(&rate)
+    }
+}
+
+/// The document's text with pauses as ElevenLabs break tags, split into
+/// several where a pause is longer than one tag allows.
+fn text_with_breaks(document: &SsmlDocument) -> String {
+    let mut text = String::new();
+    for segment in document.segments() {
+        match segment {
+            Segment::Text(segment) => text.push_str(&segment),
+            Segment::Pause(mut ms) => {
+                while ms > 0 {
+                    let pause = ms.min(ELEVENLABS_MAX_BREAK_MS);
+                    text.push_str(&format!(" <break time=\"{:.1}s\" /> ", pause as f32 / 1000.0));
+                    ms -= pause;
+                }
+            }
+        }
+    }
+    text
+}
+
+/// Groups the per-character timing ElevenLabs sends into words, leaving out
+/// the break tags in the text.
+#[derive(Default)]
+struct WordAligner {
+    word: String,
+    start_ms: u64,
+    end_ms: u64,
+    in_tag: bool,
+    /// End of the latest character, to tell whether a chunk's times restart
+    last_end_ms: u64,
+}
+
+impl WordAligner {
+    /// Add the characters of a chunk whose audio starts at `chunk_start_ms`.
+    fn push(&mut self, alignment: &Alignment, chunk_start_ms: u64, sink: &mut dyn SpeechSink) {
+        let to_ms = |secs: f64| (secs.max(0.0) * 1000.0).round() as u64;
+        // Some responses time each chunk from its own start rather than from
+        // the start of the audio
+        let offset_ms = match alignment.character_start_times_seconds.first() {
+            Some(&first) if to_ms(first) + 50 < self.last_end_ms => chunk_start_ms,
+            _ => 0,
+        };
+        let timed = alignment
+            .characters
+            .iter()
+            .zip(&alignment.character_start_times_seconds)
+            .zip(&alignment.character_end_times_seconds);
+        for ((characters, &start), &end) in timed {
+            let start_ms = to_ms(start) + offset_ms;
+            let end_ms = to_ms(end) + offset_ms;
+            self.last_end_ms = self.last_end_ms.max(end_ms);
+            for c in characters.chars() {
+                match c {
+                    '<' => {
+                        self.finish_word(sink);
+                        self.in_tag = true;
+                    }
+                    '>' if self.in_tag => self.in_tag = false,
+                    _ if self.in_tag => {}
+                    c if c.is_whitespace() => self.finish_word(sink),
+                    c => {
+                        if self.word.is_empty() {
+                            self.start_ms = start_ms;
+                        }
+                        self.word.push(c);
+                        self.end_ms = end_ms;
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish_word(&mut self, sink: &mut dyn SpeechSink) {
+        if !self.word.is_empty() {
+            sink.word(WordBoundary {
+                text: std::mem::take(&mut self.word),
+                start_ms: self.start_ms,
+                end_ms: self.end_ms,
+            });
+        }
+    }
+}
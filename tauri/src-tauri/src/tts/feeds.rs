@@ -0,0 +1,395 @@
+use super::{TtsPriority, TtsProviderKind, TtsRequest, TtsState};
+use reqwest::blocking::Client;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+
+/// How often the poller checks whether a feed is due.
+const FEED_TICK_SECS: u64 = 5;
+
+/// Allowed range of `FeedSettings::interval_secs`. Most feeds ask not to be
+/// fetched more than every few minutes.
+const MIN_FEED_INTERVAL_SECS: u64 = 60;
+const MAX_FEED_INTERVAL_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_FEED_INTERVAL_SECS: u64 = 5 * 60;
+
+const FEED_REQUEST_TIMEOUT_SECS: u64 = 30;
+
+/// Longest title spoken.
+const MAX_FEED_TITLE_CHARS: usize = 300;
+
+/// An RSS or Atom feed whose new items are read out.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Feed {
+    pub url: String,
+    pub provider: TtsProviderKind,
+    pub voice_id: String,
+    /// Read titles with the voice set for their language, when it can be
+    /// told
+    #[serde(default)]
+    pub auto_voice: bool,
+    pub device_ids: Vec<String>,
+    #[serde(default)]
+    pub priority: TtsPriority,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct FeedSettings {
+    /// Time between fetches of each feed
+    pub interval_secs: u64,
+    pub feeds: Vec<Feed>,
+}
+
+impl Default for FeedSettings {
+    fn default() -> Self {
+        Self {
+            interval_secs: DEFAULT_FEED_INTERVAL_SECS,
+            feeds: Vec::new(),
+        }
+    }
+}
+
+/// The user's feeds.
+#[derive(Default)]
+pub(super) struct FeedStore {
+    path: Option<PathBuf>,
+    settings: FeedSettings,
+}
+
+impl FeedStore {
+    /// Load the feeds from `path`. A missing or unreadable file starts empty.
+    pub(super) fn load(path: PathBuf) -> Self {
+        let settings = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Ignoring invalid feed file {:?}: {}", path, e);
+                FeedSettings::default()
+            }),
+            Err(_) => FeedSettings::default(),
+        };
+        Self {
+            path: Some(path),
+            settings,
+        }
+    }
+
+    pub(super) fn settings(&self) -> FeedSettings {
+        self.settings.clone()
+    }
+
+    /// Add a feed, replacing any existing one with the same URL.
+    pub(super) fn set(&mut self, feed: Feed) -> Result<(), String> {
+        let url = feed.url.trim();
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(format!("Feed URL must start with http:// or https://: {}", url));
+        }
+        let feed = Feed {
+            url: url.to_string(),
+            ..feed
+        };
+        match self.settings.feeds.iter_mut().find(|existing| existing.url == feed.url) {
+            Some(existing) => *existing = feed,
+            None => self.settings.feeds.push(feed),
+        }
+        self.save()
+    }
+
+    pub(super) fn remove(&mut self, url: &str) -> Result<(), String> {
+        let before = self.settings.feeds.len();
+        self.settings.feeds.retain(|feed| feed.url != url.trim());
+        if self.settings.feeds.len() == before {
+            return Err(format!("No feed {}", url));
+        }
+        self.save()
+    }
+
+    pub(super) fn set_interval(&mut self, interval_secs: u64) -> Result<(), String> {
+        if !(MIN_FEED_INTERVAL_SECS..=MAX_FEED_INTERVAL_SECS).contains(&interval_secs) {
+            return Err(format!(
+                "Feed interval must be between {} and {} seconds",
+                MIN_FEED_INTERVAL_SECS, MAX_FEED_INTERVAL_SECS
+            ));
+        }
+        self.settings.interval_secs = interval_secs;
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+        let contents = serde_json::to_string_pretty(&self.settings)
+            .map_err(|e| format!("Failed to serialize feeds: {}", e))?;
+        std::fs::write(path, contents).map_err(|e| format!("Failed to save feeds: {}", e))
+    }
+}
+
+/// An entry of a feed.
+#[derive(Debug, Clone, PartialEq)]
+struct FeedItem {
+    /// guid or id if the feed gives one, otherwise the link or title
+    id: String,
+    title: String,
+}
+
+/// Fetch each feed every `interval_secs` until the app shuts down, queueing
+/// the titles of items that weren't there the time before. Items already in
+/// a feed when it is first fetched aren't read.
+pub(super) fn spawn_feed_poller(app: AppHandle) {
+    std::thread::spawn(move || {
+        let client = match Client::builder()
+            .timeout(Duration::from_secs(FEED_REQUEST_TIMEOUT_SECS))
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("Failed to create HTTP client, feeds won't be read: {}", e);
+                return;
+            }
+        };
+        let mut seen: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut fetched_at: HashMap<String, Instant> = HashMap::new();
+        loop {
+            std::thread::sleep(Duration::from_secs(FEED_TICK_SECS));
+            let settings = app.state::<TtsState>().feeds.lock().unwrap().settings();
+            let interval = Duration::from_secs(settings.interval_secs);
+            seen.retain(|url, _| settings.feeds.iter().any(|feed| &feed.url == url));
+            fetched_at.retain(|url, _| settings.feeds.iter().any(|feed| &feed.url == url));
+
+            for feed in &settings.feeds {
+                if fetched_at.get(&feed.url).is_some_and(|at| at.elapsed() < interval) {
+                    continue;
+                }
+                fetched_at.insert(feed.url.clone(), Instant::now());
+                let items = match fetch_feed(&client, &feed.url) {
+                    Ok(items) => items,
+                    Err(e) => {
+                        eprintln!("Failed to read feed {}: {}", feed.url, e);
+                        continue;
+                    }
+                };
+                let ids: HashSet<String> = items.iter().map(|item| item.id.clone()).collect();
+                if let Some(previous) = seen.get(&feed.url) {
+                    // Feeds list newest first; read them oldest first
+                    for item in items.iter().rev().filter(|item| !previous.contains(&item.id)) {
+                        speak_item(&app, feed, item);
+                    }
+                }
+                seen.insert(feed.url.clone(), ids);
+            }
+        }
+    });
+}
+
+fn fetch_feed(client: &Client, url: &str) -> Result<Vec<FeedItem>, String> {
+    let response = client
+        .get(url)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let body = response.text().map_err(|e| e.to_string())?;
+    Ok(parse_feed(&body))
+}
+
+fn speak_item(app: &AppHandle, feed: &Feed, item: &FeedItem) {
+    let request = TtsRequest {
+        provider: feed.provider,
+        voice_id: feed.voice_id.clone(),
+        text: item.title.chars().take(MAX_FEED_TITLE_CHARS).collect(),
+        ssml: false,
+        rate: None,
+        pitch_semitones: None,
+        volume_db: None,
+        auto_voice: feed.auto_voice,
+        template: None,
+        values: HashMap::new(),
+    };
+    let tts = app.state::<TtsState>();
+    if let Err(e) = tts.enqueue(request, feed.device_ids.clone(), None, feed.priority) {
+        eprintln!("Failed to queue item from {}: {}", feed.url, e);
+    }
+}
+
+/// The items of an RSS `<item>` or Atom `<entry>` feed, in document order.
+/// Items without a title are skipped.
+fn parse_feed(xml: &str) -> Vec<FeedItem> {
+    let mut items = Vec::new();
+    let mut rest = xml;
+    while let Some((body, after)) = next_element(rest, &["item", "entry"]) {
+        rest = after;
+        let title = match element_text(body, "title") {
+            Some(title) if !title.is_empty() => title,
+            _ => continue,
+        };
+        let id = element_text(body, "guid")
+            .or_else(|| element_text(body, "id"))
+            .or_else(|| element_text(body, "link"))
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| title.clone());
+        items.push(FeedItem { id, title });
+    }
+    items
+}
+
+/// The first element named one of `names` in `xml`: its content and the
+/// text after it.
+fn next_element<'a>(xml: &'a str, names: &[&str]) -> Option<(&'a str, &'a str)> {
+    let mut offset = 0;
+    while let Some(start) = xml[offset..].find('<') {
+        let start = offset + start;
+        let tag = &xml[start + 1..];
+        offset = start + 1;
+        let name = match names.iter().find(|name| {
+            tag.starts_with(**name)
+                && tag[name.len()..].starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace())
+        }) {
+            Some(name) => *name,
+            None => continue,
+        };
+        let open_end = start + 1 + tag.find('>')?;
+        if xml[..open_end].ends_with('/') {
+            // Self-closing, so empty
+            return Some(("", &xml[open_end + 1..]));
+        }
+        let close = format!("</{}>", name);
+        let body_start = open_end + 1;
+        let body_end = body_start + xml[body_start..].find(&close)?;
+        return Some((&xml[body_start..body_end], &xml[body_end + close.len()..]));
+    }
+    None
+}
+
+/// Text of the first `name` element in `xml`, with CDATA unwrapped, entities
+/// decoded, markup removed and whitespace collapsed.
+fn element_text(xml: &str, name: &str) -> Option<String> {
+    let (body, _) = next_element(xml, &[name])?;
+    let body = body.trim();
+    let text = match body.strip_prefix("<![CDATA[").and_then(|body| body.strip_suffix("]]>")) {
+        Some(cdata) => cdata.to_string(),
+        None => decode_entities(body),
+    };
+    // Atom titles with type="html" hold escaped markup
+    let text = strip_tags(&text);
+    Some(text.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        let after = &rest[amp + 1..];
+        let decoded = after.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &after[..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => entity
+                        .strip_prefix('#')
+                        .and_then(|decimal| decimal.parse().ok())
+                        .and_then(char::from_u32),
+                },
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn strip_tags(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                out.push(' ');
+            }
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_rss_items() {
+        let xml = r#"<?xml version="1.0"?>
+            <rss version="2.0"><channel><title>Channel</title>
+            <item><title>First &amp; best</title><guid isPermaLink="false">a1</guid></item>
+            <item><title><![CDATA[Second <b>post</b>]]></title><link>https://example.com/2</link></item>
+            </channel></rss>"#;
+        assert_eq!(
+            parse_feed(xml),
+            vec![
+                FeedItem {
+                    id: "a1".to_string(),
+                    title: "First & best".to_string(),
+                },
+                FeedItem {
+                    id: "https://example.com/2".to_string(),
+                    title: "Second post".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_atom_entries() {
+        let xml = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>Feed</title>
+            <entry><title type="html">Caf&#233; &lt;i&gt;opens&lt;/i&gt;</title>
+            <link href="https://example.com/e"/><id>tag:example.com,2024:1</id></entry>
+            </feed>"#;
+        assert_eq!(
+            parse_feed(xml),
+            vec![FeedItem {
+                id: "tag:example.com,2024:1".to_string(),
+                title: "Café opens".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn skips_items_without_titles() {
+        let xml = "<rss><channel><item><guid>x</guid></item><item><title>  </title></item></channel></rss>";
+        assert!(parse_feed(xml).is_empty());
+    }
+
+    #[test]
+    fn does_not_mistake_longer_tag_names() {
+        let xml = "<rss><channel><itemCount>2</itemCount><item><title>Only</title></item></channel></rss>";
+        assert_eq!(parse_feed(xml).len(), 1);
+    }
+
+    #[test]
+    fn leaves_unknown_entities() {
+        assert_eq!(decode_entities("AT&T &nbsp; &#65;"), "AT&T &nbsp; A");
+    }
+}
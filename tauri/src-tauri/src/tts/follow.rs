@@ -0,0 +1,171 @@
+use super::{TtsPriority, TtsProviderKind, TtsRequest, TtsState};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+/// How often a followed file is checked for new lines.
+const FOLLOW_POLL_MS: u64 = 250;
+
+/// Longest line spoken; the rest is dropped so a runaway log line can't
+/// hold the queue for minutes.
+const MAX_FOLLOW_LINE_CHARS: usize = 500;
+
+/// Bytes kept of a line that hasn't ended yet. A file that never writes a
+/// newline is dropped rather than buffered without bound.
+const MAX_PARTIAL_LINE_BYTES: usize = 64 * 1024;
+
+/// A text file whose new lines are read out, such as a game log or chat dump.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FollowedFile {
+    pub path: String,
+    pub provider: TtsProviderKind,
+    pub voice_id: String,
+    /// Read each line with the voice set for its language, when it can be
+    /// told
+    #[serde(default)]
+    pub auto_voice: bool,
+    pub device_ids: Vec<String>,
+    #[serde(default)]
+    pub priority: TtsPriority,
+    /// Only lines matching one of these are spoken, if any are given
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Lines matching any of these are skipped
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// A file being followed and the flag that stops its thread.
+pub(super) struct Follower {
+    pub(super) file: FollowedFile,
+    stop: Arc<AtomicBool>,
+}
+
+impl Follower {
+    pub(super) fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Which lines of a followed file are spoken.
+struct LineFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl LineFilter {
+    fn new(file: &FollowedFile) -> Result<Self, String> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| Regex::new(pattern).map_err(|e| format!("Invalid filter {}: {}", pattern, e)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            include: compile(&file.include)?,
+            exclude: compile(&file.exclude)?,
+        })
+    }
+
+    fn accepts(&self, line: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|regex| regex.is_match(line)))
+            && !self.exclude.iter().any(|regex| regex.is_match(line))
+    }
+}
+
+/// Start reading lines appended to `file.path` from now on into the speech
+/// queue, until the returned follower is stopped.
+pub(super) fn spawn_follower(app: AppHandle, file: FollowedFile) -> Result<Follower, String> {
+    let filter = LineFilter::new(&file)?;
+    let path = Path::new(&file.path).to_path_buf();
+    let mut reader = File::open(&path).map_err(|e| format!("Failed to open {}: {}", file.path, e))?;
+    let mut position = reader
+        .seek(SeekFrom::End(0))
+        .map_err(|e| format!("Failed to read {}: {}", file.path, e))?;
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let thread_stop = stop.clone();
+    let thread_file = file.clone();
+    std::thread::spawn(move || {
+        let file = thread_file;
+        let mut partial = Vec::new();
+        let mut buffer = Vec::new();
+        while !thread_stop.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(FOLLOW_POLL_MS));
+            let len = match std::fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                // Rotated away; wait for it to come back
+                Err(_) => continue,
+            };
+            if len < position {
+                // Truncated or replaced, so start again from the top
+                reader = match File::open(&path) {
+                    Ok(reader) => reader,
+                    Err(_) => continue,
+                };
+                position = 0;
+                partial.clear();
+            }
+            if len == position {
+                continue;
+            }
+
+            buffer.clear();
+            let read = reader
+                .seek(SeekFrom::Start(position))
+                .and_then(|_| (&mut reader).take(len - position).read_to_end(&mut buffer));
+            match read {
+                Ok(count) => position += count as u64,
+                Err(e) => {
+                    eprintln!("Failed to read {}: {}", file.path, e);
+                    continue;
+                }
+            }
+            partial.extend_from_slice(&buffer);
+            let complete = match partial.iter().rposition(|byte| *byte == b'\n') {
+                Some(end) => partial.drain(..=end).collect::<Vec<u8>>(),
+                None => {
+                    if partial.len() > MAX_PARTIAL_LINE_BYTES {
+                        partial.clear();
+                    }
+                    continue;
+                }
+            };
+            for line in String::from_utf8_lossy(&complete).lines() {
+                speak_line(&app, &file, &filter, line);
+            }
+        }
+        eprintln!("Stopped following {}", file.path);
+    });
+
+    Ok(Follower { file, stop })
+}
+
+fn speak_line(app: &AppHandle, file: &FollowedFile, filter: &LineFilter, line: &str) {
+    let line = line.trim();
+    if line.is_empty() || !filter.accepts(line) {
+        return;
+    }
+    let request = TtsRequest {
+        provider: file.provider,
+        voice_id: file.voice_id.clone(),
+        text: line.chars().take(MAX_FOLLOW_LINE_CHARS).collect(),
+        ssml: false,
+        rate: None,
+        pitch_semitones: None,
+        volume_db: None,
+        auto_voice: file.auto_voice,
+        template: None,
+        values: HashMap::new(),
+    };
+    let tts = app.state::<TtsState>();
+    if let Err(e) = tts.enqueue(request, file.device_ids.clone(), None, file.priority) {
+        eprintln!("Failed to queue line from {}: {}", file.path, e);
+    }
+}
@@ -0,0 +1,171 @@
+use super::TtsProviderKind;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use whatlang::Lang;
+
+/// Confidence a detection needs before a voice is picked by it. whatlang's own
+/// reliability check rejects most chat-length messages.
+const MIN_LANGUAGE_CONFIDENCE: f64 = 0.5;
+
+/// The language of a piece of text, as far as it can be told.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DetectedLanguage {
+    /// ISO 639-1 code such as "en"
+    pub language: String,
+    /// English name of the language
+    pub name: String,
+    pub confidence: f64,
+    /// Whether the guess is sure enough to pick a voice by. Short messages
+    /// often aren't.
+    pub reliable: bool,
+}
+
+/// The voice a language is read with when its language is detected.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LanguageVoice {
+    pub provider: TtsProviderKind,
+    pub voice_id: String,
+}
+
+pub(super) fn detect(text: &str) -> Option<DetectedLanguage> {
+    let info = whatlang::detect(text)?;
+    Some(DetectedLanguage {
+        language: language_code(info.lang()).to_string(),
+        name: info.lang().eng_name().to_string(),
+        confidence: info.confidence(),
+        reliable: info.confidence() >= MIN_LANGUAGE_CONFIDENCE,
+    })
+}
+
+/// Two-letter code of a language, which is how voice locales such as
+/// "en-US" start.
+fn language_code(lang: Lang) -> &'static str {
+    match lang {
+        Lang::Afr => "af",
+        Lang::Aka => "ak",
+        Lang::Amh => "am",
+        Lang::Ara => "ar",
+        Lang::Aze => "az",
+        Lang::Bel => "be",
+        Lang::Ben => "bn",
+        Lang::Bul => "bg",
+        Lang::Cat => "ca",
+        Lang::Ces => "cs",
+        Lang::Cmn => "zh",
+        Lang::Dan => "da",
+        Lang::Deu => "de",
+        Lang::Ell => "el",
+        Lang::Eng => "en",
+        Lang::Epo => "eo",
+        Lang::Est => "et",
+        Lang::Fin => "fi",
+        Lang::Fra => "fr",
+        Lang::Guj => "gu",
+        Lang::Heb => "he",
+        Lang::Hin => "hi",
+        Lang::Hrv => "hr",
+        Lang::Hun => "hu",
+        Lang::Hye => "hy",
+        Lang::Ind => "id",
+        Lang::Ita => "it",
+        Lang::Jav => "jv",
+        Lang::Jpn => "ja",
+        Lang::Kan => "kn",
+        Lang::Kat => "ka",
+        Lang::Khm => "km",
+        Lang::Kor => "ko",
+        Lang::Lat => "la",
+        Lang::Lav => "lv",
+        Lang::Lit => "lt",
+        Lang::Mal => "ml",
+        Lang::Mar => "mr",
+        Lang::Mkd => "mk",
+        Lang::Mya => "my",
+        Lang::Nep => "ne",
+        Lang::Nld => "nl",
+        Lang::Nob => "nb",
+        Lang::Ori => "or",
+        Lang::Pan => "pa",
+        Lang::Pes => "fa",
+        Lang::Pol => "pl",
+        Lang::Por => "pt",
+        Lang::Ron => "ro",
+        Lang::Rus => "ru",
+        Lang::Sin => "si",
+        Lang::Slk => "sk",
+        Lang::Slv => "sl",
+        Lang::Sna => "sn",
+        Lang::Spa => "es",
+        Lang::Srp => "sr",
+        Lang::Swe => "sv",
+        Lang::Tam => "ta",
+        Lang::Tel => "te",
+        Lang::Tgl => "tl",
+        Lang::Tha => "th",
+        Lang::Tuk => "tk",
+        Lang::Tur => "tr",
+        Lang::Ukr => "uk",
+        Lang::Urd => "ur",
+        Lang::Uzb => "uz",
+        Lang::Vie => "vi",
+        Lang::Yid => "yi",
+        Lang::Zul => "zu",
+    }
+}
+
+/// The user's default voice for each language, keyed by language code.
+#[derive(Default)]
+pub(super) struct LanguageVoiceStore {
+    path: Option<PathBuf>,
+    voices: HashMap<String, LanguageVoice>,
+}
+
+impl LanguageVoiceStore {
+    /// Load the defaults from `path`. A missing or unreadable file starts
+    /// empty.
+    pub(super) fn load(path: PathBuf) -> Self {
+        let voices = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Ignoring invalid language voice file {:?}: {}", path, e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path: Some(path),
+            voices,
+        }
+    }
+
+    pub(super) fn get(&self, language: &str) -> Option<LanguageVoice> {
+        self.voices.get(language).cloned()
+    }
+
+    pub(super) fn all(&self) -> HashMap<String, LanguageVoice> {
+        self.voices.clone()
+    }
+
+    /// Set or clear the default voice for a language and write the file.
+    pub(super) fn set(&mut self, language: &str, voice: Option<LanguageVoice>) -> Result<(), String> {
+        let language = language.trim().to_ascii_lowercase();
+        if language.is_empty() {
+            return Err("Language code is empty".to_string());
+        }
+        match voice {
+            Some(voice) => self.voices.insert(language, voice),
+            None => self.voices.remove(&language),
+        };
+
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+        let contents = serde_json::to_string_pretty(&self.voices)
+            .map_err(|e| format!("Failed to serialize language voices: {}", e))?;
+        std::fs::write(path, contents).map_err(|e| format!("Failed to save language voices: {}", e))
+    }
+}
@@ -0,0 +1,774 @@
+mod azure;
+mod elevenlabs;
+mod feeds;
+mod follow;
+mod language;
+mod openai;
+mod pronunciation;
+mod queue;
+mod ssml;
+mod template;
+mod timeline;
+
+use crate::audio_input::viseme::{VisemeAnalyzer, VisemeChange};
+use crate::audio_output::record::{self, RecordingFormat};
+use crate::audio_output::{AudioOutputState, PlaybackOptions, SessionId};
+pub use feeds::{Feed, FeedSettings};
+pub use follow::FollowedFile;
+pub use language::{DetectedLanguage, LanguageVoice};
+pub use pronunciation::PronunciationEntry;
+pub use queue::{SpeechQueueStatus, TtsPriority};
+pub use template::TtsTemplate;
+use feeds::FeedStore;
+use follow::Follower;
+use language::LanguageVoiceStore;
+use pronunciation::PronunciationStore;
+use queue::SpeechQueue;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use ssml::{Segment, SsmlDocument};
+use template::TemplateStore;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tokio::sync::oneshot;
+
+/// Rate every provider is asked to synthesize at. All of them can return raw
+/// 16-bit mono PCM at 24 kHz, which needs no decoding.
+const TTS_SAMPLE_RATE: u32 = 24_000;
+
+/// Longest a provider gets to send a whole response. Audio is streamed, so
+/// this covers synthesizing the entire message.
+const TTS_REQUEST_TIMEOUT_SECS: u64 = 300;
+
+/// Bytes read from a provider's response at a time.
+const TTS_READ_BYTES: usize = 8192;
+
+/// Range of `TtsRequest::rate`, matching what playback can time-stretch.
+const MIN_SPEECH_RATE: f32 = 0.5;
+const MAX_SPEECH_RATE: f32 = 2.0;
+const MAX_SPEECH_PITCH_SEMITONES: f32 = 12.0;
+const MIN_SPEECH_VOLUME_DB: f32 = -40.0;
+const MAX_SPEECH_VOLUME_DB: f32 = 12.0;
+
+/// A cloud text-to-speech service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TtsProviderKind {
+    #[serde(rename = "elevenlabs")]
+    ElevenLabs,
+    Azure,
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+impl TtsProviderKind {
+    const ALL: [TtsProviderKind; 3] = [TtsProviderKind::ElevenLabs, TtsProviderKind::Azure, TtsProviderKind::OpenAi];
+
+    fn name(self) -> &'static str {
+        match self {
+            TtsProviderKind::ElevenLabs => "ElevenLabs",
+            TtsProviderKind::Azure => "Azure AI Speech",
+            TtsProviderKind::OpenAi => "OpenAI",
+        }
+    }
+}
+
+/// Credentials and options for one provider.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TtsProviderConfig {
+    pub api_key: String,
+    /// Azure region of the Speech resource, e.g. "westeurope"
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Model to synthesize with instead of the provider's default
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TtsProviderStatus {
+    pub provider: TtsProviderKind,
+    pub name: String,
+    pub configured: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TtsVoice {
+    pub id: String,
+    pub name: String,
+    /// BCP 47 tag such as "en-US", when the provider says
+    pub language: Option<String>,
+    pub gender: Option<String>,
+}
+
+/// Text to synthesize and the voice to speak it with.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TtsRequest {
+    pub provider: TtsProviderKind,
+    pub voice_id: String,
+    pub text: String,
+    /// `text` is an SSML document. Providers that only take plain text get
+    /// its words, with breaks turned into silence.
+    #[serde(default)]
+    pub ssml: bool,
+    /// Speaking rate, 0.5-2.0 (default 1.0)
+    #[serde(default)]
+    pub rate: Option<f32>,
+    /// Pitch shift, up to +/-12 semitones
+    #[serde(default)]
+    pub pitch_semitones: Option<f32>,
+    /// Volume change, -40 to +12 dB
+    #[serde(default)]
+    pub volume_db: Option<f32>,
+    /// Detect the language of the text and read it with the voice set for
+    /// that language. `provider` and `voice_id` are used when the language
+    /// can't be told or has no voice set.
+    #[serde(default)]
+    pub auto_voice: bool,
+    /// Speak this saved template instead of `text`, with its placeholders
+    /// filled in from `values`
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+}
+
+/// Where an exported message was written.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SpeechExport {
+    pub path: String,
+    pub duration_ms: u64,
+}
+
+/// When a word is spoken, counted from the start of the synthesized audio.
+#[derive(Debug, Clone, PartialEq)]
+struct WordBoundary {
+    text: String,
+    start_ms: u64,
+    end_ms: u64,
+}
+
+/// Receives synthesized audio, and timing from engines that report it, as it
+/// arrives.
+trait SpeechSink {
+    /// 16-bit mono samples at `TTS_SAMPLE_RATE`. Returns false to stop
+    /// synthesis.
+    fn audio(&mut self, samples: &[i16]) -> bool;
+
+    fn word(&mut self, _word: WordBoundary) {}
+}
+
+/// One provider's API. Calls block on the network, so they are made off the
+/// async runtime.
+trait TtsProvider: Send + Sync {
+    fn list_voices(&self) -> Result<Vec<TtsVoice>, String>;
+
+    /// Synthesize `document`, handing the audio to `sink` as it streams in,
+    /// along with word timing if the engine reports it. Stopping early
+    /// through the sink is not an error.
+    fn stream(&self, request: &TtsRequest, document: &SsmlDocument, sink: &mut dyn SpeechSink) -> Result<(), String>;
+
+    /// Whether the engine can speak at `rate` itself. Otherwise the request
+    /// reaches `stream` without a rate and playback time-stretches it.
+    fn native_rate(&self, _rate: f32) -> bool {
+        false
+    }
+
+    /// Whether the engine can shift pitch itself. Otherwise playback does.
+    fn native_pitch(&self) -> bool {
+        false
+    }
+}
+
+fn create_provider(kind: TtsProviderKind, config: TtsProviderConfig) -> Result<Arc<dyn TtsProvider>, String> {
+    if config.api_key.trim().is_empty() {
+        return Err(format!("{} needs an API key", kind.name()));
+    }
+    let client = Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(TTS_REQUEST_TIMEOUT_SECS))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    Ok(match kind {
+        TtsProviderKind::ElevenLabs => Arc::new(elevenlabs::ElevenLabs::new(client, config)),
+        TtsProviderKind::Azure => Arc::new(azure::Azure::new(client, config)?),
+        TtsProviderKind::OpenAi => Arc::new(openai::OpenAi::new(client, config)),
+    })
+}
+
+/// Speak a document through an engine that only takes plain text, one run of
+/// text at a time, inserting silence for the pauses between them. `speak`
+/// returns false once the sink has stopped synthesis.
+fn stream_segments<F>(document: &SsmlDocument, sink: &mut dyn SpeechSink, mut speak: F) -> Result<(), String>
+where
+    F: FnMut(&str, &mut dyn SpeechSink) -> Result<bool, String>,
+{
+    for segment in document.segments() {
+        let more = match segment {
+            Segment::Text(text) => speak(text.trim(), sink)?,
+            Segment::Pause(ms) => sink.audio(&vec![0; ms as usize * TTS_SAMPLE_RATE as usize / 1000]),
+        };
+        if !more {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Send a request and fail with the provider's own message on an error status.
+fn send(request: RequestBuilder, provider: TtsProviderKind) -> Result<Response, String> {
+    let response = request
+        .send()
+        .map_err(|e| format!("{} request failed: {}", provider.name(), e))?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().unwrap_or_default();
+    Err(format!("{} returned {}: {}", provider.name(), status, body.trim()))
+}
+
+/// Pass a raw little-endian 16-bit PCM response body to `sink` as it is
+/// received. Returns false if the sink stopped it.
+fn stream_pcm(mut response: Response, provider: TtsProviderKind, sink: &mut dyn SpeechSink) -> Result<bool, String> {
+    let mut buffer = vec![0u8; TTS_READ_BYTES];
+    let mut pcm = PcmReader::default();
+    loop {
+        let read = response
+            .read(&mut buffer)
+            .map_err(|e| format!("Failed to read {} audio: {}", provider.name(), e))?;
+        if read == 0 {
+            return Ok(true);
+        }
+        let samples = pcm.samples(&buffer[..read]);
+        if !samples.is_empty() && !sink.audio(samples) {
+            return Ok(false);
+        }
+    }
+}
+
+/// Turns little-endian 16-bit PCM arriving in arbitrary pieces into samples.
+#[derive(Default)]
+struct PcmReader {
+    /// A sample split across two pieces
+    carry: Option<u8>,
+    samples: Vec<i16>,
+}
+
+impl PcmReader {
+    fn samples(&mut self, bytes: &[u8]) -> &[i16] {
+        self.samples.clear();
+        let mut bytes = bytes.iter().copied();
+        if let Some(low) = self.carry.take() {
+            match bytes.next() {
+                Some(high) => self.samples.push(i16::from_le_bytes([low, high])),
+                None => self.carry = Some(low),
+            }
+        }
+        loop {
+            match (bytes.next(), bytes.next()) {
+                (Some(low), Some(high)) => self.samples.push(i16::from_le_bytes([low, high])),
+                (Some(low), None) => self.carry = Some(low),
+                _ => break,
+            }
+        }
+        &self.samples
+    }
+}
+
+/// Audio and timing of a message being synthesized.
+struct Synthesis {
+    audio: mpsc::Receiver<Result<Vec<f32>, String>>,
+    words: mpsc::Receiver<WordBoundary>,
+    /// Mouth shapes found in the audio, for lip-sync
+    visemes: mpsc::Receiver<VisemeChange>,
+    /// Playback options, including the controls the engine left to playback
+    options: PlaybackOptions,
+}
+
+/// Passes synthesis on to playback or an export as it arrives.
+struct ChannelSink {
+    audio: mpsc::Sender<Result<Vec<f32>, String>>,
+    words: mpsc::Sender<WordBoundary>,
+    visemes: mpsc::Sender<VisemeChange>,
+    analyzer: VisemeAnalyzer,
+    /// Signalled with the first audio
+    started: Option<oneshot::Sender<Result<(), String>>>,
+}
+
+impl SpeechSink for ChannelSink {
+    fn audio(&mut self, samples: &[i16]) -> bool {
+        if let Some(started) = self.started.take() {
+            let _ = started.send(Ok(()));
+        }
+        let chunk: Vec<f32> = samples.iter().map(|&sample| sample as f32 / 32768.0).collect();
+        for change in self.analyzer.process(&chunk) {
+            let _ = self.visemes.send(change);
+        }
+        // Fails once playback or the export has stopped
+        self.audio.send(Ok(chunk)).is_ok()
+    }
+
+    fn word(&mut self, word: WordBoundary) {
+        let _ = self.words.send(word);
+    }
+}
+
+/// Cloud text-to-speech. Providers are configured with their API keys at
+/// runtime; the keys are kept in memory only.
+pub struct TtsState {
+    providers: Mutex<HashMap<TtsProviderKind, Arc<dyn TtsProvider>>>,
+    queue: Mutex<SpeechQueue>,
+    pronunciations: Mutex<PronunciationStore>,
+    language_voices: Mutex<LanguageVoiceStore>,
+    templates: Mutex<TemplateStore>,
+    /// Text files whose new lines are being read out, keyed by path
+    followers: Mutex<HashMap<String, Follower>>,
+    feeds: Mutex<FeedStore>,
+    app_handle: Mutex<Option<AppHandle>>,
+}
+
+impl TtsState {
+    pub fn new() -> Self {
+        Self {
+            providers: Mutex::new(HashMap::new()),
+            queue: Mutex::new(SpeechQueue::default()),
+            pronunciations: Mutex::new(PronunciationStore::default()),
+            language_voices: Mutex::new(LanguageVoiceStore::default()),
+            templates: Mutex::new(TemplateStore::default()),
+            followers: Mutex::new(HashMap::new()),
+            feeds: Mutex::new(FeedStore::default()),
+            app_handle: Mutex::new(None),
+        }
+    }
+
+    /// Load the pronunciation dictionary, language voices, templates and
+    /// feeds, and start speaking queued messages and new feed items.
+    pub fn attach_app_handle(&self, app: AppHandle) {
+        match app.path().app_data_dir() {
+            Ok(dir) => {
+                *self.pronunciations.lock().unwrap() = PronunciationStore::load(dir.join("pronunciations.json"));
+                *self.language_voices.lock().unwrap() = LanguageVoiceStore::load(dir.join("language_voices.json"));
+                *self.templates.lock().unwrap() = TemplateStore::load(dir.join("tts_templates.json"));
+                *self.feeds.lock().unwrap() = FeedStore::load(dir.join("feeds.json"));
+            }
+            Err(e) => eprintln!("Failed to get app data dir, pronunciations won't be saved: {}", e),
+        }
+        *self.app_handle.lock().unwrap() = Some(app.clone());
+        queue::spawn_queue_worker(app.clone());
+        feeds::spawn_feed_poller(app);
+    }
+
+    /// Set up a provider, replacing its previous configuration, or remove it
+    /// with `None`.
+    pub fn configure_provider(&self, kind: TtsProviderKind, config: Option<TtsProviderConfig>) -> Result<(), String> {
+        let mut providers = self.providers.lock().unwrap();
+        match config {
+            Some(config) => {
+                providers.insert(kind, create_provider(kind, config)?);
+            }
+            None => {
+                providers.remove(&kind);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn list_providers(&self) -> Vec<TtsProviderStatus> {
+        let providers = self.providers.lock().unwrap();
+        TtsProviderKind::ALL
+            .iter()
+            .map(|&kind| TtsProviderStatus {
+                provider: kind,
+                name: kind.name().to_string(),
+                configured: providers.contains_key(&kind),
+            })
+            .collect()
+    }
+
+    fn provider(&self, kind: TtsProviderKind) -> Result<Arc<dyn TtsProvider>, String> {
+        self.providers
+            .lock()
+            .unwrap()
+            .get(&kind)
+            .cloned()
+            .ok_or_else(|| format!("{} is not configured", kind.name()))
+    }
+
+    pub async fn list_voices(&self, kind: TtsProviderKind) -> Result<Vec<TtsVoice>, String> {
+        let provider = self.provider(kind)?;
+        tauri::async_runtime::spawn_blocking(move || provider.list_voices())
+            .await
+            .map_err(|e| format!("Failed to list voices: {}", e))?
+    }
+
+    /// Synthesize `request` and play it on `device_ids` like any other clip.
+    /// Playback starts as soon as the provider sends the first audio, while
+    /// the rest is still being synthesized.
+    pub async fn speak(
+        &self,
+        output: &AudioOutputState,
+        request: TtsRequest,
+        device_ids: Vec<String>,
+        options: Option<PlaybackOptions>,
+    ) -> Result<SessionId, String> {
+        let request = self.resolve_template(request)?;
+        eprintln!(
+            "speak: {} characters with {} voice {}",
+            request.text.chars().count(),
+            request.provider.name(),
+            request.voice_id
+        );
+        let synthesis = self.synthesize(request, options).await?;
+        let speed = synthesis.options.speed();
+        // Clips on the same devices go under the message until it ends
+        let options = PlaybackOptions {
+            duck_others: true,
+            ..synthesis.options
+        };
+        let session_id = output.play_live_to_devices(synthesis.audio, TTS_SAMPLE_RATE, 1, device_ids, Some(options))?;
+        if let Some(app) = self.app_handle.lock().unwrap().clone() {
+            timeline::spawn_timeline_events(app, session_id, synthesis.words, synthesis.visemes, speed);
+        }
+        Ok(session_id)
+    }
+
+    /// Synthesize `request` into an audio file instead of playing it. The
+    /// format follows the extension of `path`: .wav, .flac, .opus or .mp3.
+    /// Speed, pitch and gain in `options` are applied as on playback.
+    pub async fn export(
+        &self,
+        request: TtsRequest,
+        path: String,
+        options: Option<PlaybackOptions>,
+    ) -> Result<SpeechExport, String> {
+        let request = self.resolve_template(request)?;
+        eprintln!(
+            "export_speech: {} characters with {} voice {} to {}",
+            request.text.chars().count(),
+            request.provider.name(),
+            request.voice_id,
+            path
+        );
+        // Check the format before paying for the synthesis
+        RecordingFormat::from_path(Path::new(&path))?;
+        let synthesis = self.synthesize(request, options).await?;
+        let file = PathBuf::from(&path);
+        let frames = tauri::async_runtime::spawn_blocking(move || {
+            record::export_stream(synthesis.audio, TTS_SAMPLE_RATE, 1, &synthesis.options, &file)
+        })
+        .await
+        .map_err(|e| format!("Failed to export speech: {}", e))??;
+        eprintln!("Exported {} frames of speech to {}", frames, path);
+        Ok(SpeechExport {
+            path,
+            duration_ms: frames * 1000 / TTS_SAMPLE_RATE as u64,
+        })
+    }
+
+    /// Start synthesizing `request` on its own thread, returning the audio,
+    /// word timing and mouth shapes as they arrive and the playback options
+    /// that make up for controls the engine can't apply itself. Resolves once
+    /// the first audio is in, so errors before any audio, such as a bad key,
+    /// are reported here.
+    async fn synthesize(
+        &self,
+        mut request: TtsRequest,
+        options: Option<PlaybackOptions>,
+    ) -> Result<Synthesis, String> {
+        let document = parse_request(&request)?;
+        if request.auto_voice {
+            if let Some(voice) = self.language_voice(&document.plain_text()) {
+                request.provider = voice.provider;
+                request.voice_id = voice.voice_id;
+            }
+        }
+        let document = self.pronunciations.lock().unwrap().apply(document);
+        let provider = self.provider(request.provider)?;
+
+        // Whatever the engine can't do is done on playback, so every
+        // provider honours the same controls
+        let mut options = options.unwrap_or_default();
+        if let Some(rate) = request.rate {
+            if !provider.native_rate(rate) {
+                options.speed = Some(options.speed.unwrap_or(1.0) * rate);
+                request.rate = None;
+            }
+        }
+        if let Some(semitones) = request.pitch_semitones {
+            if !provider.native_pitch() {
+                options.pitch_semitones += semitones;
+                request.pitch_semitones = None;
+            }
+        }
+        options.gain_db += request.volume_db.take().unwrap_or(0.0);
+
+        let (audio_tx, audio) = mpsc::channel();
+        let (words_tx, words) = mpsc::channel();
+        let (visemes_tx, visemes) = mpsc::channel();
+        let (started_tx, started_rx) = oneshot::channel::<Result<(), String>>();
+        std::thread::spawn(move || {
+            let mut sink = ChannelSink {
+                audio: audio_tx,
+                words: words_tx,
+                visemes: visemes_tx,
+                analyzer: VisemeAnalyzer::new(TTS_SAMPLE_RATE, 1),
+                started: Some(started_tx),
+            };
+            let result = provider.stream(&request, &document, &mut sink);
+            match (result, sink.started.take()) {
+                (Ok(()), None) => {}
+                (Ok(()), Some(started)) => {
+                    let _ = started.send(Err("The provider returned no audio".to_string()));
+                }
+                (Err(e), Some(started)) => {
+                    let _ = started.send(Err(e));
+                }
+                // Cut off mid-message; the clip ends where the audio stopped
+                (Err(e), None) => {
+                    let _ = sink.audio.send(Err(e));
+                }
+            }
+        });
+
+        started_rx
+            .await
+            .map_err(|_| "Speech synthesis stopped unexpectedly".to_string())??;
+        Ok(Synthesis {
+            audio,
+            words,
+            visemes,
+            options,
+        })
+    }
+
+    /// Queue `request` to be spoken after the messages ahead of it, returning
+    /// its id in the queue.
+    pub fn enqueue(
+        &self,
+        request: TtsRequest,
+        device_ids: Vec<String>,
+        options: Option<PlaybackOptions>,
+        priority: TtsPriority,
+    ) -> Result<u64, String> {
+        // Fail now rather than when its turn comes
+        let request = self.resolve_template(request)?;
+        parse_request(&request)?;
+        self.provider(request.provider)?;
+        let mut queue = self.queue.lock().unwrap();
+        let id = queue.push(request, device_ids, options, priority);
+        self.emit_queue_status(&queue.status());
+        Ok(id)
+    }
+
+    /// Stop the message being spoken and move on to the next.
+    pub fn skip(&self, output: &AudioOutputState) -> Result<(), String> {
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(session) = queue.skip()? {
+            // It may have just finished on its own
+            let _ = output.stop_playback(session, None);
+        }
+        self.emit_queue_status(&queue.status());
+        Ok(())
+    }
+
+    /// Drop every waiting message and stop the one being spoken.
+    pub fn clear_queue(&self, output: &AudioOutputState) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.clear_pending();
+        if let Ok(Some(session)) = queue.skip() {
+            let _ = output.stop_playback(session, None);
+        }
+        self.emit_queue_status(&queue.status());
+    }
+
+    pub fn queue_status(&self) -> SpeechQueueStatus {
+        self.queue.lock().unwrap().status()
+    }
+
+    pub fn pronunciations(&self) -> Vec<PronunciationEntry> {
+        self.pronunciations.lock().unwrap().entries()
+    }
+
+    /// Add a word to the pronunciation dictionary, or change how it is said.
+    pub fn set_pronunciation(&self, entry: PronunciationEntry) -> Result<(), String> {
+        self.pronunciations.lock().unwrap().set(entry)
+    }
+
+    pub fn remove_pronunciation(&self, term: &str) -> Result<(), String> {
+        self.pronunciations.lock().unwrap().remove(term)
+    }
+
+    /// The default voice for the language of `text`, if the language can be
+    /// told and has one.
+    fn language_voice(&self, text: &str) -> Option<LanguageVoice> {
+        let detected = match language::detect(text) {
+            Some(detected) if detected.reliable => detected,
+            _ => return None,
+        };
+        let voice = self.language_voices.lock().unwrap().get(&detected.language)?;
+        eprintln!(
+            "Detected {} ({:.2}), reading with {} voice {}",
+            detected.name,
+            detected.confidence,
+            voice.provider.name(),
+            voice.voice_id
+        );
+        Some(voice)
+    }
+
+    pub fn detect_language(&self, text: &str) -> Option<DetectedLanguage> {
+        language::detect(text)
+    }
+
+    pub fn language_voices(&self) -> HashMap<String, LanguageVoice> {
+        self.language_voices.lock().unwrap().all()
+    }
+
+    /// Set the voice text detected as `language` is read with, or clear it
+    /// with `None`.
+    pub fn set_language_voice(&self, language: &str, voice: Option<LanguageVoice>) -> Result<(), String> {
+        self.language_voices.lock().unwrap().set(language, voice)
+    }
+
+    pub fn templates(&self) -> Vec<TtsTemplate> {
+        self.templates.lock().unwrap().templates()
+    }
+
+    /// Save a template, replacing any existing one with the same name.
+    pub fn set_template(&self, template: TtsTemplate) -> Result<(), String> {
+        self.templates.lock().unwrap().set(template)
+    }
+
+    pub fn remove_template(&self, name: &str) -> Result<(), String> {
+        self.templates.lock().unwrap().remove(name)
+    }
+
+    /// Fill in the template `name` with `values`, as it would be spoken.
+    pub fn render_template(&self, name: &str, values: &HashMap<String, String>) -> Result<String, String> {
+        self.templates.lock().unwrap().get(name)?.render(values)
+    }
+
+    /// Replace the text of a request for a template with the filled-in
+    /// template. Other requests are returned as they are.
+    fn resolve_template(&self, mut request: TtsRequest) -> Result<TtsRequest, String> {
+        let name = match request.template.take() {
+            Some(name) => name,
+            None => return Ok(request),
+        };
+        let template = self.templates.lock().unwrap().get(&name)?;
+        request.text = template.render(&request.values)?;
+        request.ssml = template.ssml;
+        Ok(request)
+    }
+
+    /// Start queueing each line appended to a text file, replacing any
+    /// earlier settings for the same file.
+    pub fn follow_file(&self, file: FollowedFile) -> Result<FollowedFile, String> {
+        self.provider(file.provider)?;
+        let path = std::fs::canonicalize(&file.path)
+            .map_err(|e| format!("Failed to open {}: {}", file.path, e))?;
+        let file = FollowedFile {
+            path: path.to_string_lossy().into_owned(),
+            ..file
+        };
+        let app = self
+            .app_handle
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| "Speech isn't ready yet".to_string())?;
+        let follower = follow::spawn_follower(app, file.clone())?;
+        if let Some(previous) = self.followers.lock().unwrap().insert(file.path.clone(), follower) {
+            previous.stop();
+        }
+        eprintln!("Following {}", file.path);
+        Ok(file)
+    }
+
+    pub fn unfollow_file(&self, path: &str) -> Result<(), String> {
+        let path = std::fs::canonicalize(path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_string());
+        match self.followers.lock().unwrap().remove(&path) {
+            Some(follower) => {
+                follower.stop();
+                Ok(())
+            }
+            None => Err(format!("{} isn't being followed", path)),
+        }
+    }
+
+    pub fn followed_files(&self) -> Vec<FollowedFile> {
+        self.followers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|follower| follower.file.clone())
+            .collect()
+    }
+
+    pub fn feed_settings(&self) -> FeedSettings {
+        self.feeds.lock().unwrap().settings()
+    }
+
+    /// Start reading new items of a feed, or change how they are read. Items
+    /// already in the feed are skipped.
+    pub fn set_feed(&self, feed: Feed) -> Result<(), String> {
+        self.feeds.lock().unwrap().set(feed)
+    }
+
+    pub fn remove_feed(&self, url: &str) -> Result<(), String> {
+        self.feeds.lock().unwrap().remove(url)
+    }
+
+    pub fn set_feed_interval(&self, interval_secs: u64) -> Result<(), String> {
+        self.feeds.lock().unwrap().set_interval(interval_secs)
+    }
+
+    fn emit_queue_status(&self, status: &SpeechQueueStatus) {
+        if let Some(app) = self.app_handle.lock().unwrap().as_ref() {
+            queue::emit_queue_status(app, status);
+        }
+    }
+}
+
+/// The document a request asks to speak, once its controls are checked.
+fn parse_request(request: &TtsRequest) -> Result<SsmlDocument, String> {
+    if let Some(rate) = request.rate {
+        if !(MIN_SPEECH_RATE..=MAX_SPEECH_RATE).contains(&rate) {
+            return Err(format!(
+                "Speaking rate must be between {} and {}",
+                MIN_SPEECH_RATE, MAX_SPEECH_RATE
+            ));
+        }
+    }
+    if let Some(semitones) = request.pitch_semitones {
+        if !(-MAX_SPEECH_PITCH_SEMITONES..=MAX_SPEECH_PITCH_SEMITONES).contains(&semitones) {
+            return Err(format!(
+                "Pitch must be within {} semitones",
+                MAX_SPEECH_PITCH_SEMITONES
+            ));
+        }
+    }
+    if let Some(volume_db) = request.volume_db {
+        if !(MIN_SPEECH_VOLUME_DB..=MAX_SPEECH_VOLUME_DB).contains(&volume_db) {
+            return Err(format!(
+                "Volume must be between {} and +{} dB",
+                MIN_SPEECH_VOLUME_DB, MAX_SPEECH_VOLUME_DB
+            ));
+        }
+    }
+    let document = if request.ssml {
+        SsmlDocument::parse(&request.text)?
+    } else {
+        SsmlDocument::plain(&request.text)
+    };
+    if document.plain_text().trim().is_empty() {
+        return Err("Nothing to speak".to_string());
+    }
+    Ok(document)
+}
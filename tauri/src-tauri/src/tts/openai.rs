@@ -0,0 +1,76 @@
+use super::ssml::SsmlDocument;
+use super::{
+    send, stream_pcm, stream_segments, SpeechSink, TtsProvider, TtsProviderConfig, TtsProviderKind, TtsRequest,
+    TtsVoice,
+};
+use reqwest::blocking::Client;
+
+const OPENAI_SPEECH_URL: &str = "https://api.openai.com/v1/audio/speech";
+const OPENAI_DEFAULT_MODEL: &str = "gpt-4o-mini-tts";
+
+/// OpenAI has no endpoint listing its voices.
+const OPENAI_VOICES: [&str; 11] = [
+    "alloy", "ash", "ballad", "coral", "echo", "fable", "nova", "onyx", "sage", "shimmer", "verse",
+];
+
+pub(super) struct OpenAi {
+    client: Client,
+    api_key: String,
+    model: String,
+}
+
+impl OpenAi {
+    pub(super) fn new(client: Client, config: TtsProviderConfig) -> Self {
+        Self {
+            client,
+            api_key: config.api_key,
+            model: config.model.unwrap_or_else(|| OPENAI_DEFAULT_MODEL.to_string()),
+        }
+    }
+}
+
+impl TtsProvider for OpenAi {
+    fn list_voices(&self) -> Result<Vec<TtsVoice>, String> {
+        Ok(OPENAI_VOICES
+            .iter()
+            .map(|voice| {
+                let mut name = voice.to_string();
+                name[..1].make_ascii_uppercase();
+                TtsVoice {
+                    id: voice.to_string(),
+                    name,
+                    language: None,
+                    gender: None,
+                }
+            })
+            .collect())
+    }
+
+    /// OpenAI only takes plain text, so the document is spoken a piece at a
+    /// time with silence for its pauses.
+    fn stream(&self, request: &TtsRequest, document: &SsmlDocument, sink: &mut dyn SpeechSink) -> Result<(), String> {
+        stream_segments(document, sink, |text, sink| {
+            // "pcm" is 24 kHz 16-bit mono, the same as the other providers
+            let mut body = serde_json::json!({
+                "model": self.model,
+                "input": text,
+                "voice": request.voice_id,
+                "response_format": "pcm",
+            });
+            if let Some(rate) = request.rate {
+                body["speed"] = serde_json::json!(rate);
+            }
+            let request = self
+                .client
+                .post(OPENAI_SPEECH_URL)
+                .bearer_auth(&self.api_key)
+                .json(&body);
+            stream_pcm(send(request, TtsProviderKind::OpenAi)?, TtsProviderKind::OpenAi, sink)
+        })
+    }
+
+    /// OpenAI takes a speed of 0.25-4.0, wider than a request can ask for.
+    fn native_rate(&self, _rate: f32) -> bool {
+        true
+    }
+}
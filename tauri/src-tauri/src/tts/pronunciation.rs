@@ -0,0 +1,203 @@
+use super::ssml::{SsmlDocument, SsmlNode};
+use std::path::PathBuf;
+
+/// How to say a word that engines get wrong, such as a username or jargon.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PronunciationEntry {
+    /// Word or phrase as it appears in the text
+    pub term: String,
+    /// What to say instead, spelled the way it sounds
+    pub replacement: String,
+    /// Phonetic spelling for engines that read `<phoneme>`. Others speak
+    /// `replacement`.
+    #[serde(default)]
+    pub phoneme: Option<String>,
+    /// Alphabet of `phoneme`, "ipa" unless given
+    #[serde(default)]
+    pub alphabet: Option<String>,
+    #[serde(default)]
+    pub case_sensitive: bool,
+}
+
+/// The user's pronunciation dictionary, applied to every message before it is
+/// synthesized.
+#[derive(Default)]
+pub(super) struct PronunciationStore {
+    path: Option<PathBuf>,
+    entries: Vec<PronunciationEntry>,
+}
+
+impl PronunciationStore {
+    /// Load the dictionary from `path`. A missing or unreadable file starts
+    /// empty.
+    pub(super) fn load(path: PathBuf) -> Self {
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Ignoring invalid pronunciation file {:?}: {}", path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            path: Some(path),
+            entries,
+        }
+    }
+
+    pub(super) fn entries(&self) -> Vec<PronunciationEntry> {
+        self.entries.clone()
+    }
+
+    /// Add an entry, replacing any existing one for the same term.
+    pub(super) fn set(&mut self, entry: PronunciationEntry) -> Result<(), String> {
+        let term = entry.term.trim();
+        if term.is_empty() {
+            return Err("Pronunciation needs a term".to_string());
+        }
+        if entry.replacement.trim().is_empty() {
+            return Err(format!("Pronunciation of {} needs a replacement", term));
+        }
+        let entry = PronunciationEntry {
+            term: term.to_string(),
+            ..entry
+        };
+        match self.entries.iter_mut().find(|existing| existing.term == entry.term) {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+        self.save()
+    }
+
+    pub(super) fn remove(&mut self, term: &str) -> Result<(), String> {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.term != term.trim());
+        if self.entries.len() == before {
+            return Err(format!("No pronunciation for {}", term));
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+        let contents = serde_json::to_string_pretty(&self.entries)
+            .map_err(|e| format!("Failed to serialize pronunciations: {}", e))?;
+        std::fs::write(path, contents).map_err(|e| format!("Failed to save pronunciations: {}", e))
+    }
+
+    /// Substitute every dictionary term in the document's text. Terms inside
+    /// markup that already says how to read them (`sub`, `phoneme`,
+    /// `say-as`) are left alone.
+    pub(super) fn apply(&self, document: SsmlDocument) -> SsmlDocument {
+        if self.entries.is_empty() {
+            return document;
+        }
+        // Longer terms first, so "New York City" wins over "New York"
+        let mut entries: Vec<&PronunciationEntry> = self.entries.iter().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.term.chars().count()));
+        SsmlDocument {
+            nodes: apply_to_nodes(document.nodes, &entries),
+        }
+    }
+}
+
+fn apply_to_nodes(nodes: Vec<SsmlNode>, entries: &[&PronunciationEntry]) -> Vec<SsmlNode> {
+    let mut out = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match node {
+            SsmlNode::Text(text) => replace_terms(&text, entries, &mut out),
+            SsmlNode::Emphasis { level, children } => out.push(SsmlNode::Emphasis {
+                level,
+                children: apply_to_nodes(children, entries),
+            }),
+            SsmlNode::Prosody {
+                rate,
+                pitch,
+                volume,
+                children,
+            } => out.push(SsmlNode::Prosody {
+                rate,
+                pitch,
+                volume,
+                children: apply_to_nodes(children, entries),
+            }),
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Split `text` around whole-word matches of the terms, replacing each match
+/// with the node that says it.
+fn replace_terms(text: &str, entries: &[&PronunciationEntry], out: &mut Vec<SsmlNode>) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut plain = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let at_word_start = i == 0 || !chars[i - 1].is_alphanumeric();
+        let matched = if at_word_start {
+            entries.iter().find_map(|entry| match_term(&chars[i..], entry).map(|len| (*entry, len)))
+        } else {
+            None
+        };
+        match matched {
+            Some((entry, len)) => {
+                if !plain.is_empty() {
+                    out.push(SsmlNode::Text(std::mem::take(&mut plain)));
+                }
+                out.push(pronounce(entry, chars[i..i + len].iter().collect()));
+                i += len;
+            }
+            None => {
+                plain.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    if !plain.is_empty() {
+        out.push(SsmlNode::Text(plain));
+    }
+}
+
+/// Length in chars of `entry.term` at the start of `text`, if it is there as
+/// a whole word.
+fn match_term(text: &[char], entry: &PronunciationEntry) -> Option<usize> {
+    let mut len = 0;
+    for term_char in entry.term.chars() {
+        let c = *text.get(len)?;
+        let same = if entry.case_sensitive {
+            c == term_char
+        } else {
+            c.to_lowercase().eq(term_char.to_lowercase())
+        };
+        if !same {
+            return None;
+        }
+        len += 1;
+    }
+    match text.get(len) {
+        Some(next) if next.is_alphanumeric() => None,
+        _ => Some(len),
+    }
+}
+
+fn pronounce(entry: &PronunciationEntry, written: String) -> SsmlNode {
+    match &entry.phoneme {
+        // Engines without phoneme support read the replacement inside it
+        Some(ph) if !ph.trim().is_empty() => SsmlNode::Phoneme {
+            alphabet: Some(entry.alphabet.clone().unwrap_or_else(|| "ipa".to_string())),
+            ph: ph.trim().to_string(),
+            children: vec![SsmlNode::Text(entry.replacement.clone())],
+        },
+        _ => SsmlNode::Sub {
+            alias: entry.replacement.clone(),
+            text: written,
+        },
+    }
+}
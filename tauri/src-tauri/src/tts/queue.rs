@@ -0,0 +1,217 @@
+use super::{TtsRequest, TtsState};
+use crate::audio_output::{AudioOutputState, PlaybackOptions, SessionId};
+use std::cmp::Reverse;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+
+/// How often the queue checks whether the current message has finished.
+const QUEUE_POLL_MS: u64 = 100;
+
+/// Characters of each message included in queue events.
+const QUEUE_PREVIEW_CHARS: usize = 80;
+
+/// How urgently a queued message should be spoken. Higher priorities play
+/// first; equal ones play in the order they were queued.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TtsPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    /// Plays next, ahead of everything else waiting
+    Alert,
+}
+
+/// A message waiting in the queue, as reported to the frontend.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct QueuedSpeechInfo {
+    pub id: u64,
+    pub priority: TtsPriority,
+    /// The start of the message
+    pub text: String,
+}
+
+/// Payload of `tts://queue`, emitted whenever the queue changes.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SpeechQueueStatus {
+    pub current: Option<QueuedSpeechInfo>,
+    /// Session playing the current message, once synthesis has started
+    pub session_id: Option<SessionId>,
+    /// Waiting messages in the order they will play
+    pub pending: Vec<QueuedSpeechInfo>,
+}
+
+/// Payload of `tts://error`, emitted when a queued message can't be spoken.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SpeechError {
+    pub id: u64,
+    pub error: String,
+}
+
+pub(super) struct QueuedSpeech {
+    id: u64,
+    priority: TtsPriority,
+    request: TtsRequest,
+    device_ids: Vec<String>,
+    options: Option<PlaybackOptions>,
+}
+
+impl QueuedSpeech {
+    fn info(&self) -> QueuedSpeechInfo {
+        QueuedSpeechInfo {
+            id: self.id,
+            priority: self.priority,
+            text: self.request.text.chars().take(QUEUE_PREVIEW_CHARS).collect(),
+        }
+    }
+}
+
+/// The message being spoken.
+struct CurrentSpeech {
+    info: QueuedSpeechInfo,
+    session: Option<SessionId>,
+    /// Skipped while it was still being synthesized
+    skipped: bool,
+}
+
+/// Messages waiting to be spoken one after another.
+#[derive(Default)]
+pub(super) struct SpeechQueue {
+    pending: Vec<QueuedSpeech>,
+    current: Option<CurrentSpeech>,
+    next_id: u64,
+}
+
+impl SpeechQueue {
+    pub(super) fn push(
+        &mut self,
+        request: TtsRequest,
+        device_ids: Vec<String>,
+        options: Option<PlaybackOptions>,
+        priority: TtsPriority,
+    ) -> u64 {
+        self.next_id += 1;
+        self.pending.push(QueuedSpeech {
+            id: self.next_id,
+            priority,
+            request,
+            device_ids,
+            options,
+        });
+        self.next_id
+    }
+
+    /// Take the highest priority message that has waited longest.
+    fn pop_next(&mut self) -> Option<QueuedSpeech> {
+        let index = self
+            .pending
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, item)| (item.priority, Reverse(item.id)))
+            .map(|(index, _)| index)?;
+        Some(self.pending.remove(index))
+    }
+
+    /// Stop tracking the current message. Returns its session so the caller
+    /// can stop it, if it had started playing.
+    pub(super) fn skip(&mut self) -> Result<Option<SessionId>, String> {
+        match &mut self.current {
+            Some(current) if current.session.is_some() => {
+                let session = current.session;
+                self.current = None;
+                Ok(session)
+            }
+            // Stopped as soon as its session starts
+            Some(current) => {
+                current.skipped = true;
+                Ok(None)
+            }
+            None => Err("Nothing is being spoken".to_string()),
+        }
+    }
+
+    pub(super) fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    pub(super) fn status(&self) -> SpeechQueueStatus {
+        let mut pending: Vec<&QueuedSpeech> = self.pending.iter().collect();
+        pending.sort_by_key(|item| (Reverse(item.priority), item.id));
+        SpeechQueueStatus {
+            current: self.current.as_ref().map(|current| current.info.clone()),
+            session_id: self.current.as_ref().and_then(|current| current.session),
+            pending: pending.into_iter().map(QueuedSpeech::info).collect(),
+        }
+    }
+}
+
+pub(super) fn emit_queue_status(app: &AppHandle, status: &SpeechQueueStatus) {
+    if let Err(e) = app.emit("tts://queue", status) {
+        eprintln!("Failed to emit tts://queue event: {}", e);
+    }
+}
+
+/// Speak queued messages one at a time until the app shuts down. Each is
+/// synthesized once the one before has finished playing.
+pub(super) fn spawn_queue_worker(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(QUEUE_POLL_MS));
+
+        let tts = app.state::<TtsState>();
+        let output = app.state::<AudioOutputState>();
+        let next = {
+            let mut queue = tts.queue.lock().unwrap();
+            let playing = match &queue.current {
+                Some(CurrentSpeech {
+                    session: Some(session), ..
+                }) => output.playback_status(*session).is_ok(),
+                Some(_) => true,
+                None => false,
+            };
+            if playing {
+                continue;
+            }
+            let finished = queue.current.take().is_some();
+            let next = queue.pop_next();
+            if let Some(item) = &next {
+                queue.current = Some(CurrentSpeech {
+                    info: item.info(),
+                    session: None,
+                    skipped: false,
+                });
+            }
+            if finished || next.is_some() {
+                emit_queue_status(&app, &queue.status());
+            }
+            next
+        };
+        let item = match next {
+            Some(item) => item,
+            None => continue,
+        };
+
+        let result = tauri::async_runtime::block_on(tts.speak(&output, item.request, item.device_ids, item.options));
+        let mut queue = tts.queue.lock().unwrap();
+        let skipped = queue.current.as_ref().is_none_or(|current| current.skipped);
+        match result {
+            Ok(session) if skipped => {
+                let _ = output.stop_playback(session, None);
+                queue.current = None;
+            }
+            Ok(session) => {
+                if let Some(current) = &mut queue.current {
+                    current.session = Some(session);
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to speak queued message {}: {}", item.id, e);
+                if let Err(e) = app.emit("tts://error", &SpeechError { id: item.id, error: e }) {
+                    eprintln!("Failed to emit tts://error event: {}", e);
+                }
+                queue.current = None;
+            }
+        }
+        emit_queue_status(&app, &queue.status());
+    });
+}
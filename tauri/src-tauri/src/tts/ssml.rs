@@ -0,0 +1,649 @@
+/// Longest pause a `<break>` can ask for.
+const MAX_BREAK_MS: u32 = 10_000;
+/// Deepest elements can nest. SSML can come from chat, so this keeps the
+/// recursive parser from overflowing the stack.
+const MAX_SSML_DEPTH: usize = 32;
+
+/// The parts of SSML that are carried over to providers. Other elements are
+/// dropped but their text is kept, so unfamiliar markup degrades to plain
+/// speech rather than failing.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum SsmlNode {
+    Text(String),
+    Break { duration_ms: u32 },
+    Emphasis { level: String, children: Vec<SsmlNode> },
+    Prosody {
+        rate: Option<String>,
+        pitch: Option<String>,
+        volume: Option<String>,
+        children: Vec<SsmlNode>,
+    },
+    SayAs {
+        interpret_as: String,
+        format: Option<String>,
+        children: Vec<SsmlNode>,
+    },
+    /// `alias` is spoken in place of the text
+    Sub { alias: String, text: String },
+    Phoneme {
+        alphabet: Option<String>,
+        ph: String,
+        children: Vec<SsmlNode>,
+    },
+}
+
+/// A run of plain text or a pause, for engines that only take text.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Segment {
+    Text(String),
+    Pause(u32),
+}
+
+/// What to speak, parsed from SSML or taken as plain text.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct SsmlDocument {
+    pub(super) nodes: Vec<SsmlNode>,
+}
+
+impl SsmlDocument {
+    pub(super) fn plain(text: &str) -> Self {
+        Self {
+            nodes: vec![SsmlNode::Text(text.to_string())],
+        }
+    }
+
+    /// Parse an SSML document. The `<speak>` root is optional.
+    pub(super) fn parse(ssml: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            input: ssml,
+            pos: 0,
+            depth: 0,
+        };
+        let nodes = parser.parse_children(None)?;
+        Ok(Self { nodes })
+    }
+
+    /// The document as SSML markup, without the `<speak>` and `<voice>`
+    /// wrapper.
+    pub(super) fn to_markup(&self) -> String {
+        let mut markup = String::new();
+        write_markup(&self.nodes, &mut markup);
+        markup
+    }
+
+    /// The text to speak with markup flattened away, split at pauses.
+    pub(super) fn segments(&self) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        flatten(&self.nodes, &mut segments);
+        segments
+            .into_iter()
+            .filter(|segment| match segment {
+                Segment::Text(text) => !text.trim().is_empty(),
+                Segment::Pause(ms) => *ms > 0,
+            })
+            .collect()
+    }
+
+    /// Everything that would be spoken, without pauses.
+    pub(super) fn plain_text(&self) -> String {
+        self.segments()
+            .into_iter()
+            .filter_map(|segment| match segment {
+                Segment::Text(text) => Some(text),
+                Segment::Pause(_) => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn write_markup(nodes: &[SsmlNode], out: &mut String) {
+    for node in nodes {
+        match node {
+            SsmlNode::Text(text) => out.push_str(&escape_xml(text)),
+            SsmlNode::Break { duration_ms } => out.push_str(&format!("<break time=\"{}ms\"/>", duration_ms)),
+            SsmlNode::Emphasis { level, children } => {
+                out.push_str(&format!("<emphasis level=\"{}\">", escape_xml(level)));
+                write_markup(children, out);
+                out.push_str("</emphasis>");
+            }
+            SsmlNode::Prosody {
+                rate,
+                pitch,
+                volume,
+                children,
+            } => {
+                out.push_str("<prosody");
+                for (name, value) in [("rate", rate), ("pitch", pitch), ("volume", volume)] {
+                    if let Some(value) = value {
+                        out.push_str(&format!(" {}=\"{}\"", name, escape_xml(value)));
+                    }
+                }
+                out.push('>');
+                write_markup(children, out);
+                out.push_str("</prosody>");
+            }
+            SsmlNode::SayAs {
+                interpret_as,
+                format,
+                children,
+            } => {
+                out.push_str(&format!("<say-as interpret-as=\"{}\"", escape_xml(interpret_as)));
+                if let Some(format) = format {
+                    out.push_str(&format!(" format=\"{}\"", escape_xml(format)));
+                }
+                out.push('>');
+                write_markup(children, out);
+                out.push_str("</say-as>");
+            }
+            SsmlNode::Sub { alias, text } => {
+                out.push_str(&format!("<sub alias=\"{}\">{}</sub>", escape_xml(alias), escape_xml(text)));
+            }
+            SsmlNode::Phoneme { alphabet, ph, children } => {
+                out.push_str("<phoneme");
+                if let Some(alphabet) = alphabet {
+                    out.push_str(&format!(" alphabet=\"{}\"", escape_xml(alphabet)));
+                }
+                out.push_str(&format!(" ph=\"{}\">", escape_xml(ph)));
+                write_markup(children, out);
+                out.push_str("</phoneme>");
+            }
+        }
+    }
+}
+
+/// Flatten nodes into text and pauses, approximating what plain text can.
+fn flatten(nodes: &[SsmlNode], segments: &mut Vec<Segment>) {
+    for node in nodes {
+        match node {
+            SsmlNode::Text(text) => push_text(segments, text),
+            SsmlNode::Break { duration_ms } => segments.push(Segment::Pause(*duration_ms)),
+            SsmlNode::Emphasis { children, .. }
+            | SsmlNode::Prosody { children, .. }
+            | SsmlNode::Phoneme { children, .. } => flatten(children, segments),
+            SsmlNode::SayAs {
+                interpret_as, children, ..
+            } => {
+                let mut inner = Vec::new();
+                flatten(children, &mut inner);
+                for segment in inner {
+                    match segment {
+                        Segment::Text(text) => push_text(segments, &say_as_text(interpret_as, &text)),
+                        pause => segments.push(pause),
+                    }
+                }
+            }
+            SsmlNode::Sub { alias, .. } => push_text(segments, alias),
+        }
+    }
+}
+
+/// Add text, joining it onto the text before it.
+fn push_text(segments: &mut Vec<Segment>, text: &str) {
+    match segments.last_mut() {
+        Some(Segment::Text(last)) => last.push_str(text),
+        _ => segments.push(Segment::Text(text.to_string())),
+    }
+}
+
+/// Plain-text stand-in for the `say-as` types that change how text is read
+/// out letter by letter; the rest are left to the engine.
+fn say_as_text(interpret_as: &str, text: &str) -> String {
+    match interpret_as {
+        "characters" | "spell-out" | "letters" | "digits" => text
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => text.to_string(),
+    }
+}
+
+/// Length of a `<break>`, from its `time` or else its `strength`. The
+/// strengths follow Azure's defaults.
+fn break_duration(time: Option<&str>, strength: Option<&str>) -> Result<u32, String> {
+    let ms = match (time, strength) {
+        (Some(time), _) => {
+            let time = time.trim();
+            let (number, scale) = if let Some(ms) = time.strip_suffix("ms") {
+                (ms, 1.0)
+            } else if let Some(secs) = time.strip_suffix('s') {
+                (secs, 1000.0)
+            } else {
+                return Err(format!("Invalid break time: {}", time));
+            };
+            let value: f64 = number
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid break time: {}", time))?;
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("Invalid break time: {}", time));
+            }
+            (value * scale).round() as u32
+        }
+        (None, Some(strength)) => match strength {
+            "none" => 0,
+            "x-weak" => 250,
+            "weak" => 500,
+            "medium" => 750,
+            "strong" => 1000,
+            "x-strong" => 1250,
+            other => return Err(format!("Invalid break strength: {}", other)),
+        },
+        (None, None) => 750,
+    };
+    Ok(ms.min(MAX_BREAK_MS))
+}
+
+pub(super) fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_xml(text: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let end = match rest[start..].find(';') {
+            Some(end) => start + end,
+            None => return Err(format!("Unterminated entity in SSML: {}", &rest[start..])),
+        };
+        let entity = &rest[start + 1..end];
+        let c = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(decimal) = entity.strip_prefix('#') {
+                    decimal.parse().ok()
+                } else {
+                    None
+                };
+                match code.and_then(char::from_u32) {
+                    Some(c) => c,
+                    None => return Err(format!("Unknown entity in SSML: &{};", entity)),
+                }
+            }
+        };
+        out.push(c);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// A tag as written, with its attributes still escaped.
+struct Tag<'a> {
+    name: &'a str,
+    attributes: Vec<(&'a str, &'a str)>,
+    self_closing: bool,
+}
+
+impl Tag<'_> {
+    fn attribute(&self, name: &str) -> Result<Option<String>, String> {
+        match self.attributes.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => Ok(Some(unescape_xml(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn required(&self, name: &str) -> Result<String, String> {
+        self.attribute(name)?
+            .ok_or_else(|| format!("<{}> needs a {} attribute", self.name, name))
+    }
+}
+
+/// Just enough of an XML reader for SSML: elements, attributes, text,
+/// entities, comments and processing instructions.
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    /// Elements open around `pos`
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Read nodes until the closing tag of `parent`, or the end of the input
+    /// at the top level.
+    fn parse_children(&mut self, parent: Option<&str>) -> Result<Vec<SsmlNode>, String> {
+        let mut nodes = Vec::new();
+        loop {
+            let rest = &self.input[self.pos..];
+            if rest.is_empty() {
+                return match parent {
+                    Some(name) => Err(format!("<{}> is never closed", name)),
+                    None => Ok(nodes),
+                };
+            }
+            if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+                continue;
+            }
+            if rest.starts_with("<?") || rest.starts_with("<!") {
+                self.skip_past(">")?;
+                continue;
+            }
+            if let Some(closing) = rest.strip_prefix("</") {
+                let end = closing.find('>').ok_or("Unterminated closing tag in SSML")?;
+                let name = closing[..end].trim();
+                if Some(name) != parent {
+                    return Err(format!("Unexpected </{}> in SSML", name));
+                }
+                self.pos += 2 + end + 1;
+                return Ok(nodes);
+            }
+            if rest.starts_with('<') {
+                let tag = self.parse_tag()?;
+                let children = if tag.self_closing {
+                    Vec::new()
+                } else {
+                    if self.depth == MAX_SSML_DEPTH {
+                        return Err("SSML is nested too deeply".to_string());
+                    }
+                    self.depth += 1;
+                    let children = self.parse_children(Some(tag.name))?;
+                    self.depth -= 1;
+                    children
+                };
+                self.push_element(&mut nodes, &tag, children)?;
+                continue;
+            }
+
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = unescape_xml(&rest[..end])?;
+            self.pos += end;
+            match nodes.last_mut() {
+                Some(SsmlNode::Text(last)) => last.push_str(&text),
+                _ => nodes.push(SsmlNode::Text(text)),
+            }
+        }
+    }
+
+    fn skip_past(&mut self, terminator: &str) -> Result<(), String> {
+        match self.input[self.pos..].find(terminator) {
+            Some(end) => {
+                self.pos += end + terminator.len();
+                Ok(())
+            }
+            None => Err(format!("Expected {} in SSML", terminator)),
+        }
+    }
+
+    fn parse_tag(&mut self) -> Result<Tag<'a>, String> {
+        let input = self.input;
+        let start = self.pos + 1;
+        let end = start + input[start..].find('>').ok_or("Unterminated tag in SSML")?;
+        self.pos = end + 1;
+
+        let mut body = &input[start..end];
+        let self_closing = body.ends_with('/');
+        if self_closing {
+            body = &body[..body.len() - 1];
+        }
+        let name_end = body.find(char::is_whitespace).unwrap_or(body.len());
+        let name = &body[..name_end];
+        if name.is_empty() {
+            return Err("Empty tag in SSML".to_string());
+        }
+
+        let mut attributes = Vec::new();
+        let mut rest = body[name_end..].trim_start();
+        while !rest.is_empty() {
+            let eq = rest
+                .find('=')
+                .ok_or_else(|| format!("Malformed attribute in <{}>", name))?;
+            let key = rest[..eq].trim();
+            let value_part = rest[eq + 1..].trim_start();
+            let quote = match value_part.chars().next() {
+                Some(q @ ('"' | '\'')) => q,
+                _ => return Err(format!("Unquoted attribute {} in <{}>", key, name)),
+            };
+            let close = value_part[1..]
+                .find(quote)
+                .ok_or_else(|| format!("Unterminated attribute {} in <{}>", key, name))?;
+            attributes.push((key, &value_part[1..1 + close]));
+            rest = value_part[close + 2..].trim_start();
+        }
+        Ok(Tag {
+            name,
+            attributes,
+            self_closing,
+        })
+    }
+
+    fn push_element(&self, nodes: &mut Vec<SsmlNode>, tag: &Tag, children: Vec<SsmlNode>) -> Result<(), String> {
+        let node = match tag.name {
+            "break" => SsmlNode::Break {
+                duration_ms: break_duration(
+                    tag.attribute("time")?.as_deref(),
+                    tag.attribute("strength")?.as_deref(),
+                )?,
+            },
+            "emphasis" => SsmlNode::Emphasis {
+                level: tag.attribute("level")?.unwrap_or_else(|| "moderate".to_string()),
+                children,
+            },
+            "prosody" => SsmlNode::Prosody {
+                rate: tag.attribute("rate")?,
+                pitch: tag.attribute("pitch")?,
+                volume: tag.attribute("volume")?,
+                children,
+            },
+            "say-as" => SsmlNode::SayAs {
+                interpret_as: tag.required("interpret-as")?,
+                format: tag.attribute("format")?,
+                children,
+            },
+            "sub" => SsmlNode::Sub {
+                alias: tag.required("alias")?,
+                text: SsmlDocument { nodes: children }.plain_text(),
+            },
+            "phoneme" => SsmlNode::Phoneme {
+                alphabet: tag.attribute("alphabet")?,
+                ph: tag.required("ph")?,
+                children,
+            },
+            // Paragraphs and sentences read as separate sentences in plain text
+            "p" | "s" => {
+                nodes.push(SsmlNode::Text(" ".to_string()));
+                nodes.extend(children);
+                nodes.push(SsmlNode::Text(" ".to_string()));
+                return Ok(());
+            }
+            // <speak>, <voice>, <lang> and anything unknown: keep the contents
+            _ => {
+                nodes.extend(children);
+                return Ok(());
+            }
+        };
+        nodes.push(node);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(ssml: &str) -> Vec<SsmlNode> {
+        SsmlDocument::parse(ssml).unwrap().nodes
+    }
+
+    fn text(text: &str) -> SsmlNode {
+        SsmlNode::Text(text.to_string())
+    }
+
+    #[test]
+    fn plain_text_without_root() {
+        assert_eq!(parse("Hello there"), vec![text("Hello there")]);
+        assert_eq!(parse(""), Vec::<SsmlNode>::new());
+    }
+
+    #[test]
+    fn unknown_elements_keep_their_text() {
+        assert_eq!(
+            parse("<speak><voice name=\"x\">Hi <lang xml:lang=\"en\">you</lang></voice></speak>"),
+            vec![text("Hi "), text("you")]
+        );
+    }
+
+    #[test]
+    fn skips_comments_and_declarations() {
+        assert_eq!(
+            parse("<?xml version=\"1.0\"?><!DOCTYPE speak><speak>a<!-- <b> -->b</speak>"),
+            vec![text("ab")]
+        );
+    }
+
+    #[test]
+    fn parses_supported_elements() {
+        let nodes = parse(
+            "<emphasis>a</emphasis><prosody rate='slow' pitch=\"+2st\">b</prosody>\
+             <say-as interpret-as=\"characters\">ab</say-as><sub alias=\"World Wide Web\">WWW</sub>\
+             <phoneme alphabet=\"ipa\" ph=\"t&#x259;\">the</phoneme>",
+        );
+        assert_eq!(
+            nodes,
+            vec![
+                SsmlNode::Emphasis {
+                    level: "moderate".to_string(),
+                    children: vec![text("a")],
+                },
+                SsmlNode::Prosody {
+                    rate: Some("slow".to_string()),
+                    pitch: Some("+2st".to_string()),
+                    volume: None,
+                    children: vec![text("b")],
+                },
+                SsmlNode::SayAs {
+                    interpret_as: "characters".to_string(),
+                    format: None,
+                    children: vec![text("ab")],
+                },
+                SsmlNode::Sub {
+                    alias: "World Wide Web".to_string(),
+                    text: "WWW".to_string(),
+                },
+                SsmlNode::Phoneme {
+                    alphabet: Some("ipa".to_string()),
+                    ph: "t\u{259}".to_string(),
+                    children: vec![text("the")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_tags() {
+        for ssml in [
+            "<speak>unclosed",
+            "<speak>a</prosody>",
+            "a</speak>",
+            "<speak",
+            "<>a</>",
+            "<prosody rate=slow>a</prosody>",
+            "<prosody rate=\"slow>a</prosody>",
+            "<prosody rate>a</prosody>",
+            "<say-as>a</say-as>",
+            "<sub>a</sub>",
+            "<phoneme>a</phoneme>",
+            "a<!-- never closed",
+            "<s>a</p>",
+        ] {
+            assert!(SsmlDocument::parse(ssml).is_err(), "{} should fail", ssml);
+        }
+    }
+
+    #[test]
+    fn unescapes_entities() {
+        assert_eq!(parse("&amp;&lt;&gt;&quot;&apos;&#65;&#x42;"), vec![text("&<>\"'AB")]);
+        assert_eq!(
+            parse("<sub alias=\"R&amp;D\">RnD</sub>"),
+            vec![SsmlNode::Sub {
+                alias: "R&D".to_string(),
+                text: "RnD".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_entities() {
+        for ssml in ["a &amp b", "&nbsp;", "&#xD800;", "&#x;", "&#;", "&#99999999999;"] {
+            assert!(SsmlDocument::parse(ssml).is_err(), "{} should fail", ssml);
+        }
+    }
+
+    #[test]
+    fn escaping_round_trips() {
+        let original = "<tag> & \"quotes\" 'n' stuff";
+        assert_eq!(unescape_xml(&escape_xml(original)).unwrap(), original);
+        let document = SsmlDocument::plain(original);
+        assert_eq!(SsmlDocument::parse(&document.to_markup()).unwrap(), document);
+    }
+
+    #[test]
+    fn allows_nesting_up_to_the_limit() {
+        let ssml = "<p>".repeat(MAX_SSML_DEPTH) + "deep" + &"</p>".repeat(MAX_SSML_DEPTH);
+        assert_eq!(SsmlDocument::parse(&ssml).unwrap().plain_text().trim(), "deep");
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let ssml = "<p>".repeat(MAX_SSML_DEPTH + 1) + &"</p>".repeat(MAX_SSML_DEPTH + 1);
+        assert_eq!(SsmlDocument::parse(&ssml).unwrap_err(), "SSML is nested too deeply");
+        // Far past the limit, without closing tags
+        assert!(SsmlDocument::parse(&"<p>".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn break_durations() {
+        assert_eq!(break_duration(Some("500ms"), None), Ok(500));
+        assert_eq!(break_duration(Some(" 1.5s "), Some("weak")), Ok(1500));
+        assert_eq!(break_duration(Some("0s"), None), Ok(0));
+        assert_eq!(break_duration(Some("100000ms"), None), Ok(MAX_BREAK_MS));
+        assert_eq!(break_duration(Some("60s"), None), Ok(MAX_BREAK_MS));
+        assert_eq!(break_duration(None, Some("strong")), Ok(1000));
+        assert_eq!(break_duration(None, None), Ok(750));
+    }
+
+    #[test]
+    fn rejects_bad_break_durations() {
+        for time in ["", "ms", "fast", "-1s", "NaNs", "infs", "1 m s", "10"] {
+            assert!(break_duration(Some(time), None).is_err(), "{} should fail", time);
+        }
+        assert!(break_duration(None, Some("loud")).is_err());
+    }
+
+    #[test]
+    fn flattens_to_segments() {
+        let document = SsmlDocument::parse(
+            "<speak>Call <say-as interpret-as=\"digits\">1 2 3</say-as>\
+             <break time=\"200ms\"/><break strength=\"none\"/> <s>now</s></speak>",
+        )
+        .unwrap();
+        assert_eq!(
+            document.segments(),
+            vec![
+                Segment::Text("Call 1 2 3".to_string()),
+                Segment::Pause(200),
+                Segment::Text("  now ".to_string()),
+            ]
+        );
+    }
+}
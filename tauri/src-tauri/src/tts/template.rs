@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// A saved message with placeholders such as `{username}`, `{amount}` or
+/// `{time}`, filled in each time it is spoken.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TtsTemplate {
+    pub name: String,
+    /// e.g. "Thanks {username} for the {amount} bits!"
+    pub text: String,
+    /// `text` is an SSML document; values are escaped as they are filled in
+    #[serde(default)]
+    pub ssml: bool,
+}
+
+/// The user's saved templates.
+#[derive(Default)]
+pub(super) struct TemplateStore {
+    path: Option<PathBuf>,
+    templates: Vec<TtsTemplate>,
+}
+
+impl TemplateStore {
+    /// Load the templates from `path`. A missing or unreadable file starts
+    /// empty.
+    pub(super) fn load(path: PathBuf) -> Self {
+        let templates = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Ignoring invalid template file {:?}: {}", path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            path: Some(path),
+            templates,
+        }
+    }
+
+    pub(super) fn templates(&self) -> Vec<TtsTemplate> {
+        self.templates.clone()
+    }
+
+    pub(super) fn get(&self, name: &str) -> Result<TtsTemplate, String> {
+        self.templates
+            .iter()
+            .find(|template| template.name == name.trim())
+            .cloned()
+            .ok_or_else(|| format!("No template named {}", name))
+    }
+
+    /// Add a template, replacing any existing one with the same name.
+    pub(super) fn set(&mut self, template: TtsTemplate) -> Result<(), String> {
+        let name = template.name.trim();
+        if name.is_empty() {
+            return Err("Template needs a name".to_string());
+        }
+        if template.text.trim().is_empty() {
+            return Err(format!("Template {} is empty", name));
+        }
+        let template = TtsTemplate {
+            name: name.to_string(),
+            ..template
+        };
+        match self.templates.iter_mut().find(|existing| existing.name == template.name) {
+            Some(existing) => *existing = template,
+            None => self.templates.push(template),
+        }
+        self.save()
+    }
+
+    pub(super) fn remove(&mut self, name: &str) -> Result<(), String> {
+        let before = self.templates.len();
+        self.templates.retain(|template| template.name != name.trim());
+        if self.templates.len() == before {
+            return Err(format!("No template named {}", name));
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+        let contents = serde_json::to_string_pretty(&self.templates)
+            .map_err(|e| format!("Failed to serialize templates: {}", e))?;
+        std::fs::write(path, contents).map_err(|e| format!("Failed to save templates: {}", e))
+    }
+}
+
+impl TtsTemplate {
+    /// The template's text with each `{name}` replaced by its value. Braces
+    /// around anything other than a placeholder name are kept as written.
+    pub(super) fn render(&self, values: &HashMap<String, String>) -> Result<String, String> {
+        let mut out = String::with_capacity(self.text.len());
+        let mut rest = self.text.as_str();
+        while let Some(open) = rest.find('{') {
+            out.push_str(&rest[..open]);
+            let after = &rest[open + 1..];
+            let key = after.find('}').map(|close| &after[..close]).filter(|key| {
+                !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            });
+            match key {
+                Some(key) => {
+                    let value = values
+                        .get(key)
+                        .ok_or_else(|| format!("Template {} needs a value for {{{}}}", self.name, key))?;
+                    if self.ssml {
+                        out.push_str(&escape_xml(value));
+                    } else {
+                        out.push_str(value);
+                    }
+                    rest = &after[key.len() + 1..];
+                }
+                None => {
+                    out.push('{');
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,148 @@
+use super::WordBoundary;
+use crate::audio_input::viseme::{Viseme, VisemeChange};
+use crate::audio_output::{AudioOutputState, SessionId};
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, TryRecvError};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+
+/// How often playback is checked for the next word or mouth shape.
+const TIMELINE_POLL_MS: u64 = 20;
+
+/// Payload of `tts://word`, emitted as each word starts playing.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SpokenWord {
+    pub session_id: SessionId,
+    /// Position of the word in the message, counting from 0
+    pub index: usize,
+    pub text: String,
+    /// When the word starts and ends in the session's playback
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+/// Payload of `tts://viseme`, emitted when the mouth shape of the speech
+/// being played changes.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SpokenViseme {
+    pub session_id: SessionId,
+    pub viseme: Viseme,
+    /// How far open the mouth is, 0-1
+    pub weight: f32,
+    /// Position in the session's playback
+    pub offset_ms: u64,
+}
+
+/// Timed marks still arriving from synthesis, waiting for playback to reach
+/// them.
+struct Pending<T> {
+    rx: mpsc::Receiver<T>,
+    marks: VecDeque<T>,
+    synthesizing: bool,
+}
+
+impl<T> Pending<T> {
+    fn new(rx: mpsc::Receiver<T>) -> Self {
+        Self {
+            rx,
+            marks: VecDeque::new(),
+            synthesizing: true,
+        }
+    }
+
+    fn receive(&mut self) {
+        while self.synthesizing {
+            match self.rx.try_recv() {
+                Ok(mark) => self.marks.push_back(mark),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.synthesizing = false,
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        !self.synthesizing && self.marks.is_empty()
+    }
+
+    /// Take the next mark if it starts at or before `position`.
+    fn next_before(&mut self, position: u64, start: impl Fn(&T) -> u64) -> Option<T> {
+        match self.marks.front() {
+            Some(mark) if start(mark) <= position => self.marks.pop_front(),
+            _ => None,
+        }
+    }
+}
+
+/// Emit `tts://word` and `tts://viseme` as playback of `session_id` reaches
+/// each word and mouth shape. Their times are in synthesized audio, so they
+/// are scaled by the playback `speed`. Ends with the session, or once
+/// synthesis is over and everything has been emitted with the mouth closed.
+pub(super) fn spawn_timeline_events(
+    app: AppHandle,
+    session_id: SessionId,
+    words: mpsc::Receiver<WordBoundary>,
+    visemes: mpsc::Receiver<VisemeChange>,
+    speed: f64,
+) {
+    std::thread::spawn(move || {
+        let output = app.state::<AudioOutputState>();
+        let to_playback_ms = |ms: u64| (ms as f64 / speed).round() as u64;
+        let mut words = Pending::new(words);
+        let mut visemes = Pending::new(visemes);
+        let mut index = 0;
+        let mut open = false;
+        let mut position_ms = 0;
+        loop {
+            std::thread::sleep(Duration::from_millis(TIMELINE_POLL_MS));
+            words.receive();
+            visemes.receive();
+            // An open mouth is closed when the session ends
+            if words.is_done() && visemes.is_done() && !open {
+                return;
+            }
+
+            position_ms = match output.playback_status(session_id) {
+                Ok(status) => status.position_ms,
+                Err(_) => break,
+            };
+            while let Some(word) = words.next_before(position_ms, |word| to_playback_ms(word.start_ms)) {
+                let event = SpokenWord {
+                    session_id,
+                    index,
+                    text: word.text,
+                    start_ms: to_playback_ms(word.start_ms),
+                    end_ms: to_playback_ms(word.end_ms),
+                };
+                emit(&app, "tts://word", &event);
+                index += 1;
+            }
+            while let Some(change) = visemes.next_before(position_ms, |change| to_playback_ms(change.offset_ms)) {
+                open = change.viseme != Viseme::Sil;
+                let event = SpokenViseme {
+                    session_id,
+                    viseme: change.viseme,
+                    weight: change.weight,
+                    offset_ms: to_playback_ms(change.offset_ms),
+                };
+                emit(&app, "tts://viseme", &event);
+            }
+        }
+
+        // Stopped mid-word; don't leave the avatar with its mouth open
+        if open {
+            let event = SpokenViseme {
+                session_id,
+                viseme: Viseme::Sil,
+                weight: 0.0,
+                offset_ms: position_ms,
+            };
+            emit(&app, "tts://viseme", &event);
+        }
+    });
+}
+
+fn emit<S: serde::Serialize + Clone>(app: &AppHandle, event: &str, payload: &S) {
+    if let Err(e) = app.emit(event, payload) {
+        eprintln!("Failed to emit {} event: {}", event, e);
+    }
+}